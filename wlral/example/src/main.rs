@@ -7,7 +7,7 @@ use wlral::input::events::*;
 use wlral::output::Output;
 use wlral::output_management_protocol::OutputManagementProtocol;
 use wlral::output_manager::OutputManager;
-use wlral::window::{Window, WindowEdge};
+use wlral::window::{Window, WindowEdge, WindowId};
 use wlral::window_management_policy::*;
 use wlral::window_manager::WindowManager;
 use xkbcommon::xkb;
@@ -23,7 +23,7 @@ struct FloatingWindowManager {
   output_management_protocol: Rc<OutputManagementProtocol>,
 
   gesture: Option<Gesture>,
-  restore_size: BTreeMap<usize, Rectangle>,
+  restore_size: BTreeMap<WindowId, Rectangle>,
 }
 
 impl FloatingWindowManager {
@@ -45,18 +45,18 @@ impl WindowManagementPolicy for FloatingWindowManager {
     if window.can_receive_focus() {
       // Center the new window
       if let Some(output) = output {
-        window.move_to(
-          output.top_left() + ((output.size() - window.extents().size()) / 2.0).as_displacement(),
-        );
+        window.center_on(&output);
       }
 
       // Focus the new window
-      self.window_manager.focus_window(window.clone());
+      let _ = self.window_manager.focus_window(window.clone());
     }
   }
 
   fn handle_request_activate(&mut self, request: ActivateRequest) {
-    self.window_manager.focus_window(request.window);
+    if let Err(error) = self.window_manager.focus_window(request.window) {
+      println!("Could not activate window: {}", error);
+    }
   }
 
   fn handle_request_close(&mut self, request: CloseRequest) {
@@ -96,10 +96,9 @@ impl WindowManagementPolicy for FloatingWindowManager {
 
     if let Some(output) = output {
       if request.maximize {
-        self.restore_size.insert(
-          request.window.wlr_surface() as usize,
-          request.window.extents(),
-        );
+        self
+          .restore_size
+          .insert(request.window.id(), request.window.extents());
         request.window.set_maximized(true);
         request.window.set_extents(&Rectangle {
           top_left: output.top_left(),
@@ -107,10 +106,7 @@ impl WindowManagementPolicy for FloatingWindowManager {
         });
       } else {
         request.window.set_maximized(false);
-        if let Some(extents) = self
-          .restore_size
-          .get(&(request.window.wlr_surface() as usize))
-        {
+        if let Some(extents) = self.restore_size.get(&request.window.id()) {
           request.window.set_extents(extents);
         }
       }
@@ -124,10 +120,9 @@ impl WindowManagementPolicy for FloatingWindowManager {
 
     if let Some(output) = output {
       if request.fullscreen {
-        self.restore_size.insert(
-          request.window.wlr_surface() as usize,
-          request.window.extents(),
-        );
+        self
+          .restore_size
+          .insert(request.window.id(), request.window.extents());
         request.window.set_fullscreen(true);
         request.window.set_extents(&Rectangle {
           top_left: output.top_left(),
@@ -135,10 +130,7 @@ impl WindowManagementPolicy for FloatingWindowManager {
         });
       } else {
         request.window.set_fullscreen(false);
-        if let Some(extents) = self
-          .restore_size
-          .get(&(request.window.wlr_surface() as usize))
-        {
+        if let Some(extents) = self.restore_size.get(&request.window.id()) {
           request.window.set_extents(extents);
         }
       }
@@ -197,6 +189,26 @@ impl EventFilter for FloatingWindowManager {
     }
   }
 
+  fn handle_pointer_click_event(&mut self, event: &ClickEvent) -> bool {
+    // Double clicking with the middle button toggles maximize of the window
+    // under the cursor
+    if event.button() != PointerButton::Middle || event.count() != ClickCount::Double {
+      return false;
+    }
+    match self.window_manager.window_at(&event.position().into()) {
+      Some(window) => {
+        let maximize = !window.maximized();
+        self.handle_request_maximize(MaximizeRequest {
+          window,
+          maximize,
+          originator: RequestOriginator::Application,
+        });
+        true
+      }
+      None => false,
+    }
+  }
+
   fn handle_keyboard_event(&mut self, event: &KeyboardEvent) -> bool {
     let keysym = event.get_one_sym();
 
@@ -209,7 +221,8 @@ impl EventFilter for FloatingWindowManager {
         .xkb_state()
         .mod_name_is_active(xkb::MOD_NAME_CTRL, xkb::STATE_MODS_DEPRESSED)
     {
-      if let Some(window) = self.window_manager.focused_window() {
+      let seat_manager = event.keyboard().seat_manager();
+      if let Some(window) = self.window_manager.focused_window(&seat_manager) {
         window.ask_client_to_close();
       }
       true
@@ -260,6 +273,13 @@ impl EventFilter for FloatingWindowManager {
             .join(", ")
         );
       }
+      println!("Outputs:");
+      for output in self.output_manager.outputs().iter() {
+        println!("  {}:", output.name());
+        for window in self.window_manager.windows_on_output(output) {
+          println!("    {}", window.title().unwrap_or("[no title]".to_string()));
+        }
+      }
       true
     } else {
       false
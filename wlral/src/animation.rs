@@ -0,0 +1,343 @@
+use crate::{output::Output, output_manager::OutputManager, window::Window};
+use log::debug;
+use std::{
+  cell::{Cell, RefCell},
+  fmt::Debug,
+  rc::{Rc, Weak},
+  time::{Duration, Instant},
+};
+#[cfg(not(test))]
+use wlroots_sys::wlr_output_schedule_frame;
+
+/// Maps the linear progress of an animation to an eased progress
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Easing {
+  Linear,
+  EaseIn,
+  EaseOut,
+  EaseInOut,
+}
+
+impl Easing {
+  /// Applies the easing function to `t` which is in the range 0.0 to 1.0
+  pub fn apply(&self, t: f32) -> f32 {
+    let t = t.max(0.0).min(1.0);
+    match self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t * t,
+      Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+      Easing::EaseInOut => {
+        if t < 0.5 {
+          4.0 * t * t * t
+        } else {
+          1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+        }
+      }
+    }
+  }
+}
+
+/// What an animation changes, which decides on which outputs it is shown
+enum AnimationTarget {
+  AllOutputs,
+  Window(Weak<Window>),
+}
+
+struct Animation {
+  target: AnimationTarget,
+  started_at: Instant,
+  duration: Duration,
+  easing: Easing,
+  cancelled: Cell<bool>,
+  callback: RefCell<Box<dyn FnMut(f32)>>,
+}
+
+impl Animation {
+  fn progress(&self, now: Instant) -> f32 {
+    if self.duration.as_nanos() == 0 {
+      return 1.0;
+    }
+    let elapsed = now.saturating_duration_since(self.started_at);
+    (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+  }
+
+  /// The outputs the animation is shown on, or None if it is shown on all
+  fn outputs(&self) -> Option<Vec<Rc<Output>>> {
+    match &self.target {
+      AnimationTarget::AllOutputs => None,
+      AnimationTarget::Window(window) => window
+        .upgrade()
+        .map(|window| window.outputs().clone())
+        .filter(|outputs| !outputs.is_empty()),
+    }
+  }
+
+  fn is_shown_on(&self, output: &Output) -> bool {
+    self
+      .outputs()
+      .map_or(true, |outputs| outputs.iter().any(|o| **o == *output))
+  }
+}
+
+/// A handle to a running animation
+#[derive(Clone)]
+pub struct AnimationHandle(Weak<Animation>);
+
+impl AnimationHandle {
+  /// Stops the animation without calling it again
+  pub fn cancel(&self) {
+    if let Some(animation) = self.0.upgrade() {
+      animation.cancelled.set(true);
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self
+      .0
+      .upgrade()
+      .map(|animation| !animation.cancelled.get())
+      .unwrap_or(false)
+  }
+}
+
+impl Debug for AnimationHandle {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "AnimationHandle {{running: {}}}", self.is_running())
+  }
+}
+
+/// Drives animations with the elapsed wall-clock time
+///
+/// Animations are ticked right before a frame is rendered on an output they
+/// are shown on, so that they follow the refresh rate of that output. New
+/// frames are only scheduled on those outputs while an animation is running.
+pub struct AnimationManager {
+  output_manager: Rc<OutputManager>,
+  animations: RefCell<Vec<Rc<Animation>>>,
+}
+
+impl Debug for AnimationManager {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "AnimationManager {{animations: {}}}",
+      self.animations.borrow().len()
+    )
+  }
+}
+
+impl AnimationManager {
+  pub(crate) fn init(output_manager: Rc<OutputManager>) -> Rc<AnimationManager> {
+    debug!("AnimationManager::init");
+
+    let animation_manager = Rc::new(AnimationManager {
+      output_manager: output_manager.clone(),
+      animations: RefCell::new(vec![]),
+    });
+
+    for output in output_manager.outputs().iter() {
+      animation_manager.follow_output(output);
+    }
+    let weak_animation_manager = Rc::downgrade(&animation_manager);
+    output_manager
+      .on_new_output()
+      .subscribe(Box::new(move |output| {
+        if let Some(animation_manager) = weak_animation_manager.upgrade() {
+          animation_manager.follow_output(&output);
+        }
+      }));
+
+    animation_manager
+  }
+
+  /// Ticks the animations shown on the output before each frame is rendered
+  fn follow_output(self: &Rc<Self>, output: &Rc<Output>) {
+    let animation_manager: Weak<AnimationManager> = Rc::downgrade(self);
+    let weak_output = Rc::downgrade(output);
+    output.on_frame().subscribe(Box::new(move |_| {
+      if let (Some(animation_manager), Some(output)) =
+        (animation_manager.upgrade(), weak_output.upgrade())
+      {
+        animation_manager.tick(Instant::now(), |animation| animation.is_shown_on(&output));
+      }
+    }));
+  }
+
+  /// Calls `callback` with the eased progress, from 0.0 to 1.0, before every
+  /// frame on any output until `duration` has passed. The last call always
+  /// has progress 1.0.
+  pub fn animate<F>(&self, duration: Duration, easing: Easing, callback: F) -> AnimationHandle
+  where
+    F: FnMut(f32),
+    F: 'static,
+  {
+    self.start(AnimationTarget::AllOutputs, duration, easing, callback)
+  }
+
+  /// Like `animate`, but only ticks on and schedules frames for the outputs
+  /// that the window is on
+  pub fn animate_window<F>(
+    &self,
+    window: &Rc<Window>,
+    duration: Duration,
+    easing: Easing,
+    callback: F,
+  ) -> AnimationHandle
+  where
+    F: FnMut(f32),
+    F: 'static,
+  {
+    self.start(
+      AnimationTarget::Window(Rc::downgrade(window)),
+      duration,
+      easing,
+      callback,
+    )
+  }
+
+  fn start<F>(
+    &self,
+    target: AnimationTarget,
+    duration: Duration,
+    easing: Easing,
+    callback: F,
+  ) -> AnimationHandle
+  where
+    F: FnMut(f32),
+    F: 'static,
+  {
+    let animation = Rc::new(Animation {
+      target,
+      started_at: Instant::now(),
+      duration,
+      easing,
+      cancelled: Cell::new(false),
+      callback: RefCell::new(Box::new(callback)),
+    });
+    let handle = AnimationHandle(Rc::downgrade(&animation));
+    if self.output_manager.outputs().is_empty() {
+      // Without outputs there are no frames to tick on, so skip to the end
+      (animation.callback.borrow_mut())(1.0);
+      animation.cancelled.set(true);
+      return handle;
+    }
+    self.animations.borrow_mut().push(animation);
+    self.schedule_frames();
+    handle
+  }
+
+  pub fn is_animating(&self) -> bool {
+    !self.animations.borrow().is_empty()
+  }
+
+  /// Advances the animations selected by `is_due` to `now`
+  fn tick<F>(&self, now: Instant, is_due: F)
+  where
+    F: Fn(&Animation) -> bool,
+  {
+    // Callbacks may start or cancel animations so iterate over a copy
+    let animations = self.animations.borrow().clone();
+    for animation in animations.iter() {
+      if animation.cancelled.get() || !is_due(animation) {
+        continue;
+      }
+      let progress = animation.progress(now);
+      (animation.callback.borrow_mut())(animation.easing.apply(progress));
+      if progress >= 1.0 {
+        animation.cancelled.set(true);
+      }
+    }
+
+    self
+      .animations
+      .borrow_mut()
+      .retain(|animation| !animation.cancelled.get());
+
+    self.schedule_frames();
+  }
+
+  /// Schedules a frame on every output that a running animation is shown on
+  fn schedule_frames(&self) {
+    let all_outputs = self.output_manager.outputs().clone();
+    let mut outputs: Vec<Rc<Output>> = vec![];
+    for animation in self.animations.borrow().iter() {
+      for output in animation.outputs().unwrap_or_else(|| all_outputs.clone()) {
+        if !outputs.contains(&output) {
+          outputs.push(output);
+        }
+      }
+    }
+
+    #[cfg(not(test))]
+    for output in outputs.iter() {
+      unsafe {
+        wlr_output_schedule_frame(output.raw_ptr());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::MockManagers;
+
+  #[test]
+  fn it_eases_progress() {
+    for easing in [
+      Easing::Linear,
+      Easing::EaseIn,
+      Easing::EaseOut,
+      Easing::EaseInOut,
+    ]
+    .iter()
+    {
+      assert_eq!(easing.apply(0.0), 0.0);
+      assert_eq!(easing.apply(1.0), 1.0);
+    }
+    assert_eq!(Easing::Linear.apply(0.25), 0.25);
+    assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    assert!(Easing::EaseIn.apply(0.25) < 0.25);
+    assert!(Easing::EaseOut.apply(0.25) > 0.25);
+  }
+
+  #[test]
+  fn it_runs_animations_until_done_or_cancelled() {
+    let managers = MockManagers::new();
+    let animation_manager = AnimationManager::init(managers.output_manager.clone());
+
+    let started_at = Instant::now();
+    let progress = Rc::new(RefCell::new(vec![]));
+    let animation = |duration_ms| {
+      let progress = progress.clone();
+      let animation = Rc::new(Animation {
+        target: AnimationTarget::AllOutputs,
+        started_at,
+        duration: Duration::from_millis(duration_ms),
+        easing: Easing::Linear,
+        cancelled: Cell::new(false),
+        callback: RefCell::new(Box::new(move |p| progress.borrow_mut().push(p))),
+      });
+      let handle = AnimationHandle(Rc::downgrade(&animation));
+      animation_manager.animations.borrow_mut().push(animation);
+      handle
+    };
+    let first = animation(100);
+    let second = animation(100);
+    second.cancel();
+
+    animation_manager.tick(started_at + Duration::from_millis(50), |_| true);
+    assert_eq!(*progress.borrow(), vec![0.5]);
+    assert!(first.is_running());
+    assert!(!second.is_running());
+
+    // Animations are only advanced by frames of the outputs they are shown on
+    animation_manager.tick(started_at + Duration::from_millis(75), |_| false);
+    assert_eq!(*progress.borrow(), vec![0.5]);
+
+    animation_manager.tick(started_at + Duration::from_millis(150), |_| true);
+    assert_eq!(*progress.borrow(), vec![0.5, 1.0]);
+    assert!(!first.is_running());
+    assert!(!animation_manager.is_animating());
+  }
+}
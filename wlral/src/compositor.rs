@@ -1,32 +1,258 @@
 use crate::{
+  animation::AnimationManager,
   config::ConfigManager,
+  geometry::Size,
   input::cursor::*,
   input::event_filter::*,
+  input::input_method::InputMethodManager,
   input::keyboard::*,
   input::seat::*,
+  input::switch_device::*,
+  input::tablet::*,
+  magnifier::Magnifier,
   output_management_protocol::OutputManagementProtocol,
   output_manager::OutputManager,
   shell::layer::*,
   shell::xdg::*,
   shell::xwayland::*,
+  window::WindowId,
   window_management_policy::{WindowManagementPolicy, WmPolicyManager},
   window_manager::{WindowManager, WindowManagerExt},
 };
 use log::{debug, error};
 use std::{
-  cell::RefCell,
+  cell::{Cell, RefCell},
+  collections::BTreeMap,
   env,
   ffi::{CStr, CString},
+  mem, ptr,
   rc::Rc,
 };
 use wayland_sys::server::*;
 use wlroots_sys::*;
 
+/// Adds a Unix socket to the Wayland display, with the passed name or the
+/// first free `wayland-N` name, and returns the name
+unsafe fn add_socket(display: *mut wl_display, name: Option<&str>) -> Result<String, ()> {
+  match name {
+    Some(name) => {
+      let c_name = CString::new(name).map_err(|_| {
+        error!("Compositor: Invalid socket name {:?}", name);
+      })?;
+      let result = ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_display_add_socket,
+        display,
+        c_name.as_ptr()
+      );
+      if result != 0 {
+        error!("Compositor: Unable to open wayland socket {:?}", name);
+        return Err(());
+      }
+      Ok(name.to_string())
+    }
+    None => {
+      let socket = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_add_socket_auto, display);
+      if socket.is_null() {
+        error!("Compositor: Unable to open wayland socket");
+        return Err(());
+      }
+      Ok(CStr::from_ptr(socket).to_string_lossy().into_owned())
+    }
+  }
+}
+
+/// Stops the event loop of a running compositor, making `Compositor::run`
+/// return after cleaning up.
+///
+/// Does nothing once the compositor has shut down.
+#[derive(Debug, Clone)]
+pub struct TerminateHandle {
+  display: Rc<Cell<*mut wl_display>>,
+}
+
+impl TerminateHandle {
+  pub fn terminate(&self) {
+    let display = self.display.get();
+    if !display.is_null() {
+      unsafe {
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_terminate, display);
+      }
+    }
+  }
+}
+
+/// Runs the Wayland event loop until it is terminated, then disconnects all
+/// clients and destroys the display
+unsafe fn run_display(terminate_handle: &TerminateHandle) {
+  let display = terminate_handle.display.get();
+  ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_run, display);
+
+  // Once wl_display_run returns, we shut down the server.
+  terminate_handle.display.set(ptr::null_mut());
+  ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy_clients, display);
+  ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+}
+
+/// Forks and executes `cmd` with `/bin/sh` in a new session and returns the
+/// pid of the command.
+///
+/// The command is started from an intermediate child which exits directly,
+/// so that the command is reparented to init and never becomes a zombie of
+/// the compositor.
+fn spawn(cmd: &str, env: &[CString]) -> Result<libc::pid_t, ()> {
+  // Everything must be allocated before forking as only async-signal-safe
+  // functions may be called in the child
+  let cmd = CString::new(cmd).map_err(|_| {
+    error!("Compositor::spawn: Invalid command {:?}", cmd);
+  })?;
+  let sh = CString::new("/bin/sh").unwrap();
+  let flag = CString::new("-c").unwrap();
+  let argv = [sh.as_ptr(), flag.as_ptr(), cmd.as_ptr(), ptr::null()];
+  let envp = env
+    .iter()
+    .map(|var| var.as_ptr())
+    .chain(std::iter::once(ptr::null()))
+    .collect::<Vec<_>>();
+
+  unsafe {
+    let mut fds = [0; 2];
+    if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+      error!("Compositor::spawn: Could not create pipe");
+      return Err(());
+    }
+    let [read_fd, write_fd] = fds;
+
+    let child = libc::fork();
+    if child == 0 {
+      libc::setsid();
+      let mut set: libc::sigset_t = mem::zeroed();
+      libc::sigemptyset(&mut set);
+      libc::sigprocmask(libc::SIG_SETMASK, &set, ptr::null_mut());
+
+      let pid = libc::fork();
+      if pid == 0 {
+        libc::execve(sh.as_ptr(), argv.as_ptr(), envp.as_ptr());
+        libc::_exit(1);
+      }
+      libc::write(
+        write_fd,
+        &pid as *const libc::pid_t as *const libc::c_void,
+        mem::size_of::<libc::pid_t>(),
+      );
+      libc::_exit(0);
+    }
+    libc::close(write_fd);
+    if child < 0 {
+      libc::close(read_fd);
+      error!("Compositor::spawn: Could not fork");
+      return Err(());
+    }
+
+    let mut pid: libc::pid_t = -1;
+    let read = libc::read(
+      read_fd,
+      &mut pid as *mut libc::pid_t as *mut libc::c_void,
+      mem::size_of::<libc::pid_t>(),
+    );
+    libc::close(read_fd);
+    libc::waitpid(child, ptr::null_mut(), 0);
+
+    if read != mem::size_of::<libc::pid_t>() as isize || pid < 0 {
+      error!("Compositor::spawn: Could not fork");
+      return Err(());
+    }
+    Ok(pid)
+  }
+}
+
+/// A connected client and the windows it owns
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+  pub pid: libc::pid_t,
+  /// The windows of the client, from back to front
+  pub windows: Vec<WindowId>,
+}
+
+/// Groups windows, given from back to front with their pid, by client
+///
+/// Windows without a known pid, like X11 windows that do not set
+/// _NET_WM_PID, are left out.
+fn group_clients<I>(windows: I) -> Vec<ClientInfo>
+where
+  I: Iterator<Item = (Option<libc::pid_t>, WindowId)>,
+{
+  let mut clients: BTreeMap<libc::pid_t, Vec<WindowId>> = BTreeMap::new();
+  for (pid, window) in windows {
+    if let Some(pid) = pid {
+      clients.entry(pid).or_default().push(window);
+    }
+  }
+  clients
+    .into_iter()
+    .map(|(pid, windows)| ClientInfo { pid, windows })
+    .collect()
+}
+
+/// Lets the window management policy decide which clients can see the
+/// DMA-BUF export global
+struct GlobalFilter {
+  export_dmabuf_global: *mut wl_global,
+  wm_policy_manager: Rc<WmPolicyManager>,
+}
+
+unsafe extern "C" fn filter_global(
+  client: *const wl_client,
+  global: *const wl_global,
+  data: *mut libc::c_void,
+) -> bool {
+  let filter = &*(data as *const GlobalFilter);
+  if global != filter.export_dmabuf_global as *const _ {
+    return true;
+  }
+  let mut pid = 0;
+  let mut uid = 0;
+  let mut gid = 0;
+  ffi_dispatch!(
+    WAYLAND_SERVER_HANDLE,
+    wl_client_get_credentials,
+    client as *mut wl_client,
+    &mut pid,
+    &mut uid,
+    &mut gid
+  );
+  filter.wm_policy_manager.allow_export_dmabuf(pid)
+}
+
 #[allow(unused)]
+/// An additional seat, with its own cursor and keyboard focus
+pub struct Seat {
+  seat_manager: Rc<SeatManager>,
+  cursor_manager: Rc<CursorManager>,
+  keyboard_manager: Rc<KeyboardManager>,
+}
+
+impl Seat {
+  pub fn seat_manager(&self) -> Rc<SeatManager> {
+    self.seat_manager.clone()
+  }
+
+  pub fn cursor_manager(&self) -> Rc<CursorManager> {
+    self.cursor_manager.clone()
+  }
+
+  pub fn keyboard_manager(&self) -> Rc<KeyboardManager> {
+    self.keyboard_manager.clone()
+  }
+}
+
 pub struct Compositor {
   config_manager: Rc<ConfigManager>,
+  animation_manager: Rc<AnimationManager>,
 
   display: *mut wl_display,
+  socket_name: String,
+  terminate_handle: TerminateHandle,
   backend: *mut wlr_backend,
   renderer: *mut wlr_renderer,
   compositor: *mut wlr_compositor,
@@ -38,18 +264,181 @@ pub struct Compositor {
   window_manager: Rc<WindowManager>,
   layer_shell_manager: LayerShellManager,
   xdg_manager: XdgManager,
-  xwayland_manager: XwaylandManager,
+  xwayland_manager: Option<XwaylandManager>,
 
   seat_manager: Rc<SeatManager>,
   cursor_manager: Rc<CursorManager>,
   keyboard_manager: Rc<KeyboardManager>,
+  tablet_manager: Rc<TabletManager>,
+  switch_manager: Rc<SwitchManager>,
+  magnifier: Rc<Magnifier>,
+  seats: RefCell<Vec<Rc<Seat>>>,
+  input_method_manager: RefCell<Option<Rc<InputMethodManager>>>,
 
   wm_policy_manager: Rc<WmPolicyManager>,
   event_filter_manager: Rc<EventFilterManager>,
+  #[allow(unused)]
+  global_filter: Option<Box<GlobalFilter>>,
+}
+
+/// Configures which backend, renderer and Wayland globals a compositor
+/// is created with
+///
+/// All optional globals are enabled by default, while Xwayland is opt-in.
+#[derive(Debug, Clone)]
+pub struct CompositorBuilder {
+  xwayland: bool,
+  lazy_xwayland: bool,
+  screencopy: bool,
+  export_dmabuf: bool,
+  gamma_control: bool,
+  data_control: bool,
+  socket: Option<String>,
+  headless_outputs: Option<Vec<Size>>,
+  create_renderer: wlr_renderer_create_func_t,
+}
+
+impl Default for CompositorBuilder {
+  fn default() -> Self {
+    CompositorBuilder {
+      xwayland: false,
+      lazy_xwayland: true,
+      screencopy: true,
+      export_dmabuf: true,
+      gamma_control: true,
+      data_control: true,
+      socket: None,
+      headless_outputs: None,
+      create_renderer: None,
+    }
+  }
+}
+
+impl CompositorBuilder {
+  pub fn new() -> CompositorBuilder {
+    CompositorBuilder::default()
+  }
+
+  /// Runs an X server so that X11 clients can connect
+  pub fn with_xwayland(mut self, enabled: bool) -> CompositorBuilder {
+    self.xwayland = enabled;
+    self
+  }
+
+  /// Only starts the X server when the first X11 client connects, instead
+  /// of directly on startup. Enabled by default.
+  pub fn with_lazy_xwayland(mut self, lazy: bool) -> CompositorBuilder {
+    self.lazy_xwayland = lazy;
+    self
+  }
+
+  /// Allows clients to capture the content of outputs, for example for
+  /// screenshots
+  pub fn with_screencopy(mut self, enabled: bool) -> CompositorBuilder {
+    self.screencopy = enabled;
+    self
+  }
+
+  /// Allows clients to capture outputs as DMA-BUFs, which is used for
+  /// efficient screen sharing and recording
+  pub fn with_export_dmabuf(mut self, enabled: bool) -> CompositorBuilder {
+    self.export_dmabuf = enabled;
+    self
+  }
+
+  /// Allows clients to adjust the gamma of outputs, for example for night
+  /// light
+  pub fn with_gamma_control(mut self, enabled: bool) -> CompositorBuilder {
+    self.gamma_control = enabled;
+    self
+  }
+
+  /// Allows privileged clients, like clipboard managers, to read and set
+  /// the clipboard
+  pub fn with_data_control(mut self, enabled: bool) -> CompositorBuilder {
+    self.data_control = enabled;
+    self
+  }
+
+  /// Listens on a socket with a fixed name instead of the first free
+  /// `wayland-N` name, building fails if the name is already taken.
+  ///
+  /// This is useful for running nested or multiple compositors.
+  pub fn with_socket(mut self, name: &str) -> CompositorBuilder {
+    self.socket = Some(name.to_string());
+    self
+  }
+
+  /// Uses the headless backend, which renders offscreen and has no input
+  /// devices, with a virtual output of each size.
+  ///
+  /// This allows running without a GPU or display server, for example in CI.
+  pub fn headless(mut self, outputs: &[Size]) -> CompositorBuilder {
+    self.headless_outputs = Some(outputs.to_vec());
+    self
+  }
+
+  /// Uses a custom renderer, for example a software renderer, instead of
+  /// the default GLES2 renderer.
+  ///
+  /// wlroots creates the backends itself so the renderer is passed as a
+  /// function which the backend calls to create it. The renderer is owned
+  /// by the backend and is destroyed together with it, so it must not be
+  /// destroyed by the caller. It is used for all rendering, including the
+  /// rendering of windows in `Output::render_window`.
+  pub fn with_renderer(mut self, create_renderer: wlr_renderer_create_func_t) -> CompositorBuilder {
+    self.create_renderer = create_renderer;
+    self
+  }
+
+  pub fn build(self) -> Result<Compositor, ()> {
+    Compositor::init_with_builder(self)
+  }
 }
 
 impl Compositor {
+  /// Initializes the compositor with all optional globals and a lazily
+  /// started Xwayland
   pub fn init() -> Compositor {
+    CompositorBuilder::new()
+      .with_xwayland(true)
+      .build()
+      // NOTE Rationale for panicking:
+      // * Won't be in C land just yet, so it's safe to panic
+      // * Can always be returned in a Result instead, but for now if you auto create
+      //   it's assumed you can't recover.
+      .expect("Unable to open wayland socket")
+  }
+
+  /// Initializes the compositor with a custom renderer, see
+  /// `CompositorBuilder::with_renderer`
+  pub fn init_with_renderer(create_renderer: wlr_renderer_create_func_t) -> Compositor {
+    CompositorBuilder::new()
+      .with_xwayland(true)
+      .with_renderer(create_renderer)
+      .build()
+      .expect("Unable to open wayland socket")
+  }
+
+  /// Initializes the compositor listening on a socket with a fixed name, see
+  /// `CompositorBuilder::with_socket`
+  pub fn init_with_socket(name: &str) -> Result<Compositor, ()> {
+    CompositorBuilder::new()
+      .with_xwayland(true)
+      .with_socket(name)
+      .build()
+  }
+
+  /// Initializes the compositor on the headless backend, see
+  /// `CompositorBuilder::headless`
+  pub fn init_headless(outputs: &[Size]) -> Compositor {
+    CompositorBuilder::new()
+      .headless(outputs)
+      .build()
+      .expect("Unable to open wayland socket")
+  }
+
+  fn init_with_builder(builder: CompositorBuilder) -> Result<Compositor, ()> {
     let wm_policy_manager = Rc::new(WmPolicyManager::new());
     let config_manager = Rc::new(ConfigManager::default());
 
@@ -57,17 +446,33 @@ impl Compositor {
       // The Wayland display is managed by libwayland. It handles accepting
       // clients from the Unix socket, manging Wayland globals, and so on.
       let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+
+      // Add a Unix socket to the Wayland display.
+      let socket_name = match add_socket(display, builder.socket.as_deref()) {
+        Ok(socket_name) => socket_name,
+        Err(()) => {
+          ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+          return Err(());
+        }
+      };
+
+      config_manager.set_display(display);
+
       // The backend is a wlroots feature which abstracts the underlying input and
       // output hardware. The autocreate option will choose the most suitable
       // backend based on the current environment, such as opening an X11 window
-      // if an X11 server is running. The NULL argument here optionally allows you
-      // to pass in a custom renderer if wlr_renderer doesn't meet your needs. The
-      // backend uses the renderer, for example, to fall back to software cursors
-      // if the backend does not support hardware cursors (some older GPUs
+      // if an X11 server is running. The create_renderer argument optionally
+      // allows you to pass in a custom renderer if wlr_renderer doesn't meet your
+      // needs. The backend uses the renderer, for example, to fall back to software
+      // cursors if the backend does not support hardware cursors (some older GPUs
       // don't).
-      let backend = wlr_backend_autocreate(display, None);
+      let backend = if builder.headless_outputs.is_some() {
+        wlr_headless_backend_create(display, builder.create_renderer)
+      } else {
+        wlr_backend_autocreate(display, builder.create_renderer)
+      };
 
-      // If we don't provide a renderer, autocreate makes a GLES2 renderer for us.
+      // If we don't provide a renderer, the backend makes a GLES2 renderer for us.
       // The renderer is responsible for defining the various pixel formats it
       // supports for shared memory, this configures that for clients.
       let renderer = wlr_backend_get_renderer(backend);
@@ -79,17 +484,19 @@ impl Compositor {
       // to dig your fingers in and play with their behavior if you want.
       let compositor = wlr_compositor_create(display, renderer);
       wlr_data_device_manager_create(display);
-      wlr_gamma_control_manager_v1_create(display);
+      if builder.gamma_control {
+        wlr_gamma_control_manager_v1_create(display);
+      }
       wlr_gtk_primary_selection_device_manager_create(display);
 
       // Configures a seat, which is a single "seat" at which a user sits and
       // operates the computer. This conceptually includes up to one keyboard,
       // pointer, touch, and drawing tablet device. We also rig up a listener to
       // let us know when new input devices are available on the backend.
-      let seat_name = CString::new("seat0").unwrap();
+      let seat_name = CString::new(DEFAULT_SEAT).unwrap();
       let seat = wlr_seat_create(display, seat_name.as_ptr());
 
-      let seat_manager = SeatManager::init(display, backend, seat);
+      let seat_manager = SeatManager::init(config_manager.clone(), display, backend, seat);
       let window_manager = Rc::new(WindowManager::init(
         wm_policy_manager.clone(),
         seat_manager.clone(),
@@ -110,9 +517,11 @@ impl Compositor {
         output_layout,
       );
       window_manager.set_output_manager(output_manager.clone());
+      let animation_manager = AnimationManager::init(output_manager.clone());
 
       let event_filter_manager = Rc::new(EventFilterManager::new());
       let cursor_manager = CursorManager::init(
+        config_manager.clone(),
         output_manager.clone(),
         window_manager.clone(),
         seat_manager.clone(),
@@ -124,56 +533,92 @@ impl Compositor {
         seat_manager.clone(),
         event_filter_manager.clone(),
       );
-
-      let layer_shell_manager = LayerShellManager::init(
-        wm_policy_manager.clone(),
+      let tablet_manager = TabletManager::init(
         output_manager.clone(),
         window_manager.clone(),
+        seat_manager.clone(),
         cursor_manager.clone(),
+        event_filter_manager.clone(),
         display,
       );
-      let xdg_manager = XdgManager::init(
+      let switch_manager = SwitchManager::init(seat_manager.clone());
+      let magnifier = Magnifier::init(output_manager.clone(), cursor_manager.clone());
+
+      let layer_shell_manager = LayerShellManager::init(
         wm_policy_manager.clone(),
         output_manager.clone(),
         window_manager.clone(),
         cursor_manager.clone(),
         display,
       );
-      let xwayland_manager = XwaylandManager::init(
+      let xdg_manager = XdgManager::init(
         wm_policy_manager.clone(),
         output_manager.clone(),
         window_manager.clone(),
         cursor_manager.clone(),
         display,
-        compositor,
       );
+      let xwayland_manager = if builder.xwayland {
+        Some(XwaylandManager::init(
+          wm_policy_manager.clone(),
+          output_manager.clone(),
+          window_manager.clone(),
+          cursor_manager.clone(),
+          seat_manager.clone(),
+          display,
+          compositor,
+          builder.lazy_xwayland,
+        ))
+      } else {
+        None
+      };
 
       event_filter_manager.add_event_filter(Box::new(VtSwitchEventFilter::new(backend)));
 
-      wlr_export_dmabuf_manager_v1_create(display);
-      wlr_screencopy_manager_v1_create(display);
-      wlr_data_control_manager_v1_create(display);
+      let global_filter = if builder.export_dmabuf {
+        let export_dmabuf_manager = wlr_export_dmabuf_manager_v1_create(display);
+        output_manager.set_export_dmabuf_manager(export_dmabuf_manager);
+        let mut global_filter = Box::new(GlobalFilter {
+          export_dmabuf_global: (*export_dmabuf_manager).global,
+          wm_policy_manager: wm_policy_manager.clone(),
+        });
+        wl_display_set_global_filter(
+          display,
+          Some(filter_global),
+          &mut *global_filter as *mut GlobalFilter as *mut libc::c_void,
+        );
+        Some(global_filter)
+      } else {
+        None
+      };
+      if builder.screencopy {
+        output_manager.set_screencopy_manager(wlr_screencopy_manager_v1_create(display));
+      }
+      if builder.data_control {
+        wlr_data_control_manager_v1_create(display);
+      }
       wlr_primary_selection_v1_device_manager_create(display);
 
-      // Add a Unix socket to the Wayland display.
-      let socket = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_add_socket_auto, display);
-      if socket.is_null() {
-        // NOTE Rationale for panicking:
-        // * Won't be in C land just yet, so it's safe to panic
-        // * Can always be returned in a Result instead, but for now if you auto create
-        //   it's assumed you can't recover.
-        panic!("Unable to open wayland socket");
-      }
-      let socket_name = CStr::from_ptr(socket).to_string_lossy().into_owned();
       env::set_var("WAYLAND_DISPLAY", socket_name.clone());
-      env::set_var("_WAYLAND_DISPLAY", socket_name);
+      env::set_var("_WAYLAND_DISPLAY", socket_name.clone());
+
+      for size in builder.headless_outputs.iter().flatten() {
+        if output_manager.add_virtual_output(*size).is_err() {
+          error!("Compositor::init: Could not add output of size {:?}", size);
+        }
+      }
 
       debug!("Compositor::init");
 
-      Compositor {
+      Ok(Compositor {
         config_manager,
+        animation_manager,
 
         display,
+        socket_name,
+        terminate_handle: TerminateHandle {
+          display: Rc::new(Cell::new(display)),
+        },
         backend,
         renderer,
         compositor,
@@ -190,17 +635,104 @@ impl Compositor {
         seat_manager,
         cursor_manager,
         keyboard_manager,
+        tablet_manager,
+        switch_manager,
+        magnifier,
+        seats: RefCell::new(vec![]),
+        input_method_manager: RefCell::new(None),
 
         wm_policy_manager,
         event_filter_manager,
+        global_filter,
+      })
+    }
+  }
+
+  /// A handle that stops the compositor, for example from an event filter
+  pub fn terminate_handle(&self) -> TerminateHandle {
+    self.terminate_handle.clone()
+  }
+
+  /// Runs a shell command, for example to start a panel or a terminal, and
+  /// returns its pid. The command connects to this compositor, also for X11
+  /// clients when Xwayland is running.
+  pub fn spawn(&self, cmd: &str) -> Result<libc::pid_t, ()> {
+    let mut env = env::vars()
+      .filter(|(key, _)| key != "WAYLAND_DISPLAY" && key != "DISPLAY")
+      .collect::<Vec<_>>();
+    env.push(("WAYLAND_DISPLAY".to_string(), self.socket_name.clone()));
+    if let Some(display) = self.xwayland_display_name() {
+      env.push(("DISPLAY".to_string(), display));
+    }
+    let env = env
+      .into_iter()
+      .filter_map(|(key, value)| CString::new(format!("{}={}", key, value)).ok())
+      .collect::<Vec<_>>();
+
+    spawn(cmd, &env)
+  }
+
+  /// The connected clients that have windows, ordered by pid
+  ///
+  /// X11 windows are listed under the pid they report with _NET_WM_PID.
+  pub fn clients(&self) -> Vec<ClientInfo> {
+    group_clients(
+      self
+        .window_manager
+        .windows()
+        .map(|window| (window.pid(), window.id())),
+    )
+  }
+
+  /// Disconnects the Wayland clients of the process, which destroys all of
+  /// their windows
+  ///
+  /// X11 clients can not be disconnected this way as their windows are
+  /// owned by the Xwayland server, which would be disconnected instead.
+  pub fn kill_client(&self, pid: libc::pid_t) -> Result<(), ()> {
+    let mut wl_clients = vec![];
+    for window in self.window_manager.windows() {
+      let is_owned = window.credentials().map(|(window_pid, _, _)| window_pid) == Some(pid);
+      if is_owned && !wl_clients.contains(&window.wl_client()) {
+        wl_clients.push(window.wl_client());
+      }
+    }
+    if wl_clients.is_empty() {
+      error!(
+        "Compositor::kill_client: No Wayland client with pid {}",
+        pid
+      );
+      return Err(());
+    }
+    for wl_client in wl_clients {
+      unsafe {
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_client_destroy, wl_client);
       }
     }
+    Ok(())
+  }
+
+  /// The X11 display that X11 clients connect to, if Xwayland is enabled
+  pub fn xwayland_display_name(&self) -> Option<String> {
+    self
+      .xwayland_manager
+      .as_ref()
+      .map(|xwayland_manager| xwayland_manager.display_name().to_string())
+  }
+
+  /// The name of the Wayland socket that clients connect to
+  pub fn socket_name(&self) -> String {
+    self.socket_name.clone()
   }
 
   pub fn config_manager(&self) -> Rc<ConfigManager> {
     self.config_manager.clone()
   }
 
+  pub fn animation_manager(&self) -> Rc<AnimationManager> {
+    self.animation_manager.clone()
+  }
+
   pub fn output_manager(&self) -> Rc<OutputManager> {
     self.output_manager.clone()
   }
@@ -209,10 +741,93 @@ impl Compositor {
     self.window_manager.clone()
   }
 
+  /// The primary seat, `seat0`
+  pub fn seat_manager(&self) -> Rc<SeatManager> {
+    self.seat_manager.clone()
+  }
+
   pub fn cursor_manager(&self) -> Rc<CursorManager> {
     self.cursor_manager.clone()
   }
 
+  pub fn keyboard_manager(&self) -> Rc<KeyboardManager> {
+    self.keyboard_manager.clone()
+  }
+
+  pub fn tablet_manager(&self) -> Rc<TabletManager> {
+    self.tablet_manager.clone()
+  }
+
+  pub fn switch_manager(&self) -> Rc<SwitchManager> {
+    self.switch_manager.clone()
+  }
+
+  pub fn magnifier(&self) -> Rc<Magnifier> {
+    self.magnifier.clone()
+  }
+
+  /// The seats added with `add_seat`
+  pub fn seats(&self) -> Vec<Rc<Seat>> {
+    self.seats.borrow().clone()
+  }
+
+  /// Creates an additional seat that gets the pointers and keyboards the
+  /// config assigns to it in `device_seats`
+  ///
+  /// Devices are assigned when they are added, so seats should be added
+  /// before the compositor is run. Drawing tablets and switches always
+  /// belong to the primary seat.
+  pub fn add_seat(&self, name: &str) -> Result<Rc<Seat>, ()> {
+    let exists = name == DEFAULT_SEAT
+      || self
+        .seats
+        .borrow()
+        .iter()
+        .any(|seat| seat.seat_manager.name() == name);
+    if exists {
+      error!(
+        "Compositor::add_seat: A seat named {:?} already exists",
+        name
+      );
+      return Err(());
+    }
+    let c_name = CString::new(name).map_err(|_| {
+      error!("Compositor::add_seat: Invalid seat name {:?}", name);
+    })?;
+
+    let seat = unsafe { wlr_seat_create(self.display, c_name.as_ptr()) };
+    let seat_manager = SeatManager::init_secondary(&self.seat_manager, name, seat);
+    let cursor_manager = CursorManager::init(
+      self.config_manager.clone(),
+      self.output_manager.clone(),
+      self.window_manager.clone(),
+      seat_manager.clone(),
+      self.event_filter_manager.clone(),
+      self.output_layout,
+    );
+    let keyboard_manager = KeyboardManager::init(
+      self.config_manager.clone(),
+      seat_manager.clone(),
+      self.event_filter_manager.clone(),
+    );
+
+    let seat = Rc::new(Seat {
+      seat_manager,
+      cursor_manager,
+      keyboard_manager,
+    });
+    self.seats.borrow_mut().push(seat.clone());
+    Ok(seat)
+  }
+
+  /// Enables or disables sharing of the clipboard and primary selection
+  /// between X11 and Wayland clients. Enabled by default.
+  pub fn set_xwayland_selection_bridge_enabled(&self, enabled: bool) {
+    if let Some(xwayland_manager) = &self.xwayland_manager {
+      xwayland_manager.set_selection_bridge_enabled(enabled);
+    }
+  }
+
   pub fn output_management_protocol(&self) -> Option<Rc<OutputManagementProtocol>> {
     self.output_management_protocol.borrow().clone()
   }
@@ -235,10 +850,53 @@ impl Compositor {
     Ok(protocol)
   }
 
-  pub fn add_event_filter(&mut self, filter: Box<dyn EventFilter>) {
+  pub fn input_method_manager(&self) -> Option<Rc<InputMethodManager>> {
+    self.input_method_manager.borrow().clone()
+  }
+
+  /// Enables the text-input and input-method protocols so that an input
+  /// method, like an IME or an on-screen keyboard, can enter text into the
+  /// focused client
+  pub fn enable_input_method(&self) -> Result<Rc<InputMethodManager>, ()> {
+    if self.input_method_manager.borrow().is_some() {
+      error!("Compositor::enable_input_method: input method is already enabled");
+      return Err(());
+    }
+    let input_method_manager = InputMethodManager::init(
+      self.window_manager.clone(),
+      self.seat_manager.clone(),
+      self.display,
+    );
+    self
+      .input_method_manager
+      .borrow_mut()
+      .replace(input_method_manager.clone());
+
+    Ok(input_method_manager)
+  }
+
+  /// Adds an event filter with the default priority, which is the same as
+  /// the priority of the window management policy
+  pub fn add_event_filter(&mut self, filter: Box<dyn EventFilter>) -> EventFilterHandle {
     self.event_filter_manager.add_event_filter(filter)
   }
 
+  /// Adds an event filter which is called before filters with lower priority.
+  /// Use a negative priority to be called after the window management policy.
+  pub fn add_event_filter_with_priority(
+    &mut self,
+    filter: Box<dyn EventFilter>,
+    priority: i32,
+  ) -> EventFilterHandle {
+    self
+      .event_filter_manager
+      .add_event_filter_with_priority(filter, priority)
+  }
+
+  pub fn remove_event_filter(&mut self, handle: EventFilterHandle) -> Option<Box<dyn EventFilter>> {
+    self.event_filter_manager.remove_event_filter(handle)
+  }
+
   pub fn run<T>(self, window_management_policy: T) -> Result<(), u32>
   where
     T: 'static + WindowManagementPolicy + EventFilter,
@@ -258,32 +916,144 @@ impl Compositor {
       // master, etc
       if !wlr_backend_start(self.backend) {
         wlr_backend_destroy(self.backend);
+        self.terminate_handle.display.set(ptr::null_mut());
         ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, self.display);
         return Err(2);
       }
 
-      // if (startup_cmd) {
-      //   if (fork() == 0) {
-      //     execl("/bin/sh", "/bin/sh", "-c", startup_cmd, (void *)NULL);
-      //   }
-      // }
-
       // Run the Wayland event loop. This does not return until you exit the
       // compositor. Starting the backend rigged up all of the necessary event
       // loop configuration to listen to libinput events, DRM events, generate
       // frame events at the refresh rate, and so on.
       // wlr_log(WLR_INFO, "Running Wayland compositor on WAYLAND_DISPLAY=%s",
       //		 socket);
-      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_run, self.display);
+      run_display(&self.terminate_handle);
+    }
+    Ok(())
+  }
+}
 
-      // Once wl_display_run returns, we shut down the server.
-      ffi_dispatch!(
-        WAYLAND_SERVER_HANDLE,
-        wl_display_destroy_clients,
-        self.display
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::wayland_timer::WlTimer;
+
+  #[test]
+  fn it_groups_windows_by_client() {
+    let windows = vec![
+      (Some(20), WindowId(1)),
+      (Some(10), WindowId(2)),
+      (None, WindowId(3)),
+      (Some(20), WindowId(4)),
+    ];
+    assert_eq!(
+      group_clients(windows.into_iter()),
+      vec![
+        ClientInfo {
+          pid: 10,
+          windows: vec![WindowId(2)],
+        },
+        ClientInfo {
+          pid: 20,
+          windows: vec![WindowId(1), WindowId(4)],
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn it_hides_the_export_dmabuf_global_from_denied_clients() {
+    struct AllowPid(libc::pid_t);
+    impl WindowManagementPolicy for AllowPid {
+      fn allow_export_dmabuf(&self, pid: libc::pid_t) -> bool {
+        pid == self.0
+      }
+    }
+
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      // The credentials of the client are read from its socket, so the
+      // client is this process
+      let mut fds = [0; 2];
+      assert_eq!(
+        libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()),
+        0
       );
-      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, self.display);
+      let client = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_client_create, display, fds[0]);
+      assert!(!client.is_null());
+
+      let wm_policy_manager = Rc::new(WmPolicyManager::new());
+      let mut filter = GlobalFilter {
+        export_dmabuf_global: 1 as *mut wl_global,
+        wm_policy_manager: wm_policy_manager.clone(),
+      };
+      let data = &mut filter as *mut GlobalFilter as *mut libc::c_void;
+      let export_dmabuf_global = 1 as *const wl_global;
+      let other_global = 2 as *const wl_global;
+
+      wm_policy_manager.set_policy(Rc::new(AllowPid(libc::getpid())));
+      assert!(filter_global(client, export_dmabuf_global, data));
+      assert!(filter_global(client, other_global, data));
+
+      wm_policy_manager.set_policy(Rc::new(AllowPid(libc::getpid() + 1)));
+      assert!(!filter_global(client, export_dmabuf_global, data));
+      assert!(filter_global(client, other_global, data));
+
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_client_destroy, client);
+      libc::close(fds[1]);
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+    }
+  }
+
+  #[test]
+  fn it_spawns_commands_detached() {
+    let dir = env::temp_dir().join(format!("wlral-spawn-{}", std::process::id()));
+    let env = [CString::new(format!("WLRAL_SPAWN_TEST={}", dir.display())).unwrap()];
+
+    let pid = spawn("echo $$ > \"$WLRAL_SPAWN_TEST\"", &env).unwrap();
+    assert!(pid > 0);
+
+    let mut written = String::new();
+    for _ in 0..100 {
+      written = std::fs::read_to_string(&dir).unwrap_or_default();
+      if written.ends_with('\n') {
+        break;
+      }
+      std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let _ = std::fs::remove_file(&dir);
+    assert_eq!(written.trim(), pid.to_string());
+  }
+
+  #[test]
+  fn it_stops_the_event_loop_when_terminated() {
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      let terminate_handle = TerminateHandle {
+        display: Rc::new(Cell::new(display)),
+      };
+
+      let terminated = Rc::new(Cell::new(false));
+      let timer = WlTimer::init(display, 1, {
+        let terminate_handle = terminate_handle.clone();
+        let terminated = terminated.clone();
+        move || {
+          terminated.set(true);
+          terminate_handle.terminate();
+        }
+      })
+      .unwrap();
+
+      // Returns as the handle terminates the loop
+      run_display(&terminate_handle);
+      // The event loop is gone with the display so the timer must not try to
+      // remove its event source
+      std::mem::forget(timer);
+
+      assert!(terminated.get());
+      assert!(terminate_handle.display.get().is_null());
+      // Does nothing after the display is destroyed
+      terminate_handle.terminate();
     }
-    Ok(())
   }
 }
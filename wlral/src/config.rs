@@ -1,19 +1,175 @@
-use crate::{event::Event, input::keyboard::KeyboardConfig};
-use log::debug;
+use crate::{
+  event::Event,
+  input::{
+    cursor::{ClickConfig, CursorConfig, PointerConfig},
+    keyboard::KeyboardConfig,
+  },
+  output::BackgroundMode,
+  wayland_fd::{WlFdSource, WL_EVENT_READABLE},
+  wayland_timer::WlTimer,
+};
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
-use std::{cell::RefCell, rc::Rc};
+use std::{
+  cell::RefCell,
+  collections::BTreeMap,
+  error::Error,
+  ffi::{CString, OsString},
+  fmt, fs, io, mem,
+  os::unix::{ffi::OsStrExt, io::RawFd},
+  path::{Path, PathBuf},
+  rc::Rc,
+};
+use wayland_sys::server::wl_display;
+use wlroots_sys::libc;
+
+/// How long to wait for more writes before reloading a watched config file
+const WATCH_DEBOUNCE_MS: u32 = 100;
 
 #[derive(Default, Debug, PartialEq, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
   pub keyboard: KeyboardConfig,
+  /// Applies to all pointer devices
+  pub pointer: PointerConfig,
+  /// Per device overrides of `pointer`, keyed by device name
+  pub pointer_devices: BTreeMap<String, PointerConfig>,
+  /// Assigns input devices to seats, keyed by device name. Devices that are
+  /// not listed, or listed with a seat that does not exist, use `seat0`.
+  pub device_seats: BTreeMap<String, String>,
+  pub cursor: CursorConfig,
+  pub click: ClickConfig,
+  /// Focus windows when the cursor is moved over them
+  pub focus_follows_mouse: bool,
+  /// How long the cursor must stay over a window before it is focused
+  pub focus_follows_mouse_delay_ms: u32,
+  /// Focuses the top window when the focused window is closed
+  pub autofocus_on_close: bool,
+  /// Moves windows back when they are moved off all outputs
+  pub keep_windows_on_screen: bool,
+  /// How many pixels of a window must stay on an output in each direction
+  /// when `keep_windows_on_screen` is set, or 0 to keep the whole window
+  /// on the output
+  pub keep_on_screen_margin: u32,
   pub background_color: [f32; 3],
+  /// A PNG image to render below all windows instead of `background_color`
+  pub background_image: Option<PathBuf>,
+  pub background_mode: BackgroundMode,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  /// The file could not be read or written
+  Io(io::Error),
+  /// The file extension is not one of toml or json
+  UnsupportedFormat,
+  /// The file could not be parsed
+  Parse {
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+  },
+  /// The config could not be serialized
+  Serialize(String),
+  /// The config file could not be watched
+  Watch(String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigError::Io(error) => write!(f, "{}", error),
+      ConfigError::UnsupportedFormat => write!(f, "Unsupported config format, use .toml or .json"),
+      ConfigError::Parse {
+        message,
+        line: Some(line),
+        column: Some(column),
+      } => write!(f, "{} at line {} column {}", message, line, column),
+      ConfigError::Parse { message, .. } => write!(f, "{}", message),
+      ConfigError::Serialize(message) => write!(f, "{}", message),
+      ConfigError::Watch(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+  fn from(error: io::Error) -> Self {
+    ConfigError::Io(error)
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum ConfigFormat {
+  Toml,
+  Json,
+}
+
+impl ConfigFormat {
+  fn from_path(path: &Path) -> Result<ConfigFormat, ConfigError> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("toml") => Ok(ConfigFormat::Toml),
+      Some("json") => Ok(ConfigFormat::Json),
+      _ => Err(ConfigError::UnsupportedFormat),
+    }
+  }
+
+  fn parse(&self, contents: &str) -> Result<Config, ConfigError> {
+    match self {
+      ConfigFormat::Toml => toml::from_str(contents).map_err(|error| {
+        // toml reports zero based positions
+        let position = error.line_col();
+        ConfigError::Parse {
+          message: error.to_string(),
+          line: position.map(|(line, _)| line + 1),
+          column: position.map(|(_, column)| column + 1),
+        }
+      }),
+      ConfigFormat::Json => serde_json::from_str(contents).map_err(|error| ConfigError::Parse {
+        message: error.to_string(),
+        line: Some(error.line()),
+        column: Some(error.column()),
+      }),
+    }
+  }
+
+  fn serialize(&self, config: &Config) -> Result<String, ConfigError> {
+    match self {
+      // Serialize through a toml::Value so that plain values are written
+      // before tables, as required by TOML
+      ConfigFormat::Toml => toml::Value::try_from(config)
+        .and_then(|value| toml::to_string_pretty(&value))
+        .map_err(|error| ConfigError::Serialize(error.to_string())),
+      ConfigFormat::Json => serde_json::to_string_pretty(config)
+        .map_err(|error| ConfigError::Serialize(error.to_string())),
+    }
+  }
+}
+
+struct ConfigWatcher {
+  fd: RawFd,
+  source: Option<WlFdSource>,
+}
+
+impl Drop for ConfigWatcher {
+  fn drop(&mut self) {
+    // Remove the source from the event loop before closing its fd
+    self.source.take();
+    unsafe {
+      libc::close(self.fd);
+    }
+  }
 }
 
 #[derive(Default)]
 pub struct ConfigManager {
   config: RefCell<Rc<Config>>,
   on_config_changed: Event<Rc<Config>>,
+
+  display: RefCell<Option<*mut wl_display>>,
+  watcher: RefCell<Option<ConfigWatcher>>,
+  reload_timer: RefCell<Option<WlTimer>>,
 }
 
 impl ConfigManager {
@@ -32,7 +188,207 @@ impl ConfigManager {
     self.on_config_changed.fire(self.config.borrow().clone());
   }
 
+  /// Replaces the config with the config in the file at path
+  ///
+  /// The format is decided by the file extension, which must be either
+  /// `toml` or `json`. Settings missing in the file are set to their
+  /// default value.
+  pub fn load_from_path(&self, path: &Path) -> Result<(), ConfigError> {
+    let format = ConfigFormat::from_path(path)?;
+    let contents = fs::read_to_string(path)?;
+    let config = format.parse(&contents)?;
+    debug!("ConfigManager::load_from_path: {}", path.display());
+    self.update_config(move |current| *current = config);
+    Ok(())
+  }
+
+  /// Writes the current config to the file at path
+  ///
+  /// The format is decided by the file extension, which must be either
+  /// `toml` or `json`.
+  pub fn save_to_path(&self, path: &Path) -> Result<(), ConfigError> {
+    let format = ConfigFormat::from_path(path)?;
+    let contents = format.serialize(&self.config())?;
+    fs::write(path, contents)?;
+    Ok(())
+  }
+
+  pub(crate) fn set_display(&self, display: *mut wl_display) {
+    *self.display.borrow_mut() = Some(display);
+  }
+
+  /// Loads the config from the file at path and reloads it when the file
+  /// changes
+  ///
+  /// Rapid writes are debounced so that the file is only read once it has
+  /// been completely written. Errors when reloading are logged and the
+  /// previous config is kept. Only one file can be watched at a time,
+  /// calling watch again replaces the previous watch.
+  pub fn watch(self: &Rc<Self>, path: &Path) -> Result<(), ConfigError> {
+    let display = self
+      .display
+      .borrow()
+      .ok_or_else(|| ConfigError::Watch("The compositor is not initialized".to_string()))?;
+    ConfigFormat::from_path(path)?;
+    self.load_from_path(path)?;
+
+    let path = path.to_path_buf();
+    let file_name = path
+      .file_name()
+      .map(|file_name| file_name.to_os_string())
+      .ok_or_else(|| ConfigError::Watch("The path has no file name".to_string()))?;
+    // Watch the directory instead of the file as many editors save by
+    // replacing the file, which would end a watch on the file itself.
+    let directory = match path.parent() {
+      Some(directory) if directory.as_os_str().is_empty() => Path::new("."),
+      Some(directory) => directory,
+      None => Path::new("/"),
+    };
+    let directory = CString::new(directory.as_os_str().as_bytes())
+      .map_err(|error| ConfigError::Watch(error.to_string()))?;
+
+    unsafe {
+      let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+      if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+      }
+      let watch = libc::inotify_add_watch(
+        fd,
+        directory.as_ptr(),
+        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE,
+      );
+      if watch < 0 {
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        return Err(error.into());
+      }
+
+      let config_manager = Rc::downgrade(self);
+      let source = WlFdSource::init(display, fd, WL_EVENT_READABLE, move |_| {
+        if !read_inotify_events(fd, &file_name) {
+          return;
+        }
+        if let Some(config_manager) = config_manager.upgrade() {
+          config_manager.schedule_reload(display, path.clone());
+        }
+      });
+      let source = match source {
+        Ok(source) => source,
+        Err(_) => {
+          libc::close(fd);
+          return Err(ConfigError::Watch(
+            "Could not add the watch to the event loop".to_string(),
+          ));
+        }
+      };
+
+      *self.watcher.borrow_mut() = Some(ConfigWatcher {
+        fd,
+        source: Some(source),
+      });
+    }
+    Ok(())
+  }
+
+  fn schedule_reload(self: &Rc<Self>, display: *mut wl_display, path: PathBuf) {
+    let config_manager = Rc::downgrade(self);
+    let timer = unsafe {
+      WlTimer::init(display, WATCH_DEBOUNCE_MS, move || {
+        if let Some(config_manager) = config_manager.upgrade() {
+          if let Err(error) = config_manager.load_from_path(&path) {
+            error!(
+              "ConfigManager::watch: Could not reload {}: {}",
+              path.display(),
+              error
+            );
+          }
+        }
+      })
+    };
+    match timer {
+      // Replacing the timer cancels any previously scheduled reload
+      Ok(timer) => *self.reload_timer.borrow_mut() = Some(timer),
+      Err(_) => error!("ConfigManager::schedule_reload: Could not create timer"),
+    }
+  }
+
   pub fn on_config_changed(&self) -> &Event<Rc<Config>> {
     &self.on_config_changed
   }
 }
+
+/// Drains all pending inotify events on fd and returns true if any of them
+/// concerns file_name
+fn read_inotify_events(fd: RawFd, file_name: &OsString) -> bool {
+  let mut changed = false;
+  let mut buffer = [0u8; 4096];
+  loop {
+    let length = unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+    if length <= 0 {
+      return changed;
+    }
+    let length = length as usize;
+    let mut offset = 0;
+    while offset + mem::size_of::<libc::inotify_event>() <= length {
+      let event: libc::inotify_event =
+        unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const _) };
+      let name_start = offset + mem::size_of::<libc::inotify_event>();
+      let name_end = (name_start + event.len as usize).min(length);
+      let name = &buffer[name_start..name_end];
+      // The name is padded with nul bytes
+      let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+      if name == file_name.as_bytes() {
+        changed = true;
+      }
+      offset = name_end;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_parses_config_formats() {
+    let config = ConfigFormat::Toml
+      .parse("focus_follows_mouse = true\n[cursor]\nsize = 32\n")
+      .unwrap();
+    assert!(config.focus_follows_mouse);
+    assert_eq!(config.cursor.size, 32);
+    assert_eq!(config.cursor.theme, None);
+
+    let config = ConfigFormat::Json
+      .parse(r#"{"background_color": [1.0, 0.5, 0.0]}"#)
+      .unwrap();
+    assert_eq!(config.background_color, [1.0, 0.5, 0.0]);
+    assert_eq!(config.cursor, CursorConfig::default());
+  }
+
+  #[test]
+  fn it_reports_parse_error_position() {
+    match ConfigFormat::Toml.parse("[cursor]\nsize = \"big\"\n") {
+      Err(ConfigError::Parse { line, .. }) => assert_eq!(line, Some(2)),
+      result => panic!("Expected parse error, got {:?}", result),
+    }
+    match ConfigFormat::Json.parse("{\n\"cursor\": 5}") {
+      Err(ConfigError::Parse { line, .. }) => assert_eq!(line, Some(2)),
+      result => panic!("Expected parse error, got {:?}", result),
+    }
+  }
+
+  #[test]
+  fn it_roundtrips_config() {
+    let mut config = Config::default();
+    config.focus_follows_mouse = true;
+    config.cursor.theme = Some("Adwaita".to_string());
+    config
+      .pointer_devices
+      .insert("touchpad".to_string(), PointerConfig::default());
+
+    for format in &[ConfigFormat::Toml, ConfigFormat::Json] {
+      let serialized = format.serialize(&config).unwrap();
+      assert_eq!(format.parse(&serialized).unwrap(), config);
+    }
+  }
+}
@@ -1,10 +1,43 @@
-use std::{cell::RefCell, collections::BTreeMap, fmt::Debug, rc::Rc};
+use std::{
+  cell::RefCell,
+  collections::BTreeMap,
+  fmt::Debug,
+  rc::{Rc, Weak},
+};
 
 type EventListener<Data> = Box<dyn Fn(&Data)>;
+type EventListeners<Data> = RefCell<BTreeMap<u64, Rc<EventListener<Data>>>>;
 
 pub struct Event<Data> {
   next_id: RefCell<u64>,
-  listeners: RefCell<BTreeMap<u64, Rc<EventListener<Data>>>>,
+  listeners: Rc<EventListeners<Data>>,
+}
+
+/// Unsubscribes the listener from the event when dropped
+#[must_use = "the listener is unsubscribed when the Subscription is dropped"]
+pub struct Subscription {
+  unsubscribe: Option<Box<dyn FnOnce()>>,
+}
+
+impl Subscription {
+  /// Keeps the listener subscribed for as long as the event exists
+  pub fn detach(mut self) {
+    self.unsubscribe = None;
+  }
+}
+
+impl Debug for Subscription {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Subscription")
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    if let Some(unsubscribe) = self.unsubscribe.take() {
+      unsubscribe();
+    }
+  }
 }
 
 impl<T> Debug for Event<T> {
@@ -17,7 +50,7 @@ impl<T> Default for Event<T> {
   fn default() -> Self {
     Event {
       next_id: RefCell::new(0),
-      listeners: RefCell::new(BTreeMap::new()),
+      listeners: Rc::new(RefCell::new(BTreeMap::new())),
     }
   }
 }
@@ -33,17 +66,63 @@ impl<T> Event<T> {
     self.listeners.borrow_mut().remove(&id);
   }
 
+  /// The number of subscribed listeners
+  #[cfg(test)]
+  pub(crate) fn listener_count(&self) -> usize {
+    self.listeners.borrow().len()
+  }
+
   pub fn fire(&self, data: T) {
-    for listener in self.listeners.borrow().values() {
+    // Listeners may subscribe or unsubscribe while the event is fired
+    let listeners: Vec<_> = self.listeners.borrow().values().cloned().collect();
+    for listener in listeners {
       listener(&data);
     }
   }
 }
 
+impl<T: 'static> Event<T> {
+  /// Subscribes to the event until the returned Subscription is dropped
+  pub fn subscribe_scoped(&self, handler: EventListener<T>) -> Subscription {
+    let id = self.subscribe(handler);
+    let listeners = Rc::downgrade(&self.listeners);
+    Subscription {
+      unsubscribe: Some(Box::new(move || {
+        if let Some(listeners) = Weak::upgrade(&listeners) {
+          listeners.borrow_mut().remove(&id);
+        }
+      })),
+    }
+  }
+}
+
 type EventListenerOnce<Data> = Box<dyn FnOnce(&Data)>;
+type EventListenersOnce<Data> = RefCell<Vec<(u64, EventListenerOnce<Data>)>>;
 
 pub struct EventOnce<Data> {
-  listeners: RefCell<Vec<EventListenerOnce<Data>>>,
+  next_id: RefCell<u64>,
+  fired: RefCell<bool>,
+  listeners: Rc<EventListenersOnce<Data>>,
+}
+
+/// A listener added with `EventOnce::then` that has not been called yet
+pub struct PendingListener {
+  cancel: Box<dyn Fn() -> bool>,
+}
+
+impl PendingListener {
+  /// Removes the listener so that it is never called
+  ///
+  /// Returns false if the listener has already been called or canceled.
+  pub fn cancel(&self) -> bool {
+    (self.cancel)()
+  }
+}
+
+impl Debug for PendingListener {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "PendingListener")
+  }
 }
 
 impl<T> Debug for EventOnce<T> {
@@ -55,19 +134,106 @@ impl<T> Debug for EventOnce<T> {
 impl<T> Default for EventOnce<T> {
   fn default() -> Self {
     EventOnce {
-      listeners: RefCell::new(vec![]),
+      next_id: RefCell::new(0),
+      fired: RefCell::new(false),
+      listeners: Rc::new(RefCell::new(vec![])),
+    }
+  }
+}
+
+impl<T: 'static> EventOnce<T> {
+  pub fn then(&self, handler: EventListenerOnce<T>) -> PendingListener {
+    let id = *self.next_id.borrow();
+    *self.next_id.borrow_mut() = id + 1;
+    self.listeners.borrow_mut().push((id, handler));
+
+    let listeners = Rc::downgrade(&self.listeners);
+    PendingListener {
+      cancel: Box::new(move || {
+        if let Some(listeners) = Weak::upgrade(&listeners) {
+          let mut listeners = listeners.borrow_mut();
+          if let Some(index) = listeners
+            .iter()
+            .position(|(listener_id, _)| *listener_id == id)
+          {
+            listeners.remove(index);
+            return true;
+          }
+        }
+        false
+      }),
     }
   }
 }
 
 impl<T> EventOnce<T> {
-  pub fn then(&self, handler: EventListenerOnce<T>) {
-    self.listeners.borrow_mut().push(handler);
+  /// If the event has been fired
+  pub fn has_fired(&self) -> bool {
+    *self.fired.borrow()
   }
 
   pub fn fire(&self, data: T) {
-    while let Some(listener) = self.listeners.borrow_mut().pop() {
-      listener(&data);
+    *self.fired.borrow_mut() = true;
+    loop {
+      // Listeners may add or cancel other listeners when called
+      let listener = self.listeners.borrow_mut().pop();
+      match listener {
+        Some((_, listener)) => listener(&data),
+        None => break,
+      }
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+
+  #[test]
+  fn it_unsubscribes_scoped_subscriptions_on_drop() {
+    let event = Event::default();
+    let calls = Rc::new(Cell::new(0));
+
+    let subscription = event.subscribe_scoped(listener!(calls => move |value| {
+      calls.set(calls.get() + value);
+    }));
+    event.fire(1);
+    drop(subscription);
+    event.fire(1);
+    assert_eq!(calls.get(), 1);
+
+    event
+      .subscribe_scoped(listener!(calls => move |value| {
+        calls.set(calls.get() + value);
+      }))
+      .detach();
+    event.fire(2);
+    assert_eq!(calls.get(), 3);
+  }
+
+  #[test]
+  fn it_cancels_pending_listeners() {
+    let event = EventOnce::default();
+    let calls = Rc::new(Cell::new(0));
+
+    let canceled = event.then(listener!(calls => move || calls.set(calls.get() + 1)));
+    let called = event.then(listener!(calls => move || calls.set(calls.get() + 10)));
+    assert!(!event.has_fired());
+    assert!(canceled.cancel());
+    assert!(!canceled.cancel());
+
+    event.fire(());
+    assert!(event.has_fired());
+    assert_eq!(calls.get(), 10);
+    assert!(!called.cancel());
+  }
+
+  #[test]
+  fn it_allows_dropping_subscriptions_after_the_event() {
+    let event = Event::<()>::default();
+    let subscription = event.subscribe_scoped(Box::new(|_| {}));
+    drop(event);
+    drop(subscription);
+  }
+}
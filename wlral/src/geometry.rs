@@ -1,7 +1,8 @@
+use std::cmp::Ordering;
 use std::ops::{Add, Div, Mul, Sub};
 use wlroots_sys::wlr_box;
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct TPoint<T: Copy> {
   pub x: T,
   pub y: T,
@@ -31,6 +32,18 @@ impl Point {
   pub const ZERO: Point = Point { x: 0, y: 0 };
 }
 
+/// Points are ordered row by row, from top to bottom and then left to right
+impl Ord for Point {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.y, self.x).cmp(&(other.y, other.x))
+  }
+}
+impl PartialOrd for Point {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
 impl FPoint {
   pub const ZERO: FPoint = FPoint { x: 0.0, y: 0.0 };
 }
@@ -64,7 +77,7 @@ impl Mul<f32> for Point {
   }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Size {
   pub width: i32,
   pub height: i32,
@@ -150,7 +163,7 @@ impl Div<f32> for Size {
   }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
 pub struct Rectangle {
   pub top_left: Point,
   pub size: Size,
@@ -228,6 +241,32 @@ impl Rectangle {
 
     !disjoint
   }
+
+  /// If `rectangle` is completely inside of this rectangle
+  pub fn contains_rectangle(&self, rectangle: &Rectangle) -> bool {
+    self.left() <= rectangle.left()
+      && self.right() >= rectangle.right()
+      && self.top() <= rectangle.top()
+      && self.bottom() >= rectangle.bottom()
+  }
+
+  /// The area covered by both rectangles, if they overlap
+  pub fn intersection(&self, rectangle: &Rectangle) -> Option<Rectangle> {
+    if !self.overlaps(rectangle) {
+      return None;
+    }
+    let top_left = Point {
+      x: self.left().max(rectangle.left()),
+      y: self.top().max(rectangle.top()),
+    };
+    Some(Rectangle {
+      top_left,
+      size: Size {
+        width: self.right().min(rectangle.right()) - top_left.x,
+        height: self.bottom().min(rectangle.bottom()) - top_left.y,
+      },
+    })
+  }
 }
 
 impl From<wlr_box> for Rectangle {
@@ -256,7 +295,7 @@ impl From<Rectangle> for wlr_box {
   }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
 pub struct TDisplacement<T: Copy> {
   pub dx: T,
   pub dy: T,
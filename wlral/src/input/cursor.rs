@@ -3,30 +3,283 @@ use crate::geometry::FPoint;
 use crate::input::device::{Device, DeviceType};
 use crate::input::event_filter::{EventFilter, EventFilterManager};
 use crate::input::events::*;
-use crate::{output_manager::OutputManager, window_manager::WindowManager};
-use log::debug;
+use crate::input::keybindings::ModMask;
+use crate::{
+  config::{Config, ConfigManager},
+  output::monotonic_now,
+  output_manager::OutputManager,
+  wayland_timer::WlTimer,
+  window::Window,
+  window_manager::WindowManager,
+};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::ffi::CString;
 use std::ops::Deref;
 use std::pin::Pin;
 use std::ptr;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use wlroots_sys::*;
+use xkbcommon::xkb::{self, ffi::xkb_state_ref};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum AccelProfile {
+  /// Constant acceleration independent of the pointer speed
+  Flat,
+  /// Acceleration increases with the pointer speed
+  Adaptive,
+}
+
+/// Configuration for pointer devices like mice and touchpads
+///
+/// Settings that are left as `None` keep the default of the device.
+/// Settings not supported by a device are ignored.
+#[derive(Default, Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PointerConfig {
+  pub natural_scroll: Option<bool>,
+  pub tap_to_click: Option<bool>,
+  pub disable_while_typing: Option<bool>,
+  pub accel_profile: Option<AccelProfile>,
+  /// Pointer acceleration in the range -1.0..=1.0
+  pub accel_speed: Option<f64>,
+}
+
+impl PointerConfig {
+  /// Returns a new config where the set values of overrides take
+  /// precedence over self
+  pub fn merged_with(&self, overrides: &PointerConfig) -> PointerConfig {
+    PointerConfig {
+      natural_scroll: overrides.natural_scroll.or(self.natural_scroll),
+      tap_to_click: overrides.tap_to_click.or(self.tap_to_click),
+      disable_while_typing: overrides.disable_while_typing.or(self.disable_while_typing),
+      accel_profile: overrides.accel_profile.or(self.accel_profile),
+      accel_speed: overrides.accel_speed.or(self.accel_speed),
+    }
+  }
+
+  /// The config to use for the device with the passed name
+  pub(crate) fn for_device(config: &Config, device_name: &str) -> PointerConfig {
+    match config.pointer_devices.get(device_name) {
+      Some(overrides) => config.pointer.merged_with(overrides),
+      None => config.pointer.clone(),
+    }
+  }
+}
+
+/// Configuration for the cursor image drawn by the compositor
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CursorConfig {
+  /// The name of the Xcursor theme to use, or None for the default theme
+  pub theme: Option<String>,
+  /// The size of the cursor at scale 1
+  pub size: u32,
+  /// Hide the cursor after it has not been moved for this many milliseconds
+  pub auto_hide_timeout_ms: Option<u32>,
+}
+
+/// Configuration for detecting double and triple clicks
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClickConfig {
+  /// The longest time between presses of a multi-click
+  pub multi_click_timeout_ms: u32,
+  /// How far the cursor may move between presses of a multi-click
+  pub multi_click_distance: f64,
+}
+
+impl Default for ClickConfig {
+  fn default() -> Self {
+    ClickConfig {
+      multi_click_timeout_ms: 400,
+      multi_click_distance: 4.0,
+    }
+  }
+}
+
+impl Default for CursorConfig {
+  fn default() -> Self {
+    CursorConfig {
+      theme: None,
+      size: 24,
+      auto_hide_timeout_ms: None,
+    }
+  }
+}
+
+/// The cursor shapes of the cursor-shape-v1 protocol, in protocol order
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorShape {
+  Default,
+  ContextMenu,
+  Help,
+  Pointer,
+  Progress,
+  Wait,
+  Cell,
+  Crosshair,
+  Text,
+  VerticalText,
+  Alias,
+  Copy,
+  Move,
+  NoDrop,
+  NotAllowed,
+  Grab,
+  Grabbing,
+  EResize,
+  NResize,
+  NeResize,
+  NwResize,
+  SResize,
+  SeResize,
+  SwResize,
+  WResize,
+  EwResize,
+  NsResize,
+  NeswResize,
+  NwseResize,
+  ColResize,
+  RowResize,
+  AllScroll,
+  ZoomIn,
+  ZoomOut,
+}
+
+impl CursorShape {
+  /// Maps the value of a `wp_cursor_shape_device_v1.shape` enum
+  pub fn from_raw(shape: u32) -> Option<CursorShape> {
+    Some(match shape {
+      1 => CursorShape::Default,
+      2 => CursorShape::ContextMenu,
+      3 => CursorShape::Help,
+      4 => CursorShape::Pointer,
+      5 => CursorShape::Progress,
+      6 => CursorShape::Wait,
+      7 => CursorShape::Cell,
+      8 => CursorShape::Crosshair,
+      9 => CursorShape::Text,
+      10 => CursorShape::VerticalText,
+      11 => CursorShape::Alias,
+      12 => CursorShape::Copy,
+      13 => CursorShape::Move,
+      14 => CursorShape::NoDrop,
+      15 => CursorShape::NotAllowed,
+      16 => CursorShape::Grab,
+      17 => CursorShape::Grabbing,
+      18 => CursorShape::EResize,
+      19 => CursorShape::NResize,
+      20 => CursorShape::NeResize,
+      21 => CursorShape::NwResize,
+      22 => CursorShape::SResize,
+      23 => CursorShape::SeResize,
+      24 => CursorShape::SwResize,
+      25 => CursorShape::WResize,
+      26 => CursorShape::EwResize,
+      27 => CursorShape::NsResize,
+      28 => CursorShape::NeswResize,
+      29 => CursorShape::NwseResize,
+      30 => CursorShape::ColResize,
+      31 => CursorShape::RowResize,
+      32 => CursorShape::AllScroll,
+      33 => CursorShape::ZoomIn,
+      34 => CursorShape::ZoomOut,
+      _ => return None,
+    })
+  }
+
+  /// The name of the image in the cursor theme, which follows the CSS
+  /// cursor names
+  pub fn xcursor_name(&self) -> &'static str {
+    match self {
+      CursorShape::Default => "default",
+      CursorShape::ContextMenu => "context-menu",
+      CursorShape::Help => "help",
+      CursorShape::Pointer => "pointer",
+      CursorShape::Progress => "progress",
+      CursorShape::Wait => "wait",
+      CursorShape::Cell => "cell",
+      CursorShape::Crosshair => "crosshair",
+      CursorShape::Text => "text",
+      CursorShape::VerticalText => "vertical-text",
+      CursorShape::Alias => "alias",
+      CursorShape::Copy => "copy",
+      CursorShape::Move => "move",
+      CursorShape::NoDrop => "no-drop",
+      CursorShape::NotAllowed => "not-allowed",
+      CursorShape::Grab => "grab",
+      CursorShape::Grabbing => "grabbing",
+      CursorShape::EResize => "e-resize",
+      CursorShape::NResize => "n-resize",
+      CursorShape::NeResize => "ne-resize",
+      CursorShape::NwResize => "nw-resize",
+      CursorShape::SResize => "s-resize",
+      CursorShape::SeResize => "se-resize",
+      CursorShape::SwResize => "sw-resize",
+      CursorShape::WResize => "w-resize",
+      CursorShape::EwResize => "ew-resize",
+      CursorShape::NsResize => "ns-resize",
+      CursorShape::NeswResize => "nesw-resize",
+      CursorShape::NwseResize => "nwse-resize",
+      CursorShape::ColResize => "col-resize",
+      CursorShape::RowResize => "row-resize",
+      CursorShape::AllScroll => "all-scroll",
+      CursorShape::ZoomIn => "zoom-in",
+      CursorShape::ZoomOut => "zoom-out",
+    }
+  }
+}
+
+fn create_xcursor_manager(config: &CursorConfig) -> *mut wlr_xcursor_manager {
+  let theme = config
+    .theme
+    .as_ref()
+    .and_then(|theme| match CString::new(theme.as_str()) {
+      Ok(theme) => Some(theme),
+      Err(_) => {
+        error!(
+          "Cursor theme {:?} contains a NUL byte, using the default theme",
+          theme
+        );
+        None
+      }
+    });
+  unsafe {
+    wlr_xcursor_manager_create(
+      theme.as_ref().map_or(ptr::null(), |theme| theme.as_ptr()),
+      config.size,
+    )
+  }
+}
 
 pub struct CursorManager {
+  config_manager: Rc<ConfigManager>,
   output_manager: Rc<OutputManager>,
   window_manager: Rc<WindowManager>,
   seat_manager: Rc<SeatManager>,
   event_filter_manager: Rc<EventFilterManager>,
   cursor: *mut wlr_cursor,
-  cursor_mgr: *mut wlr_xcursor_manager,
+  cursor_mgr: RefCell<*mut wlr_xcursor_manager>,
+  cursor_config: RefCell<CursorConfig>,
+  cursor_image: RefCell<String>,
+  hidden: RefCell<bool>,
+  auto_hide_timer: RefCell<Option<WlTimer>>,
+  focus_follows_mouse_target: RefCell<Option<Weak<Window>>>,
+  focus_follows_mouse_timer: RefCell<Option<WlTimer>>,
   pointers: RefCell<Vec<Rc<Device>>>,
+  click_detector: RefCell<ClickDetector>,
+  /// Buttons whose press was consumed as a click, so that their release is
+  /// not sent to clients either
+  consumed_clicks: RefCell<Vec<u32>>,
 
   event_manager: RefCell<Option<Pin<Box<CursorEventManager>>>>,
 }
 
 impl CursorManager {
   pub(crate) fn init(
+    config_manager: Rc<ConfigManager>,
     output_manager: Rc<OutputManager>,
     window_manager: Rc<WindowManager>,
     seat_manager: Rc<SeatManager>,
@@ -43,18 +296,29 @@ impl CursorManager {
     // Creates an xcursor manager, another wlroots utility which loads up
     // Xcursor themes to source cursor images from and makes sure that cursor
     // images are available at all scale factors on the screen (necessary for
-    // HiDPI support). We add a cursor theme at scale factor 1 to begin with.
-    let cursor_mgr = unsafe { wlr_xcursor_manager_create(ptr::null(), 24) };
+    // HiDPI support). We add a cursor theme at scale factor 1 to begin with
+    // and load the scales of the outputs as they are added.
+    let cursor_config = config_manager.config().cursor.clone();
+    let cursor_mgr = create_xcursor_manager(&cursor_config);
     unsafe { wlr_xcursor_manager_load(cursor_mgr, 1.0) };
 
     let cursor_manager = Rc::new(CursorManager {
+      config_manager: config_manager.clone(),
       output_manager: output_manager.clone(),
       window_manager,
       seat_manager: seat_manager.clone(),
       event_filter_manager,
       cursor,
-      cursor_mgr,
+      cursor_mgr: RefCell::new(cursor_mgr),
+      cursor_config: RefCell::new(cursor_config),
+      cursor_image: RefCell::new("left_ptr".to_string()),
+      hidden: RefCell::new(false),
+      auto_hide_timer: RefCell::new(None),
+      focus_follows_mouse_target: RefCell::new(None),
+      focus_follows_mouse_timer: RefCell::new(None),
       pointers: RefCell::new(vec![]),
+      click_detector: RefCell::new(ClickDetector::default()),
+      consumed_clicks: RefCell::new(vec![]),
 
       event_manager: RefCell::new(None),
     });
@@ -90,6 +354,7 @@ impl CursorManager {
             wlr_cursor_attach_input_device(cursor, device.raw_ptr());
           }
 
+          cursor_manager.apply_pointer_config(&device, &cursor_manager.config_manager.config());
           cursor_manager.pointers.borrow_mut().push(device.clone());
 
           cursor_manager.refresh_device_mappings();
@@ -97,6 +362,24 @@ impl CursorManager {
         }
       }));
 
+    config_manager
+      .on_config_changed()
+      .subscribe(listener!(cursor_manager => move |config| {
+        for pointer in cursor_manager.pointers.borrow().iter() {
+          cursor_manager.apply_pointer_config(pointer, config);
+        }
+        let theme_changed = {
+          let cursor_config = cursor_manager.cursor_config.borrow();
+          cursor_config.theme != config.cursor.theme || cursor_config.size != config.cursor.size
+        };
+        if theme_changed {
+          cursor_manager.reload_cursor_theme(&config.cursor);
+        }
+        cursor_manager.cursor_config.borrow_mut().auto_hide_timeout_ms =
+          config.cursor.auto_hide_timeout_ms;
+        cursor_manager.restart_auto_hide_timer();
+      }));
+
     #[allow(unused_mut)]
     let mut event_manager = CursorEventManager::new(cursor_manager.clone());
     #[cfg(not(test))]
@@ -110,11 +393,14 @@ impl CursorManager {
     }
     *cursor_manager.event_manager.borrow_mut() = Some(event_manager);
 
+    cursor_manager.restart_auto_hide_timer();
+
     cursor_manager
   }
 
   #[cfg(test)]
   pub(crate) fn mock(
+    config_manager: Rc<ConfigManager>,
     output_manager: Rc<OutputManager>,
     window_manager: Rc<WindowManager>,
     seat_manager: Rc<SeatManager>,
@@ -123,20 +409,60 @@ impl CursorManager {
     cursor_mgr: *mut wlr_xcursor_manager,
   ) -> Rc<CursorManager> {
     Rc::new(CursorManager {
+      config_manager,
       output_manager: output_manager.clone(),
       window_manager,
       seat_manager: seat_manager.clone(),
       event_filter_manager,
       cursor,
-      cursor_mgr,
+      cursor_mgr: RefCell::new(cursor_mgr),
+      cursor_config: RefCell::new(CursorConfig::default()),
+      cursor_image: RefCell::new("left_ptr".to_string()),
+      hidden: RefCell::new(false),
+      auto_hide_timer: RefCell::new(None),
+      focus_follows_mouse_target: RefCell::new(None),
+      focus_follows_mouse_timer: RefCell::new(None),
       pointers: RefCell::new(vec![]),
+      click_detector: RefCell::new(ClickDetector::default()),
+      consumed_clicks: RefCell::new(vec![]),
 
       event_manager: RefCell::new(None),
     })
   }
 
+  fn reload_cursor_theme(&self, cursor_config: &CursorConfig) {
+    debug!("CursorManager::reload_cursor_theme: {:?}", cursor_config);
+    let cursor_mgr = create_xcursor_manager(cursor_config);
+    let old_cursor_mgr = self.cursor_mgr.replace(cursor_mgr);
+    *self.cursor_config.borrow_mut() = cursor_config.clone();
+    unsafe {
+      wlr_xcursor_manager_load(cursor_mgr, 1.0);
+      wlr_xcursor_manager_destroy(old_cursor_mgr);
+    }
+    self.refresh_device_mappings();
+    // Surfaces provide their own cursor image so only the image we have set
+    // needs to be updated
+    let has_focused_surface = unsafe {
+      !(*self.seat_manager.raw_seat())
+        .pointer_state
+        .focused_surface
+        .is_null()
+    };
+    if !has_focused_surface {
+      let cursor_image = self.cursor_image();
+      self.set_cursor_image(&cursor_image);
+    }
+  }
+
   fn refresh_device_mappings(&self) {
     debug!("CursorManager::refresh_device_mappings");
+    // Make sure the cursor theme is available at the scale of every output so
+    // that it looks crisp on HiDPI outputs.
+    for output in self.output_manager.outputs().iter() {
+      unsafe {
+        wlr_xcursor_manager_load(*self.cursor_mgr.borrow(), output.scale());
+      }
+    }
     for pointer in self.pointers.borrow().iter() {
       if let Some(output_name) = pointer.output_name() {
         for output in self.output_manager.outputs().iter() {
@@ -150,25 +476,121 @@ impl CursorManager {
     }
   }
 
-  fn process_motion(&self, event: MotionEvent) {
-    let position = event.position();
+  fn apply_pointer_config(&self, device: &Device, config: &Config) {
+    unsafe {
+      if !wlr_input_device_is_libinput(device.raw_ptr()) {
+        return;
+      }
+      let handle = wlr_libinput_get_device_handle(device.raw_ptr());
+      if handle.is_null() {
+        return;
+      }
+      let config = PointerConfig::for_device(config, &device.name());
+      debug!(
+        "CursorManager::apply_pointer_config: {} {:?}",
+        device.name(),
+        config
+      );
+
+      if let Some(natural_scroll) = config.natural_scroll {
+        if libinput_device_config_scroll_has_natural_scroll(handle) != 0 {
+          libinput_device_config_scroll_set_natural_scroll_enabled(handle, natural_scroll as i32);
+        }
+      }
+      if let Some(tap_to_click) = config.tap_to_click {
+        if libinput_device_config_tap_get_finger_count(handle) > 0 {
+          libinput_device_config_tap_set_enabled(
+            handle,
+            if tap_to_click {
+              libinput_config_tap_state_LIBINPUT_CONFIG_TAP_ENABLED
+            } else {
+              libinput_config_tap_state_LIBINPUT_CONFIG_TAP_DISABLED
+            },
+          );
+        }
+      }
+      if let Some(disable_while_typing) = config.disable_while_typing {
+        if libinput_device_config_dwt_is_available(handle) != 0 {
+          libinput_device_config_dwt_set_enabled(
+            handle,
+            if disable_while_typing {
+              libinput_config_dwt_state_LIBINPUT_CONFIG_DWT_ENABLED
+            } else {
+              libinput_config_dwt_state_LIBINPUT_CONFIG_DWT_DISABLED
+            },
+          );
+        }
+      }
+      if libinput_device_config_accel_is_available(handle) != 0 {
+        if let Some(accel_profile) = config.accel_profile {
+          libinput_device_config_accel_set_profile(
+            handle,
+            match accel_profile {
+              AccelProfile::Flat => {
+                libinput_config_accel_profile_LIBINPUT_CONFIG_ACCEL_PROFILE_FLAT
+              }
+              AccelProfile::Adaptive => {
+                libinput_config_accel_profile_LIBINPUT_CONFIG_ACCEL_PROFILE_ADAPTIVE
+              }
+            },
+          );
+        }
+        if let Some(accel_speed) = config.accel_speed {
+          if accel_speed < -1.0 || accel_speed > 1.0 {
+            warn!(
+              "CursorManager::apply_pointer_config: accel_speed {} is out of range",
+              accel_speed
+            );
+          } else {
+            libinput_device_config_accel_set_speed(handle, accel_speed);
+          }
+        }
+      }
+    }
+  }
 
+  /// Moves the cursor to the passed point in global coordinates
+  ///
+  /// If the point is outside of the output layout, the cursor is moved to
+  /// the closest point inside. Pointer focus is updated as if the cursor was
+  /// moved by a pointer device.
+  pub fn warp(&self, point: FPoint) {
     unsafe {
-      wlr_cursor_warp(self.cursor, event.raw_device(), position.x(), position.y());
+      wlr_cursor_warp_closest(self.cursor, ptr::null_mut(), point.x, point.y);
     }
+    // Input events are timestamped in milliseconds of the monotonic clock
+    let now = monotonic_now();
+    let time_msec = (now.tv_sec as i64 * 1000 + now.tv_nsec as i64 / 1_000_000) as u32;
+    self.update_pointer_focus(self.position(), time_msec);
+  }
+
+  /// Moves the cursor to the center of the passed window
+  pub fn warp_to_window_center(&self, window: &Window) {
+    let extents = window.extents();
+    self.warp(FPoint {
+      x: extents.center_x() as f64,
+      y: extents.center_y() as f64,
+    });
+  }
+
+  fn update_pointer_focus(&self, position: FPoint, time_msec: u32) {
+    self.seat_manager.set_drag_position(position);
 
-    let surface = self.window_manager.window_buffer_at(&position.into());
+    let window = self.window_manager.window_buffer_at(&position.into());
+    let surface = window.as_ref().and_then(|window| {
+      window
+        .surface_at(self.window_manager.hit_test_point(window, position))
+        .map(|(surface, surface_position)| (window, surface, surface_position))
+    });
 
-    if let Some(surface) = surface {
-      if self.seat_manager.is_input_allowed(&surface) {
+    if let Some((window, surface, surface_position)) = surface {
+      if self.seat_manager.is_input_allowed(window) {
         let focus_changed = unsafe {
           (*self.seat_manager.raw_seat())
             .pointer_state
             .focused_surface
-            != surface.wlr_surface()
+            != surface
         };
-        let surface_position =
-          position - FPoint::from(surface.buffer_extents().top_left()).as_displacement();
 
         // "Enter" the surface if necessary. This lets the client know that the
         // cursor has entered one of its surfaces.
@@ -179,7 +601,7 @@ impl CursorManager {
         unsafe {
           wlr_seat_pointer_notify_enter(
             self.seat_manager.raw_seat(),
-            surface.wlr_surface(),
+            surface,
             surface_position.x,
             surface_position.y,
           );
@@ -188,7 +610,7 @@ impl CursorManager {
             // on motion if the focus did not change.
             wlr_seat_pointer_notify_motion(
               self.seat_manager.raw_seat(),
-              event.time_msec(),
+              time_msec,
               surface_position.x,
               surface_position.y,
             );
@@ -196,24 +618,50 @@ impl CursorManager {
         }
       }
     } else {
+      // If there's no surface under the cursor, set the cursor image to a
+      // default. This is what makes the cursor image appear when you move it
+      // around the screen, not over any surfaces.
+      let cursor_image = self.cursor_image();
+      self.set_cursor_image(&cursor_image);
       unsafe {
-        // If there's no surface under the cursor, set the cursor image to a
-        // default. This is what makes the cursor image appear when you move it
-        // around the screen, not over any surfaces.
-        let cursor_image_name = CString::new("left_ptr").unwrap();
-        wlr_xcursor_manager_set_cursor_image(
-          self.cursor_mgr,
-          cursor_image_name.as_ptr(),
-          self.cursor,
-        );
         // TODO: Change to wlr_seat_pointer_notify_clear_focus after updating wlroots
         wlr_seat_pointer_clear_focus(self.seat_manager.raw_seat());
       }
     }
+  }
 
-    self
-      .event_filter_manager
-      .handle_pointer_motion_event(&event);
+  /// Hides the cursor until show is called or, if auto hide is enabled, the
+  /// cursor is moved
+  pub fn hide(&self) {
+    if self.is_hidden() {
+      return;
+    }
+    debug!("CursorManager::hide");
+    *self.hidden.borrow_mut() = true;
+    unsafe {
+      // Clearing the image also removes it from the hardware cursor planes
+      wlr_cursor_set_surface(self.cursor, ptr::null_mut(), 0, 0);
+    }
+  }
+
+  /// Shows the cursor again after it has been hidden
+  pub fn show(&self) {
+    if !self.is_hidden() {
+      return;
+    }
+    debug!("CursorManager::show");
+    *self.hidden.borrow_mut() = false;
+    let cursor_image = self.cursor_image();
+    self.set_cursor_image(&cursor_image);
+    unsafe {
+      // The client under the cursor has to set its cursor image again,
+      // which it does when it receives a new enter event.
+      wlr_seat_pointer_clear_focus(self.seat_manager.raw_seat());
+    }
+  }
+
+  pub fn is_hidden(&self) -> bool {
+    *self.hidden.borrow()
   }
 
   /// If there are any pointer device (mouse, touchpad, etc.) attached
@@ -231,11 +679,200 @@ impl CursorManager {
     }
   }
 
+  /// Get the modifiers that are currently held down on the active keyboard
+  /// of the seat
+  pub fn modifiers(&self) -> ModMask {
+    unsafe {
+      let keyboard = wlr_seat_get_keyboard(self.seat_manager.raw_seat());
+      if keyboard.is_null() || (*keyboard).xkb_state.is_null() {
+        return ModMask::NONE;
+      }
+      let xkb_state = xkb::State::from_raw_ptr(xkb_state_ref((*keyboard).xkb_state));
+      ModMask::from_xkb_state(&xkb_state)
+    }
+  }
+
+  /// Sets the cursor image to the named image from the cursor theme
+  ///
+  /// The image is kept when the cursor is moved over the desktop, so a
+  /// policy can show e.g. `grabbing` or `se-resize` during a move or resize
+  /// gesture and then restore `left_ptr` when done.
+  pub fn set_cursor_image(&self, name: &str) {
+    let cursor_image_name = match CString::new(name) {
+      Ok(cursor_image_name) => cursor_image_name,
+      Err(_) => {
+        error!("Cursor image name {:?} contains a NUL byte", name);
+        return;
+      }
+    };
+    *self.cursor_image.borrow_mut() = name.to_string();
+    if self.is_hidden() {
+      return;
+    }
+    unsafe {
+      wlr_xcursor_manager_set_cursor_image(
+        *self.cursor_mgr.borrow(),
+        cursor_image_name.as_ptr(),
+        self.cursor,
+      );
+    }
+  }
+
+  /// Sets the cursor image to the image of the shape in the cursor theme
+  pub fn set_cursor_shape(&self, shape: CursorShape) {
+    self.set_cursor_image(shape.xcursor_name());
+  }
+
+  /// The name of the image from the cursor theme that is currently used
+  pub fn cursor_image(&self) -> String {
+    self.cursor_image.borrow().clone()
+  }
+
   pub fn raw_cursor(&self) -> *mut wlr_cursor {
     self.cursor
   }
 }
 
+trait CursorManagerExt {
+  fn restart_auto_hide_timer(&self);
+  fn focus_window_under_cursor(&self);
+  fn process_motion(&self, event: MotionEvent);
+}
+
+impl CursorManagerExt for Rc<CursorManager> {
+  /// Restarts the inactivity timeout, or stops it if auto hide is disabled
+  fn restart_auto_hide_timer(&self) {
+    let timeout_ms = match self.cursor_config.borrow().auto_hide_timeout_ms {
+      Some(timeout_ms) => timeout_ms,
+      None => {
+        if let Some(timer) = self.auto_hide_timer.borrow().as_ref() {
+          timer.cancel();
+        }
+        return;
+      }
+    };
+
+    // The timer is created once and rearmed on every motion
+    if let Some(timer) = self.auto_hide_timer.borrow().as_ref() {
+      if timer.reschedule(timeout_ms).is_err() {
+        error!("CursorManager::restart_auto_hide_timer: Could not reschedule timer");
+      }
+      return;
+    }
+
+    let cursor_manager = Rc::downgrade(self);
+    let timer = unsafe {
+      WlTimer::init(self.output_manager.raw_display(), timeout_ms, move || {
+        if let Some(cursor_manager) = cursor_manager.upgrade() {
+          cursor_manager.hide();
+        }
+      })
+    };
+    match timer {
+      Ok(timer) => *self.auto_hide_timer.borrow_mut() = Some(timer),
+      Err(_) => error!("CursorManager::restart_auto_hide_timer: Could not create timer"),
+    }
+  }
+
+  /// Focuses the window under the cursor, after the configured delay
+  fn focus_window_under_cursor(&self) {
+    let config = self.config_manager.config();
+    let window = self
+      .window_manager
+      .window_buffer_at(&self.position().into())
+      .filter(|window| window.can_receive_focus() && self.seat_manager.is_input_allowed(window));
+
+    let window = match window {
+      Some(window) => window,
+      None => {
+        // Stop any pending focus change when the cursor leaves the window
+        self.focus_follows_mouse_target.borrow_mut().take();
+        self.focus_follows_mouse_timer.borrow_mut().take();
+        return;
+      }
+    };
+    if self
+      .window_manager
+      .focused_window(&self.seat_manager)
+      .as_ref()
+      == Some(&window)
+    {
+      self.focus_follows_mouse_target.borrow_mut().take();
+      self.focus_follows_mouse_timer.borrow_mut().take();
+      return;
+    }
+
+    if config.focus_follows_mouse_delay_ms == 0 {
+      let _ = self
+        .window_manager
+        .focus_window_on_seat(window, &self.seat_manager);
+      return;
+    }
+
+    let is_pending = self
+      .focus_follows_mouse_target
+      .borrow()
+      .as_ref()
+      .and_then(|target| target.upgrade())
+      .map_or(false, |target| Rc::ptr_eq(&target, &window));
+    if is_pending {
+      return;
+    }
+
+    *self.focus_follows_mouse_target.borrow_mut() = Some(Rc::downgrade(&window));
+    let cursor_manager = Rc::downgrade(self);
+    let timer = unsafe {
+      WlTimer::init(
+        self.output_manager.raw_display(),
+        config.focus_follows_mouse_delay_ms,
+        move || {
+          if let Some(cursor_manager) = cursor_manager.upgrade() {
+            let target = cursor_manager
+              .focus_follows_mouse_target
+              .borrow_mut()
+              .take()
+              .and_then(|target| target.upgrade());
+            if let Some(window) = target {
+              let _ = cursor_manager
+                .window_manager
+                .focus_window_on_seat(window, &cursor_manager.seat_manager);
+            }
+          }
+        },
+      )
+    };
+    match timer {
+      Ok(timer) => *self.focus_follows_mouse_timer.borrow_mut() = Some(timer),
+      Err(_) => error!("CursorManager::focus_window_under_cursor: Could not create timer"),
+    }
+  }
+
+  fn process_motion(&self, event: MotionEvent) {
+    let position = event.position();
+
+    if self.is_hidden() {
+      self.show();
+    }
+    if self.cursor_config.borrow().auto_hide_timeout_ms.is_some() {
+      self.restart_auto_hide_timer();
+    }
+
+    unsafe {
+      wlr_cursor_warp(self.cursor, event.raw_device(), position.x(), position.y());
+    }
+
+    self.update_pointer_focus(position, event.time_msec());
+
+    if self.config_manager.config().focus_follows_mouse {
+      self.focus_window_under_cursor();
+    }
+
+    self
+      .event_filter_manager
+      .handle_pointer_motion_event(&event);
+  }
+}
+
 pub(crate) trait CursorEventHandler {
   fn request_set_cursor(&self, event: *const wlr_seat_pointer_request_set_cursor_event);
   fn axis(&self, event: *const wlr_event_pointer_axis);
@@ -252,7 +889,7 @@ impl CursorEventHandler for Rc<CursorManager> {
       let focused_client = (*self.seat_manager.raw_seat()).pointer_state.focused_client;
       // This can be sent by any client, so we check to make sure this one is
       // actually has pointer focus first.
-      if focused_client == (*event).seat_client {
+      if focused_client == (*event).seat_client && !self.is_hidden() {
         // Once we've vetted the client, we can tell the cursor to use the
         // provided surface as the cursor image. It will set the hardware cursor
         // on the output that it's currently on and continue to do so as the
@@ -289,11 +926,45 @@ impl CursorEventHandler for Rc<CursorManager> {
   fn button(&self, event: *const wlr_event_pointer_button) {
     let event = unsafe { ButtonEvent::from_ptr(self.clone(), event) };
 
-    let handled = self
+    // Presses are always classified so that a handled press still counts
+    // towards the next click
+    let click = if event.state() == ButtonState::Pressed {
+      let config = self.config_manager.config().click.clone();
+      Some(self.click_detector.borrow_mut().press(
+        &config,
+        event.pointer_button(),
+        self.position(),
+        event.time_msec(),
+      ))
+    } else {
+      None
+    };
+
+    let handled_by_filter = self
       .event_filter_manager
       .handle_pointer_button_event(&event);
+    let handled_as_click = !handled_by_filter
+      && click.map_or(false, |click| {
+        self.event_filter_manager.handle_pointer_click_event(&click)
+      });
 
-    if !handled {
+    // The client never saw the press of a consumed click, so it must not see
+    // its release
+    let mut consumed_clicks = self.consumed_clicks.borrow_mut();
+    let release_of_consumed_click = event.state() == ButtonState::Released
+      && match consumed_clicks.iter().position(|b| *b == event.button()) {
+        Some(index) => {
+          consumed_clicks.remove(index);
+          true
+        }
+        None => false,
+      };
+    if handled_as_click && !consumed_clicks.contains(&event.button()) {
+      consumed_clicks.push(event.button());
+    }
+    drop(consumed_clicks);
+
+    if !handled_by_filter && !handled_as_click && !release_of_consumed_click {
       if event.state() == ButtonState::Pressed {
         let surface = self
           .window_manager
@@ -301,7 +972,9 @@ impl CursorEventHandler for Rc<CursorManager> {
 
         if let Some(surface) = surface {
           if surface.can_receive_focus() {
-            self.window_manager.focus_window(surface);
+            let _ = self
+              .window_manager
+              .focus_window_on_seat(surface, &self.seat_manager);
           }
         }
       }
@@ -389,28 +1062,29 @@ wayland_listener!(
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::input::seat::SeatManager;
-  use crate::output_manager::OutputManager;
-  use crate::{config::ConfigManager, test_util::*, window_management_policy::WmPolicyManager};
+  use crate::test_util::*;
   use std::ptr;
   use std::rc::Rc;
 
+  #[test]
+  fn it_maps_cursor_shapes_to_xcursor_names() {
+    assert_eq!(CursorShape::from_raw(1), Some(CursorShape::Default));
+    assert_eq!(CursorShape::from_raw(34), Some(CursorShape::ZoomOut));
+    assert_eq!(CursorShape::from_raw(0), None);
+    assert_eq!(CursorShape::from_raw(35), None);
+    assert_eq!(CursorShape::NwseResize.xcursor_name(), "nwse-resize");
+    assert_eq!(CursorShape::VerticalText.xcursor_name(), "vertical-text");
+  }
+
   #[test]
   fn it_drops_and_cleans_up_on_destroy() {
-    let config_manager = Rc::new(ConfigManager::default());
-    let wm_policy_manager = Rc::new(WmPolicyManager::new());
-    let seat_manager = SeatManager::mock(ptr::null_mut(), ptr::null_mut());
-    let window_manager = Rc::new(WindowManager::init(
-      wm_policy_manager.clone(),
-      seat_manager.clone(),
-      ptr::null_mut(),
-    ));
-    let output_manager =
-      OutputManager::mock(config_manager, wm_policy_manager, window_manager.clone());
+    let managers = MockManagers::new();
+    let seat_manager = &managers.seat_manager;
     let event_filter_manager = Rc::new(EventFilterManager::new());
     let cursor_manager = CursorManager::init(
-      output_manager,
-      window_manager.clone(),
+      managers.config_manager.clone(),
+      managers.output_manager.clone(),
+      managers.window_manager.clone(),
       seat_manager.clone(),
       event_filter_manager,
       ptr::null_mut(),
@@ -474,11 +1148,85 @@ mod tests {
     assert!(destroy_signal.listener_count() == 0);
     assert!(!cursor_manager.has_pointer_device());
   }
+
+  struct DoubleClickFilter;
+
+  impl EventFilter for DoubleClickFilter {
+    fn handle_pointer_click_event(&self, event: &ClickEvent) -> bool {
+      event.count() == ClickCount::Double
+    }
+  }
+
+  #[test]
+  fn it_does_not_forward_the_release_of_a_consumed_click() {
+    let managers = MockManagers::new();
+    let event_filter_manager = Rc::new(EventFilterManager::new());
+    event_filter_manager.add_event_filter(Box::new(DoubleClickFilter));
+    let mut raw_cursor: wlr_cursor = unsafe { std::mem::zeroed() };
+    let cursor_manager = CursorManager::mock(
+      managers.config_manager.clone(),
+      managers.output_manager.clone(),
+      managers.window_manager.clone(),
+      managers.seat_manager.clone(),
+      event_filter_manager,
+      &mut raw_cursor,
+      ptr::null_mut(),
+    );
+
+    let mut time_msec = 0;
+    let mut click = |state| {
+      time_msec += 50;
+      let event = wlr_event_pointer_button {
+        device: ptr::null_mut(),
+        time_msec,
+        button: BTN_MIDDLE,
+        state,
+      };
+      cursor_manager.button(&event);
+    };
+    NOTIFIED_BUTTONS.with(|buttons| buttons.borrow_mut().clear());
+
+    click(wlr_button_state_WLR_BUTTON_PRESSED);
+    click(wlr_button_state_WLR_BUTTON_RELEASED);
+    // The second press is a double click which the filter consumes
+    click(wlr_button_state_WLR_BUTTON_PRESSED);
+    click(wlr_button_state_WLR_BUTTON_RELEASED);
+
+    NOTIFIED_BUTTONS.with(|buttons| {
+      assert_eq!(
+        *buttons.borrow(),
+        vec![
+          (BTN_MIDDLE, wlr_button_state_WLR_BUTTON_PRESSED),
+          (BTN_MIDDLE, wlr_button_state_WLR_BUTTON_RELEASED),
+        ]
+      )
+    });
+    assert!(cursor_manager.consumed_clicks.borrow().is_empty());
+  }
+}
+
+#[cfg(test)]
+thread_local! {
+  static NOTIFIED_BUTTONS: RefCell<Vec<(u32, wlr_button_state)>> = RefCell::new(vec![]);
+}
+#[cfg(test)]
+unsafe fn wlr_seat_pointer_notify_button(
+  _: *mut wlr_seat,
+  _: u32,
+  button: u32,
+  state: wlr_button_state,
+) -> u32 {
+  NOTIFIED_BUTTONS.with(|buttons| buttons.borrow_mut().push((button, state)));
+  0
 }
 
 #[cfg(test)]
 unsafe fn wlr_cursor_attach_input_device(_: *mut wlr_cursor, _: *mut wlr_input_device) {}
 #[cfg(test)]
+unsafe fn wlr_input_device_is_libinput(_: *mut wlr_input_device) -> bool {
+  false
+}
+#[cfg(test)]
 unsafe fn wlr_cursor_map_input_to_output(
   _: *mut wlr_cursor,
   _: *mut wlr_input_device,
@@ -13,6 +13,8 @@ use wlroots_sys::*;
 pub enum DeviceType {
   Keyboard(*mut wlr_keyboard),
   Pointer(*mut wlr_pointer),
+  Tablet(*mut wlr_tablet),
+  Switch(*mut wlr_switch),
   Unknown,
 }
 
@@ -55,6 +57,12 @@ impl Device {
         type_ if type_ == wlr_input_device_type_WLR_INPUT_DEVICE_POINTER => {
           DeviceType::Pointer(device.__bindgen_anon_1.pointer)
         }
+        type_ if type_ == wlr_input_device_type_WLR_INPUT_DEVICE_TABLET_TOOL => {
+          DeviceType::Tablet(device.__bindgen_anon_1.tablet)
+        }
+        type_ if type_ == wlr_input_device_type_WLR_INPUT_DEVICE_SWITCH => {
+          DeviceType::Switch(device.__bindgen_anon_1.switch_device)
+        }
         _ => DeviceType::Unknown,
       }
     }
@@ -6,10 +6,10 @@ use xkbcommon::xkb;
 /// Implement EventFilter to handle input events.
 ///
 /// Each event handler return a bool to inform if it has handled
-/// the event or not. EventFilters are called in order added and
-/// as soon as the event is handled, the process stops. If no
-/// EventFilter handles the event it will be forwarded to the
-/// appropriate client.
+/// the event or not. EventFilters are called in order of priority,
+/// highest first, and in order added for equal priorities. As soon
+/// as the event is handled, the process stops. If no EventFilter
+/// handles the event it will be forwarded to the appropriate client.
 pub trait EventFilter {
   fn handle_keyboard_event(&self, _event: &KeyboardEvent) -> bool {
     false
@@ -23,6 +23,19 @@ pub trait EventFilter {
   fn handle_pointer_axis_event(&self, _event: &AxisEvent) -> bool {
     false
   }
+  /// Called for presses that were not handled as a button event
+  fn handle_pointer_click_event(&self, _event: &ClickEvent) -> bool {
+    false
+  }
+  fn handle_tablet_tool_proximity_event(&self, _event: &TabletToolProximityEvent) -> bool {
+    false
+  }
+  fn handle_tablet_tool_tip_event(&self, _event: &TabletToolTipEvent) -> bool {
+    false
+  }
+  fn handle_tablet_tool_axis_event(&self, _event: &TabletToolAxisEvent) -> bool {
+    false
+  }
 }
 
 impl<T> EventFilter for Rc<T>
@@ -41,21 +54,87 @@ where
   fn handle_pointer_axis_event(&self, event: &AxisEvent) -> bool {
     Deref::deref(self).handle_pointer_axis_event(event)
   }
+  fn handle_pointer_click_event(&self, event: &ClickEvent) -> bool {
+    Deref::deref(self).handle_pointer_click_event(event)
+  }
+  fn handle_tablet_tool_proximity_event(&self, event: &TabletToolProximityEvent) -> bool {
+    Deref::deref(self).handle_tablet_tool_proximity_event(event)
+  }
+  fn handle_tablet_tool_tip_event(&self, event: &TabletToolTipEvent) -> bool {
+    Deref::deref(self).handle_tablet_tool_tip_event(event)
+  }
+  fn handle_tablet_tool_axis_event(&self, event: &TabletToolAxisEvent) -> bool {
+    Deref::deref(self).handle_tablet_tool_axis_event(event)
+  }
+}
+
+/// Identifies an added event filter so that it can be removed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EventFilterHandle(u64);
+
+/// The priority of event filters added without an explicit priority,
+/// including the window management policy
+pub const DEFAULT_EVENT_FILTER_PRIORITY: i32 = 0;
+
+struct EventFilterEntry {
+  handle: EventFilterHandle,
+  priority: i32,
+  filter: Box<dyn EventFilter>,
 }
 
 pub(crate) struct EventFilterManager {
-  event_filters: RefCell<Vec<Box<dyn EventFilter>>>,
+  next_id: RefCell<u64>,
+  event_filters: RefCell<Vec<EventFilterEntry>>,
 }
 
 impl EventFilterManager {
   pub(crate) fn new() -> EventFilterManager {
     EventFilterManager {
+      next_id: RefCell::new(0),
       event_filters: RefCell::new(vec![]),
     }
   }
 
-  pub(crate) fn add_event_filter(&self, filter: Box<dyn EventFilter>) {
-    self.event_filters.borrow_mut().push(filter)
+  pub(crate) fn add_event_filter(&self, filter: Box<dyn EventFilter>) -> EventFilterHandle {
+    self.add_event_filter_with_priority(filter, DEFAULT_EVENT_FILTER_PRIORITY)
+  }
+
+  pub(crate) fn add_event_filter_with_priority(
+    &self,
+    filter: Box<dyn EventFilter>,
+    priority: i32,
+  ) -> EventFilterHandle {
+    let id = *self.next_id.borrow();
+    *self.next_id.borrow_mut() = id + 1;
+    let handle = EventFilterHandle(id);
+
+    let mut event_filters = self.event_filters.borrow_mut();
+    let index = event_filters
+      .iter()
+      .position(|entry| entry.priority < priority)
+      .unwrap_or_else(|| event_filters.len());
+    event_filters.insert(
+      index,
+      EventFilterEntry {
+        handle,
+        priority,
+        filter,
+      },
+    );
+
+    handle
+  }
+
+  /// Removes an event filter, returning it if it was still added
+  pub(crate) fn remove_event_filter(
+    &self,
+    handle: EventFilterHandle,
+  ) -> Option<Box<dyn EventFilter>> {
+    let mut event_filters = self.event_filters.borrow_mut();
+    let index = event_filters
+      .iter()
+      .position(|entry| entry.handle == handle)?;
+    Some(event_filters.remove(index).filter)
   }
 }
 
@@ -65,28 +144,56 @@ impl EventFilter for EventFilterManager {
       .event_filters
       .borrow()
       .iter()
-      .any(|filter| filter.handle_keyboard_event(event))
+      .any(|entry| entry.filter.handle_keyboard_event(event))
   }
   fn handle_pointer_motion_event(&self, event: &MotionEvent) -> bool {
     self
       .event_filters
       .borrow()
       .iter()
-      .any(|filter| filter.handle_pointer_motion_event(event))
+      .any(|entry| entry.filter.handle_pointer_motion_event(event))
   }
   fn handle_pointer_button_event(&self, event: &ButtonEvent) -> bool {
     self
       .event_filters
       .borrow()
       .iter()
-      .any(|filter| filter.handle_pointer_button_event(event))
+      .any(|entry| entry.filter.handle_pointer_button_event(event))
   }
   fn handle_pointer_axis_event(&self, event: &AxisEvent) -> bool {
     self
       .event_filters
       .borrow()
       .iter()
-      .any(|filter| filter.handle_pointer_axis_event(event))
+      .any(|entry| entry.filter.handle_pointer_axis_event(event))
+  }
+  fn handle_pointer_click_event(&self, event: &ClickEvent) -> bool {
+    self
+      .event_filters
+      .borrow()
+      .iter()
+      .any(|entry| entry.filter.handle_pointer_click_event(event))
+  }
+  fn handle_tablet_tool_proximity_event(&self, event: &TabletToolProximityEvent) -> bool {
+    self
+      .event_filters
+      .borrow()
+      .iter()
+      .any(|entry| entry.filter.handle_tablet_tool_proximity_event(event))
+  }
+  fn handle_tablet_tool_tip_event(&self, event: &TabletToolTipEvent) -> bool {
+    self
+      .event_filters
+      .borrow()
+      .iter()
+      .any(|entry| entry.filter.handle_tablet_tool_tip_event(event))
+  }
+  fn handle_tablet_tool_axis_event(&self, event: &TabletToolAxisEvent) -> bool {
+    self
+      .event_filters
+      .borrow()
+      .iter()
+      .any(|entry| entry.filter.handle_tablet_tool_axis_event(event))
   }
 }
 
@@ -120,3 +227,35 @@ impl EventFilter for VtSwitchEventFilter {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct NoopFilter;
+  impl EventFilter for NoopFilter {}
+
+  fn handles(manager: &EventFilterManager) -> Vec<EventFilterHandle> {
+    manager
+      .event_filters
+      .borrow()
+      .iter()
+      .map(|entry| entry.handle)
+      .collect()
+  }
+
+  #[test]
+  fn it_orders_filters_by_priority() {
+    let manager = EventFilterManager::new();
+    let default = manager.add_event_filter(Box::new(NoopFilter));
+    let low = manager.add_event_filter_with_priority(Box::new(NoopFilter), -1);
+    let high = manager.add_event_filter_with_priority(Box::new(NoopFilter), 1);
+    let default_2 = manager.add_event_filter(Box::new(NoopFilter));
+
+    assert_eq!(handles(&manager), vec![high, default, default_2, low]);
+
+    assert!(manager.remove_event_filter(high).is_some());
+    assert!(manager.remove_event_filter(high).is_none());
+    assert_eq!(handles(&manager), vec![default, default_2, low]);
+  }
+}
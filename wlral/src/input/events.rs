@@ -1,7 +1,8 @@
 use crate::geometry::{FDisplacement, FPoint};
-use crate::input::cursor::CursorManager;
+use crate::input::cursor::{ClickConfig, CursorManager};
+use crate::input::keybindings::ModMask;
 use crate::input::keyboard::Keyboard;
-use std::rc::Rc;
+use std::{borrow::Cow, ffi::CStr, rc::Rc};
 use wlroots_sys::*;
 use xkbcommon::xkb;
 
@@ -16,6 +17,50 @@ pub const BTN_FORWARD: u32 = 0x115;
 pub const BTN_BACK: u32 = 0x116;
 pub const BTN_TASK: u32 = 0x117;
 
+/// A pointer button, with the raw code for buttons without a name
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PointerButton {
+  Left,
+  Right,
+  Middle,
+  Side,
+  Extra,
+  Forward,
+  Back,
+  Task,
+  Other(u32),
+}
+
+impl PointerButton {
+  pub fn from_raw(button: u32) -> PointerButton {
+    match button {
+      BTN_LEFT => PointerButton::Left,
+      BTN_RIGHT => PointerButton::Right,
+      BTN_MIDDLE => PointerButton::Middle,
+      BTN_SIDE => PointerButton::Side,
+      BTN_EXTRA => PointerButton::Extra,
+      BTN_FORWARD => PointerButton::Forward,
+      BTN_BACK => PointerButton::Back,
+      BTN_TASK => PointerButton::Task,
+      button => PointerButton::Other(button),
+    }
+  }
+
+  pub fn as_raw(&self) -> u32 {
+    match self {
+      PointerButton::Left => BTN_LEFT,
+      PointerButton::Right => BTN_RIGHT,
+      PointerButton::Middle => BTN_MIDDLE,
+      PointerButton::Side => BTN_SIDE,
+      PointerButton::Extra => BTN_EXTRA,
+      PointerButton::Forward => BTN_FORWARD,
+      PointerButton::Back => BTN_BACK,
+      PointerButton::Task => BTN_TASK,
+      PointerButton::Other(button) => *button,
+    }
+  }
+}
+
 pub trait InputEvent {
   /// Get the timestamp of this event
   fn time_msec(&self) -> u32;
@@ -35,6 +80,16 @@ pub trait CursorEvent {
   /// (e.g see [this fun wlc bug](https://github.com/Cloudef/wlc/issues/181)).
   fn delta(&self) -> FDisplacement;
   fn delta_unaccel(&self) -> FDisplacement;
+
+  /// Get the keyboard modifiers that were held down when the event fired
+  fn modifiers(&self) -> ModMask;
+}
+
+/// The direction of an axis event relative to the physical motion
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AxisRelativeDirection {
+  Identical,
+  Inverted,
 }
 
 /// Event that triggers when the pointer device scrolls (e.g using a wheel
@@ -70,14 +125,46 @@ impl AxisEvent {
 
   /// Get the change from the last axis value
   ///
-  /// Useful to determine e.g how much to scroll.
+  /// Useful to determine e.g how much to scroll. This is always set, for
+  /// wheels it is the scroll distance of the wheel clicks.
   pub fn delta(&self) -> f64 {
     unsafe { (*self.event).delta }
   }
 
+  /// Get the number of wheel clicks
+  ///
+  /// Only set for sources with discrete steps like mouse wheels, it is
+  /// zero for continuous sources like touchpads.
   pub fn delta_discrete(&self) -> i32 {
     unsafe { (*self.event).delta_discrete }
   }
+
+  /// If the event has discrete steps, see `delta_discrete`
+  pub fn is_discrete(&self) -> bool {
+    self.delta_discrete() != 0
+  }
+
+  /// Get the name of the device that fired this event
+  pub fn device_name(&self) -> Cow<str> {
+    unsafe { CStr::from_ptr((*self.raw_device()).name).to_string_lossy() }
+  }
+
+  /// Get if the delta has the same direction as the physical motion or if
+  /// it has been inverted, e.g by natural scrolling
+  pub fn relative_direction(&self) -> AxisRelativeDirection {
+    unsafe {
+      let device = self.raw_device();
+      if !wlr_input_device_is_libinput(device) {
+        return AxisRelativeDirection::Identical;
+      }
+      let handle = wlr_libinput_get_device_handle(device);
+      if libinput_device_config_scroll_get_natural_scroll_enabled(handle) != 0 {
+        AxisRelativeDirection::Inverted
+      } else {
+        AxisRelativeDirection::Identical
+      }
+    }
+  }
 }
 
 impl InputEvent for AxisEvent {
@@ -100,6 +187,9 @@ impl CursorEvent for AxisEvent {
   fn delta_unaccel(&self) -> FDisplacement {
     CursorEvent::delta(self)
   }
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -125,6 +215,86 @@ impl ButtonState {
   }
 }
 
+/// How many times a button was clicked in quick succession
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClickCount {
+  Single,
+  Double,
+  Triple,
+}
+
+/// A press of a pointer button, classified by the presses of the same
+/// button shortly before it at nearly the same position
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClickEvent {
+  button: PointerButton,
+  count: ClickCount,
+  position: FPoint,
+  time_msec: u32,
+}
+
+impl ClickEvent {
+  pub fn button(&self) -> PointerButton {
+    self.button
+  }
+
+  pub fn count(&self) -> ClickCount {
+    self.count
+  }
+
+  /// The position of the cursor in layout coordinates
+  pub fn position(&self) -> FPoint {
+    self.position
+  }
+
+  pub fn time_msec(&self) -> u32 {
+    self.time_msec
+  }
+}
+
+/// Classifies button presses as single, double or triple clicks
+///
+/// A click after a triple click starts over as a single click.
+#[derive(Debug, Default)]
+pub struct ClickDetector {
+  last_click: Option<ClickEvent>,
+}
+
+impl ClickDetector {
+  pub fn press(
+    &mut self,
+    config: &ClickConfig,
+    button: PointerButton,
+    position: FPoint,
+    time_msec: u32,
+  ) -> ClickEvent {
+    let previous_count = self.last_click.as_ref().and_then(|last_click| {
+      let distance = (position.x - last_click.position.x).hypot(position.y - last_click.position.y);
+      let is_continued = last_click.button == button
+        && time_msec.wrapping_sub(last_click.time_msec) <= config.multi_click_timeout_ms
+        && distance <= config.multi_click_distance;
+      if is_continued {
+        Some(last_click.count)
+      } else {
+        None
+      }
+    });
+    let count = match previous_count {
+      Some(ClickCount::Single) => ClickCount::Double,
+      Some(ClickCount::Double) => ClickCount::Triple,
+      Some(ClickCount::Triple) | None => ClickCount::Single,
+    };
+    let click = ClickEvent {
+      button,
+      count,
+      position,
+      time_msec,
+    };
+    self.last_click = Some(click.clone());
+    click
+  }
+}
+
 /// Event that triggers when a button is pressed (e.g left click, right click,
 /// a gaming mouse button, etc.)
 pub struct ButtonEvent {
@@ -162,6 +332,11 @@ impl ButtonEvent {
   pub fn button(&self) -> u32 {
     unsafe { (*self.event).button }
   }
+
+  /// Get the button that was pressed or released
+  pub fn pointer_button(&self) -> PointerButton {
+    PointerButton::from_raw(self.button())
+  }
 }
 
 impl InputEvent for ButtonEvent {
@@ -184,6 +359,9 @@ impl CursorEvent for ButtonEvent {
   fn delta_unaccel(&self) -> FDisplacement {
     self.delta()
   }
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
 }
 
 /// Event that triggers when the pointer moves
@@ -227,6 +405,12 @@ impl CursorEvent for MotionEvent {
       MotionEvent::Absolute(event) => event.delta_unaccel(),
     }
   }
+  fn modifiers(&self) -> ModMask {
+    match self {
+      MotionEvent::Relative(event) => event.modifiers(),
+      MotionEvent::Absolute(event) => event.modifiers(),
+    }
+  }
 }
 
 pub struct RelativeMotionEvent {
@@ -283,6 +467,10 @@ impl CursorEvent for RelativeMotionEvent {
       }
     }
   }
+
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
 }
 
 pub struct AbsoluteMotionEvent {
@@ -343,6 +531,246 @@ impl CursorEvent for AbsoluteMotionEvent {
   fn delta_unaccel(&self) -> FDisplacement {
     self.delta()
   }
+
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TabletToolProximityState {
+  Out,
+  In,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TabletToolTipState {
+  Up,
+  Down,
+}
+
+/// Event that triggers when a tablet tool, like a stylus, comes close enough
+/// to the tablet to be tracked or leaves it
+pub struct TabletToolProximityEvent {
+  cursor_manager: Rc<CursorManager>,
+  event: *const wlr_event_tablet_tool_proximity,
+}
+
+impl TabletToolProximityEvent {
+  pub(crate) unsafe fn from_ptr(
+    cursor_manager: Rc<CursorManager>,
+    event: *const wlr_event_tablet_tool_proximity,
+  ) -> Self {
+    TabletToolProximityEvent {
+      cursor_manager,
+      event,
+    }
+  }
+
+  /// Get the raw pointer to this event
+  pub fn raw_event(&self) -> *const wlr_event_tablet_tool_proximity {
+    self.event
+  }
+
+  /// Get the raw pointer to the tool that fired this event
+  pub fn raw_tool(&self) -> *mut wlr_tablet_tool {
+    unsafe { (*self.event).tool }
+  }
+
+  pub fn state(&self) -> TabletToolProximityState {
+    if unsafe { (*self.event).state }
+      == wlr_tablet_tool_proximity_state_WLR_TABLET_TOOL_PROXIMITY_IN
+    {
+      TabletToolProximityState::In
+    } else {
+      TabletToolProximityState::Out
+    }
+  }
+}
+
+impl InputEvent for TabletToolProximityEvent {
+  fn raw_device(&self) -> *mut wlr_input_device {
+    unsafe { (*self.event).device }
+  }
+
+  fn time_msec(&self) -> u32 {
+    unsafe { (*self.event).time_msec }
+  }
+}
+
+impl CursorEvent for TabletToolProximityEvent {
+  fn position(&self) -> FPoint {
+    self.cursor_manager.position()
+  }
+  fn delta(&self) -> FDisplacement {
+    FDisplacement::ZERO
+  }
+  fn delta_unaccel(&self) -> FDisplacement {
+    self.delta()
+  }
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
+}
+
+/// Event that triggers when a tablet tool touches the tablet or is lifted
+/// from it
+pub struct TabletToolTipEvent {
+  cursor_manager: Rc<CursorManager>,
+  event: *const wlr_event_tablet_tool_tip,
+}
+
+impl TabletToolTipEvent {
+  pub(crate) unsafe fn from_ptr(
+    cursor_manager: Rc<CursorManager>,
+    event: *const wlr_event_tablet_tool_tip,
+  ) -> Self {
+    TabletToolTipEvent {
+      cursor_manager,
+      event,
+    }
+  }
+
+  /// Get the raw pointer to this event
+  pub fn raw_event(&self) -> *const wlr_event_tablet_tool_tip {
+    self.event
+  }
+
+  /// Get the raw pointer to the tool that fired this event
+  pub fn raw_tool(&self) -> *mut wlr_tablet_tool {
+    unsafe { (*self.event).tool }
+  }
+
+  pub fn state(&self) -> TabletToolTipState {
+    if unsafe { (*self.event).state } == wlr_tablet_tool_tip_state_WLR_TABLET_TOOL_TIP_DOWN {
+      TabletToolTipState::Down
+    } else {
+      TabletToolTipState::Up
+    }
+  }
+}
+
+impl InputEvent for TabletToolTipEvent {
+  fn raw_device(&self) -> *mut wlr_input_device {
+    unsafe { (*self.event).device }
+  }
+
+  fn time_msec(&self) -> u32 {
+    unsafe { (*self.event).time_msec }
+  }
+}
+
+impl CursorEvent for TabletToolTipEvent {
+  fn position(&self) -> FPoint {
+    self.cursor_manager.position()
+  }
+  fn delta(&self) -> FDisplacement {
+    FDisplacement::ZERO
+  }
+  fn delta_unaccel(&self) -> FDisplacement {
+    self.delta()
+  }
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
+}
+
+/// Event that triggers when a tablet tool moves or any of its other axes,
+/// like pressure or tilt, change
+///
+/// Only the axes that changed are set, the others are None.
+pub struct TabletToolAxisEvent {
+  cursor_manager: Rc<CursorManager>,
+  event: *const wlr_event_tablet_tool_axis,
+  delta: FDisplacement,
+}
+
+impl TabletToolAxisEvent {
+  pub(crate) unsafe fn from_ptr(
+    cursor_manager: Rc<CursorManager>,
+    event: *const wlr_event_tablet_tool_axis,
+    delta: FDisplacement,
+  ) -> Self {
+    TabletToolAxisEvent {
+      cursor_manager,
+      event,
+      delta,
+    }
+  }
+
+  /// Get the raw pointer to this event
+  pub fn raw_event(&self) -> *const wlr_event_tablet_tool_axis {
+    self.event
+  }
+
+  /// Get the raw pointer to the tool that fired this event
+  pub fn raw_tool(&self) -> *mut wlr_tablet_tool {
+    unsafe { (*self.event).tool }
+  }
+
+  fn has_axis(&self, axis: wlr_tablet_tool_axes) -> bool {
+    unsafe { (*self.event).updated_axes & axis != 0 }
+  }
+
+  /// If the tool moved
+  pub fn moved(&self) -> bool {
+    self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_X)
+      || self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_Y)
+  }
+
+  /// The pressure of the tip, from 0.0 to 1.0
+  pub fn pressure(&self) -> Option<f64> {
+    if self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_PRESSURE) {
+      Some(unsafe { (*self.event).pressure })
+    } else {
+      None
+    }
+  }
+
+  /// The distance of the tool from the tablet, from 0.0 to 1.0
+  pub fn distance(&self) -> Option<f64> {
+    if self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_DISTANCE) {
+      Some(unsafe { (*self.event).distance })
+    } else {
+      None
+    }
+  }
+
+  /// The tilt of the tool in degrees, as (x, y)
+  pub fn tilt(&self) -> Option<(f64, f64)> {
+    if self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_TILT_X)
+      || self.has_axis(wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_TILT_Y)
+    {
+      Some(unsafe { ((*self.event).tilt_x, (*self.event).tilt_y) })
+    } else {
+      None
+    }
+  }
+}
+
+impl InputEvent for TabletToolAxisEvent {
+  fn raw_device(&self) -> *mut wlr_input_device {
+    unsafe { (*self.event).device }
+  }
+
+  fn time_msec(&self) -> u32 {
+    unsafe { (*self.event).time_msec }
+  }
+}
+
+impl CursorEvent for TabletToolAxisEvent {
+  fn position(&self) -> FPoint {
+    self.cursor_manager.position()
+  }
+  fn delta(&self) -> FDisplacement {
+    self.delta.clone()
+  }
+  fn delta_unaccel(&self) -> FDisplacement {
+    self.delta()
+  }
+  fn modifiers(&self) -> ModMask {
+    self.cursor_manager.modifiers()
+  }
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -364,6 +792,11 @@ impl<'a> KeyboardEvent<'a> {
     KeyboardEvent { keyboard, event }
   }
 
+  /// Get the keyboard that fired this event
+  pub fn keyboard(&self) -> &Keyboard {
+    self.keyboard
+  }
+
   pub fn libinput_keycode(&self) -> xkb::Keycode {
     unsafe { (*self.event).keycode }
   }
@@ -377,6 +810,11 @@ impl<'a> KeyboardEvent<'a> {
     self.keyboard.xkb_state()
   }
 
+  /// Get the modifiers that are currently held down
+  pub fn modifiers(&self) -> ModMask {
+    ModMask::from_xkb_state(&self.keyboard.xkb_state())
+  }
+
   pub fn raw_state(&self) -> wlr_key_state {
     unsafe { (*self.event).state }
   }
@@ -414,3 +852,37 @@ impl<'a> InputEvent for KeyboardEvent<'a> {
     unsafe { (*self.event).time_msec }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_maps_pointer_buttons() {
+    assert_eq!(PointerButton::from_raw(0x110), PointerButton::Left);
+    assert_eq!(PointerButton::from_raw(BTN_TASK), PointerButton::Task);
+    assert_eq!(PointerButton::from_raw(0x118), PointerButton::Other(0x118));
+    assert_eq!(PointerButton::Back.as_raw(), BTN_BACK);
+    assert_eq!(PointerButton::Other(0x118).as_raw(), 0x118);
+  }
+
+  #[test]
+  fn it_counts_clicks() {
+    let config = ClickConfig::default();
+    let mut detector = ClickDetector::default();
+    let at = |x| FPoint { x, y: 10.0 };
+    let mut press = |button, x, time| detector.press(&config, button, at(x), time).count();
+
+    assert_eq!(press(PointerButton::Left, 0.0, 1000), ClickCount::Single);
+    assert_eq!(press(PointerButton::Left, 2.0, 1200), ClickCount::Double);
+    assert_eq!(press(PointerButton::Left, 2.0, 1500), ClickCount::Triple);
+    assert_eq!(press(PointerButton::Left, 2.0, 1600), ClickCount::Single);
+    // Too late
+    assert_eq!(press(PointerButton::Left, 2.0, 2100), ClickCount::Single);
+    // Too far
+    assert_eq!(press(PointerButton::Left, 20.0, 2200), ClickCount::Single);
+    // Another button
+    assert_eq!(press(PointerButton::Right, 20.0, 2300), ClickCount::Single);
+    assert_eq!(press(PointerButton::Right, 20.0, 2400), ClickCount::Double);
+  }
+}
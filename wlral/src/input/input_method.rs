@@ -0,0 +1,395 @@
+use crate::geometry::{Displacement, Rectangle, Size};
+use crate::input::seat::SeatManager;
+use crate::window_manager::WindowManager;
+use log::debug;
+use std::cell::{Cell, RefCell};
+use std::pin::Pin;
+use std::ptr;
+use std::rc::{Rc, Weak};
+use wayland_sys::server::wl_display;
+use wlroots_sys::*;
+
+unsafe fn resource_client(resource: *mut wl_resource) -> *mut wl_client {
+  ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_resource_get_client, resource)
+}
+
+struct TextInput {
+  text_input: *mut wlr_text_input_v3,
+
+  event_manager: RefCell<Option<Pin<Box<TextInputEventManager>>>>,
+}
+
+wayland_listener!(
+  TextInputEventManager,
+  Weak<InputMethodManager>,
+  [
+    enable => enable_func: |this: &mut TextInputEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.text_input_enable(data as _);
+      }
+    };
+    commit => commit_func: |this: &mut TextInputEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.text_input_commit(data as _);
+      }
+    };
+    disable => disable_func: |this: &mut TextInputEventManager, _data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.text_input_disable();
+      }
+    };
+    destroy => destroy_func: |this: &mut TextInputEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.text_input_destroy(data as _);
+      }
+    };
+  ]
+);
+
+wayland_listener!(
+  InputMethodEventManager,
+  Weak<InputMethodManager>,
+  [
+    commit => commit_func: |this: &mut InputMethodEventManager, _data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.input_method_commit();
+      }
+    };
+    destroy => destroy_func: |this: &mut InputMethodEventManager, _data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.input_method_destroy();
+      }
+    };
+  ]
+);
+
+wayland_listener!(
+  InputMethodManagerEventManager,
+  Weak<InputMethodManager>,
+  [
+    text_input => text_input_func: |this: &mut InputMethodManagerEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.new_text_input(data as _);
+      }
+    };
+    input_method => input_method_func: |this: &mut InputMethodManagerEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.new_input_method(data as _);
+      }
+    };
+    keyboard_focus_change => keyboard_focus_change_func: |this: &mut InputMethodManagerEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        let event = data as *mut wlr_seat_keyboard_focus_change_event;
+        handler.set_focus((*event).new_surface);
+      }
+    };
+  ]
+);
+
+/// Relays text input between clients and an input method, like an IME or an
+/// on-screen keyboard, using the text-input-v3 and input-method-v2 protocols
+///
+/// Only one input method can be active on the primary seat at a time. The
+/// input method is activated when the focused client enables text input and
+/// its preedit and commit strings are forwarded to that client.
+///
+/// Keyboard grabs and popup surfaces of input methods are not supported by
+/// this version of wlroots, so key events are still delivered to the focused
+/// client. Use `cursor_rectangle` to position the window of the input method
+/// next to the text cursor.
+pub struct InputMethodManager {
+  window_manager: Rc<WindowManager>,
+  seat_manager: Rc<SeatManager>,
+  text_input_manager: *mut wlr_text_input_manager_v3,
+  input_method_manager: *mut wlr_input_method_manager_v2,
+  text_inputs: RefCell<Vec<Rc<TextInput>>>,
+  input_method: Cell<*mut wlr_input_method_v2>,
+
+  event_manager: RefCell<Option<Pin<Box<InputMethodManagerEventManager>>>>,
+  input_method_event_manager: RefCell<Option<Pin<Box<InputMethodEventManager>>>>,
+}
+
+impl InputMethodManager {
+  pub(crate) fn init(
+    window_manager: Rc<WindowManager>,
+    seat_manager: Rc<SeatManager>,
+    display: *mut wl_display,
+  ) -> Rc<InputMethodManager> {
+    debug!("InputMethodManager::init");
+
+    let input_method_manager = Rc::new(InputMethodManager {
+      window_manager,
+      seat_manager: seat_manager.clone(),
+      text_input_manager: unsafe { wlr_text_input_manager_v3_create(display) },
+      input_method_manager: unsafe { wlr_input_method_manager_v2_create(display) },
+      text_inputs: RefCell::new(vec![]),
+      input_method: Cell::new(ptr::null_mut()),
+
+      event_manager: RefCell::new(None),
+      input_method_event_manager: RefCell::new(None),
+    });
+
+    let mut event_manager =
+      InputMethodManagerEventManager::new(Rc::downgrade(&input_method_manager));
+    unsafe {
+      event_manager.text_input(&mut (*input_method_manager.text_input_manager).events.text_input);
+      event_manager.input_method(
+        &mut (*input_method_manager.input_method_manager)
+          .events
+          .input_method,
+      );
+      event_manager.keyboard_focus_change(
+        &mut (*seat_manager.raw_seat())
+          .keyboard_state
+          .events
+          .focus_change,
+      );
+    }
+    *input_method_manager.event_manager.borrow_mut() = Some(event_manager);
+
+    input_method_manager
+  }
+
+  pub fn raw_text_input_manager(&self) -> *mut wlr_text_input_manager_v3 {
+    self.text_input_manager
+  }
+
+  pub fn raw_input_method_manager(&self) -> *mut wlr_input_method_manager_v2 {
+    self.input_method_manager
+  }
+
+  /// If an input method is connected
+  pub fn has_input_method(&self) -> bool {
+    !self.input_method.get().is_null()
+  }
+
+  /// Where the text cursor of the focused client is, in layout coordinates,
+  /// if it has enabled text input
+  pub fn cursor_rectangle(&self) -> Option<Rectangle> {
+    let text_input = self.focused_text_input()?;
+    unsafe {
+      let focused_surface = (*text_input).focused_surface;
+      let window = self.window_manager.window_by_surface(focused_surface)?;
+      let cursor_rectangle = (*text_input).current.cursor_rectangle;
+      Some(Rectangle {
+        top_left: window.buffer_extents().top_left()
+          + Displacement {
+            dx: cursor_rectangle.x,
+            dy: cursor_rectangle.y,
+          },
+        size: Size {
+          width: cursor_rectangle.width,
+          height: cursor_rectangle.height,
+        },
+      })
+    }
+  }
+
+  /// The text input of the surface with keyboard focus, if it is enabled
+  fn focused_text_input(&self) -> Option<*mut wlr_text_input_v3> {
+    let focused_surface = unsafe {
+      (*self.seat_manager.raw_seat())
+        .keyboard_state
+        .focused_surface
+    };
+    if focused_surface.is_null() {
+      return None;
+    }
+    self
+      .text_inputs
+      .borrow()
+      .iter()
+      .map(|text_input| text_input.text_input)
+      .find(|text_input| unsafe {
+        (*(*text_input)).current_enabled && (*(*text_input)).focused_surface == focused_surface
+      })
+  }
+
+  /// Sends the state of the text input to the input method
+  unsafe fn send_state(&self, text_input: *mut wlr_text_input_v3) {
+    let input_method = self.input_method.get();
+    if input_method.is_null() {
+      return;
+    }
+    let current = &(*text_input).current;
+    if !current.surrounding.text.is_null() {
+      wlr_input_method_v2_send_surrounding_text(
+        input_method,
+        current.surrounding.text,
+        current.surrounding.cursor,
+        current.surrounding.anchor,
+      );
+    }
+    wlr_input_method_v2_send_text_change_cause(input_method, current.text_change_cause);
+    wlr_input_method_v2_send_content_type(
+      input_method,
+      current.content_type.hint,
+      current.content_type.purpose,
+    );
+    wlr_input_method_v2_send_done(input_method);
+  }
+
+  /// Moves the text inputs to the newly focused surface
+  fn set_focus(&self, surface: *mut wlr_surface) {
+    for text_input in self.text_inputs.borrow().iter() {
+      let text_input = text_input.text_input;
+      unsafe {
+        let focused_surface = (*text_input).focused_surface;
+        if !focused_surface.is_null() && focused_surface != surface {
+          if (*text_input).current_enabled && self.has_input_method() {
+            wlr_input_method_v2_send_deactivate(self.input_method.get());
+            wlr_input_method_v2_send_done(self.input_method.get());
+          }
+          wlr_text_input_v3_send_leave(text_input);
+        }
+        if !surface.is_null()
+          && (*text_input).focused_surface.is_null()
+          && resource_client((*text_input).resource) == resource_client((*surface).resource)
+        {
+          wlr_text_input_v3_send_enter(text_input, surface);
+        }
+      }
+    }
+  }
+
+  fn new_text_input(self: &Rc<Self>, text_input: *mut wlr_text_input_v3) {
+    unsafe {
+      if (*text_input).seat != self.seat_manager.raw_seat() {
+        return;
+      }
+    }
+    debug!("InputMethodManager::new_text_input");
+
+    let mut event_manager = TextInputEventManager::new(Rc::downgrade(self));
+    unsafe {
+      event_manager.enable(&mut (*text_input).events.enable);
+      event_manager.commit(&mut (*text_input).events.commit);
+      event_manager.disable(&mut (*text_input).events.disable);
+      event_manager.destroy(&mut (*text_input).events.destroy);
+    }
+    self.text_inputs.borrow_mut().push(Rc::new(TextInput {
+      text_input,
+      event_manager: RefCell::new(Some(event_manager)),
+    }));
+
+    // The client may already have keyboard focus
+    unsafe {
+      let focused_surface = (*self.seat_manager.raw_seat())
+        .keyboard_state
+        .focused_surface;
+      if !focused_surface.is_null()
+        && resource_client((*text_input).resource) == resource_client((*focused_surface).resource)
+      {
+        wlr_text_input_v3_send_enter(text_input, focused_surface);
+      }
+    }
+  }
+
+  fn text_input_enable(&self, text_input: *mut wlr_text_input_v3) {
+    debug!("InputMethodManager::text_input_enable");
+    if !self.has_input_method() {
+      return;
+    }
+    unsafe {
+      wlr_input_method_v2_send_activate(self.input_method.get());
+      self.send_state(text_input);
+    }
+  }
+
+  fn text_input_commit(&self, text_input: *mut wlr_text_input_v3) {
+    unsafe {
+      if !(*text_input).current_enabled {
+        return;
+      }
+      self.send_state(text_input);
+    }
+  }
+
+  fn text_input_disable(&self) {
+    debug!("InputMethodManager::text_input_disable");
+    if !self.has_input_method() {
+      return;
+    }
+    unsafe {
+      wlr_input_method_v2_send_deactivate(self.input_method.get());
+      wlr_input_method_v2_send_done(self.input_method.get());
+    }
+  }
+
+  fn text_input_destroy(&self, text_input: *mut wlr_text_input_v3) {
+    debug!("InputMethodManager::text_input_destroy");
+    self.text_inputs.borrow_mut().retain(|t| {
+      if t.text_input == text_input {
+        t.event_manager.borrow_mut().take();
+        false
+      } else {
+        true
+      }
+    });
+  }
+
+  fn new_input_method(self: &Rc<Self>, input_method: *mut wlr_input_method_v2) {
+    unsafe {
+      if (*input_method).seat != self.seat_manager.raw_seat() {
+        return;
+      }
+      if self.has_input_method() {
+        debug!("InputMethodManager::new_input_method: An input method is already active");
+        wlr_input_method_v2_send_unavailable(input_method);
+        return;
+      }
+    }
+    debug!("InputMethodManager::new_input_method");
+
+    self.input_method.set(input_method);
+    let mut event_manager = InputMethodEventManager::new(Rc::downgrade(self));
+    unsafe {
+      event_manager.commit(&mut (*input_method).events.commit);
+      event_manager.destroy(&mut (*input_method).events.destroy);
+    }
+    *self.input_method_event_manager.borrow_mut() = Some(event_manager);
+
+    if let Some(text_input) = self.focused_text_input() {
+      unsafe {
+        wlr_input_method_v2_send_activate(input_method);
+        self.send_state(text_input);
+      }
+    }
+  }
+
+  /// Forwards the pending text of the input method to the focused client
+  fn input_method_commit(&self) {
+    let text_input = match self.focused_text_input() {
+      Some(text_input) => text_input,
+      None => return,
+    };
+    unsafe {
+      let current = &(*self.input_method.get()).current;
+      if !current.preedit.text.is_null() {
+        wlr_text_input_v3_send_preedit_string(
+          text_input,
+          current.preedit.text,
+          current.preedit.cursor_begin,
+          current.preedit.cursor_end,
+        );
+      }
+      if !current.commit_text.is_null() {
+        wlr_text_input_v3_send_commit_string(text_input, current.commit_text);
+      }
+      if current.delete.before_length != 0 || current.delete.after_length != 0 {
+        wlr_text_input_v3_send_delete_surrounding_text(
+          text_input,
+          current.delete.before_length,
+          current.delete.after_length,
+        );
+      }
+      wlr_text_input_v3_send_done(text_input);
+    }
+  }
+
+  fn input_method_destroy(&self) {
+    debug!("InputMethodManager::input_method_destroy");
+    self.input_method.set(ptr::null_mut());
+    self.input_method_event_manager.borrow_mut().take();
+  }
+}
@@ -0,0 +1,263 @@
+use crate::input::event_filter::EventFilter;
+use crate::input::events::{KeyState, KeyboardEvent};
+use bitflags::bitflags;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use xkbcommon::xkb;
+
+bitflags! {
+  /// A set of keyboard modifiers
+  ///
+  /// Can be combined with `|` or built using the builder methods, e.g.
+  /// `ModMask::NONE.ctrl().shift()`.
+  pub struct ModMask: u32 {
+    const NONE  = 0b0000;
+    const CTRL  = 0b0001;
+    const ALT   = 0b0010;
+    const SHIFT = 0b0100;
+    const LOGO  = 0b1000;
+  }
+}
+
+impl ModMask {
+  pub fn ctrl(self) -> ModMask {
+    self | ModMask::CTRL
+  }
+  pub fn alt(self) -> ModMask {
+    self | ModMask::ALT
+  }
+  pub fn shift(self) -> ModMask {
+    self | ModMask::SHIFT
+  }
+  pub fn logo(self) -> ModMask {
+    self | ModMask::LOGO
+  }
+
+  /// The modifiers that are currently depressed in the passed xkb state
+  pub fn from_xkb_state(xkb_state: &xkb::State) -> ModMask {
+    ModMask::from_xkb_state_component(xkb_state, xkb::STATE_MODS_DEPRESSED)
+  }
+
+  /// The modifiers that are active in the passed component of the xkb state
+  pub fn from_xkb_state_component(
+    xkb_state: &xkb::State,
+    component: xkb::StateComponent,
+  ) -> ModMask {
+    let mut modifiers = ModMask::NONE;
+    if xkb_state.mod_name_is_active(xkb::MOD_NAME_CTRL, component) {
+      modifiers |= ModMask::CTRL;
+    }
+    if xkb_state.mod_name_is_active(xkb::MOD_NAME_ALT, component) {
+      modifiers |= ModMask::ALT;
+    }
+    if xkb_state.mod_name_is_active(xkb::MOD_NAME_SHIFT, component) {
+      modifiers |= ModMask::SHIFT;
+    }
+    if xkb_state.mod_name_is_active(xkb::MOD_NAME_LOGO, component) {
+      modifiers |= ModMask::LOGO;
+    }
+    modifiers
+  }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum KeybindingTrigger {
+  /// The handler is called when the key is pressed
+  Press,
+  /// The handler is called when the key is released
+  Release,
+}
+
+type KeybindingHandler = Rc<RefCell<Box<dyn FnMut()>>>;
+
+struct Keybinding {
+  modifiers: ModMask,
+  keysym: xkb::Keysym,
+  trigger: KeybindingTrigger,
+  handler: KeybindingHandler,
+}
+
+/// An EventFilter that dispatches key presses to registered handlers
+///
+/// A binding matches when the pressed keysym is equal to the registered
+/// one and exactly the registered modifiers are held. Both the press and
+/// the release of a bound key are consumed so that the focused client never
+/// sees half of a key stroke.
+///
+/// # Example
+/// ```rust,no_run,ignore
+/// let keybindings = Rc::new(Keybindings::new());
+/// keybindings.bind(
+///   ModMask::NONE.ctrl(),
+///   xkb::KEY_Escape,
+///   Box::new(move || {
+///     if let Some(window) = window_manager.focused_window(&seat_manager) {
+///       window.ask_client_to_close();
+///     }
+///   }),
+/// );
+/// compositor.add_event_filter(Box::new(keybindings.clone()));
+/// ```
+#[derive(Default)]
+pub struct Keybindings {
+  bindings: RefCell<Vec<Keybinding>>,
+  grabbed_keys: RefCell<BTreeSet<xkb::Keycode>>,
+}
+
+impl Keybindings {
+  pub fn new() -> Keybindings {
+    Keybindings::default()
+  }
+
+  /// Call handler when keysym is pressed while exactly modifiers are held
+  ///
+  /// Replaces any previous binding for the same combination and trigger.
+  pub fn bind(&self, modifiers: ModMask, keysym: xkb::Keysym, handler: Box<dyn FnMut()>) {
+    self.add_binding(modifiers, keysym, KeybindingTrigger::Press, handler);
+  }
+
+  /// Call handler when keysym is released while exactly modifiers are held
+  ///
+  /// The key press is grabbed as well so that the client does not see it.
+  pub fn bind_on_release(
+    &self,
+    modifiers: ModMask,
+    keysym: xkb::Keysym,
+    handler: Box<dyn FnMut()>,
+  ) {
+    self.add_binding(modifiers, keysym, KeybindingTrigger::Release, handler);
+  }
+
+  /// Removes all bindings for the passed combination
+  pub fn unbind(&self, modifiers: ModMask, keysym: xkb::Keysym) {
+    self
+      .bindings
+      .borrow_mut()
+      .retain(|binding| !(binding.modifiers == modifiers && binding.keysym == keysym));
+  }
+
+  pub fn is_bound(&self, modifiers: ModMask, keysym: xkb::Keysym) -> bool {
+    self
+      .bindings
+      .borrow()
+      .iter()
+      .any(|binding| binding.modifiers == modifiers && binding.keysym == keysym)
+  }
+
+  fn add_binding(
+    &self,
+    modifiers: ModMask,
+    keysym: xkb::Keysym,
+    trigger: KeybindingTrigger,
+    handler: Box<dyn FnMut()>,
+  ) {
+    let mut bindings = self.bindings.borrow_mut();
+    bindings.retain(|binding| {
+      !(binding.modifiers == modifiers && binding.keysym == keysym && binding.trigger == trigger)
+    });
+    bindings.push(Keybinding {
+      modifiers,
+      keysym,
+      trigger,
+      handler: Rc::new(RefCell::new(handler)),
+    });
+  }
+
+  fn find_handler(
+    &self,
+    modifiers: ModMask,
+    keysym: xkb::Keysym,
+    trigger: KeybindingTrigger,
+  ) -> Option<KeybindingHandler> {
+    self
+      .bindings
+      .borrow()
+      .iter()
+      .find(|binding| {
+        binding.modifiers == modifiers && binding.keysym == keysym && binding.trigger == trigger
+      })
+      .map(|binding| binding.handler.clone())
+  }
+}
+
+impl EventFilter for Keybindings {
+  fn handle_keyboard_event(&self, event: &KeyboardEvent) -> bool {
+    let keysym = event.get_one_sym();
+    let modifiers = event.modifiers();
+
+    match event.state() {
+      KeyState::Pressed => {
+        if !self.is_bound(modifiers, keysym) {
+          return false;
+        }
+        self.grabbed_keys.borrow_mut().insert(event.xkb_keycode());
+        // The handler is called without holding any borrows so that it
+        // is free to modify the bindings.
+        if let Some(handler) = self.find_handler(modifiers, keysym, KeybindingTrigger::Press) {
+          (handler.borrow_mut())();
+        }
+        true
+      }
+      KeyState::Released => {
+        if !self.grabbed_keys.borrow_mut().remove(&event.xkb_keycode()) {
+          return false;
+        }
+        if let Some(handler) = self.find_handler(modifiers, keysym, KeybindingTrigger::Release) {
+          (handler.borrow_mut())();
+        }
+        true
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell;
+
+  #[test]
+  fn it_builds_mod_masks() {
+    assert_eq!(ModMask::NONE.ctrl(), ModMask::CTRL);
+    assert_eq!(
+      ModMask::NONE.ctrl().shift().logo(),
+      ModMask::CTRL | ModMask::SHIFT | ModMask::LOGO
+    );
+    assert_eq!(ModMask::NONE.alt().alt(), ModMask::ALT);
+  }
+
+  #[test]
+  fn it_replaces_and_removes_bindings() {
+    let keybindings = Keybindings::new();
+    let calls = Rc::new(Cell::new(0));
+
+    keybindings.bind(
+      ModMask::NONE.ctrl(),
+      xkb::KEY_a,
+      Box::new(|| panic!("Replaced binding should not be called")),
+    );
+    let handler_calls = calls.clone();
+    keybindings.bind(
+      ModMask::NONE.ctrl(),
+      xkb::KEY_a,
+      Box::new(move || handler_calls.set(handler_calls.get() + 1)),
+    );
+
+    assert!(keybindings.is_bound(ModMask::CTRL, xkb::KEY_a));
+    assert!(!keybindings.is_bound(ModMask::CTRL | ModMask::SHIFT, xkb::KEY_a));
+    assert!(!keybindings.is_bound(ModMask::CTRL, xkb::KEY_b));
+
+    let handler = keybindings
+      .find_handler(ModMask::CTRL, xkb::KEY_a, KeybindingTrigger::Press)
+      .unwrap();
+    (handler.borrow_mut())();
+    assert_eq!(calls.get(), 1);
+    assert!(keybindings
+      .find_handler(ModMask::CTRL, xkb::KEY_a, KeybindingTrigger::Release)
+      .is_none());
+
+    keybindings.unbind(ModMask::CTRL, xkb::KEY_a);
+    assert!(!keybindings.is_bound(ModMask::CTRL, xkb::KEY_a));
+  }
+}
@@ -1,10 +1,12 @@
 use crate::input::device::{Device, DeviceType};
 use crate::input::event_filter::{EventFilter, EventFilterManager};
 use crate::input::events::{InputEvent, KeyboardEvent};
-use crate::{config::ConfigManager, input::seat::SeatManager};
+use crate::input::keybindings::ModMask;
+use crate::{config::ConfigManager, event::Event, input::seat::SeatManager};
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
+use std::ffi::CStr;
 use std::ops::Deref;
 use std::pin::Pin;
 use std::rc::{Rc, Weak};
@@ -12,6 +14,7 @@ use wlroots_sys::*;
 use xkbcommon::xkb;
 #[cfg(not(test))]
 use xkbcommon::xkb::ffi::xkb_state_ref;
+use xkbcommon::xkb::ffi::{xkb_keymap_layout_get_name, xkb_keymap_num_layouts};
 
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub struct RepeatRate(u32);
@@ -43,12 +46,57 @@ pub struct KeyboardConfig {
   pub repeat_delay: RepeatDelay,
 }
 
+/// Why a keyboard layout could not be activated
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum LayoutError {
+  /// The index is not below the number of layouts in the keymap
+  OutOfRange { index: u32, num_layouts: u32 },
+}
+
+impl std::fmt::Display for LayoutError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LayoutError::OutOfRange { index, num_layouts } => write!(
+        f,
+        "Layout index {} is out of range, the keymap has {} layouts",
+        index, num_layouts
+      ),
+    }
+  }
+}
+
+impl std::error::Error for LayoutError {}
+
+/// The state of the keyboard lock LEDs
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct LedState {
+  pub num_lock: bool,
+  pub caps_lock: bool,
+  pub scroll_lock: bool,
+}
+
+/// The modifiers of a keyboard split up by how they are active
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct ModState {
+  /// Modifiers that are physically held down
+  pub depressed: ModMask,
+  /// Modifiers that are active until the next key press (sticky keys)
+  pub latched: ModMask,
+  /// Modifiers that are active until they are pressed again (e.g. caps lock)
+  pub locked: ModMask,
+}
+
 pub struct Keyboard {
   seat_manager: Rc<SeatManager>,
   event_filter_manager: Rc<EventFilterManager>,
   device: Rc<Device>,
   keyboard: *mut wlr_keyboard,
   xkb_state: RefCell<xkb::State>,
+  layout_index: RefCell<u32>,
+  led_state: RefCell<LedState>,
+
+  on_layout_changed: Event<u32>,
+  on_led_state_changed: Event<LedState>,
 
   event_manager: RefCell<Option<Pin<Box<KeyboardEventManager>>>>,
 }
@@ -79,22 +127,30 @@ impl Keyboard {
       xkb_state: RefCell::new(unsafe {
         xkb::State::from_raw_ptr(xkb_state_ref((*keyboard_ptr).xkb_state))
       }),
+      layout_index: RefCell::new(unsafe { (*keyboard_ptr).modifiers.group }),
+      led_state: RefCell::new(LedState::default()),
+      on_layout_changed: Event::default(),
+      on_led_state_changed: Event::default(),
       event_manager: RefCell::new(None),
     });
+    // Start from the LEDs of the keymap so that a lock that is already on
+    // is not reported as a change on the first modifiers event
+    let led_state = keyboard.led_state();
+    *keyboard.led_state.borrow_mut() = led_state;
 
     let subscription =
       config_manager
         .on_config_changed()
-        .subscribe(listener!(keyboard => move |config| {
+        .subscribe_scoped(listener!(keyboard => move |config| {
           set_keymap_from_config(keyboard.raw_ptr(), &config.keyboard);
           *keyboard.xkb_state.borrow_mut() = unsafe {
             xkb::State::from_raw_ptr(xkb_state_ref((*keyboard_ptr).xkb_state))
           };
         }));
 
-    device.on_destroy.then(listener!(config_manager => move || {
-      config_manager.on_config_changed().unsubscribe(subscription);
-    }));
+    device
+      .on_destroy
+      .then(Box::new(move |_| drop(subscription)));
 
     let mut event_manager = KeyboardEventManager::new(Rc::downgrade(&keyboard));
     unsafe {
@@ -114,9 +170,109 @@ impl Keyboard {
     self.device.clone()
   }
 
+  /// The seat the keyboard belongs to
+  pub fn seat_manager(&self) -> Rc<SeatManager> {
+    self.seat_manager.clone()
+  }
+
   pub fn xkb_state(&self) -> xkb::State {
     self.xkb_state.borrow().clone()
   }
+
+  /// The index of the currently active layout in `layout_names`
+  pub fn active_layout_index(&self) -> u32 {
+    unsafe { (*self.keyboard).modifiers.group }
+  }
+
+  /// The names of all layouts in the keymap, in the order configured
+  /// by `KeyboardConfig::xkb_layout`
+  pub fn layout_names(&self) -> Vec<String> {
+    unsafe {
+      let keymap = (*self.keyboard).keymap;
+      (0..self.num_layouts())
+        .map(|index| {
+          let name = xkb_keymap_layout_get_name(keymap, index);
+          if name.is_null() {
+            String::new()
+          } else {
+            CStr::from_ptr(name).to_string_lossy().into_owned()
+          }
+        })
+        .collect()
+    }
+  }
+
+  /// The number of layouts in the keymap
+  pub fn num_layouts(&self) -> u32 {
+    unsafe {
+      let keymap = (*self.keyboard).keymap;
+      if keymap.is_null() {
+        0
+      } else {
+        xkb_keymap_num_layouts(keymap)
+      }
+    }
+  }
+
+  /// Switches to the layout at the passed index
+  pub fn set_layout(&self, index: u32) -> Result<(), LayoutError> {
+    let num_layouts = self.num_layouts();
+    if index >= num_layouts {
+      return Err(LayoutError::OutOfRange { index, num_layouts });
+    }
+    unsafe {
+      let modifiers = (*self.keyboard).modifiers;
+      // This updates the xkb state and emits the modifiers event if anything
+      // changed, which in turn notifies the seat and fires on_layout_changed.
+      wlr_keyboard_notify_modifiers(
+        self.keyboard,
+        modifiers.depressed,
+        modifiers.latched,
+        modifiers.locked,
+        index,
+      );
+    }
+    Ok(())
+  }
+
+  /// Switches to the next layout, wrapping around after the last one
+  pub fn cycle_layout(&self) {
+    let num_layouts = self.num_layouts();
+    if num_layouts > 1 {
+      let _ = self.set_layout((self.active_layout_index() + 1) % num_layouts);
+    }
+  }
+
+  /// Fired with the new layout index when the active layout changes
+  pub fn on_layout_changed(&self) -> &Event<u32> {
+    &self.on_layout_changed
+  }
+
+  pub fn led_state(&self) -> LedState {
+    let xkb_state = self.xkb_state.borrow();
+    if xkb_state.get_raw_ptr().is_null() {
+      return LedState::default();
+    }
+    LedState {
+      num_lock: xkb_state.led_name_is_active(xkb::LED_NAME_NUM),
+      caps_lock: xkb_state.led_name_is_active(xkb::LED_NAME_CAPS),
+      scroll_lock: xkb_state.led_name_is_active(xkb::LED_NAME_SCROLL),
+    }
+  }
+
+  /// Fired with the new state when any of the lock LEDs changes
+  pub fn on_led_state_changed(&self) -> &Event<LedState> {
+    &self.on_led_state_changed
+  }
+
+  pub fn mod_state(&self) -> ModState {
+    let xkb_state = self.xkb_state.borrow();
+    ModState {
+      depressed: ModMask::from_xkb_state_component(&xkb_state, xkb::STATE_MODS_DEPRESSED),
+      latched: ModMask::from_xkb_state_component(&xkb_state, xkb::STATE_MODS_LATCHED),
+      locked: ModMask::from_xkb_state_component(&xkb_state, xkb::STATE_MODS_LOCKED),
+    }
+  }
 }
 
 fn set_keymap_from_config(keyboard_ptr: *mut wlr_keyboard, config: &KeyboardConfig) {
@@ -162,6 +318,18 @@ impl KeyboardEventHandler for Keyboard {
         &mut (*self.keyboard).modifiers,
       );
     }
+
+    let layout_index = self.active_layout_index();
+    if layout_index != *self.layout_index.borrow() {
+      *self.layout_index.borrow_mut() = layout_index;
+      self.on_layout_changed.fire(layout_index);
+    }
+
+    let led_state = self.led_state();
+    if led_state != *self.led_state.borrow() {
+      *self.led_state.borrow_mut() = led_state;
+      self.on_led_state_changed.fire(led_state);
+    }
   }
 
   fn key(&self, event: *const wlr_event_keyboard_key) {
@@ -256,6 +424,10 @@ impl KeyboardManager {
   pub fn has_keyboard(&self) -> bool {
     !self.keyboards.borrow().is_empty()
   }
+
+  pub fn keyboards(&self) -> Ref<Vec<Rc<Keyboard>>> {
+    self.keyboards.borrow()
+  }
 }
 
 #[cfg(test)]
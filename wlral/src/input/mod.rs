@@ -2,5 +2,9 @@ pub mod cursor;
 pub mod device;
 pub mod event_filter;
 pub mod events;
+pub mod input_method;
+pub mod keybindings;
 pub mod keyboard;
 pub mod seat;
+pub mod switch_device;
+pub mod tablet;
@@ -1,9 +1,17 @@
 use super::device::Device;
-use crate::{event::Event, window::Window};
+use crate::{
+  config::{Config, ConfigManager},
+  event::Event,
+  geometry::{Displacement, FPoint, Point},
+  window::Window,
+};
 use log::debug;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::pin::Pin;
-use std::{ptr, rc::Rc};
+use std::{
+  ptr,
+  rc::{Rc, Weak},
+};
 use wlroots_sys::*;
 
 mod wl_seat_capability {
@@ -14,10 +22,24 @@ mod wl_seat_capability {
 }
 use wl_seat_capability::*;
 
+/// The name of the seat that is always created and that input devices are
+/// assigned to unless configured otherwise
+pub const DEFAULT_SEAT: &str = "seat0";
+
+/// The name of the seat the device with the passed name should be assigned to
+pub(crate) fn seat_name_for_device<'a>(config: &'a Config, device_name: &str) -> &'a str {
+  config
+    .device_seats
+    .get(device_name)
+    .map_or(DEFAULT_SEAT, |seat_name| seat_name.as_str())
+}
+
 pub(crate) trait SeatEventHandler {
   fn new_input(&self, device_ptr: *mut wlr_input_device);
   fn inhibit_activate(&self);
   fn inhibit_deactivate(&self);
+  fn request_start_drag(&self, event: *mut wlr_seat_request_start_drag_event);
+  fn start_drag(&self, drag: *mut wlr_drag);
 }
 
 wayland_listener!(
@@ -36,50 +58,132 @@ wayland_listener!(
          let handler = &mut this.data;
          handler.inhibit_deactivate()
      };
+     request_start_drag => request_start_drag_func: |this: &mut SeatEventManager, data: *mut libc::c_void,| unsafe {
+         let handler = &mut this.data;
+         handler.request_start_drag(data as _)
+     };
+     start_drag => start_drag_func: |this: &mut SeatEventManager, data: *mut libc::c_void,| unsafe {
+         let handler = &mut this.data;
+         handler.start_drag(data as _)
+     };
+  ]
+);
+
+wayland_listener!(
+  DragEventManager,
+  Weak<SeatManager>,
+  [
+    destroy => destroy_func: |this: &mut DragEventManager, _data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.end_drag();
+      }
+    };
   ]
 );
 
 pub struct SeatManager {
+  pub(crate) name: String,
+  pub(crate) config_manager: Rc<ConfigManager>,
   pub(crate) seat: *mut wlr_seat,
   pub(crate) inhibit: *mut wlr_input_inhibit_manager,
+  /// Additional seats that new devices can be routed to, only set on the
+  /// primary seat as that is the one receiving new devices from the backend
+  pub(crate) secondary_seats: RefCell<Vec<Weak<SeatManager>>>,
 
   pub(crate) has_any_pointer: RefCell<bool>,
   pub(crate) has_any_keyboard: RefCell<bool>,
   pub(crate) exclusive_client: RefCell<*mut wl_client>,
   pub(crate) on_new_device: Event<Rc<Device>>,
+  pub(crate) drag: Cell<*mut wlr_drag>,
+  pub(crate) drag_position: Cell<FPoint>,
+  pub(crate) on_drag_start: Event<()>,
+  pub(crate) on_drag_end: Event<()>,
+  pub(crate) on_inhibit_changed: Event<bool>,
 
   pub(crate) event_manager: RefCell<Option<Pin<Box<SeatEventManager>>>>,
+  pub(crate) drag_event_manager: RefCell<Option<Pin<Box<DragEventManager>>>>,
 }
 
 impl SeatManager {
-  pub(crate) fn init(
-    display: *mut wl_display,
-    backend: *mut wlr_backend,
+  fn new(
+    name: &str,
+    config_manager: Rc<ConfigManager>,
     seat: *mut wlr_seat,
+    inhibit: *mut wlr_input_inhibit_manager,
   ) -> Rc<SeatManager> {
-    debug!("SeatManager::init");
-
-    let inhibit = unsafe { wlr_input_inhibit_manager_create(display) };
-
-    let seat_manager = Rc::new(SeatManager {
+    Rc::new(SeatManager {
+      name: name.to_string(),
+      config_manager,
       seat,
       inhibit,
+      secondary_seats: RefCell::new(vec![]),
 
       has_any_pointer: RefCell::new(false),
       has_any_keyboard: RefCell::new(false),
       exclusive_client: RefCell::new(ptr::null_mut()),
       on_new_device: Event::default(),
+      drag: Cell::new(ptr::null_mut()),
+      drag_position: Cell::new(FPoint::ZERO),
+      on_drag_start: Event::default(),
+      on_drag_end: Event::default(),
+      on_inhibit_changed: Event::default(),
 
       event_manager: RefCell::new(None),
-    });
+      drag_event_manager: RefCell::new(None),
+    })
+  }
 
+  /// Listens to the signals of the seat and the inhibit manager, and to new
+  /// devices if a backend is passed
+  fn bind(seat_manager: &Rc<SeatManager>, backend: Option<*mut wlr_backend>) {
     let mut event_manager = SeatEventManager::new(Box::new(seat_manager.clone()));
     unsafe {
-      event_manager.new_input(&mut (*backend).events.new_input);
-      event_manager.inhibit_activate(&mut (*inhibit).events.activate);
-      event_manager.inhibit_deactivate(&mut (*inhibit).events.deactivate);
+      if let Some(backend) = backend {
+        event_manager.new_input(&mut (*backend).events.new_input);
+      }
+      event_manager.inhibit_activate(&mut (*seat_manager.inhibit).events.activate);
+      event_manager.inhibit_deactivate(&mut (*seat_manager.inhibit).events.deactivate);
+      event_manager.request_start_drag(&mut (*seat_manager.seat).events.request_start_drag);
+      event_manager.start_drag(&mut (*seat_manager.seat).events.start_drag);
     }
     *seat_manager.event_manager.borrow_mut() = Some(event_manager);
+  }
+
+  pub(crate) fn init(
+    config_manager: Rc<ConfigManager>,
+    display: *mut wl_display,
+    backend: *mut wlr_backend,
+    seat: *mut wlr_seat,
+  ) -> Rc<SeatManager> {
+    debug!("SeatManager::init");
+
+    let inhibit = unsafe { wlr_input_inhibit_manager_create(display) };
+
+    let seat_manager = SeatManager::new(DEFAULT_SEAT, config_manager, seat, inhibit);
+    SeatManager::bind(&seat_manager, Some(backend));
+
+    seat_manager
+  }
+
+  /// Creates an additional seat that gets the devices the config routes to
+  /// `name` from `primary`
+  ///
+  /// Devices are routed when they are added, so additional seats should be
+  /// created before the compositor is started.
+  pub(crate) fn init_secondary(
+    primary: &Rc<SeatManager>,
+    name: &str,
+    seat: *mut wlr_seat,
+  ) -> Rc<SeatManager> {
+    debug!("SeatManager::init_secondary: {}", name);
+
+    let seat_manager =
+      SeatManager::new(name, primary.config_manager.clone(), seat, primary.inhibit);
+    SeatManager::bind(&seat_manager, None);
+    primary
+      .secondary_seats
+      .borrow_mut()
+      .push(Rc::downgrade(&seat_manager));
 
     seat_manager
   }
@@ -89,17 +193,17 @@ impl SeatManager {
     seat: *mut wlr_seat,
     inhibit: *mut wlr_input_inhibit_manager,
   ) -> Rc<SeatManager> {
-    Rc::new(SeatManager {
+    SeatManager::new(
+      DEFAULT_SEAT,
+      Rc::new(ConfigManager::default()),
       seat,
       inhibit,
+    )
+  }
 
-      has_any_pointer: RefCell::new(false),
-      has_any_keyboard: RefCell::new(false),
-      exclusive_client: RefCell::new(ptr::null_mut()),
-      on_new_device: Event::default(),
-
-      event_manager: RefCell::new(None),
-    })
+  /// The name of the seat, as advertised to clients
+  pub fn name(&self) -> &str {
+    &self.name
   }
 
   pub fn raw_seat(&self) -> *mut wlr_seat {
@@ -152,10 +256,88 @@ impl SeatManager {
       }
     }
 
+    let was_inhibited = self.is_inhibited();
     *self.exclusive_client.borrow_mut() = exclusive_client;
+    if self.is_inhibited() != was_inhibited {
+      self.on_inhibit_changed.fire(self.is_inhibited());
+    }
+  }
+
+  /// If a client, like a lockscreen, has grabbed all input using the input
+  /// inhibit protocol
+  pub fn is_inhibited(&self) -> bool {
+    !self.exclusive_client.borrow().is_null()
   }
 
-  pub(crate) fn is_input_allowed(&self, window: &Window) -> bool {
+  /// The client that has grabbed all input, if input is inhibited
+  pub fn inhibiting_client(&self) -> Option<*mut wl_client> {
+    let exclusive_client = *self.exclusive_client.borrow();
+    if exclusive_client.is_null() {
+      None
+    } else {
+      Some(exclusive_client)
+    }
+  }
+
+  /// Fired with the new state when input becomes inhibited or is released
+  pub fn on_inhibit_changed(&self) -> &Event<bool> {
+    &self.on_inhibit_changed
+  }
+
+  /// If a drag-and-drop operation is in progress
+  pub fn is_dragging(&self) -> bool {
+    !self.drag.get().is_null()
+  }
+
+  /// Fired when a client starts a drag-and-drop operation
+  pub fn on_drag_start(&self) -> &Event<()> {
+    &self.on_drag_start
+  }
+  /// Fired when a drag-and-drop operation is dropped or cancelled
+  pub fn on_drag_end(&self) -> &Event<()> {
+    &self.on_drag_end
+  }
+
+  /// The surface of the icon of the drag-and-drop operation, if it has a
+  /// mapped icon, and where to draw it in layout coordinates
+  pub(crate) fn drag_icon(&self) -> Option<(*mut wlr_surface, Point)> {
+    let drag = self.drag.get();
+    if drag.is_null() {
+      return None;
+    }
+    unsafe {
+      let icon = (*drag).icon;
+      if icon.is_null() || !(*icon).mapped {
+        return None;
+      }
+      let surface = (*icon).surface;
+      let position: Point = self.drag_position.get().into();
+      Some((
+        surface,
+        position
+          + Displacement {
+            dx: (*surface).sx,
+            dy: (*surface).sy,
+          },
+      ))
+    }
+  }
+
+  /// Moves the drag icon, called when the cursor moves
+  pub(crate) fn set_drag_position(&self, position: FPoint) {
+    self.drag_position.set(position);
+  }
+
+  fn end_drag(&self) {
+    debug!("SeatManager::end_drag");
+    self.drag.set(ptr::null_mut());
+    self.drag_event_manager.borrow_mut().take();
+    self.on_drag_end.fire(());
+  }
+
+  /// If the window may receive input, which is only the case for windows of
+  /// the inhibiting client while input is inhibited
+  pub fn is_input_allowed(&self, window: &Window) -> bool {
     let exclusive_client = *self.exclusive_client.borrow();
     exclusive_client.is_null() || exclusive_client == window.wl_client()
   }
@@ -166,7 +348,22 @@ impl SeatEventHandler for Rc<SeatManager> {
     debug!("SeatManager::new_input");
     let device = Device::init(device_ptr);
 
-    self.on_new_device.fire(device);
+    let config = self.config_manager.config();
+    let seat_name = seat_name_for_device(&config, &device.name());
+    let seat_manager = self
+      .secondary_seats
+      .borrow()
+      .iter()
+      .filter_map(|seat_manager| seat_manager.upgrade())
+      .find(|seat_manager| seat_manager.name == seat_name)
+      .unwrap_or_else(|| self.clone());
+
+    debug!(
+      "SeatManager::new_input: Assigning {} to {}",
+      device.name(),
+      seat_manager.name
+    );
+    seat_manager.on_new_device.fire(device);
   }
   fn inhibit_activate(&self) {
     debug!("LayersEventHandler::inhibit_activate");
@@ -178,6 +375,66 @@ impl SeatEventHandler for Rc<SeatManager> {
     debug!("LayersEventHandler::inhibit_deactivate");
     self.set_exclusive_client(ptr::null_mut());
   }
+  fn request_start_drag(&self, event: *mut wlr_seat_request_start_drag_event) {
+    debug!("SeatManager::request_start_drag");
+    unsafe {
+      // Only accept drags started by a button press of the client with
+      // pointer focus
+      if wlr_seat_validate_pointer_grab_serial(self.seat, (*event).origin, (*event).serial) {
+        wlr_seat_start_pointer_drag(self.seat, (*event).drag, (*event).serial);
+      } else {
+        wlr_data_source_destroy((*(*event).drag).source);
+      }
+    }
+  }
+  fn start_drag(&self, drag: *mut wlr_drag) {
+    debug!("SeatManager::start_drag");
+    self.drag.set(drag);
+    let mut drag_event_manager = DragEventManager::new(Rc::downgrade(self));
+    unsafe {
+      drag_event_manager.destroy(&mut (*drag).events.destroy);
+    }
+    *self.drag_event_manager.borrow_mut() = Some(drag_event_manager);
+    self.on_drag_start.fire(());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_routes_devices_to_configured_seats() {
+    let mut config = Config::default();
+    config
+      .device_seats
+      .insert("keyboard-2".to_string(), "seat1".to_string());
+
+    assert_eq!(seat_name_for_device(&config, "keyboard-1"), DEFAULT_SEAT);
+    assert_eq!(seat_name_for_device(&config, "keyboard-2"), "seat1");
+  }
+
+  #[test]
+  fn it_reports_when_inhibit_is_released() {
+    let seat_manager = SeatManager::mock(ptr::null_mut(), ptr::null_mut());
+    let changes = Rc::new(RefCell::new(vec![]));
+    seat_manager
+      .on_inhibit_changed()
+      .subscribe(listener!(changes => move |inhibited| {
+        changes.borrow_mut().push(*inhibited);
+      }));
+
+    let client = ptr::NonNull::<wl_client>::dangling().as_ptr();
+    *seat_manager.exclusive_client.borrow_mut() = client;
+    assert!(seat_manager.is_inhibited());
+    assert_eq!(seat_manager.inhibiting_client(), Some(client));
+
+    seat_manager.set_exclusive_client(ptr::null_mut());
+    seat_manager.set_exclusive_client(ptr::null_mut());
+    assert!(!seat_manager.is_inhibited());
+    assert_eq!(seat_manager.inhibiting_client(), None);
+    assert_eq!(*changes.borrow(), vec![false]);
+  }
 }
 
 #[cfg(test)]
@@ -0,0 +1,203 @@
+use crate::event::Event;
+use crate::input::device::{Device, DeviceType};
+use crate::input::seat::SeatManager;
+use log::debug;
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use wlroots_sys::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwitchType {
+  /// The lid of a laptop, on when the lid is closed
+  Lid,
+  /// On when a convertible is folded into tablet mode
+  TabletMode,
+  Other,
+}
+
+impl SwitchType {
+  pub fn from_raw(switch_type: wlr_switch_type) -> SwitchType {
+    match switch_type {
+      t if t == wlr_switch_type_WLR_SWITCH_TYPE_LID => SwitchType::Lid,
+      t if t == wlr_switch_type_WLR_SWITCH_TYPE_TABLET_MODE => SwitchType::TabletMode,
+      _ => SwitchType::Other,
+    }
+  }
+}
+
+/// The state of a switch after a toggle event, `previous` is the state
+/// before the event
+fn next_state(previous: bool, state: wlr_switch_state) -> bool {
+  match state {
+    s if s == wlr_switch_state_WLR_SWITCH_STATE_ON => true,
+    s if s == wlr_switch_state_WLR_SWITCH_STATE_OFF => false,
+    _ => !previous,
+  }
+}
+
+pub struct Switch {
+  switch_manager: Weak<SwitchManager>,
+  device: Rc<Device>,
+
+  event_manager: RefCell<Option<Pin<Box<SwitchEventManager>>>>,
+}
+
+impl Switch {
+  fn init(switch_manager: Weak<SwitchManager>, device: Rc<Device>) -> Rc<Switch> {
+    debug!("Switch::init: {}", device.name());
+
+    let switch_ptr = match device.device_type() {
+      DeviceType::Switch(switch_ptr) => switch_ptr,
+      _ => panic!("Switch::init expects a switch device"),
+    };
+
+    let switch = Rc::new(Switch {
+      switch_manager,
+      device,
+
+      event_manager: RefCell::new(None),
+    });
+
+    let mut event_manager = SwitchEventManager::new(Rc::downgrade(&switch));
+    unsafe {
+      event_manager.toggle(&mut (*switch_ptr).events.toggle);
+    }
+    *switch.event_manager.borrow_mut() = Some(event_manager);
+
+    switch
+  }
+
+  pub fn device(&self) -> Rc<Device> {
+    self.device.clone()
+  }
+
+  fn toggle(&self, event: *const wlr_event_switch_toggle) {
+    let switch_manager = match self.switch_manager.upgrade() {
+      Some(switch_manager) => switch_manager,
+      None => return,
+    };
+    let (switch_type, state) = unsafe { ((*event).switch_type, (*event).switch_state) };
+    match SwitchType::from_raw(switch_type) {
+      SwitchType::Lid => {
+        let closed = next_state(switch_manager.lid_closed.get(), state);
+        if closed != switch_manager.lid_closed.get() {
+          switch_manager.lid_closed.set(closed);
+          if closed {
+            switch_manager.on_lid_close.fire(());
+          } else {
+            switch_manager.on_lid_open.fire(());
+          }
+        }
+      }
+      SwitchType::TabletMode => {
+        let tablet_mode = next_state(switch_manager.tablet_mode.get(), state);
+        if tablet_mode != switch_manager.tablet_mode.get() {
+          switch_manager.tablet_mode.set(tablet_mode);
+          switch_manager.on_tablet_mode.fire(tablet_mode);
+        }
+      }
+      SwitchType::Other => {}
+    }
+  }
+}
+
+wayland_listener!(
+  SwitchEventManager,
+  Weak<Switch>,
+  [
+    toggle => toggle_func: |this: &mut SwitchEventManager, data: *mut libc::c_void,| unsafe {
+      if let Some(handler) = this.data.upgrade() {
+        handler.toggle(data as _);
+      }
+    };
+  ]
+);
+
+/// Tracks switch devices like the lid of a laptop or the tablet mode switch
+/// of a convertible
+///
+/// A compositor can for example disable the internal output when the lid
+/// is closed.
+pub struct SwitchManager {
+  switches: RefCell<Vec<Rc<Switch>>>,
+  lid_closed: Cell<bool>,
+  tablet_mode: Cell<bool>,
+
+  on_lid_close: Event<()>,
+  on_lid_open: Event<()>,
+  on_tablet_mode: Event<bool>,
+}
+
+impl SwitchManager {
+  pub(crate) fn init(seat_manager: Rc<SeatManager>) -> Rc<SwitchManager> {
+    let switch_manager = Rc::new(SwitchManager {
+      switches: RefCell::new(vec![]),
+      lid_closed: Cell::new(false),
+      tablet_mode: Cell::new(false),
+
+      on_lid_close: Event::default(),
+      on_lid_open: Event::default(),
+      on_tablet_mode: Event::default(),
+    });
+
+    seat_manager
+      .on_new_device
+      .subscribe(listener!(switch_manager => move |device| {
+        if let DeviceType::Switch(_) = device.device_type() {
+          device.on_destroy.then(listener!(device, switch_manager => move || {
+            switch_manager
+              .switches
+              .borrow_mut()
+              .retain(|switch| switch.device.deref() != device.deref());
+          }));
+
+          let switch = Switch::init(Rc::downgrade(&switch_manager), device.clone());
+          switch_manager.switches.borrow_mut().push(switch);
+        }
+      }));
+
+    switch_manager
+  }
+
+  pub fn switches(&self) -> Vec<Rc<Switch>> {
+    self.switches.borrow().clone()
+  }
+
+  /// If the lid is closed, as last reported by a lid switch
+  pub fn is_lid_closed(&self) -> bool {
+    self.lid_closed.get()
+  }
+
+  /// If the device is in tablet mode, as last reported by a tablet mode
+  /// switch
+  pub fn is_tablet_mode(&self) -> bool {
+    self.tablet_mode.get()
+  }
+
+  pub fn on_lid_close(&self) -> &Event<()> {
+    &self.on_lid_close
+  }
+  pub fn on_lid_open(&self) -> &Event<()> {
+    &self.on_lid_open
+  }
+  /// Fired with the new state when tablet mode is entered or left
+  pub fn on_tablet_mode(&self) -> &Event<bool> {
+    &self.on_tablet_mode
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_toggles_switch_state() {
+    assert!(next_state(false, wlr_switch_state_WLR_SWITCH_STATE_ON));
+    assert!(next_state(true, wlr_switch_state_WLR_SWITCH_STATE_ON));
+    assert!(!next_state(true, wlr_switch_state_WLR_SWITCH_STATE_OFF));
+    assert!(next_state(false, wlr_switch_state_WLR_SWITCH_STATE_TOGGLE));
+    assert!(!next_state(true, wlr_switch_state_WLR_SWITCH_STATE_TOGGLE));
+  }
+}
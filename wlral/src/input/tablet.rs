@@ -0,0 +1,340 @@
+use crate::geometry::{FDisplacement, FPoint};
+use crate::input::cursor::CursorManager;
+use crate::input::device::{Device, DeviceType};
+use crate::input::event_filter::{EventFilter, EventFilterManager};
+use crate::input::events::*;
+use crate::input::seat::SeatManager;
+use crate::{output_manager::OutputManager, window_manager::WindowManager};
+use log::debug;
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+use wayland_sys::server::wl_display;
+use wlroots_sys::*;
+
+struct Tablet {
+  device: Rc<Device>,
+  tablet_v2: *mut wlr_tablet_v2_tablet,
+}
+
+/// Handles drawing tablets and their tools, like a stylus
+///
+/// Tablets are attached to the cursor so that tools move it and are mapped
+/// to outputs the same way as pointers. Events that are not handled by an
+/// event filter are sent to the surface under the tool using the tablet
+/// protocol, if the client supports it.
+pub struct TabletManager {
+  output_manager: Rc<OutputManager>,
+  window_manager: Rc<WindowManager>,
+  seat_manager: Rc<SeatManager>,
+  cursor_manager: Rc<CursorManager>,
+  event_filter_manager: Rc<EventFilterManager>,
+  tablet_manager: *mut wlr_tablet_manager_v2,
+  tablets: RefCell<Vec<Tablet>>,
+
+  event_manager: RefCell<Option<Pin<Box<TabletEventManager>>>>,
+}
+
+impl TabletManager {
+  pub(crate) fn init(
+    output_manager: Rc<OutputManager>,
+    window_manager: Rc<WindowManager>,
+    seat_manager: Rc<SeatManager>,
+    cursor_manager: Rc<CursorManager>,
+    event_filter_manager: Rc<EventFilterManager>,
+    display: *mut wl_display,
+  ) -> Rc<TabletManager> {
+    debug!("TabletManager::init");
+
+    let tablet_manager = Rc::new(TabletManager {
+      output_manager: output_manager.clone(),
+      window_manager,
+      seat_manager: seat_manager.clone(),
+      cursor_manager: cursor_manager.clone(),
+      event_filter_manager,
+      tablet_manager: unsafe { wlr_tablet_v2_create(display) },
+      tablets: RefCell::new(vec![]),
+
+      event_manager: RefCell::new(None),
+    });
+
+    output_manager
+      .on_new_output()
+      .subscribe(listener!(tablet_manager => move |output| {
+        tablet_manager.refresh_device_mappings();
+        output.on_destroy.then(listener!(tablet_manager => move || {
+          tablet_manager.refresh_device_mappings();
+        }));
+      }));
+
+    seat_manager
+      .on_new_device
+      .subscribe(listener!(tablet_manager => move |device| {
+        if let DeviceType::Tablet(_) = device.device_type() {
+          device.on_destroy.then(listener!(tablet_manager, device => move || {
+            debug!("TabletManager::destroy_input_device");
+            tablet_manager
+              .tablets
+              .borrow_mut()
+              .retain(|tablet| tablet.device.deref() != device.deref());
+          }));
+
+          debug!("TabletManager::add_input_device: {}", device.name());
+
+          let tablet_v2 = unsafe {
+            wlr_cursor_attach_input_device(
+              tablet_manager.cursor_manager.raw_cursor(),
+              device.raw_ptr(),
+            );
+            wlr_tablet_create(
+              tablet_manager.tablet_manager,
+              tablet_manager.seat_manager.raw_seat(),
+              device.raw_ptr(),
+            )
+          };
+          tablet_manager.tablets.borrow_mut().push(Tablet {
+            device: device.clone(),
+            tablet_v2,
+          });
+
+          tablet_manager.refresh_device_mappings();
+        }
+      }));
+
+    let mut event_manager = TabletEventManager::new(tablet_manager.clone());
+    unsafe {
+      let cursor = cursor_manager.raw_cursor();
+      event_manager.tablet_tool_axis(&mut (*cursor).events.tablet_tool_axis);
+      event_manager.tablet_tool_proximity(&mut (*cursor).events.tablet_tool_proximity);
+      event_manager.tablet_tool_tip(&mut (*cursor).events.tablet_tool_tip);
+      event_manager.tablet_tool_button(&mut (*cursor).events.tablet_tool_button);
+    }
+    *tablet_manager.event_manager.borrow_mut() = Some(event_manager);
+
+    tablet_manager
+  }
+
+  /// If there are any drawing tablets attached
+  pub fn has_tablet_device(&self) -> bool {
+    !self.tablets.borrow().is_empty()
+  }
+
+  fn refresh_device_mappings(&self) {
+    debug!("TabletManager::refresh_device_mappings");
+    for tablet in self.tablets.borrow().iter() {
+      if let Some(output_name) = tablet.device.output_name() {
+        for output in self.output_manager.outputs().iter() {
+          if output_name == output.name() {
+            unsafe {
+              wlr_cursor_map_input_to_output(
+                self.cursor_manager.raw_cursor(),
+                tablet.device.raw_ptr(),
+                output.raw_ptr(),
+              );
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn tablet_v2(&self, device: *mut wlr_input_device) -> Option<*mut wlr_tablet_v2_tablet> {
+    self
+      .tablets
+      .borrow()
+      .iter()
+      .find(|tablet| tablet.device.raw_ptr() == device)
+      .map(|tablet| tablet.tablet_v2)
+  }
+
+  /// The protocol object of a tool, created when the tool is first used
+  unsafe fn tool_v2(&self, tool: *mut wlr_tablet_tool) -> *mut wlr_tablet_v2_tablet_tool {
+    if (*tool).data.is_null() {
+      (*tool).data = wlr_tablet_tool_create(self.tablet_manager, self.seat_manager.raw_seat(), tool)
+        as *mut libc::c_void;
+    }
+    (*tool).data as *mut wlr_tablet_v2_tablet_tool
+  }
+
+  /// Moves the cursor to the tool. Axes that are NaN keep their position.
+  fn warp(&self, device: *mut wlr_input_device, x: f64, y: f64) {
+    unsafe {
+      wlr_cursor_warp_absolute(self.cursor_manager.raw_cursor(), device, x, y);
+    }
+  }
+
+  /// Gives tablet focus to the surface under the cursor and sends the
+  /// position of the tool to it
+  ///
+  /// Surfaces of clients that do not support the tablet protocol lose focus.
+  fn notify_motion(&self, device: *mut wlr_input_device, tool: *mut wlr_tablet_tool) {
+    let tablet_v2 = match self.tablet_v2(device) {
+      Some(tablet_v2) => tablet_v2,
+      None => return,
+    };
+    let position = self.cursor_manager.position();
+    let window = self
+      .window_manager
+      .window_buffer_at(&position.into())
+      .filter(|window| self.seat_manager.is_input_allowed(window));
+
+    unsafe {
+      let tool_v2 = self.tool_v2(tool);
+      match window {
+        Some(window) if wlr_surface_accepts_tablet_v2(tablet_v2, window.wlr_surface()) => {
+          let surface_position = self.window_manager.hit_test_point(&window, position)
+            - FPoint::from(window.buffer_extents().top_left()).as_displacement();
+
+          if (*tool_v2).focused_surface != window.wlr_surface() {
+            wlr_send_tablet_v2_tablet_tool_proximity_in(tool_v2, tablet_v2, window.wlr_surface());
+          }
+          wlr_send_tablet_v2_tablet_tool_motion(tool_v2, surface_position.x, surface_position.y);
+        }
+        _ => wlr_send_tablet_v2_tablet_tool_proximity_out(tool_v2),
+      }
+    }
+  }
+}
+
+pub(crate) trait TabletEventHandler {
+  fn tablet_tool_axis(&self, event: *const wlr_event_tablet_tool_axis);
+  fn tablet_tool_proximity(&self, event: *const wlr_event_tablet_tool_proximity);
+  fn tablet_tool_tip(&self, event: *const wlr_event_tablet_tool_tip);
+  fn tablet_tool_button(&self, event: *const wlr_event_tablet_tool_button);
+}
+
+impl TabletEventHandler for Rc<TabletManager> {
+  fn tablet_tool_axis(&self, event: *const wlr_event_tablet_tool_axis) {
+    let previous_position = self.cursor_manager.position();
+    unsafe {
+      let axes = (*event).updated_axes;
+      let x = if axes & wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_X != 0 {
+        (*event).x
+      } else {
+        std::f64::NAN
+      };
+      let y = if axes & wlr_tablet_tool_axes_WLR_TABLET_TOOL_AXIS_Y != 0 {
+        (*event).y
+      } else {
+        std::f64::NAN
+      };
+      self.warp((*event).device, x, y);
+    }
+    let delta: FDisplacement = self.cursor_manager.position() - previous_position;
+    let event = unsafe { TabletToolAxisEvent::from_ptr(self.cursor_manager.clone(), event, delta) };
+
+    let handled = self
+      .event_filter_manager
+      .handle_tablet_tool_axis_event(&event);
+
+    if !handled {
+      if event.moved() {
+        self.notify_motion(event.raw_device(), event.raw_tool());
+      }
+      unsafe {
+        let tool_v2 = self.tool_v2(event.raw_tool());
+        if let Some(pressure) = event.pressure() {
+          wlr_send_tablet_v2_tablet_tool_pressure(tool_v2, pressure);
+        }
+        if let Some(distance) = event.distance() {
+          wlr_send_tablet_v2_tablet_tool_distance(tool_v2, distance);
+        }
+        if let Some((tilt_x, tilt_y)) = event.tilt() {
+          wlr_send_tablet_v2_tablet_tool_tilt(tool_v2, tilt_x, tilt_y);
+        }
+      }
+    }
+  }
+
+  fn tablet_tool_proximity(&self, event: *const wlr_event_tablet_tool_proximity) {
+    unsafe {
+      self.warp((*event).device, (*event).x, (*event).y);
+    }
+    let event = unsafe { TabletToolProximityEvent::from_ptr(self.cursor_manager.clone(), event) };
+
+    let handled = self
+      .event_filter_manager
+      .handle_tablet_tool_proximity_event(&event);
+
+    if !handled {
+      match event.state() {
+        TabletToolProximityState::In => {
+          self.notify_motion(event.raw_device(), event.raw_tool());
+        }
+        TabletToolProximityState::Out => unsafe {
+          wlr_send_tablet_v2_tablet_tool_proximity_out(self.tool_v2(event.raw_tool()));
+        },
+      }
+    }
+  }
+
+  fn tablet_tool_tip(&self, event: *const wlr_event_tablet_tool_tip) {
+    unsafe {
+      self.warp((*event).device, (*event).x, (*event).y);
+    }
+    let event = unsafe { TabletToolTipEvent::from_ptr(self.cursor_manager.clone(), event) };
+
+    let handled = self
+      .event_filter_manager
+      .handle_tablet_tool_tip_event(&event);
+
+    if !handled {
+      match event.state() {
+        TabletToolTipState::Down => {
+          let window = self
+            .window_manager
+            .window_buffer_at(&self.cursor_manager.position().into());
+
+          if let Some(window) = window {
+            if window.can_receive_focus() {
+              let _ = self
+                .window_manager
+                .focus_window_on_seat(window, &self.seat_manager);
+            }
+          }
+
+          unsafe {
+            wlr_send_tablet_v2_tablet_tool_down(self.tool_v2(event.raw_tool()));
+          }
+        }
+        TabletToolTipState::Up => unsafe {
+          wlr_send_tablet_v2_tablet_tool_up(self.tool_v2(event.raw_tool()));
+        },
+      }
+    }
+  }
+
+  fn tablet_tool_button(&self, event: *const wlr_event_tablet_tool_button) {
+    unsafe {
+      wlr_send_tablet_v2_tablet_tool_button(
+        self.tool_v2((*event).tool),
+        (*event).button,
+        (*event).state,
+      );
+    }
+  }
+}
+
+wayland_listener!(
+  TabletEventManager,
+  Rc<TabletManager>,
+  [
+    tablet_tool_axis => tablet_tool_axis_func: |this: &mut TabletEventManager, data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.tablet_tool_axis(data as _)
+    };
+    tablet_tool_proximity => tablet_tool_proximity_func: |this: &mut TabletEventManager, data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.tablet_tool_proximity(data as _)
+    };
+    tablet_tool_tip => tablet_tool_tip_func: |this: &mut TabletEventManager, data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.tablet_tool_tip(data as _)
+    };
+    tablet_tool_button => tablet_tool_button_func: |this: &mut TabletEventManager, data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.tablet_tool_button(data as _)
+    };
+  ]
+);
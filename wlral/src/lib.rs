@@ -1,15 +1,20 @@
 pub mod macros;
 
+pub mod animation;
 pub mod compositor;
 pub mod config;
 pub mod event;
 pub mod geometry;
 pub mod input;
+pub mod magnifier;
 pub mod output;
 pub mod output_management_protocol;
 pub mod output_manager;
+pub mod overview;
 pub mod shell;
 pub mod surface;
+pub mod texture;
+pub mod wayland_fd;
 pub mod wayland_timer;
 pub mod window;
 pub mod window_management_policy;
@@ -17,7 +22,15 @@ pub mod window_manager;
 
 #[cfg(test)]
 pub mod test_util {
+  use crate::{
+    config::ConfigManager,
+    input::seat::SeatManager,
+    output_manager::OutputManager,
+    window_management_policy::WmPolicyManager,
+    window_manager::{WindowManager, WindowManagerExt},
+  };
   use std::ptr;
+  use std::rc::Rc;
   use wayland_sys::common::wl_list;
   use wayland_sys::ffi_dispatch;
   use wayland_sys::server::{
@@ -25,6 +38,48 @@ pub mod test_util {
     wl_signal, WAYLAND_SERVER_HANDLE,
   };
 
+  /// The managers that windows are created with, connected like the
+  /// compositor does but without a display or backend
+  ///
+  /// Keep this alive for as long as the windows are used, as the window
+  /// manager only holds the output manager weakly.
+  pub(crate) struct MockManagers {
+    pub(crate) config_manager: Rc<ConfigManager>,
+    pub(crate) wm_policy_manager: Rc<WmPolicyManager>,
+    pub(crate) seat_manager: Rc<SeatManager>,
+    pub(crate) window_manager: Rc<WindowManager>,
+    pub(crate) output_manager: Rc<OutputManager>,
+  }
+
+  impl MockManagers {
+    pub(crate) fn new() -> MockManagers {
+      MockManagers::with_seat_manager(SeatManager::mock(ptr::null_mut(), ptr::null_mut()))
+    }
+
+    pub(crate) fn with_seat_manager(seat_manager: Rc<SeatManager>) -> MockManagers {
+      let config_manager = Rc::new(ConfigManager::default());
+      let wm_policy_manager = Rc::new(WmPolicyManager::new());
+      let window_manager = Rc::new(WindowManager::init(
+        wm_policy_manager.clone(),
+        seat_manager.clone(),
+        ptr::null_mut(),
+      ));
+      let output_manager = OutputManager::mock(
+        config_manager.clone(),
+        wm_policy_manager.clone(),
+        window_manager.clone(),
+      );
+      window_manager.set_output_manager(output_manager.clone());
+      MockManagers {
+        config_manager,
+        wm_policy_manager,
+        seat_manager,
+        window_manager,
+        output_manager,
+      }
+    }
+  }
+
   pub fn new_wl_list() -> wl_list {
     wl_list {
       next: ptr::null_mut(),
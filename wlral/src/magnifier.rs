@@ -0,0 +1,110 @@
+use crate::geometry::FPoint;
+use crate::input::cursor::CursorManager;
+use crate::output::Output;
+use crate::output_manager::OutputManager;
+use log::debug;
+use std::cell::Cell;
+use std::rc::{Rc, Weak};
+
+/// The largest supported zoom factor
+pub const MAX_ZOOM: f32 = 16.0;
+/// How much `zoom_in` and `zoom_out` change the zoom factor
+const ZOOM_STEP: f32 = 1.25;
+
+/// Magnifies the output under the cursor, for accessibility
+///
+/// The output is scaled around the cursor when rendered, without changing
+/// client buffers or the layout. The point under the cursor stays in place
+/// so that input still lines up with what is shown at the cursor, and
+/// moving the cursor pans the magnified area.
+pub struct Magnifier {
+  cursor_manager: Rc<CursorManager>,
+  output_manager: Rc<OutputManager>,
+  enabled: Cell<bool>,
+  zoom: Cell<f32>,
+}
+
+impl Magnifier {
+  pub(crate) fn init(
+    output_manager: Rc<OutputManager>,
+    cursor_manager: Rc<CursorManager>,
+  ) -> Rc<Magnifier> {
+    debug!("Magnifier::init");
+
+    let magnifier = Rc::new(Magnifier {
+      cursor_manager,
+      output_manager: output_manager.clone(),
+      enabled: Cell::new(false),
+      zoom: Cell::new(2.0),
+    });
+
+    for output in output_manager.outputs().iter() {
+      magnifier.follow_output(output);
+    }
+    let weak_magnifier = Rc::downgrade(&magnifier);
+    output_manager
+      .on_new_output()
+      .subscribe(Box::new(move |output| {
+        if let Some(magnifier) = weak_magnifier.upgrade() {
+          magnifier.follow_output(&output);
+        }
+      }));
+
+    magnifier
+  }
+
+  /// Updates the magnification of the output before each frame is rendered
+  fn follow_output(self: &Rc<Self>, output: &Rc<Output>) {
+    let magnifier: Weak<Magnifier> = Rc::downgrade(self);
+    let weak_output = Rc::downgrade(output);
+    output.on_frame().subscribe(Box::new(move |_| {
+      if let (Some(magnifier), Some(output)) = (magnifier.upgrade(), weak_output.upgrade()) {
+        magnifier.update_output(&output);
+      }
+    }));
+  }
+
+  fn update_output(&self, output: &Output) {
+    let position = self.cursor_manager.position();
+    let magnification = if self.enabled.get() && output.extents().contains(&position.into()) {
+      let top_left = FPoint::from(output.top_left());
+      let scale = output.scale() as f64;
+      let focus = FPoint {
+        x: (position.x - top_left.x) * scale,
+        y: (position.y - top_left.y) * scale,
+      };
+      Some((focus, self.zoom.get()))
+    } else {
+      None
+    };
+    output.magnification.set(magnification);
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.get()
+  }
+
+  pub fn set_enabled(&self, enabled: bool) {
+    self.enabled.set(enabled);
+    for output in self.output_manager.outputs().iter() {
+      self.update_output(output);
+    }
+  }
+
+  pub fn zoom(&self) -> f32 {
+    self.zoom.get()
+  }
+
+  /// Sets the zoom factor, which is clamped between 1.0 and `MAX_ZOOM`
+  pub fn set_zoom(&self, zoom: f32) {
+    self.zoom.set(zoom.max(1.0).min(MAX_ZOOM));
+  }
+
+  pub fn zoom_in(&self) {
+    self.set_zoom(self.zoom() * ZOOM_STEP);
+  }
+
+  pub fn zoom_out(&self) {
+    self.set_zoom(self.zoom() / ZOOM_STEP);
+  }
+}
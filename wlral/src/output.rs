@@ -1,17 +1,301 @@
-use crate::geometry::{Displacement, Point, Rectangle, Size, TransformMatrix};
+use crate::geometry::{Displacement, FPoint, Point, Rectangle, Size, TransformMatrix};
+use crate::texture::Texture;
 use crate::window::Window;
 use crate::window_management_policy::WmPolicyManager;
 use crate::{
   event::{Event, EventOnce},
   window_manager::WindowManager,
 };
-use std::cell::RefCell;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::ptr;
 use std::rc::{Rc, Weak};
-use std::{borrow::Cow, ffi::CStr, fmt::Debug, time::Instant};
+use std::{
+  borrow::Cow,
+  ffi::CStr,
+  fmt::Debug,
+  time::{Duration, Instant},
+};
 use wlroots_sys::*;
 
+/// How a background image is placed on an output
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundMode {
+  /// Scale to cover the whole output, cropping what does not fit
+  Fill,
+  /// Scale to fit inside the output, keeping the aspect ratio
+  Fit,
+  /// Keep the original size, centered on the output
+  Center,
+  /// Keep the original size, repeated from the top left corner
+  Tile,
+}
+
+impl Default for BackgroundMode {
+  fn default() -> Self {
+    BackgroundMode::Fill
+  }
+}
+
+/// Calculates where to draw a background image of `image` size on an output
+/// of `output` size, in output local pixels.
+fn background_boxes(mode: BackgroundMode, output: Size, image: Size) -> Vec<Rectangle> {
+  if image.width <= 0 || image.height <= 0 || output.width <= 0 || output.height <= 0 {
+    return vec![];
+  }
+
+  let centered = |size: Size| Rectangle {
+    top_left: Point {
+      x: (output.width - size.width) / 2,
+      y: (output.height - size.height) / 2,
+    },
+    size,
+  };
+  let scale_x = output.width as f32 / image.width as f32;
+  let scale_y = output.height as f32 / image.height as f32;
+
+  match mode {
+    BackgroundMode::Fill => vec![centered(image * scale_x.max(scale_y))],
+    BackgroundMode::Fit => vec![centered(image * scale_x.min(scale_y))],
+    BackgroundMode::Center => vec![centered(image)],
+    BackgroundMode::Tile => {
+      let mut boxes = vec![];
+      let mut y = 0;
+      while y < output.height {
+        let mut x = 0;
+        while x < output.width {
+          boxes.push(Rectangle {
+            top_left: Point { x, y },
+            size: image,
+          });
+          x += image.width;
+        }
+        y += image.height;
+      }
+      boxes
+    }
+  }
+}
+
+/// The configuration of an output, stored by an `OutputProfileStore` so that
+/// it can be restored when the output is connected again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputProfile {
+  /// The width of the mode in pixels
+  pub width: i32,
+  /// The height of the mode in pixels
+  pub height: i32,
+  /// The refresh rate of the mode in mHz
+  pub refresh: i32,
+  /// The position in the output layout
+  pub x: i32,
+  pub y: i32,
+  pub scale: f32,
+}
+
+/// The layout of the subpixels of an output
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Subpixel {
+  Unknown,
+  None,
+  HorizontalRgb,
+  HorizontalBgr,
+  VerticalRgb,
+  VerticalBgr,
+}
+
+impl Subpixel {
+  fn from_raw(subpixel: wl_output_subpixel) -> Subpixel {
+    match subpixel {
+      s if s == wl_output_subpixel_WL_OUTPUT_SUBPIXEL_NONE => Subpixel::None,
+      s if s == wl_output_subpixel_WL_OUTPUT_SUBPIXEL_HORIZONTAL_RGB => Subpixel::HorizontalRgb,
+      s if s == wl_output_subpixel_WL_OUTPUT_SUBPIXEL_HORIZONTAL_BGR => Subpixel::HorizontalBgr,
+      s if s == wl_output_subpixel_WL_OUTPUT_SUBPIXEL_VERTICAL_RGB => Subpixel::VerticalRgb,
+      s if s == wl_output_subpixel_WL_OUTPUT_SUBPIXEL_VERTICAL_BGR => Subpixel::VerticalBgr,
+      _ => Subpixel::Unknown,
+    }
+  }
+}
+
+/// How long an output is considered captured after the last captured
+/// frame, as clients capture one frame at a time
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn is_captured(last_captured_at: Option<Instant>, now: Instant) -> bool {
+  last_captured_at
+    .map(|captured_at| now.saturating_duration_since(captured_at) < CAPTURE_TIMEOUT)
+    .unwrap_or(false)
+}
+
+/// How far back frames are counted for the frames per second estimate
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// The box to render a surface of `surface_size`, in surface local
+/// coordinates, at `top_left`, in output local coordinates
+///
+/// wlroots already divides the committed buffer by the buffer scale of the
+/// client and applies the buffer transform when it computes
+/// `wlr_surface_state.width` and `height`, so only the output scale is left
+/// to apply here.
+fn render_box(top_left: Point, surface_size: Size, output_scale: f32) -> wlr_box {
+  Rectangle {
+    top_left: top_left * output_scale,
+    size: surface_size * output_scale,
+  }
+  .into()
+}
+
+/// Which of the windows, ordered back to front, are visible in `area`
+///
+/// Each window is given as its rendered extents and its opaque region. A
+/// window is hidden when it is outside of the area or when its part inside
+/// the area is covered by a single opaque rectangle of a window in front.
+fn visible_windows(area: &Rectangle, windows: &[(Rectangle, Vec<Rectangle>)]) -> Vec<bool> {
+  let mut opaque: Vec<&Rectangle> = vec![];
+  let mut visible = vec![false; windows.len()];
+  for (index, (extents, opaque_region)) in windows.iter().enumerate().rev() {
+    visible[index] = match extents.intersection(area) {
+      Some(extents) => !opaque
+        .iter()
+        .any(|rectangle| rectangle.contains_rectangle(&extents)),
+      None => false,
+    };
+    if visible[index] {
+      opaque.extend(opaque_region);
+    }
+  }
+  visible
+}
+
+/// Scales by `zoom` around `focus`, in output pixels, so that the focus
+/// point stays in place
+fn magnification_matrix(focus: FPoint, zoom: f32) -> TransformMatrix {
+  let (x, y) = (focus.x as f32, focus.y as f32);
+  TransformMatrix::translate(x, y)
+    * TransformMatrix::scale(zoom, zoom)
+    * TransformMatrix::translate(-x, -y)
+}
+
+/// The frames per second of frames started at `frame_times`, oldest first
+fn frames_per_second(frame_times: &VecDeque<Instant>) -> f64 {
+  match (frame_times.front(), frame_times.back()) {
+    (Some(first), Some(last)) if last > first => {
+      (frame_times.len() - 1) as f64 / last.duration_since(*first).as_secs_f64()
+    }
+    _ => 0.0,
+  }
+}
+
+/// The time since the previous frame started, or zero for the first frame
+fn frame_delta(frame_times: &VecDeque<Instant>, started_at: Instant) -> Duration {
+  frame_times
+    .back()
+    .map_or(Duration::from_secs(0), |previous| {
+      started_at.saturating_duration_since(*previous)
+    })
+}
+
+/// Draws custom content, like a status bar or a selection rectangle, into
+/// a frame of an output
+///
+/// Rectangles are in layout coordinates relative to the top left corner of
+/// the output and are magnified together with the windows. A context is
+/// only valid while the `on_render_overlay` listeners are called.
+pub struct RenderContext {
+  renderer: *mut wlr_renderer,
+  projection: TransformMatrix,
+  size: Size,
+  scale: f32,
+}
+
+impl RenderContext {
+  pub fn raw_renderer(&self) -> *mut wlr_renderer {
+    self.renderer
+  }
+
+  /// The size of the output in layout coordinates
+  pub fn size(&self) -> Size {
+    self.size
+  }
+
+  pub fn scale(&self) -> f32 {
+    self.scale
+  }
+
+  fn output_box(&self, rectangle: &Rectangle) -> wlr_box {
+    render_box(rectangle.top_left(), rectangle.size(), self.scale)
+  }
+
+  /// Fills the rectangle with an RGBA color
+  pub fn draw_rectangle(&self, rectangle: &Rectangle, color: [f32; 4]) {
+    let output_box = self.output_box(rectangle);
+    unsafe {
+      wlr_render_rect(
+        self.renderer,
+        &output_box,
+        color.as_ptr(),
+        self.projection.as_ptr(),
+      );
+    }
+  }
+
+  /// Draws the texture stretched to the rectangle
+  pub fn draw_texture(&self, texture: &Texture, rectangle: &Rectangle, alpha: f32) {
+    let output_box = self.output_box(rectangle);
+    let mut matrix = TransformMatrix::IDENTITY.clone();
+    unsafe {
+      wlr_matrix_project_box(
+        matrix.as_mut_ptr(),
+        &output_box,
+        wl_output_transform_WL_OUTPUT_TRANSFORM_NORMAL,
+        0.0,
+        self.projection.as_ptr(),
+      );
+      wlr_render_texture_with_matrix(self.renderer, texture.raw_ptr(), matrix.as_ptr(), alpha);
+    }
+  }
+}
+
+/// A frame that was rendered and committed to an output
+#[derive(Debug, Clone, Copy)]
+pub struct FrameEvent {
+  /// The monotonic timestamp sent to clients as the frame time
+  pub time: timespec,
+  /// The time since the previous frame of the output started, or zero for
+  /// the first frame
+  pub delta: Duration,
+}
+
+/// Statistics about a rendered frame, for performance debugging
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+  /// How long it took to render and commit the frame
+  pub render_duration: Duration,
+  /// If a client buffer was scanned out directly instead of being composited
+  pub direct_scanout: bool,
+  /// The number of pixels that were redrawn
+  pub damage_area: i64,
+}
+
+/// The current time of the monotonic clock, which wlroots and clients use
+/// for frame timestamps
+pub fn monotonic_now() -> timespec {
+  let mut now = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+  };
+  unsafe {
+    libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+  }
+  timespec {
+    tv_sec: now.tv_sec as _,
+    tv_nsec: now.tv_nsec as _,
+  }
+}
+
 #[derive(Debug)]
 pub struct Output {
   pub(crate) wm_policy_manager: Rc<WmPolicyManager>,
@@ -20,11 +304,27 @@ pub struct Output {
   pub(crate) renderer: *mut wlr_renderer,
   pub(crate) output_layout: *mut wlr_output_layout,
   pub(crate) output: *mut wlr_output,
-  pub(crate) created_at: Instant,
+  pub(crate) screencopy_manager: *mut wlr_screencopy_manager_v1,
+  pub(crate) export_dmabuf_manager: *mut wlr_export_dmabuf_manager_v1,
   pub(crate) background_color: RefCell<[f32; 3]>,
+  pub(crate) background_image: RefCell<Option<Rc<Texture>>>,
+  pub(crate) background_mode: RefCell<BackgroundMode>,
 
   pub(crate) on_destroy: EventOnce<()>,
   pub(crate) on_frame: Event<()>,
+  pub(crate) on_frame_stats: Event<FrameStats>,
+  pub(crate) on_present: Event<FrameEvent>,
+  pub(crate) on_render_overlay: Event<RenderContext>,
+  pub(crate) last_frame_duration: Cell<Option<Duration>>,
+  /// When recent frames started, oldest first
+  pub(crate) frame_times: RefCell<VecDeque<Instant>>,
+  /// The focus point, in output pixels, and zoom factor of the magnifier
+  pub(crate) magnification: Cell<Option<(FPoint, f32)>>,
+  pub(crate) last_captured_at: Cell<Option<Instant>>,
+  pub(crate) captured: Cell<bool>,
+  pub(crate) on_capture_started: Event<()>,
+  pub(crate) on_capture_stopped: Event<()>,
+  pub(crate) on_frame_exported: Event<()>,
 
   pub(crate) event_manager: RefCell<Option<Pin<Box<OutputEventManager>>>>,
 }
@@ -68,6 +368,56 @@ impl Output {
     Ok(())
   }
 
+  /// The current mode, position and scale of the output
+  pub fn profile(&self) -> OutputProfile {
+    let top_left = self.top_left();
+    unsafe {
+      OutputProfile {
+        width: (*self.output).width,
+        height: (*self.output).height,
+        refresh: (*self.output).refresh,
+        x: top_left.x,
+        y: top_left.y,
+        scale: (*self.output).scale,
+      }
+    }
+  }
+
+  /// Sets the mode and scale of the profile on the output. The position is
+  /// set by the output layout.
+  pub(crate) fn apply_profile(&self, profile: &OutputProfile) -> Result<(), ()> {
+    unsafe {
+      self.set_pending_mode(profile.width, profile.height, profile.refresh);
+      wlr_output_set_scale(self.output, profile.scale);
+
+      wlr_output_enable(self.output, true);
+      if !wlr_output_commit(self.output) {
+        return Err(());
+      }
+    }
+    Ok(())
+  }
+
+  /// Sets the mode with the passed size and refresh rate if the output has
+  /// one, otherwise a custom mode. Takes effect on the next commit.
+  pub(crate) unsafe fn set_pending_mode(&self, width: i32, height: i32, refresh: i32) {
+    let mut matching_mode = ptr::null_mut();
+    wl_list_for_each!(
+      (*self.output).modes,
+      link,
+      (mode: wlr_output_mode) => {
+        if (*mode).width == width && (*mode).height == height && (*mode).refresh == refresh {
+          matching_mode = mode;
+        }
+      }
+    );
+    if matching_mode.is_null() {
+      wlr_output_set_custom_mode(self.output, width, height, refresh);
+    } else {
+      wlr_output_set_mode(self.output, matching_mode);
+    }
+  }
+
   pub fn top_left(&self) -> Point {
     let mut x = 0.0;
     let mut y = 0.0;
@@ -96,6 +446,23 @@ impl Output {
     }
   }
 
+  /// Converts a point in layout coordinates to coordinates relative to the
+  /// top left of the output
+  pub fn global_to_local(&self, point: Point) -> Point {
+    point - self.top_left().as_displacement()
+  }
+
+  /// Converts a point relative to the top left of the output to layout
+  /// coordinates
+  pub fn local_to_global(&self, point: Point) -> Point {
+    point + self.top_left().as_displacement()
+  }
+
+  /// If the point in layout coordinates is on the output
+  pub fn contains_global(&self, point: &Point) -> bool {
+    self.extents().contains(point)
+  }
+
   pub fn scale(&self) -> f32 {
     unsafe { (*self.output).scale }
   }
@@ -104,6 +471,14 @@ impl Output {
     unsafe { TransformMatrix((*self.output).transform_matrix) }
   }
 
+  /// The transform matrix with the magnification of the magnifier applied
+  fn projection(&self) -> TransformMatrix {
+    match self.magnification.get() {
+      Some((focus, zoom)) => self.transform_matrix() * magnification_matrix(focus, zoom),
+      None => self.transform_matrix(),
+    }
+  }
+
   pub fn name(&self) -> Cow<str> {
     let name: &CStr = unsafe { CStr::from_ptr((*self.output).name.as_ptr()) };
     name.to_string_lossy()
@@ -124,12 +499,203 @@ impl Output {
     model.to_string_lossy()
   }
 
+  pub fn serial(&self) -> Cow<str> {
+    let serial: &CStr = unsafe { CStr::from_ptr((*self.output).serial.as_ptr()) };
+    serial.to_string_lossy()
+  }
+
+  /// The physical size of the output in millimeters, zero if unknown
+  ///
+  /// This and the subpixel layout are advertised to clients in the
+  /// wl_output geometry event.
+  pub fn physical_size(&self) -> Size {
+    unsafe {
+      Size {
+        width: (*self.output).phys_width,
+        height: (*self.output).phys_height,
+      }
+    }
+  }
+
+  pub fn subpixel(&self) -> Subpixel {
+    Subpixel::from_raw(unsafe { (*self.output).subpixel })
+  }
+
+  /// The refresh rate of the current mode in mHz, zero if unknown
+  pub fn refresh_rate_mhz(&self) -> i32 {
+    unsafe { (*self.output).refresh }
+  }
+
+  /// Sets an image to render below all windows, the background color is
+  /// used when no image is set
+  pub fn set_background_image(&self, texture: Option<Rc<Texture>>) {
+    *self.background_image.borrow_mut() = texture;
+  }
+
+  pub fn background_mode(&self) -> BackgroundMode {
+    *self.background_mode.borrow()
+  }
+
+  pub fn set_background_mode(&self, mode: BackgroundMode) {
+    *self.background_mode.borrow_mut() = mode;
+  }
+
+  /// Loads a PNG file as a texture that can be used with this output
+  pub fn load_texture(&self, path: &std::path::Path) -> Result<Rc<Texture>, ()> {
+    Texture::from_png(self.renderer, path).map(Rc::new)
+  }
+
   pub fn on_destroy(&self) -> &EventOnce<()> {
     &self.on_destroy
   }
   pub fn on_frame(&self) -> &Event<()> {
     &self.on_frame
   }
+  /// Fired after each frame is rendered
+  pub fn on_frame_stats(&self) -> &Event<FrameStats> {
+    &self.on_frame_stats
+  }
+  /// Fired after each frame is committed, with the time since the previous
+  /// frame, for running animations at the refresh rate of the output
+  pub fn on_present(&self) -> &Event<FrameEvent> {
+    &self.on_present
+  }
+  /// Fired while a frame is rendered, after the windows but before software
+  /// cursors, to draw custom content on top of the windows
+  pub fn on_render_overlay(&self) -> &Event<RenderContext> {
+    &self.on_render_overlay
+  }
+
+  /// How long it took to render and commit the last frame
+  pub fn last_frame_duration(&self) -> Option<Duration> {
+    self.last_frame_duration.get()
+  }
+
+  /// The number of frames rendered per second, over the most recent second
+  /// of frames
+  pub fn frames_per_second(&self) -> f64 {
+    frames_per_second(&self.frame_times.borrow())
+  }
+
+  fn record_frame_start(&self, started_at: Instant) {
+    let mut frame_times = self.frame_times.borrow_mut();
+    while frame_times.front().map_or(false, |time| {
+      started_at.saturating_duration_since(*time) > FPS_WINDOW
+    }) {
+      frame_times.pop_front();
+    }
+    frame_times.push_back(started_at);
+  }
+
+  /// If a client is capturing the content of the output, with screencopy or
+  /// DMA-BUF export, for example to record the screen
+  pub fn is_being_captured(&self) -> bool {
+    self.captured.get()
+  }
+
+  pub fn on_capture_started(&self) -> &Event<()> {
+    &self.on_capture_started
+  }
+
+  pub fn on_capture_stopped(&self) -> &Event<()> {
+    &self.on_capture_stopped
+  }
+
+  /// Fired once for every frame that a client has exported as a DMA-BUF
+  pub fn on_frame_exported(&self) -> &Event<()> {
+    &self.on_frame_exported
+  }
+
+  /// The number of frames that clients are currently exporting as DMA-BUFs
+  pub fn export_dmabuf_frame_count(&self) -> usize {
+    if self.export_dmabuf_manager.is_null() {
+      return 0;
+    }
+    let mut count = 0;
+    unsafe {
+      wl_list_for_each!(
+        (*self.export_dmabuf_manager).frames,
+        link,
+        (frame: wlr_export_dmabuf_frame_v1) => {
+          if (*frame).output == self.output {
+            count += 1;
+          }
+        }
+      );
+    }
+    count
+  }
+
+  fn has_pending_capture_frames(&self) -> bool {
+    if self.export_dmabuf_frame_count() > 0 {
+      return true;
+    }
+    if self.screencopy_manager.is_null() {
+      return false;
+    }
+    let mut has_frames = false;
+    unsafe {
+      wl_list_for_each!(
+        (*self.screencopy_manager).frames,
+        link,
+        (frame: wlr_screencopy_frame_v1) => {
+          if (*frame).output == self.output {
+            has_frames = true;
+          }
+        }
+      );
+    }
+    has_frames
+  }
+
+  fn update_capture_state(&self, now: Instant) {
+    if self.has_pending_capture_frames() {
+      self.last_captured_at.set(Some(now));
+    }
+    let captured = is_captured(self.last_captured_at.get(), now);
+    if captured != self.captured.replace(captured) {
+      if captured {
+        self.on_capture_started.fire(());
+      } else {
+        self.on_capture_stopped.fire(());
+      }
+    }
+  }
+
+  fn render_background(&self) {
+    let background_image = self.background_image.borrow();
+    let texture = match background_image.as_ref() {
+      Some(texture) => texture,
+      None => return,
+    };
+
+    let mut output_size = Size {
+      width: 0,
+      height: 0,
+    };
+    unsafe {
+      wlr_output_transformed_resolution(
+        self.output,
+        &mut output_size.width,
+        &mut output_size.height,
+      );
+    }
+
+    for render_box in background_boxes(self.background_mode(), output_size, texture.size()) {
+      let render_box: wlr_box = render_box.into();
+      let mut matrix = TransformMatrix::IDENTITY.clone();
+      unsafe {
+        wlr_matrix_project_box(
+          matrix.as_mut_ptr(),
+          &render_box,
+          wl_output_transform_WL_OUTPUT_TRANSFORM_NORMAL,
+          0.0,
+          self.projection().as_ptr(),
+        );
+        wlr_render_texture_with_matrix(self.renderer, texture.raw_ptr(), matrix.as_ptr(), 1.0);
+      }
+    }
+  }
 
   pub(crate) fn render_window(&self, frame_time: &timespec, window: Rc<Window>) {
     unsafe {
@@ -150,21 +716,28 @@ impl Output {
       // have layout coordinates of 2000,100. We need to translate that to
       // output-local coordinates, or (2000 - 1920).
       let buffer_extents = window.buffer_extents();
+      let origin = self.global_to_local(buffer_extents.top_left());
 
-      let top_left = buffer_extents.top_left() - self.top_left().as_displacement()
+      let top_left = origin
         + Displacement {
           dx: wlr_surface.sx,
           dy: wlr_surface.sy,
-        }
-        + window.translate.borrow().clone();
+        };
 
-      // We also have to apply the scale factor for HiDPI outputs. This is only
-      // part of the puzzle, TinyWL does not fully support HiDPI.
-      let render_box = Rectangle {
-        top_left: top_left * self.scale(),
-        size: buffer_extents.size() * self.scale(),
-      }
-      .into();
+      // We also have to apply the scale factor for HiDPI outputs. The buffer
+      // extents are already divided by the buffer scale of the client.
+      let render_box = render_box(top_left, buffer_extents.size(), self.scale());
+
+      // The render transform scales the window around its top left corner
+      // and then translates it, in output pixels.
+      let (translate, render_scale) = window.render_transform();
+      let origin = origin * self.scale();
+      let render_transform = TransformMatrix::translate(
+        origin.x as f32 + translate.dx as f32 * self.scale(),
+        origin.y as f32 + translate.dy as f32 * self.scale(),
+      ) * TransformMatrix::scale(render_scale, render_scale)
+        * TransformMatrix::translate(-origin.x as f32, -origin.y as f32);
+      let projection = self.projection() * render_transform;
 
       // Those familiar with OpenGL are also familiar with the role of matricies
       // in graphics programming. We need to prepare a matrix to render the view
@@ -183,7 +756,7 @@ impl Output {
         &render_box,
         transform,
         rotation,
-        self.transform_matrix().as_ptr(),
+        projection.as_ptr(),
       );
 
       // This takes our matrix, the texture, and an alpha, and performs the actual
@@ -191,13 +764,56 @@ impl Output {
       let alpha = 1.0;
       wlr_render_texture_with_matrix(self.renderer, texture, matrix.as_ptr(), alpha);
 
-      // This lets the client know that we've displayed that frame and it can
-      // prepare another one now if it likes.
-      wlr_surface_send_frame_done(wlr_surface, frame_time);
+      self.send_frame_done(frame_time, &window);
+    }
+  }
+
+  /// This lets the client know that we've displayed that frame and it can
+  /// prepare another one now if it likes. Only windows on this output are
+  /// told so that clients are driven by the refresh rate of the outputs
+  /// they are shown on.
+  fn send_frame_done(&self, frame_time: &timespec, window: &Window) {
+    if self.extents().overlaps(&window.buffer_extents()) {
+      unsafe {
+        wlr_surface_send_frame_done(window.wlr_surface(), frame_time);
+      }
     }
   }
 }
 
+impl Output {
+  /// Renders the icon of a drag-and-drop operation at `top_left`, in layout
+  /// coordinates
+  unsafe fn render_drag_icon(
+    &self,
+    frame_time: &timespec,
+    surface: *mut wlr_surface,
+    top_left: Point,
+  ) {
+    let texture = wlr_surface_get_texture(surface);
+    if texture.is_null() {
+      return;
+    }
+    let size = Size {
+      width: (*surface).current.width,
+      height: (*surface).current.height,
+    };
+    let render_box = render_box(self.global_to_local(top_left), size, self.scale());
+
+    let transform = wlr_output_transform_invert((*surface).current.transform);
+    let mut matrix = TransformMatrix::IDENTITY.clone();
+    wlr_matrix_project_box(
+      matrix.as_mut_ptr(),
+      &render_box,
+      transform,
+      0.0,
+      self.projection().as_ptr(),
+    );
+    wlr_render_texture_with_matrix(self.renderer, texture, matrix.as_ptr(), 1.0);
+    wlr_surface_send_frame_done(surface, frame_time);
+  }
+}
+
 impl Eq for Output {}
 impl PartialEq for Output {
   fn eq(&self, other: &Output) -> bool {
@@ -225,7 +841,9 @@ pub(crate) trait OutputEventHandler {
 
 impl OutputEventHandler for Rc<Output> {
   fn frame(&self) {
+    let started_at = Instant::now();
     self.on_frame.fire(());
+    self.update_capture_state(started_at);
 
     unsafe {
       // wlr_output_attach_render makes the OpenGL context current.
@@ -248,17 +866,38 @@ impl OutputEventHandler for Rc<Output> {
       ];
       wlr_renderer_clear(self.renderer, &color[0]);
 
-      let now = Instant::now();
-      let since_creation = now.duration_since(self.created_at);
-      let frame_time = timespec {
-        tv_sec: since_creation.as_secs() as i64,
-        tv_nsec: since_creation.subsec_nanos() as i64,
-      };
+      self.render_background();
+
+      let frame_time = monotonic_now();
 
-      for window in self.window_manager.windows_to_render() {
-        self.render_window(&frame_time, window);
+      // Windows that are covered by opaque windows in front of them are
+      // skipped, but are still told that the frame is done so that they
+      // keep updating.
+      let windows: Vec<_> = self.window_manager.windows_to_render().collect();
+      let regions: Vec<_> = windows
+        .iter()
+        .map(|window| (window.render_extents(), window.opaque_region()))
+        .collect();
+      let visible = visible_windows(&self.extents(), &regions);
+      for (window, visible) in windows.into_iter().zip(visible) {
+        if visible {
+          self.render_window(&frame_time, window);
+        } else {
+          self.send_frame_done(&frame_time, &window);
+        }
       }
 
+      if let Some((surface, top_left)) = self.window_manager.seat_manager().drag_icon() {
+        self.render_drag_icon(&frame_time, surface, top_left);
+      }
+
+      self.on_render_overlay.fire(RenderContext {
+        renderer: self.renderer,
+        projection: self.projection(),
+        size: Size { width, height },
+        scale: self.scale(),
+      });
+
       // Hardware cursors are rendered by the GPU on a separate plane, and can be
       // moved around without re-rendering what's beneath them - which is more
       // efficient. However, not all hardware supports hardware cursors. For this
@@ -271,6 +910,21 @@ impl OutputEventHandler for Rc<Output> {
       // on-screen.
       wlr_renderer_end(self.renderer);
       wlr_output_commit(self.output);
+
+      let render_duration = started_at.elapsed();
+      self.last_frame_duration.set(Some(render_duration));
+      let delta = frame_delta(&self.frame_times.borrow(), started_at);
+      self.record_frame_start(started_at);
+      self.on_present.fire(FrameEvent {
+        time: frame_time,
+        delta,
+      });
+      // The whole output is redrawn every frame
+      self.on_frame_stats.fire(FrameStats {
+        render_duration,
+        direct_scanout: false,
+        damage_area: width as i64 * height as i64,
+      });
     }
   }
 
@@ -352,3 +1006,182 @@ impl OutputEvents for Rc<Output> {
     *self.event_manager.borrow_mut() = Some(event_manager);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::mem;
+
+  fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+    Rectangle {
+      top_left: Point { x, y },
+      size: Size { width, height },
+    }
+  }
+
+  #[test]
+  fn it_estimates_frames_per_second() {
+    let start = Instant::now();
+    let mut frame_times = VecDeque::new();
+    assert_eq!(frames_per_second(&frame_times), 0.0);
+    frame_times.push_back(start);
+    assert_eq!(frames_per_second(&frame_times), 0.0);
+    for frame in 1..=30 {
+      frame_times.push_back(start + Duration::from_millis(frame * 20));
+    }
+    assert!((frames_per_second(&frame_times) - 50.0).abs() < 0.001);
+  }
+
+  #[test]
+  fn it_does_not_scale_the_render_box_by_the_buffer_scale_again() {
+    // A 200x100 buffer committed with buffer scale 2, as wlroots leaves
+    // the surface state after the commit
+    let mut state: wlr_surface_state = unsafe { mem::zeroed() };
+    state.buffer_width = 200;
+    state.buffer_height = 100;
+    state.scale = 2;
+    state.width = 100;
+    state.height = 50;
+    let surface_size = Size {
+      width: state.width,
+      height: state.height,
+    };
+
+    let render_box = render_box(Point { x: 10, y: 20 }, surface_size, 1.0);
+    assert_eq!(Rectangle::from(render_box), rect(10, 20, 100, 50));
+
+    let render_box = render_box(Point { x: 10, y: 20 }, surface_size, 2.0);
+    assert_eq!(Rectangle::from(render_box), rect(20, 40, 200, 100));
+  }
+
+  #[test]
+  fn it_skips_windows_covered_by_opaque_windows() {
+    let output = rect(0, 0, 1920, 1080);
+    let windows = vec![
+      (rect(100, 100, 400, 300), vec![rect(100, 100, 400, 300)]),
+      (rect(1800, 1000, 400, 300), vec![]),
+      (rect(2000, 0, 400, 300), vec![]),
+      (rect(0, 0, 1920, 1080), vec![rect(0, 0, 1920, 1080)]),
+      (rect(50, 50, 100, 100), vec![]),
+    ];
+    assert_eq!(
+      visible_windows(&output, &windows),
+      vec![false, false, false, true, true]
+    );
+
+    let windows = vec![
+      (rect(100, 100, 400, 300), vec![]),
+      (rect(0, 0, 1920, 1080), vec![rect(0, 0, 1920, 40)]),
+    ];
+    assert_eq!(visible_windows(&output, &windows), vec![true, true]);
+  }
+
+  #[test]
+  fn it_magnifies_around_the_focus_point() {
+    let apply = |matrix: &TransformMatrix, x: f32, y: f32| {
+      let m = &matrix.0;
+      (m[0] * x + m[1] * y + m[2], m[3] * x + m[4] * y + m[5])
+    };
+    let matrix = magnification_matrix(FPoint { x: 100.0, y: 50.0 }, 2.0);
+    assert_eq!(apply(&matrix, 100.0, 50.0), (100.0, 50.0));
+    assert_eq!(apply(&matrix, 110.0, 40.0), (120.0, 30.0));
+    assert_eq!(apply(&matrix, 0.0, 0.0), (-100.0, -50.0));
+  }
+
+  #[test]
+  fn it_measures_the_time_since_the_previous_frame() {
+    let start = Instant::now();
+    let mut frame_times = VecDeque::new();
+    assert_eq!(frame_delta(&frame_times, start), Duration::from_secs(0));
+    frame_times.push_back(start);
+    frame_times.push_back(start + Duration::from_millis(16));
+    assert_eq!(
+      frame_delta(&frame_times, start + Duration::from_millis(33)),
+      Duration::from_millis(17)
+    );
+  }
+
+  #[test]
+  fn it_scales_overlay_rectangles_to_output_pixels() {
+    let context = RenderContext {
+      renderer: ptr::null_mut(),
+      projection: TransformMatrix::IDENTITY.clone(),
+      size: Size {
+        width: 960,
+        height: 540,
+      },
+      scale: 2.0,
+    };
+    assert_eq!(
+      Rectangle::from(context.output_box(&rect(10, 20, 100, 30))),
+      rect(20, 40, 200, 60)
+    );
+  }
+
+  #[test]
+  fn it_reads_the_monotonic_clock() {
+    let first = monotonic_now();
+    let second = monotonic_now();
+    assert!((second.tv_sec, second.tv_nsec) >= (first.tv_sec, first.tv_nsec));
+    assert!(first.tv_sec > 0 || first.tv_nsec > 0);
+  }
+
+  #[test]
+  fn it_calculates_background_boxes() {
+    let output = Size {
+      width: 200,
+      height: 100,
+    };
+    let image = Size {
+      width: 100,
+      height: 100,
+    };
+
+    assert_eq!(
+      background_boxes(BackgroundMode::Fill, output, image),
+      vec![rect(0, -50, 200, 200)]
+    );
+    assert_eq!(
+      background_boxes(BackgroundMode::Fit, output, image),
+      vec![rect(50, 0, 100, 100)]
+    );
+    assert_eq!(
+      background_boxes(BackgroundMode::Center, output, image),
+      vec![rect(50, 0, 100, 100)]
+    );
+    assert_eq!(
+      background_boxes(
+        BackgroundMode::Tile,
+        output,
+        Size {
+          width: 80,
+          height: 60
+        }
+      ),
+      vec![
+        rect(0, 0, 80, 60),
+        rect(80, 0, 80, 60),
+        rect(160, 0, 80, 60),
+        rect(0, 60, 80, 60),
+        rect(80, 60, 80, 60),
+        rect(160, 60, 80, 60),
+      ]
+    );
+  }
+
+  #[test]
+  fn it_stays_captured_between_screencopy_frames() {
+    let captured_at = Instant::now();
+
+    assert!(!is_captured(None, captured_at));
+    assert!(is_captured(Some(captured_at), captured_at));
+    assert!(is_captured(
+      Some(captured_at),
+      captured_at + Duration::from_millis(500)
+    ));
+    assert!(!is_captured(
+      Some(captured_at),
+      captured_at + CAPTURE_TIMEOUT
+    ));
+  }
+}
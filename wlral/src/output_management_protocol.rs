@@ -1,8 +1,109 @@
-use crate::{event::Event, output_manager::OutputManager, wayland_timer::WlTimer};
+use crate::geometry::Point;
+use crate::{
+  event::Event,
+  output_manager::{OutputConfig, OutputManager},
+  wayland_timer::WlTimer,
+};
 use log::{debug, error};
-use std::{cell::RefCell, pin::Pin, rc::Rc};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::VecDeque, ffi::CStr, pin::Pin, rc::Rc};
 use wlroots_sys::*;
 
+/// The configuration of a single output in an `OutputConfigSnapshot`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputHeadSnapshot {
+  /// The name of the output, like `DP-1`
+  pub name: String,
+  pub enabled: bool,
+  /// The width of the mode in pixels
+  pub width: i32,
+  /// The height of the mode in pixels
+  pub height: i32,
+  /// The refresh rate of the mode in mHz
+  pub refresh: i32,
+  /// The position in the output layout
+  pub x: i32,
+  pub y: i32,
+  pub scale: f32,
+  pub transform: wl_output_transform,
+}
+
+/// A serializable copy of an output configuration applied by a client
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutputConfigSnapshot {
+  pub heads: Vec<OutputHeadSnapshot>,
+}
+
+impl OutputConfigSnapshot {
+  unsafe fn from_config(config: *mut wlr_output_configuration_v1) -> OutputConfigSnapshot {
+    let mut heads = vec![];
+    wl_list_for_each!(
+      (*config).heads,
+      link,
+      (head: wlr_output_configuration_head_v1) => {
+        let state = &(*head).state;
+        let (width, height, refresh) = if state.mode.is_null() {
+          (state.custom_mode.width, state.custom_mode.height, state.custom_mode.refresh)
+        } else {
+          ((*state.mode).width, (*state.mode).height, (*state.mode).refresh)
+        };
+        heads.push(OutputHeadSnapshot {
+          name: CStr::from_ptr((*state.output).name.as_ptr())
+            .to_string_lossy()
+            .into_owned(),
+          enabled: state.enabled,
+          width,
+          height,
+          refresh,
+          x: state.x,
+          y: state.y,
+          scale: state.scale as f32,
+          transform: state.transform,
+        });
+      }
+    );
+    OutputConfigSnapshot { heads }
+  }
+}
+
+/// What to do when a client requests a test while another test is pending
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputTestPolicy {
+  /// Fail the new test
+  Reject,
+  /// Revert and fail the pending test, then start the new test
+  Supersede,
+  /// Start the new test when the pending test has been applied or canceled
+  Queue,
+}
+
+impl Default for OutputTestPolicy {
+  fn default() -> Self {
+    OutputTestPolicy::Reject
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum NewTestAction {
+  Start,
+  Reject,
+  CancelPendingAndStart,
+  Enqueue,
+}
+
+impl OutputTestPolicy {
+  fn new_test_action(&self, has_pending_test: bool) -> NewTestAction {
+    if !has_pending_test {
+      return NewTestAction::Start;
+    }
+    match self {
+      OutputTestPolicy::Reject => NewTestAction::Reject,
+      OutputTestPolicy::Supersede => NewTestAction::CancelPendingAndStart,
+      OutputTestPolicy::Queue => NewTestAction::Enqueue,
+    }
+  }
+}
+
 struct OutputTest {
   old_config: *mut wlr_output_configuration_v1,
   new_config: *mut wlr_output_configuration_v1,
@@ -32,9 +133,12 @@ pub struct OutputManagementProtocol {
   is_applying_output_config: RefCell<bool>,
   pending_output_test: RefCell<Option<OutputTest>>,
   pending_test_timeout_ms: RefCell<u32>,
+  test_policy: RefCell<OutputTestPolicy>,
+  queued_tests: RefCell<VecDeque<*mut wlr_output_configuration_v1>>,
 
   on_output_management_test_started: Event<()>,
   on_output_management_test_timed_out: Event<()>,
+  on_output_config_applied: Event<OutputConfigSnapshot>,
 
   output_manager: Rc<OutputManager>,
   output_manager_v1: *mut wlr_output_manager_v1,
@@ -52,9 +156,12 @@ impl OutputManagementProtocol {
       is_applying_output_config: RefCell::new(false),
       pending_output_test: RefCell::new(None),
       pending_test_timeout_ms: RefCell::new(pending_test_timeout_ms),
+      test_policy: RefCell::new(OutputTestPolicy::default()),
+      queued_tests: RefCell::new(VecDeque::new()),
 
       on_output_management_test_started: Event::default(),
       on_output_management_test_timed_out: Event::default(),
+      on_output_config_applied: Event::default(),
 
       output_manager: output_manager.clone(),
       output_manager_v1,
@@ -122,41 +229,79 @@ impl OutputManagementProtocol {
   /// active outputs.
   unsafe fn apply_output_config(&self, config: *mut wlr_output_configuration_v1) {
     debug!("OutputManagementProtocol::apply_output_config");
-    // wlr_output_commit() is being called in a loop, and it can trigger
-    // an output_layout.change event each time it's called.
-    *self.is_applying_output_config.borrow_mut() = true;
+    if self
+      .apply_heads(&OutputConfigSnapshot::from_config(config))
+      .is_err()
+    {
+      error!("OutputManagementProtocol::apply_output_config: Could not apply output configuration");
+    }
+  }
 
-    wl_list_for_each!(
-      (*config).heads,
-      link,
-      (head: wlr_output_configuration_head_v1) => {
-        let output = (*head).state.output;
-        let output_layout = self.output_manager.raw_output_layout();
-        if (*head).state.enabled && !(*output).enabled {
-          wlr_output_layout_add_auto(output_layout, output);
-        } else if !(*head).state.enabled && (*output).enabled {
-          wlr_output_layout_remove(output_layout, output);
+  /// Commits the settings of each head to the output with the same name,
+  /// heads for outputs that are not connected are ignored.
+  unsafe fn apply_heads(&self, snapshot: &OutputConfigSnapshot) -> Result<(), ()> {
+    let mut configs = vec![];
+    for head in snapshot.heads.iter() {
+      let output = match self
+        .output_manager
+        .outputs()
+        .iter()
+        .find(|output| output.name() == head.name.as_str())
+      {
+        Some(output) => output.clone(),
+        None => {
+          debug!(
+            "OutputManagementProtocol::apply_heads: Output {} is not connected",
+            head.name
+          );
+          continue;
         }
-        wlr_output_enable(output, (*head).state.enabled);
-        // All other settings only have an effect if the output is enabled.
-        if (*head).state.enabled {
-          if !(*head).state.mode.is_null() {
-            wlr_output_set_mode(output, (*head).state.mode);
-          } else {
-            wlr_output_set_custom_mode(output,
-                (*head).state.custom_mode.width, (*head).state.custom_mode.height,
-                (*head).state.custom_mode.refresh);
-          }
-          wlr_output_layout_move(output_layout, output,
-              (*head).state.x, (*head).state.y);
-          wlr_output_set_scale(output, (*head).state.scale as f32);
-          wlr_output_set_transform(output, (*head).state.transform);
-        }
-        wlr_output_commit(output);
-      }
-    );
+      };
+      configs.push(OutputConfig {
+        output,
+        enabled: head.enabled,
+        width: head.width,
+        height: head.height,
+        refresh: head.refresh,
+        top_left: Point {
+          x: head.x,
+          y: head.y,
+        },
+        scale: head.scale,
+        transform: head.transform,
+      });
+    }
 
+    // Applying the configuration triggers an output_layout.change event,
+    // which should not be sent to clients as an unrequested change.
+    *self.is_applying_output_config.borrow_mut() = true;
+    let result = self.output_manager.commit_all(&configs);
     *self.is_applying_output_config.borrow_mut() = false;
+    result
+  }
+
+  /// Applies a configuration, for example one saved from
+  /// `on_output_config_applied` to be restored at startup. Heads for
+  /// outputs that are not connected are ignored.
+  pub fn apply_snapshot(&self, snapshot: &OutputConfigSnapshot) {
+    debug!("OutputManagementProtocol::apply_snapshot");
+    unsafe {
+      if self.apply_heads(snapshot).is_err() {
+        error!("OutputManagementProtocol::apply_snapshot: Could not apply output configuration");
+      }
+
+      // Change events are ignored while applying so tell clients about the
+      // new configuration here
+      if let Some(config) = self.create_output_config() {
+        wlr_output_manager_v1_set_configuration(self.output_manager_v1, config);
+      }
+    }
+  }
+
+  /// Fired when a client has permanently changed the output configuration,
+  /// with the applied configuration so that it can be persisted
+  pub fn on_output_config_applied(&self) -> &Event<OutputConfigSnapshot> {
+    &self.on_output_config_applied
   }
 
   pub fn raw_output_manager(&self) -> *mut wlr_output_manager_v1 {
@@ -171,16 +316,34 @@ impl OutputManagementProtocol {
     *self.pending_test_timeout_ms.borrow_mut() = timeout
   }
 
+  pub fn test_policy(&self) -> OutputTestPolicy {
+    *self.test_policy.borrow()
+  }
+
+  /// Sets what to do when a client requests a test while another test is
+  /// pending. Defaults to `OutputTestPolicy::Reject`.
+  pub fn set_test_policy(&self, policy: OutputTestPolicy) {
+    *self.test_policy.borrow_mut() = policy;
+  }
+
   pub fn has_pending_test(&self) -> bool {
     self.pending_output_test.borrow().is_some()
   }
 
-  pub fn apply_pending_test(&self) -> Result<(), ()> {
+  /// The number of tests waiting for the pending test to finish
+  pub fn queued_test_count(&self) -> usize {
+    self.queued_tests.borrow().len()
+  }
+
+  pub fn apply_pending_test(self: &Rc<Self>) -> Result<(), ()> {
     debug!("OutputManagementProtocol::apply_pending_test");
-    if let Some(test) = self.pending_output_test.borrow_mut().take() {
+    let test = self.pending_output_test.borrow_mut().take();
+    if let Some(test) = test {
       unsafe {
         wlr_output_configuration_v1_send_succeeded(test.new_config);
       }
+      drop(test);
+      self.start_queued_test();
       Ok(())
     } else {
       Err(())
@@ -189,9 +352,16 @@ impl OutputManagementProtocol {
 
   /// Change the output configuration back to the old one and tell the
   /// client the new one failed
-  pub fn cancel_pending_test(&self) -> Result<(), ()> {
-    debug!("OutputManagementProtocol::cancel_pending_test");
-    if let Some(test) = self.pending_output_test.borrow_mut().take() {
+  pub fn cancel_pending_test(self: &Rc<Self>) -> Result<(), ()> {
+    self.revert_pending_test()?;
+    self.start_queued_test();
+    Ok(())
+  }
+
+  fn revert_pending_test(&self) -> Result<(), ()> {
+    debug!("OutputManagementProtocol::revert_pending_test");
+    let test = self.pending_output_test.borrow_mut().take();
+    if let Some(test) = test {
       unsafe {
         self.apply_output_config(test.old_config);
         wlr_output_configuration_v1_send_failed(test.new_config);
@@ -201,6 +371,28 @@ impl OutputManagementProtocol {
       Err(())
     }
   }
+
+  fn start_queued_test(self: &Rc<Self>) {
+    let config = self.queued_tests.borrow_mut().pop_front();
+    if let Some(config) = config {
+      unsafe {
+        if self.test_output_config(config).is_err() {
+          wlr_output_configuration_v1_send_failed(config);
+          wlr_output_configuration_v1_destroy(config);
+        }
+      }
+    }
+  }
+}
+
+impl Drop for OutputManagementProtocol {
+  fn drop(&mut self) {
+    for config in self.queued_tests.borrow_mut().drain(..) {
+      unsafe {
+        wlr_output_configuration_v1_destroy(config);
+      }
+    }
+  }
 }
 
 trait OutputManagementProtocolExt {
@@ -211,9 +403,22 @@ impl OutputManagementProtocolExt for Rc<OutputManagementProtocol> {
   unsafe fn test_output_config(&self, config: *mut wlr_output_configuration_v1) -> Result<(), ()> {
     debug!("OutputManagementProtocol::test_output_config: Testing new output config");
     // We can not handle multiple simultaneous tests.
-    if self.pending_output_test.borrow().is_some() {
-      error!("OutputManagementProtocol::test_output_config: Previous test already active");
-      return Err(());
+    let has_pending_test = self.pending_output_test.borrow().is_some();
+    match self.test_policy().new_test_action(has_pending_test) {
+      NewTestAction::Start => {}
+      NewTestAction::Reject => {
+        error!("OutputManagementProtocol::test_output_config: Previous test already active");
+        return Err(());
+      }
+      NewTestAction::CancelPendingAndStart => {
+        debug!("OutputManagementProtocol::test_output_config: Superseding previous test");
+        self.revert_pending_test()?;
+      }
+      NewTestAction::Enqueue => {
+        debug!("OutputManagementProtocol::test_output_config: Queueing behind previous test");
+        self.queued_tests.borrow_mut().push_back(config);
+        return Ok(());
+      }
     }
 
     let output_manager_protocol = self.clone();
@@ -222,12 +427,16 @@ impl OutputManagementProtocolExt for Rc<OutputManagementProtocol> {
       *self.pending_test_timeout_ms.borrow(),
       move || {
         debug!("OutputManagementProtocol::test_output_config: Timeout reached, reverting config");
-        if output_manager_protocol.cancel_pending_test().is_err() {
+        // Canceling the test drops this timer, and with it this closure, so
+        // keep a reference on the stack
+        let output_manager_protocol = output_manager_protocol.clone();
+        if output_manager_protocol.revert_pending_test().is_err() {
           error!("OutputManagementProtocol::test_output_config: Error when canceling test after a timeout");
         }
         output_manager_protocol
           .on_output_management_test_timed_out
           .fire(());
+        output_manager_protocol.start_queued_test();
       },
     )?;
     let current_config = match self.create_output_config() {
@@ -259,9 +468,15 @@ wayland_listener!(
       // to the output configuration.
       let handler = &this.data;
       let config = data as *mut _;
-      handler.apply_output_config(config);
-      wlr_output_configuration_v1_send_succeeded(config);
-      wlr_output_configuration_v1_destroy(config);
+      let snapshot = OutputConfigSnapshot::from_config(config);
+      if handler.apply_heads(&snapshot).is_ok() {
+        wlr_output_configuration_v1_send_succeeded(config);
+        wlr_output_configuration_v1_destroy(config);
+        handler.on_output_config_applied.fire(snapshot);
+      } else {
+        wlr_output_configuration_v1_send_failed(config);
+        wlr_output_configuration_v1_destroy(config);
+      }
     };
     test => test_func: |this: &mut OututManagementProtocolEventManager, data: *mut libc::c_void,| unsafe {
       // This event is raised by a client requesting a test for a new
@@ -276,3 +491,189 @@ wayland_listener!(
     };
   ]
 );
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::ConfigManager;
+  use crate::input::seat::SeatManager;
+  use crate::window_management_policy::WmPolicyManager;
+  use crate::window_manager::WindowManager;
+  use std::ptr;
+  use wayland_sys::server::WAYLAND_SERVER_HANDLE;
+
+  fn init_protocol(display: *mut wl_display) -> Rc<OutputManagementProtocol> {
+    let wm_policy_manager = Rc::new(WmPolicyManager::new());
+    let seat_manager = SeatManager::mock(ptr::null_mut(), ptr::null_mut());
+    let window_manager = Rc::new(WindowManager::init(
+      wm_policy_manager.clone(),
+      seat_manager,
+      ptr::null_mut(),
+    ));
+    let output_manager = OutputManager::mock_with_display(
+      Rc::new(ConfigManager::default()),
+      wm_policy_manager,
+      window_manager,
+      display,
+    );
+    OutputManagementProtocol::init(output_manager, 1)
+  }
+
+  /// Dispatches the event loop until `done` returns true
+  unsafe fn dispatch_until<F>(display: *mut wl_display, done: F)
+  where
+    F: Fn() -> bool,
+  {
+    let event_loop = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_get_event_loop, display);
+    for _ in 0..100 {
+      if done() {
+        return;
+      }
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_event_loop_dispatch,
+        event_loop,
+        10
+      );
+    }
+    panic!("Timed out waiting for the event loop");
+  }
+
+  fn pending_config(protocol: &OutputManagementProtocol) -> *mut wlr_output_configuration_v1 {
+    protocol
+      .pending_output_test
+      .borrow()
+      .as_ref()
+      .map_or(ptr::null_mut(), |test| test.new_config)
+  }
+
+  #[test]
+  fn it_reverts_the_pending_test_when_superseded() {
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      let protocol = init_protocol(display);
+      protocol.set_test_policy(OutputTestPolicy::Supersede);
+      SENT_RESULTS.with(|results| results.borrow_mut().clear());
+
+      let first = wlr_output_configuration_v1_create();
+      let second = wlr_output_configuration_v1_create();
+      assert!(protocol.test_output_config(first).is_ok());
+      assert!(protocol.test_output_config(second).is_ok());
+      assert_eq!(pending_config(&protocol), second);
+      SENT_RESULTS.with(|results| assert_eq!(*results.borrow(), vec![(first, false)]));
+
+      // The superseding test still times out on its own timer
+      dispatch_until(display, || !protocol.has_pending_test());
+      SENT_RESULTS
+        .with(|results| assert_eq!(*results.borrow(), vec![(first, false), (second, false)]));
+
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+    }
+  }
+
+  #[test]
+  fn it_drains_queued_tests_in_order() {
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      let protocol = init_protocol(display);
+      protocol.set_test_policy(OutputTestPolicy::Queue);
+      SENT_RESULTS.with(|results| results.borrow_mut().clear());
+
+      let first = wlr_output_configuration_v1_create();
+      let second = wlr_output_configuration_v1_create();
+      let third = wlr_output_configuration_v1_create();
+      assert!(protocol.test_output_config(first).is_ok());
+      assert!(protocol.test_output_config(second).is_ok());
+      assert!(protocol.test_output_config(third).is_ok());
+      assert_eq!(pending_config(&protocol), first);
+      assert_eq!(protocol.queued_test_count(), 2);
+
+      assert!(protocol.apply_pending_test().is_ok());
+      assert_eq!(pending_config(&protocol), second);
+      assert_eq!(protocol.queued_test_count(), 1);
+
+      // A timed out test starts the next one as well
+      dispatch_until(display, || pending_config(&protocol) == third);
+      assert_eq!(protocol.queued_test_count(), 0);
+
+      assert!(protocol.cancel_pending_test().is_ok());
+      assert!(!protocol.has_pending_test());
+      SENT_RESULTS.with(|results| {
+        assert_eq!(
+          *results.borrow(),
+          vec![(first, true), (second, false), (third, false)]
+        )
+      });
+
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+    }
+  }
+
+  #[test]
+  fn it_starts_tests_according_to_the_policy() {
+    for policy in [
+      OutputTestPolicy::Reject,
+      OutputTestPolicy::Supersede,
+      OutputTestPolicy::Queue,
+    ]
+    .iter()
+    {
+      assert_eq!(policy.new_test_action(false), NewTestAction::Start);
+    }
+    assert_eq!(
+      OutputTestPolicy::Reject.new_test_action(true),
+      NewTestAction::Reject
+    );
+    assert_eq!(
+      OutputTestPolicy::Supersede.new_test_action(true),
+      NewTestAction::CancelPendingAndStart
+    );
+    assert_eq!(
+      OutputTestPolicy::Queue.new_test_action(true),
+      NewTestAction::Enqueue
+    );
+  }
+}
+
+#[cfg(test)]
+thread_local! {
+  /// The configurations that a test result was sent for, and if it succeeded
+  static SENT_RESULTS: RefCell<Vec<(*mut wlr_output_configuration_v1, bool)>> =
+    RefCell::new(vec![]);
+}
+#[cfg(test)]
+unsafe fn wlr_output_manager_v1_create(_display: *mut wl_display) -> *mut wlr_output_manager_v1 {
+  let manager: *mut wlr_output_manager_v1 = Box::into_raw(Box::new(std::mem::zeroed()));
+  wayland_sys::server::signal::wl_signal_init(&mut (*manager).events.apply);
+  wayland_sys::server::signal::wl_signal_init(&mut (*manager).events.test);
+  manager
+}
+#[cfg(test)]
+unsafe fn wlr_output_manager_v1_set_configuration(
+  _manager: *mut wlr_output_manager_v1,
+  config: *mut wlr_output_configuration_v1,
+) {
+  wlr_output_configuration_v1_destroy(config);
+}
+#[cfg(test)]
+unsafe fn wlr_output_configuration_v1_create() -> *mut wlr_output_configuration_v1 {
+  let config: *mut wlr_output_configuration_v1 = Box::into_raw(Box::new(std::mem::zeroed()));
+  ffi_dispatch!(
+    wayland_sys::server::WAYLAND_SERVER_HANDLE,
+    wl_list_init,
+    &mut (*config).heads
+  );
+  config
+}
+#[cfg(test)]
+unsafe fn wlr_output_configuration_v1_destroy(config: *mut wlr_output_configuration_v1) {
+  Box::from_raw(config);
+}
+#[cfg(test)]
+unsafe fn wlr_output_configuration_v1_send_succeeded(config: *mut wlr_output_configuration_v1) {
+  SENT_RESULTS.with(|results| results.borrow_mut().push((config, true)));
+}
+#[cfg(test)]
+unsafe fn wlr_output_configuration_v1_send_failed(config: *mut wlr_output_configuration_v1) {
+  SENT_RESULTS.with(|results| results.borrow_mut().push((config, false)));
+}
@@ -1,35 +1,98 @@
+use crate::geometry::{Point, Rectangle, Size};
 #[cfg_attr(test, allow(unused))]
-use crate::output::{Output, OutputEvents};
+use crate::output::{Output, OutputEvents, OutputProfile};
+use crate::texture::Texture;
 use crate::window_management_policy::WmPolicyManager;
 use crate::{
-  config::ConfigManager,
+  config::{Config, ConfigManager},
   event::{Event, EventOnce},
-  window_manager::WindowManager,
+  window::Window,
+  window_manager::{WindowLayer, WindowManager},
 };
 #[cfg_attr(test, allow(unused))]
 use log::{debug, error};
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::path::PathBuf;
 use std::pin::Pin;
-use std::rc::Rc;
-use std::{fmt::Debug, time::Instant};
+use std::rc::{Rc, Weak};
 use wayland_sys::server::wl_display;
 use wlroots_sys::*;
 
+/// The distance from `point` to the closest point of `rectangle`
+fn distance_to(rectangle: &Rectangle, point: Point) -> f64 {
+  let dx = (rectangle.left() - point.x)
+    .max(point.x - rectangle.right())
+    .max(0);
+  let dy = (rectangle.top() - point.y)
+    .max(point.y - rectangle.bottom())
+    .max(0);
+  ((dx * dx + dy * dy) as f64).sqrt()
+}
+
+/// The top left corner that moves `extents` the shortest distance so that it
+/// is fully inside `area`, or aligned to the top left of `area` if too large
+pub(crate) fn position_inside(extents: &Rectangle, area: &Rectangle) -> Point {
+  position_partly_inside(extents, area, 0)
+}
+
+/// The top left corner that moves `extents` the shortest distance so that at
+/// least `margin` pixels of it are inside `area` in each direction, or all of
+/// it if `margin` is 0 or larger than `extents`
+pub(crate) fn position_partly_inside(extents: &Rectangle, area: &Rectangle, margin: i32) -> Point {
+  let margin_x = if margin == 0 {
+    extents.width()
+  } else {
+    margin.min(extents.width())
+  };
+  let margin_y = if margin == 0 {
+    extents.height()
+  } else {
+    margin.min(extents.height())
+  };
+  Point {
+    x: extents
+      .left()
+      .min(area.right() - margin_x)
+      .max(area.left() + margin_x - extents.width()),
+    y: extents
+      .top()
+      .min(area.bottom() - margin_y)
+      .max(area.top() + margin_y - extents.height()),
+  }
+}
+
 fn new_output(manager: Rc<OutputManager>, output: *mut wlr_output) {
   let wm_policy_manager = manager.wm_policy_manager.clone();
   let window_manager = manager.window_manager.clone();
   let renderer = manager.renderer;
   let output_layout = manager.output_layout;
+  let config = manager.config_manager.config();
   let output = Output {
     wm_policy_manager,
     window_manager,
     renderer,
     output_layout,
     output,
-    created_at: Instant::now(),
-    background_color: RefCell::new(manager.config_manager.config().background_color),
+    screencopy_manager: manager.screencopy_manager.get(),
+    export_dmabuf_manager: manager.export_dmabuf_manager.get(),
+    background_color: RefCell::new(config.background_color),
+    background_image: RefCell::new(manager.background_image(&config)),
+    background_mode: RefCell::new(config.background_mode),
     on_destroy: EventOnce::default(),
     on_frame: Event::default(),
+    on_frame_stats: Event::default(),
+    on_present: Event::default(),
+    on_render_overlay: Event::default(),
+    last_frame_duration: Cell::new(None),
+    frame_times: RefCell::new(VecDeque::new()),
+    magnification: Cell::new(None),
+    last_captured_at: Cell::new(None),
+    captured: Cell::new(false),
+    on_capture_started: Event::default(),
+    on_capture_stopped: Event::default(),
+    on_frame_exported: Event::default(),
     event_manager: RefCell::new(None),
   };
 
@@ -52,12 +115,29 @@ fn new_output(manager: Rc<OutputManager>, output: *mut wlr_output) {
     return;
   }
 
+  let profile = manager
+    .profile_store
+    .borrow()
+    .as_ref()
+    .and_then(|store| store.load(&OutputManager::profile_key(&output)));
+
   unsafe {
-    // Adds this to the output layout. The add_auto function arranges outputs
-    // from left-to-right in the order they appear. A more sophisticated
-    // compositor would let the user configure the arrangement of outputs in the
-    // layout.
-    wlr_output_layout_add_auto(manager.output_layout, output.raw_ptr());
+    match profile {
+      Some(profile) => {
+        if output.apply_profile(&profile).is_err() {
+          error!("Failed applying stored profile for new output");
+        }
+        wlr_output_layout_add(
+          manager.output_layout,
+          output.raw_ptr(),
+          profile.x,
+          profile.y,
+        );
+      }
+      // Adds this to the output layout. The add_auto function arranges outputs
+      // from left-to-right in the order they appear.
+      None => wlr_output_layout_add_auto(manager.output_layout, output.raw_ptr()),
+    }
 
     // Creating the global adds a wl_output global to the display, which Wayland
     // clients can see to find out information about the output (such as
@@ -69,19 +149,17 @@ fn new_output(manager: Rc<OutputManager>, output: *mut wlr_output) {
 
   #[cfg(not(test))]
   output.bind_events();
-  let subscription_id =
-    manager
-      .config_manager
-      .on_config_changed()
-      .subscribe(listener!(output => move |config| {
-        *output.background_color.borrow_mut() = config.background_color;
-      }));
+  let subscription = manager.config_manager.on_config_changed().subscribe_scoped(
+    listener!(manager, output => move |config| {
+      *output.background_color.borrow_mut() = config.background_color;
+      output.set_background_image(manager.background_image(&config));
+      output.set_background_mode(config.background_mode);
+    }),
+  );
   output
     .on_destroy
     .then(listener!(manager, output => move || {
-      manager
-      .config_manager
-      .on_config_changed().unsubscribe(subscription_id);
+      drop(subscription);
 
       manager
         .wm_policy_manager
@@ -91,6 +169,8 @@ fn new_output(manager: Rc<OutputManager>, output: *mut wlr_output) {
         .outputs
         .borrow_mut()
         .retain(|o| o.raw_ptr() != output.raw_ptr());
+
+      manager.rescue_orphaned_windows(&output);
     }));
 
   manager.outputs.borrow_mut().push(output.clone());
@@ -105,11 +185,20 @@ pub struct OutputManager {
   wm_policy_manager: Rc<WmPolicyManager>,
   window_manager: Rc<WindowManager>,
   display: *mut wl_display,
+  backend: *mut wlr_backend,
   renderer: *mut wlr_renderer,
   output_layout: *mut wlr_output_layout,
   #[allow(unused)]
   xdg_output_manager_v1: *mut wlr_xdg_output_manager_v1,
+  screencopy_manager: Cell<*mut wlr_screencopy_manager_v1>,
+  export_dmabuf_manager: Cell<*mut wlr_export_dmabuf_manager_v1>,
+  export_dmabuf_event_manager: RefCell<Option<Pin<Box<ExportDmabufEventManager>>>>,
   outputs: RefCell<Vec<Rc<Output>>>,
+  background_image: RefCell<Option<(PathBuf, Rc<Texture>)>>,
+  profile_store: RefCell<Option<Box<dyn OutputProfileStore>>>,
+  /// Layout changes are reported once, after all outputs are committed
+  is_committing: Cell<bool>,
+  layout_changed_while_committing: Cell<bool>,
 
   on_new_output: Event<Rc<Output>>,
   on_output_layout_change: Event<()>,
@@ -117,6 +206,34 @@ pub struct OutputManager {
   event_manager: RefCell<Option<Pin<Box<OutputManagerEventManager>>>>,
 }
 
+/// Stores the configuration of outputs, for example on disk, so that it is
+/// restored when an output is connected again
+pub trait OutputProfileStore {
+  /// Returns the stored profile of the output with the `key`, if any
+  fn load(&self, key: &str) -> Option<OutputProfile>;
+  /// Called with the current profile of an output when its mode, position
+  /// or scale changes
+  fn save(&self, key: &str, profile: OutputProfile);
+}
+
+/// The desired state of an output, applied together with the state of other
+/// outputs by `OutputManager::commit_all`
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+  pub output: Rc<Output>,
+  pub enabled: bool,
+  /// The width of the mode in pixels
+  pub width: i32,
+  /// The height of the mode in pixels
+  pub height: i32,
+  /// The refresh rate of the mode in mHz
+  pub refresh: i32,
+  /// The position in the output layout
+  pub top_left: Point,
+  pub scale: f32,
+  pub transform: wl_output_transform,
+}
+
 impl Debug for OutputManager {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(
@@ -136,10 +253,201 @@ impl OutputManager {
     self.output_layout
   }
 
+  pub(crate) fn config_manager(&self) -> Rc<ConfigManager> {
+    self.config_manager.clone()
+  }
+
   pub fn outputs(&self) -> Ref<Vec<Rc<Output>>> {
     self.outputs.borrow()
   }
 
+  /// Adds an output of the given size which renders offscreen
+  ///
+  /// This is only supported when running on the headless backend.
+  pub fn add_virtual_output(&self, size: Size) -> Result<(), ()> {
+    unsafe {
+      if !wlr_backend_is_headless(self.backend) {
+        error!("OutputManager::add_virtual_output: Virtual outputs require the headless backend");
+        return Err(());
+      }
+      let output = wlr_headless_add_output(self.backend, size.width as u32, size.height as u32);
+      if output.is_null() {
+        error!("OutputManager::add_virtual_output: Could not create output");
+        return Err(());
+      }
+    }
+    Ok(())
+  }
+
+  /// Applies the configurations to their outputs as one change
+  ///
+  /// All outputs are tested before any of them is committed, so if the
+  /// backend rejects any configuration no output is changed. The outputs
+  /// are then committed back to back and `on_output_layout_change` is only
+  /// fired once.
+  pub fn commit_all(&self, configs: &[OutputConfig]) -> Result<(), ()> {
+    debug!("OutputManager::commit_all");
+    unsafe {
+      for config in configs.iter() {
+        let raw_output = config.output.raw_ptr();
+        wlr_output_enable(raw_output, config.enabled);
+        // All other settings only have an effect if the output is enabled.
+        if config.enabled {
+          config
+            .output
+            .set_pending_mode(config.width, config.height, config.refresh);
+          wlr_output_set_scale(raw_output, config.scale);
+          wlr_output_set_transform(raw_output, config.transform);
+        }
+      }
+
+      if !configs
+        .iter()
+        .all(|config| wlr_output_test(config.output.raw_ptr()))
+      {
+        error!("OutputManager::commit_all: Output configuration rejected by the backend");
+        for config in configs.iter() {
+          wlr_output_rollback(config.output.raw_ptr());
+        }
+        return Err(());
+      }
+
+      self.is_committing.set(true);
+      let mut result = Ok(());
+      for config in configs.iter() {
+        let raw_output = config.output.raw_ptr();
+        if !wlr_output_commit(raw_output) {
+          error!(
+            "OutputManager::commit_all: Could not commit output {}",
+            config.output.name()
+          );
+          result = Err(());
+          continue;
+        }
+        if config.enabled {
+          wlr_output_layout_add(
+            self.output_layout,
+            raw_output,
+            config.top_left.x,
+            config.top_left.y,
+          );
+        } else {
+          wlr_output_layout_remove(self.output_layout, raw_output);
+        }
+      }
+      self.is_committing.set(false);
+
+      if self.layout_changed_while_committing.replace(false) {
+        self.output_layout_changed();
+      }
+      result
+    }
+  }
+
+  fn output_layout_changed(&self) {
+    if self.is_committing.get() {
+      self.layout_changed_while_committing.set(true);
+      return;
+    }
+    self.save_profiles();
+    self.on_output_layout_change.fire(());
+  }
+
+  /// Identifies a physical output by make, model and serial number, as
+  /// read from its EDID
+  pub fn profile_key(output: &Output) -> String {
+    format!("{} {} {}", output.make(), output.model(), output.serial())
+  }
+
+  /// Sets a store used to restore the mode, position and scale of outputs
+  /// when they are connected, and which is updated whenever they change
+  pub fn set_profile_store(&self, store: Option<Box<dyn OutputProfileStore>>) {
+    *self.profile_store.borrow_mut() = store;
+  }
+
+  /// Lets outputs detect when they are being captured
+  pub(crate) fn set_screencopy_manager(&self, screencopy_manager: *mut wlr_screencopy_manager_v1) {
+    self.screencopy_manager.set(screencopy_manager);
+  }
+
+  /// Lets outputs detect when they are being exported, and report the
+  /// frames that are exported
+  pub(crate) fn set_export_dmabuf_manager(
+    self: &Rc<Self>,
+    export_dmabuf_manager: *mut wlr_export_dmabuf_manager_v1,
+  ) {
+    self.export_dmabuf_manager.set(export_dmabuf_manager);
+
+    let export_dmabuf_logger = Rc::new(ExportDmabufLogger {
+      export_dmabuf_manager,
+      output_manager: Rc::downgrade(self),
+      logger: Cell::new(std::ptr::null_mut()),
+    });
+    let mut event_manager = ExportDmabufEventManager::new(export_dmabuf_logger.clone());
+    unsafe {
+      export_dmabuf_logger
+        .logger
+        .set(wl_display_add_protocol_logger(
+          self.display,
+          Some(handle_export_dmabuf_event),
+          &*export_dmabuf_logger as *const ExportDmabufLogger as *mut libc::c_void,
+        ));
+      event_manager.destroy(&mut (*export_dmabuf_manager).events.destroy);
+    }
+    *self.export_dmabuf_event_manager.borrow_mut() = Some(event_manager);
+  }
+
+  /// Offers windows that were only on `removed_output` to the policy and
+  /// moves the ones it does not handle onto the nearest remaining output
+  fn rescue_orphaned_windows(&self, removed_output: &Rc<Output>) {
+    let orphaned_windows: Vec<Rc<Window>> = self
+      .window_manager
+      .windows()
+      .filter(|window| {
+        let layer = window.original_layer();
+        layer == WindowLayer::Normal || layer == WindowLayer::Unmanaged
+      })
+      .filter(|window| window.outputs().iter().any(|o| o == removed_output))
+      .collect();
+
+    for window in orphaned_windows {
+      window.leave_output(removed_output);
+      if !window.outputs().is_empty() {
+        continue;
+      }
+      if self
+        .wm_policy_manager
+        .handle_window_orphaned(window.clone(), removed_output.clone())
+      {
+        continue;
+      }
+
+      let extents = window.extents();
+      let center = Point {
+        x: extents.center_x(),
+        y: extents.center_y(),
+      };
+      let nearest_output = self.outputs().iter().cloned().min_by(|a, b| {
+        distance_to(&a.extents(), center)
+          .partial_cmp(&distance_to(&b.extents(), center))
+          .unwrap_or(std::cmp::Ordering::Equal)
+      });
+      if let Some(output) = nearest_output {
+        let top_left = position_inside(&extents, &output.extents());
+        let window_top_left = *window.top_left.borrow();
+        window.move_to_with_children(window_top_left + (top_left - extents.top_left()));
+      }
+    }
+  }
+
+  fn save_profiles(&self) {
+    if let Some(store) = self.profile_store.borrow().as_ref() {
+      for output in self.outputs.borrow().iter() {
+        store.save(&OutputManager::profile_key(output), output.profile());
+      }
+    }
+  }
+
   pub fn on_new_output(&self) -> &Event<Rc<Output>> {
     &self.on_new_output
   }
@@ -152,6 +460,37 @@ impl OutputManager {
 }
 
 impl OutputManager {
+  /// Loads the configured background image, reusing the texture while the
+  /// path is unchanged. Failing to load falls back to the background color.
+  fn background_image(&self, config: &Config) -> Option<Rc<Texture>> {
+    let path = match &config.background_image {
+      Some(path) => path,
+      None => {
+        *self.background_image.borrow_mut() = None;
+        return None;
+      }
+    };
+
+    if let Some((loaded_path, texture)) = self.background_image.borrow().as_ref() {
+      if loaded_path == path {
+        return Some(texture.clone());
+      }
+    }
+
+    match Texture::from_png(self.renderer, path) {
+      Ok(texture) => {
+        let texture = Rc::new(texture);
+        *self.background_image.borrow_mut() = Some((path.clone(), texture.clone()));
+        Some(texture)
+      }
+      Err(()) => {
+        error!("Failed loading background image {:?}", path);
+        *self.background_image.borrow_mut() = None;
+        None
+      }
+    }
+  }
+
   pub(crate) fn init(
     config_manager: Rc<ConfigManager>,
     wm_policy_manager: Rc<WmPolicyManager>,
@@ -170,10 +509,18 @@ impl OutputManager {
       wm_policy_manager,
       window_manager,
       display,
+      backend,
       renderer,
       output_layout,
       xdg_output_manager_v1,
+      screencopy_manager: Cell::new(std::ptr::null_mut()),
+      export_dmabuf_manager: Cell::new(std::ptr::null_mut()),
+      export_dmabuf_event_manager: RefCell::new(None),
       outputs: RefCell::new(vec![]),
+      background_image: RefCell::new(None),
+      profile_store: RefCell::new(None),
+      is_committing: Cell::new(false),
+      layout_changed_while_committing: Cell::new(false),
 
       on_new_output: Event::default(),
       on_output_layout_change: Event::default(),
@@ -198,16 +545,40 @@ impl OutputManager {
     config_manager: Rc<ConfigManager>,
     wm_policy_manager: Rc<WmPolicyManager>,
     window_manager: Rc<WindowManager>,
+  ) -> Rc<OutputManager> {
+    OutputManager::mock_with_display(
+      config_manager,
+      wm_policy_manager,
+      window_manager,
+      std::ptr::null_mut(),
+    )
+  }
+
+  /// Like `mock` but with a display, for tests that need its event loop
+  #[cfg(test)]
+  pub(crate) fn mock_with_display(
+    config_manager: Rc<ConfigManager>,
+    wm_policy_manager: Rc<WmPolicyManager>,
+    window_manager: Rc<WindowManager>,
+    display: *mut wl_display,
   ) -> Rc<OutputManager> {
     Rc::new(OutputManager {
       config_manager,
       wm_policy_manager,
       window_manager,
-      display: std::ptr::null_mut(),
+      display,
+      backend: std::ptr::null_mut(),
       renderer: std::ptr::null_mut(),
       output_layout: std::ptr::null_mut(),
       xdg_output_manager_v1: std::ptr::null_mut(),
+      screencopy_manager: Cell::new(std::ptr::null_mut()),
+      export_dmabuf_manager: Cell::new(std::ptr::null_mut()),
+      export_dmabuf_event_manager: RefCell::new(None),
       outputs: RefCell::new(vec![]),
+      background_image: RefCell::new(None),
+      profile_store: RefCell::new(None),
+      is_committing: Cell::new(false),
+      layout_changed_while_committing: Cell::new(false),
 
       on_new_output: Event::default(),
       on_output_layout_change: Event::default(),
@@ -217,6 +588,96 @@ impl OutputManager {
   }
 }
 
+/// The opcode of the zwlr_export_dmabuf_frame_v1.ready event
+const EXPORT_DMABUF_FRAME_READY: u32 = 2;
+
+struct ExportDmabufLogger {
+  export_dmabuf_manager: *mut wlr_export_dmabuf_manager_v1,
+  output_manager: Weak<OutputManager>,
+  logger: Cell<*mut wl_protocol_logger>,
+}
+
+impl ExportDmabufLogger {
+  /// The logger has to be destroyed before the display, as the display does
+  /// not free its loggers
+  fn destroy(&self) {
+    let logger = self.logger.replace(std::ptr::null_mut());
+    if !logger.is_null() {
+      unsafe {
+        wl_protocol_logger_destroy(logger);
+      }
+    }
+  }
+}
+
+impl Drop for ExportDmabufLogger {
+  fn drop(&mut self) {
+    self.destroy();
+  }
+}
+
+wayland_listener!(
+  ExportDmabufEventManager,
+  Rc<ExportDmabufLogger>,
+  [
+    destroy => destroy_func: |this: &mut ExportDmabufEventManager, _data: *mut libc::c_void,| unsafe {
+      this.data.destroy();
+    };
+  ]
+);
+
+/// The output of the export frame that `message` tells the client is ready
+unsafe fn exported_frame_output(
+  export_dmabuf_manager: *mut wlr_export_dmabuf_manager_v1,
+  direction: wl_protocol_logger_type,
+  message: &wl_protocol_logger_message,
+) -> Option<*mut wlr_output> {
+  if direction != wl_protocol_logger_type_WL_PROTOCOL_LOGGER_EVENT
+    || message.message_opcode as u32 != EXPORT_DMABUF_FRAME_READY
+  {
+    return None;
+  }
+  let mut output = None;
+  wl_list_for_each!(
+    (*export_dmabuf_manager).frames,
+    link,
+    (frame: wlr_export_dmabuf_frame_v1) => {
+      if (*frame).resource == message.resource {
+        output = Some((*frame).output);
+      }
+    }
+  );
+  output
+}
+
+/// Fires on_frame_exported when wlroots sends a ready event for an export
+/// frame, as it does not otherwise announce exported frames
+unsafe extern "C" fn handle_export_dmabuf_event(
+  user_data: *mut libc::c_void,
+  direction: wl_protocol_logger_type,
+  message: *const wl_protocol_logger_message,
+) {
+  let export_dmabuf_logger = &*(user_data as *const ExportDmabufLogger);
+  let wlr_output = match exported_frame_output(
+    export_dmabuf_logger.export_dmabuf_manager,
+    direction,
+    &*message,
+  ) {
+    Some(wlr_output) => wlr_output,
+    None => return,
+  };
+  if let Some(output_manager) = export_dmabuf_logger.output_manager.upgrade() {
+    let output = output_manager
+      .outputs()
+      .iter()
+      .find(|output| output.raw_ptr() == wlr_output)
+      .cloned();
+    if let Some(output) = output {
+      output.on_frame_exported.fire(());
+    }
+  }
+}
+
 wayland_listener!(
   OutputManagerEventManager,
   Rc<OutputManager>,
@@ -225,7 +686,7 @@ wayland_listener!(
       new_output(this.data.clone(), data as *mut wlr_output)
     };
     output_layout_change => output_layout_change_func: |this: &mut OutputManagerEventManager, _data: *mut libc::c_void,| unsafe {
-      this.data.on_output_layout_change.fire(());
+      this.data.output_layout_changed();
     };
   ]
 );
@@ -237,6 +698,112 @@ mod tests {
   use std::ptr;
   use std::rc::Rc;
 
+  fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+    Rectangle {
+      top_left: Point { x, y },
+      size: Size { width, height },
+    }
+  }
+
+  #[test]
+  fn it_moves_orphaned_windows_inside_the_nearest_output() {
+    let output = rect(0, 0, 1000, 800);
+
+    assert_eq!(distance_to(&output, Point { x: 500, y: 400 }), 0.0);
+    assert_eq!(distance_to(&output, Point { x: 1300, y: 1200 }), 500.0);
+
+    assert_eq!(
+      position_inside(&rect(1500, 100, 400, 300), &output),
+      Point { x: 600, y: 100 }
+    );
+    assert_eq!(
+      position_inside(&rect(-200, -50, 400, 300), &output),
+      Point { x: 0, y: 0 }
+    );
+    assert_eq!(
+      position_inside(&rect(1500, 100, 1200, 300), &output),
+      Point { x: 0, y: 100 }
+    );
+  }
+
+  #[test]
+  fn it_keeps_a_margin_of_windows_inside_the_output() {
+    let output = rect(0, 0, 1000, 800);
+
+    assert_eq!(
+      position_partly_inside(&rect(1500, 100, 400, 300), &output, 50),
+      Point { x: 950, y: 100 }
+    );
+    assert_eq!(
+      position_partly_inside(&rect(-600, -400, 400, 300), &output, 50),
+      Point { x: -350, y: -250 }
+    );
+    assert_eq!(
+      position_partly_inside(&rect(-100, 100, 400, 300), &output, 50),
+      Point { x: -100, y: 100 }
+    );
+    assert_eq!(
+      position_partly_inside(&rect(1500, 100, 400, 300), &output, 0),
+      Point { x: 600, y: 100 }
+    );
+  }
+
+  #[test]
+  fn it_finds_the_output_of_exported_frames() {
+    unsafe {
+      let mut export_dmabuf_manager: wlr_export_dmabuf_manager_v1 = std::mem::zeroed();
+      let mut frame: wlr_export_dmabuf_frame_v1 = std::mem::zeroed();
+      let wlr_output = 1 as *mut wlr_output;
+      let resource = 2 as *mut wl_resource;
+      frame.output = wlr_output;
+      frame.resource = resource;
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_list_init,
+        &mut export_dmabuf_manager.frames
+      );
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_list_insert,
+        &mut export_dmabuf_manager.frames,
+        &mut frame.link
+      );
+
+      let mut message: wl_protocol_logger_message = std::mem::zeroed();
+      message.resource = resource;
+      message.message_opcode = EXPORT_DMABUF_FRAME_READY as i32;
+      assert_eq!(
+        exported_frame_output(
+          &mut export_dmabuf_manager,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_EVENT,
+          &message
+        ),
+        Some(wlr_output)
+      );
+
+      // The frame and object events are sent before the frame is ready
+      message.message_opcode = 0;
+      assert_eq!(
+        exported_frame_output(
+          &mut export_dmabuf_manager,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_EVENT,
+          &message
+        ),
+        None
+      );
+      message.message_opcode = EXPORT_DMABUF_FRAME_READY as i32;
+      message.resource = 3 as *mut wl_resource;
+      assert_eq!(
+        exported_frame_output(
+          &mut export_dmabuf_manager,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_EVENT,
+          &message
+        ),
+        None
+      );
+    }
+  }
+
   #[test]
   fn it_drops_and_cleans_up_on_destroy() {
     let config_manager = Rc::new(ConfigManager::default());
@@ -252,10 +819,18 @@ mod tests {
       wm_policy_manager: wm_policy_manager.clone(),
       window_manager: window_manager.clone(),
       display: ptr::null_mut(),
+      backend: ptr::null_mut(),
       renderer: ptr::null_mut(),
       output_layout: ptr::null_mut(),
       xdg_output_manager_v1: ptr::null_mut(),
+      screencopy_manager: Cell::new(ptr::null_mut()),
+      export_dmabuf_manager: Cell::new(ptr::null_mut()),
+      export_dmabuf_event_manager: RefCell::new(None),
       outputs: RefCell::new(vec![]),
+      background_image: RefCell::new(None),
+      profile_store: RefCell::new(None),
+      is_committing: Cell::new(false),
+      layout_changed_while_committing: Cell::new(false),
       on_new_output: Event::default(),
       on_output_layout_change: Event::default(),
 
@@ -277,4 +852,7 @@ mod tests {
 #[cfg(test)]
 pub unsafe fn wlr_output_layout_add_auto(_: *mut wlr_output_layout, _: *mut wlr_output) {}
 #[cfg(test)]
+pub unsafe fn wlr_output_layout_add(_: *mut wlr_output_layout, _: *mut wlr_output, _: i32, _: i32) {
+}
+#[cfg(test)]
 pub unsafe fn wlr_output_create_global(_: *mut wlr_output) {}
@@ -0,0 +1,205 @@
+use crate::{
+  geometry::{FDisplacement, Point, Rectangle, Size},
+  window::Window,
+  window_manager::{WindowLayer, WindowManager},
+};
+use std::rc::Rc;
+
+/// Splits `area` into a grid with a cell for each of `count` windows,
+/// with `spacing` pixels between and around the cells
+fn grid_cells(count: usize, area: &Rectangle, spacing: i32) -> Vec<Rectangle> {
+  if count == 0 {
+    return vec![];
+  }
+  let columns = (count as f64).sqrt().ceil() as i32;
+  let rows = (count as i32 + columns - 1) / columns;
+  let cell_size = Size {
+    width: ((area.width() - spacing) / columns - spacing).max(1),
+    height: ((area.height() - spacing) / rows - spacing).max(1),
+  };
+
+  (0..count as i32)
+    .map(|i| Rectangle {
+      top_left: Point {
+        x: area.left() + spacing + (i % columns) * (cell_size.width + spacing),
+        y: area.top() + spacing + (i / columns) * (cell_size.height + spacing),
+      },
+      size: cell_size,
+    })
+    .collect()
+}
+
+/// Scales `size` down to fit inside `cell`, centered, keeping the aspect ratio
+fn fit_in_cell(size: Size, cell: &Rectangle) -> (Rectangle, f32) {
+  let scale = (cell.width() as f32 / size.width.max(1) as f32)
+    .min(cell.height() as f32 / size.height.max(1) as f32)
+    .min(1.0);
+  let size = size * scale;
+  let thumbnail = Rectangle {
+    top_left: Point {
+      x: cell.center_x() - size.width / 2,
+      y: cell.center_y() - size.height / 2,
+    },
+    size,
+  };
+  (thumbnail, scale)
+}
+
+#[derive(Debug)]
+pub struct OverviewSlot {
+  window: Rc<Window>,
+  thumbnail: Rectangle,
+  scale: f32,
+  /// The render transform of the window before the overview was applied
+  previous_transform: (FDisplacement, f32),
+}
+
+impl OverviewSlot {
+  pub fn window(&self) -> Rc<Window> {
+    self.window.clone()
+  }
+  /// Where the window is rendered in the overview, in layout coordinates
+  pub fn thumbnail(&self) -> Rectangle {
+    self.thumbnail.clone()
+  }
+}
+
+/// Lays out windows scaled down into a non-overlapping grid using their
+/// render transforms, without changing their actual position or size.
+#[derive(Debug)]
+pub struct Overview {
+  slots: Vec<OverviewSlot>,
+}
+
+impl Overview {
+  /// Lays out `windows` in `area`. The windows are placed in the order they
+  /// are passed, row by row.
+  pub fn new(windows: Vec<Rc<Window>>, area: &Rectangle, spacing: i32) -> Overview {
+    let cells = grid_cells(windows.len(), area, spacing);
+    let slots = windows
+      .into_iter()
+      .zip(cells.iter())
+      .map(|(window, cell)| {
+        let (thumbnail, scale) = fit_in_cell(window.buffer_extents().size(), cell);
+        let previous_transform = window.render_transform();
+        OverviewSlot {
+          window,
+          thumbnail,
+          scale,
+          previous_transform,
+        }
+      })
+      .collect();
+    Overview { slots }
+  }
+
+  /// Lays out all normal windows that are rendered, in rendering order
+  pub fn for_window_manager(
+    window_manager: &WindowManager,
+    area: &Rectangle,
+    spacing: i32,
+  ) -> Overview {
+    let windows = window_manager
+      .windows_to_render()
+      .filter(|window| window.original_layer() == WindowLayer::Normal)
+      .collect();
+    Overview::new(windows, area, spacing)
+  }
+
+  pub fn slots(&self) -> &Vec<OverviewSlot> {
+    &self.slots
+  }
+
+  /// Sets the render transforms of the windows to show the overview
+  pub fn apply(&self) {
+    for slot in self.slots.iter() {
+      let top_left = slot.window.buffer_extents().top_left();
+      slot
+        .window
+        .set_render_transform((slot.thumbnail.top_left() - top_left).into(), slot.scale);
+    }
+  }
+
+  /// Restores the render transforms the windows had before the overview
+  pub fn restore(&self) {
+    for slot in self.slots.iter() {
+      let (translate, scale) = slot.previous_transform.clone();
+      slot.window.set_render_transform(translate, scale);
+    }
+  }
+
+  /// The window whose thumbnail is at the point
+  pub fn window_at(&self, point: &Point) -> Option<Rc<Window>> {
+    self
+      .slots
+      .iter()
+      .find(|slot| slot.thumbnail.contains(point))
+      .map(|slot| slot.window.clone())
+  }
+
+  /// Focuses the window whose thumbnail is at the point, returning it if it
+  /// could be focused
+  pub fn focus_window_at(
+    &self,
+    window_manager: &WindowManager,
+    point: &Point,
+  ) -> Option<Rc<Window>> {
+    let window = self.window_at(point)?;
+    window_manager.focus_window(window.clone()).ok()?;
+    Some(window)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+    Rectangle {
+      top_left: Point { x, y },
+      size: Size { width, height },
+    }
+  }
+
+  #[test]
+  fn it_lays_out_grid_cells() {
+    let area = rect(0, 0, 1000, 500);
+
+    assert_eq!(grid_cells(0, &area, 10), Vec::<Rectangle>::new());
+    assert_eq!(grid_cells(1, &area, 10), vec![rect(10, 10, 980, 480)]);
+    assert_eq!(
+      grid_cells(3, &area, 10),
+      vec![
+        rect(10, 10, 485, 235),
+        rect(505, 10, 485, 235),
+        rect(10, 255, 485, 235),
+      ]
+    );
+  }
+
+  #[test]
+  fn it_fits_windows_in_cells() {
+    let cell = rect(0, 0, 200, 200);
+
+    assert_eq!(
+      fit_in_cell(
+        Size {
+          width: 800,
+          height: 400
+        },
+        &cell
+      ),
+      (rect(0, 50, 200, 100), 0.25)
+    );
+    assert_eq!(
+      fit_in_cell(
+        Size {
+          width: 100,
+          height: 50
+        },
+        &cell
+      ),
+      (rect(50, 75, 100, 50), 1.0)
+    );
+  }
+}
@@ -8,7 +8,7 @@ use crate::window_manager::{WindowLayer, WindowManager, WindowManagerExt};
 use log::{debug, error, trace};
 use std::cell::RefCell;
 use std::pin::Pin;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use wlroots_sys::*;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -76,6 +76,12 @@ impl SurfaceExt for LayerSurface {
   fn parent_wlr_surface(&self) -> Option<*mut wlr_surface> {
     None
   }
+  fn popup_root_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    None
+  }
+  fn transient_for_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    None
+  }
 
   fn buffer_displacement(&self) -> Displacement {
     let surface = unsafe { &*self.wlr_surface() };
@@ -92,6 +98,18 @@ impl SurfaceExt for LayerSurface {
     Displacement::ZERO
   }
 
+  fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)> {
+    let mut sub_x = 0.0;
+    let mut sub_y = 0.0;
+    let surface =
+      unsafe { wlr_layer_surface_v1_surface_at(self.0, point.x, point.y, &mut sub_x, &mut sub_y) };
+    if surface.is_null() {
+      None
+    } else {
+      Some((surface, FPoint { x: sub_x, y: sub_y }))
+    }
+  }
+
   fn extents(&self) -> Rectangle {
     unsafe {
       Rectangle {
@@ -154,10 +172,19 @@ impl SurfaceExt for LayerSurface {
   fn set_resizing(&self, _resizing: bool) -> u32 {
     0
   }
+  fn set_tiled(&self, _edges: u32) -> u32 {
+    0
+  }
 
   fn is_toplevel(&self) -> bool {
     false
   }
+  fn window_type(&self) -> WindowType {
+    WindowType::Layer
+  }
+  fn is_modal(&self) -> bool {
+    false
+  }
   fn app_id(&self) -> Option<String> {
     None
   }
@@ -191,6 +218,7 @@ wayland_listener!(
     commit => commit_func: |this: &mut LayerSurfaceEventManager, _data: *mut libc::c_void,| unsafe {
       let handler = &mut this.data;
       if let Some(window) = handler.window.upgrade() {
+        window.invalidate_extents();
         update_anchor_edges(handler.output_manager.clone(), &window);
         if let Surface::Layer(ref layer_surface_v1) = window.surface {
           handler.commit(WindowCommitEvent {
@@ -275,7 +303,6 @@ impl LayersEventHandler {
       window_manager: self.window_manager.clone(),
       cursor_manager: self.cursor_manager.clone(),
       window: Rc::downgrade(&window),
-      foreign_toplevel_handle: None,
       foreign_toplevel_event_manager: None,
     });
 
@@ -289,23 +316,31 @@ impl LayersEventHandler {
     *window.event_manager.borrow_mut() = Some(SurfaceEventManager::Layer(event_manager));
 
     update_anchor_edges(self.output_manager.clone(), &window);
-
-    let output_manager = &self.output_manager;
-    let subscription_id = self.output_manager.on_output_layout_change().subscribe(
-      listener!(output_manager, window => move || {
-        update_anchor_edges(output_manager.clone(), &window);
-      }),
-    );
-    window
-      .on_destroy()
-      .then(listener!(output_manager => move || {
-        output_manager.on_output_layout_change().unsubscribe(subscription_id);
-      }));
+    follow_output_layout(&self.output_manager, &window);
 
     self.wm_policy_manager.advise_new_window(window);
   }
 }
 
+/// Updates the position of the window whenever the output layout changes,
+/// until the window is destroyed
+///
+/// The listener only holds a weak reference so that it does not keep the
+/// window alive.
+fn follow_output_layout(output_manager: &Rc<OutputManager>, window: &Rc<Window>) {
+  let weak_window = Rc::downgrade(window);
+  let subscription = output_manager.on_output_layout_change().subscribe_scoped(
+    listener!(output_manager => move || {
+      if let Some(window) = Weak::upgrade(&weak_window) {
+        update_anchor_edges(output_manager.clone(), &window);
+      }
+    }),
+  );
+  window
+    .on_destroy()
+    .then(Box::new(move |_| drop(subscription)));
+}
+
 fn update_anchor_edges(output_manager: Rc<OutputManager>, window: &Window) {
   if let Surface::Layer(surface) = window.surface() {
     let attached_edges = surface.client_pending().attached_edges();
@@ -314,9 +349,9 @@ fn update_anchor_edges(output_manager: Rc<OutputManager>, window: &Window) {
     let configured = unsafe { (*surface.0).configured };
     let output = output_manager
       .outputs()
-      .clone()
-      .into_iter()
-      .find(|output| output.raw_ptr() == unsafe { (*surface.0).output });
+      .iter()
+      .find(|output| output.raw_ptr() == unsafe { (*surface.0).output })
+      .cloned();
     let output = match output {
       Some(output) => output,
       None => {
@@ -418,3 +453,34 @@ impl LayerShellManager {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::MockManagers;
+
+  #[test]
+  fn it_unsubscribes_from_layout_changes_on_destroy() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let output_manager = &managers.output_manager;
+    let listener_count = output_manager.on_output_layout_change().listener_count();
+
+    let window = window_manager.new_window(WindowLayer::Top, Surface::Null);
+    follow_output_layout(output_manager, &window);
+    assert_eq!(
+      output_manager.on_output_layout_change().listener_count(),
+      listener_count + 1
+    );
+
+    let weak_window = Rc::downgrade(&window);
+    window.on_destroy.fire(());
+    window_manager.destroy_window(window);
+
+    assert_eq!(
+      output_manager.on_output_layout_change().listener_count(),
+      listener_count
+    );
+    assert!(weak_window.upgrade().is_none());
+  }
+}
@@ -6,10 +6,10 @@ use crate::window::*;
 use crate::window_management_policy::WmPolicyManager;
 use crate::window_manager::{WindowLayer, WindowManager, WindowManagerExt};
 use log::debug;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::ffi::CStr;
 use std::pin::Pin;
-use std::ptr::NonNull;
+use std::ptr::{self, NonNull};
 use std::rc::Rc;
 use wlroots_sys::*;
 
@@ -48,6 +48,29 @@ impl XdgSurface {
       }
     }
   }
+
+  /// Pings the client, which has the ping timeout of the shell to respond
+  /// before the ping_timeout event is emitted
+  pub(crate) fn ping(&self) {
+    unsafe {
+      wlr_xdg_surface_ping(self.0);
+    }
+  }
+
+  fn xdg_client(&self) -> *mut wlr_xdg_client {
+    unsafe { (*self.0).client }
+  }
+
+  /// Flips or slides a popup, as allowed by its positioner, so that it fits
+  /// inside `area` which is relative to the buffer of the popup tree root
+  pub(crate) fn unconstrain_popup(&self, area: &Rectangle) {
+    if let Popup(popup) = self.get_type() {
+      let wlr_box = area.clone().into();
+      unsafe {
+        wlr_xdg_popup_unconstrain_from_box(popup, &wlr_box);
+      }
+    }
+  }
 }
 
 impl SurfaceExt for XdgSurface {
@@ -65,6 +88,34 @@ impl SurfaceExt for XdgSurface {
       _ => None,
     }
   }
+  fn popup_root_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    let mut parent = match self.get_type() {
+      Popup(popup) => unsafe { (*popup).parent },
+      _ => return None,
+    };
+    unsafe {
+      while wlr_surface_is_xdg_surface(parent) {
+        match XdgSurface(wlr_xdg_surface_from_wlr_surface(parent)).get_type() {
+          Popup(popup) => parent = (*popup).parent,
+          _ => break,
+        }
+      }
+    }
+    Some(parent)
+  }
+  fn transient_for_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    match self.get_type() {
+      Toplevel(toplevel) => unsafe {
+        let parent = (*toplevel).parent;
+        if parent.is_null() {
+          None
+        } else {
+          Some((*parent).surface)
+        }
+      },
+      _ => None,
+    }
+  }
 
   fn buffer_displacement(&self) -> Displacement {
     let surface = unsafe { &*self.wlr_surface() };
@@ -78,26 +129,45 @@ impl SurfaceExt for XdgSurface {
   }
 
   fn parent_displacement(&self) -> Displacement {
-    match self.get_type() {
-      Popup(popup) => unsafe {
-        if !wlr_surface_is_xdg_surface((*popup).parent) {
-          return Displacement {
+    // A popup is positioned relative to the window geometry of its parent,
+    // which is itself a popup for nested menus. The offsets are accumulated
+    // up to the root of the popup tree, as the window geometry offsets of
+    // intermediate popups cancel out.
+    let mut displacement = Displacement::ZERO;
+    let mut popup = match self.get_type() {
+      Popup(popup) => popup,
+      _ => return displacement,
+    };
+    unsafe {
+      loop {
+        displacement = displacement
+          + Displacement {
             dx: (*popup).geometry.x,
             dy: (*popup).geometry.y,
           };
-        }
-
-        let parent = wlr_xdg_surface_from_wlr_surface((*popup).parent);
-        let mut parent_geo = Rectangle::ZERO.into();
-
-        wlr_xdg_surface_get_geometry(parent, &mut parent_geo);
 
-        Displacement {
-          dx: parent_geo.x + (*popup).geometry.x,
-          dy: parent_geo.y + (*popup).geometry.y,
+        let parent = (*popup).parent;
+        if !wlr_surface_is_xdg_surface(parent) {
+          return displacement;
         }
-      },
-      _ => Displacement::ZERO,
+        let parent = XdgSurface(wlr_xdg_surface_from_wlr_surface(parent));
+        match parent.get_type() {
+          Popup(parent_popup) => popup = parent_popup,
+          _ => return displacement + parent.extents().top_left().as_displacement(),
+        }
+      }
+    }
+  }
+
+  fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)> {
+    let mut sub_x = 0.0;
+    let mut sub_y = 0.0;
+    let surface =
+      unsafe { wlr_xdg_surface_surface_at(self.0, point.x, point.y, &mut sub_x, &mut sub_y) };
+    if surface.is_null() {
+      None
+    } else {
+      Some((surface, FPoint { x: sub_x, y: sub_y }))
     }
   }
 
@@ -214,6 +284,12 @@ impl SurfaceExt for XdgSurface {
       _ => 0,
     }
   }
+  fn set_tiled(&self, edges: u32) -> u32 {
+    match self.get_type() {
+      Toplevel(_) => unsafe { wlr_xdg_toplevel_set_tiled(self.0, edges) },
+      _ => 0,
+    }
+  }
 
   fn is_toplevel(&self) -> bool {
     match self.get_type() {
@@ -221,6 +297,24 @@ impl SurfaceExt for XdgSurface {
       _ => false,
     }
   }
+  fn window_type(&self) -> WindowType {
+    match self.get_type() {
+      Toplevel(toplevel) => unsafe {
+        if (*toplevel).parent.is_null() {
+          WindowType::Toplevel
+        } else {
+          WindowType::Dialog
+        }
+      },
+      Popup(_) => WindowType::Popup,
+      XdgSurfaceType::None => WindowType::Toplevel,
+    }
+  }
+  fn is_modal(&self) -> bool {
+    // xdg-shell does not have a modal hint and being transient does not
+    // make a window modal
+    false
+  }
   fn app_id(&self) -> Option<String> {
     match self.get_type() {
       Toplevel(toplevel) => unsafe {
@@ -275,8 +369,17 @@ wayland_listener!(
       let handler = &mut this.data;
       handler.destroy();
     };
-    new_popup => new_popup_func: |_this: &mut XdgSurfaceEventManager, _data: *mut libc::c_void,| unsafe {
+    new_popup => new_popup_func: |this: &mut XdgSurfaceEventManager, data: *mut libc::c_void,| unsafe {
       debug!("XdgSurfaceEventManager::new_popup");
+      let handler = &mut this.data;
+      let popup: *mut wlr_xdg_popup = data as _;
+      XdgEventHandler {
+        wm_policy_manager: handler.wm_policy_manager.clone(),
+        output_manager: handler.output_manager.clone(),
+        window_manager: handler.window_manager.clone(),
+        cursor_manager: handler.cursor_manager.clone(),
+      }
+      .new_surface((*popup).base);
     };
     commit => commit_func: |this: &mut XdgSurfaceEventManager, _data: *mut libc::c_void,| unsafe {
       let handler = &mut this.data;
@@ -331,6 +434,10 @@ wayland_listener!(
       let handler = &mut this.data;
       handler.updated_title();
     };
+    ping_timeout => ping_timeout_func: |this: &mut XdgSurfaceEventManager, _data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.ping_timeout();
+    };
   ]
 );
 
@@ -344,14 +451,20 @@ impl XdgEventHandler {
   fn new_surface(&mut self, xdg_surface: *mut wlr_xdg_surface) {
     debug!("XdgEventHandler::new_surface");
 
+    // Popups are announced both by their parent, when created, and by the
+    // shell, on their first commit, so they may already have a window
+    let wlr_surface = unsafe { (*xdg_surface).surface };
+    if self.window_manager.window_by_surface(wlr_surface).is_some() {
+      return;
+    }
+
     let layer = match XdgSurface(xdg_surface).get_type() {
       Popup(popup) => {
         let wlr_surface = unsafe { (*popup).parent };
         self
           .window_manager
-          .windows()
-          .find(|window| window.wlr_surface() == wlr_surface)
-          .map_or(WindowLayer::Normal, |window| window.layer)
+          .window_by_surface(wlr_surface)
+          .map_or(WindowLayer::Normal, |window| window.layer())
       }
       _ => WindowLayer::Normal,
     };
@@ -366,7 +479,6 @@ impl XdgEventHandler {
       window_manager: self.window_manager.clone(),
       cursor_manager: self.cursor_manager.clone(),
       window: Rc::downgrade(&window),
-      foreign_toplevel_handle: None,
       foreign_toplevel_event_manager: None,
     });
 
@@ -376,6 +488,7 @@ impl XdgEventHandler {
       event_manager.destroy(&mut (*xdg_surface).events.destroy);
       event_manager.new_popup(&mut (*xdg_surface).events.new_popup);
       event_manager.commit(&mut (*(*xdg_surface).surface).events.commit);
+      event_manager.ping_timeout(&mut (*xdg_surface).events.ping_timeout);
 
       if let Toplevel(toplevel) = XdgSurface(xdg_surface).get_type() {
         let toplevel = &mut *toplevel;
@@ -392,8 +505,47 @@ impl XdgEventHandler {
 
     *window.event_manager.borrow_mut() = Some(SurfaceEventManager::Xdg(event_manager));
 
+    self.unconstrain_popup(&window);
+
+    if let Toplevel(_) = XdgSurface(xdg_surface).get_type() {
+      let state = self.wm_policy_manager.initial_window_state(window.clone());
+      window.apply_initial_state(state);
+    }
     self.wm_policy_manager.advise_new_window(window);
   }
+
+  /// Keeps popups inside the output they are anchored on
+  ///
+  /// The positioner is only applied when the popup is created as the
+  /// xdg_popup.reposition request is not supported by this version of wlroots.
+  fn unconstrain_popup(&self, window: &Window) {
+    let xdg_surface = match window.surface {
+      Surface::Xdg(ref xdg_surface) => xdg_surface,
+      _ => return,
+    };
+    let root = match xdg_surface
+      .popup_root_wlr_surface()
+      .and_then(|root_wlr_surface| self.window_manager.window_by_surface(root_wlr_surface))
+    {
+      Some(root) => root,
+      None => return,
+    };
+
+    let anchor = window.extents().top_left();
+    let output = self
+      .output_manager
+      .outputs()
+      .iter()
+      .find(|output| output.extents().contains(&anchor))
+      .cloned()
+      .or_else(|| root.outputs().first().cloned());
+
+    if let Some(output) = output {
+      let root_top_left = root.buffer_extents().top_left();
+      let area = output.extents() - root_top_left.as_displacement();
+      xdg_surface.unconstrain_popup(&area);
+    }
+  }
 }
 
 wayland_listener!(
@@ -407,9 +559,90 @@ wayland_listener!(
   ]
 );
 
+/// The opcode of the xdg_wm_base.pong request
+const XDG_WM_BASE_PONG: u32 = 3;
+
+struct PongLogger {
+  xdg_shell: *mut wlr_xdg_shell,
+  window_manager: Rc<WindowManager>,
+  logger: Cell<*mut wl_protocol_logger>,
+}
+
+impl PongLogger {
+  /// The logger has to be destroyed before the display, as the display does
+  /// not free its loggers
+  fn destroy(&self) {
+    let logger = self.logger.replace(ptr::null_mut());
+    if !logger.is_null() {
+      unsafe {
+        wl_protocol_logger_destroy(logger);
+      }
+    }
+  }
+}
+
+wayland_listener!(
+  PongLoggerEventManager,
+  Rc<PongLogger>,
+  [
+     destroy => destroy_func: |this: &mut PongLoggerEventManager, _data: *mut libc::c_void,| unsafe {
+         this.data.destroy()
+     };
+  ]
+);
+
+/// The client of `xdg_shell` that sent `message` if it is a pong
+unsafe fn pong_client(
+  xdg_shell: *mut wlr_xdg_shell,
+  direction: wl_protocol_logger_type,
+  message: &wl_protocol_logger_message,
+) -> Option<*mut wlr_xdg_client> {
+  if direction != wl_protocol_logger_type_WL_PROTOCOL_LOGGER_REQUEST
+    || message.message_opcode as u32 != XDG_WM_BASE_PONG
+  {
+    return None;
+  }
+  let mut pong_client = None;
+  wl_list_for_each!(
+    (*xdg_shell).clients,
+    link,
+    (client: wlr_xdg_client) => {
+      if (*client).resource == message.resource {
+        pong_client = Some(client);
+      }
+    }
+  );
+  pong_client
+}
+
+/// Marks the windows of a client as responsive as soon as it answers a ping
+///
+/// wlroots does not announce pongs, and ignores pongs that arrive after the
+/// ping timed out, so requests are inspected by a protocol logger instead.
+unsafe extern "C" fn handle_pong_request(
+  user_data: *mut libc::c_void,
+  direction: wl_protocol_logger_type,
+  message: *const wl_protocol_logger_message,
+) {
+  let pong_logger = &*(user_data as *const PongLogger);
+  let client = match pong_client(pong_logger.xdg_shell, direction, &*message) {
+    Some(client) => client,
+    None => return,
+  };
+  for window in pong_logger.window_manager.windows() {
+    if let Surface::Xdg(surface) = &window.surface {
+      if surface.xdg_client() == client {
+        window.handle_pong();
+      }
+    }
+  }
+}
+
 #[allow(unused)]
 pub(crate) struct XdgManager {
   xdg_shell: *mut wlr_xdg_shell,
+  pong_logger: Rc<PongLogger>,
+  pong_logger_event_manager: Pin<Box<PongLoggerEventManager>>,
 
   event_manager: Pin<Box<XdgEventManager>>,
   event_handler: Rc<RefCell<XdgEventHandler>>,
@@ -427,6 +660,23 @@ impl XdgManager {
 
     let xdg_shell = unsafe { wlr_xdg_shell_create(display) };
 
+    window_manager.set_xdg_shell(xdg_shell);
+
+    let pong_logger = Rc::new(PongLogger {
+      xdg_shell,
+      window_manager: window_manager.clone(),
+      logger: Cell::new(ptr::null_mut()),
+    });
+    let mut pong_logger_event_manager = PongLoggerEventManager::new(pong_logger.clone());
+    unsafe {
+      pong_logger.logger.set(wl_display_add_protocol_logger(
+        display,
+        Some(handle_pong_request),
+        &*pong_logger as *const PongLogger as *mut libc::c_void,
+      ));
+      pong_logger_event_manager.destroy(&mut (*xdg_shell).events.destroy);
+    }
+
     let event_handler = Rc::new(RefCell::new(XdgEventHandler {
       wm_policy_manager,
       output_manager,
@@ -441,9 +691,379 @@ impl XdgManager {
 
     XdgManager {
       xdg_shell,
+      pong_logger,
+      pong_logger_event_manager,
 
       event_manager,
       event_handler,
     }
   }
 }
+
+impl Drop for XdgManager {
+  fn drop(&mut self) {
+    self.pong_logger.destroy();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::animation::AnimationManager;
+  use crate::input::event_filter::EventFilterManager;
+  use crate::test_util::MockManagers;
+  use std::mem;
+
+  fn wlr_box(x: i32, y: i32, width: i32, height: i32) -> wlr_box {
+    wlr_box {
+      x,
+      y,
+      width,
+      height,
+    }
+  }
+
+  /// Connects zeroed wlroots structs into an xdg toplevel
+  unsafe fn init_toplevel(
+    surface: &mut wlr_surface,
+    xdg_surface: &mut wlr_xdg_surface,
+    toplevel: &mut wlr_xdg_toplevel,
+  ) {
+    xdg_surface.role = wlr_xdg_surface_role_WLR_XDG_SURFACE_ROLE_TOPLEVEL;
+    xdg_surface.__bindgen_anon_1.toplevel = toplevel;
+    xdg_surface.surface = surface;
+    surface.role_data = xdg_surface as *mut _ as *mut libc::c_void;
+  }
+
+  /// Connects zeroed wlroots structs into an xdg popup of `parent`
+  unsafe fn init_popup(
+    surface: &mut wlr_surface,
+    xdg_surface: &mut wlr_xdg_surface,
+    popup: &mut wlr_xdg_popup,
+    parent: *mut wlr_surface,
+  ) {
+    popup.parent = parent;
+    xdg_surface.role = wlr_xdg_surface_role_WLR_XDG_SURFACE_ROLE_POPUP;
+    xdg_surface.__bindgen_anon_1.popup = popup;
+    xdg_surface.surface = surface;
+    surface.role_data = xdg_surface as *mut _ as *mut libc::c_void;
+  }
+
+  #[test]
+  fn it_relates_popups_and_transient_windows_to_their_parent() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    unsafe {
+      let mut parent_surface: wlr_surface = mem::zeroed();
+      let mut parent_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut parent_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      init_toplevel(&mut parent_surface, &mut parent_xdg, &mut parent_toplevel);
+
+      let mut dialog_surface: wlr_surface = mem::zeroed();
+      let mut dialog_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut dialog_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      dialog_toplevel.parent = &mut parent_xdg;
+      init_toplevel(&mut dialog_surface, &mut dialog_xdg, &mut dialog_toplevel);
+
+      let mut menu_surface: wlr_surface = mem::zeroed();
+      let mut menu_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut menu_popup: wlr_xdg_popup = mem::zeroed();
+      init_popup(
+        &mut menu_surface,
+        &mut menu_xdg,
+        &mut menu_popup,
+        &mut parent_surface,
+      );
+
+      let parent = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut parent_xdg)),
+      );
+      let dialog = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut dialog_xdg)),
+      );
+      let menu =
+        window_manager.new_window(WindowLayer::Normal, Surface::Xdg(XdgSurface(&mut menu_xdg)));
+      let unrelated = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+      assert_eq!(dialog.parent(), Some(parent.clone()));
+      assert_eq!(menu.parent(), Some(parent.clone()));
+      assert_eq!(parent.parent(), None);
+      assert_eq!(unrelated.parent(), None);
+      assert_eq!(parent.children(), vec![dialog.clone(), menu.clone()]);
+      assert!(dialog.children().is_empty());
+      assert!(unrelated.children().is_empty());
+
+      let parent_events = Rc::new(RefCell::new(vec![]));
+      dialog.on_parent_event().subscribe(Box::new({
+        let parent_events = parent_events.clone();
+        move |event| parent_events.borrow_mut().push(*event)
+      }));
+      let unrelated_events = Rc::new(Cell::new(0));
+      unrelated.on_parent_event().subscribe(Box::new({
+        let unrelated_events = unrelated_events.clone();
+        move |_| unrelated_events.set(unrelated_events.get() + 1)
+      }));
+
+      let cursor_manager = CursorManager::mock(
+        managers.config_manager.clone(),
+        managers.output_manager.clone(),
+        window_manager.clone(),
+        managers.seat_manager.clone(),
+        Rc::new(EventFilterManager::new()),
+        ptr::null_mut(),
+        ptr::null_mut(),
+      );
+      let mut event_handler = WindowEventHandler {
+        wm_policy_manager: managers.wm_policy_manager.clone(),
+        output_manager: managers.output_manager.clone(),
+        window_manager: window_manager.clone(),
+        cursor_manager,
+        window: Rc::downgrade(&parent),
+        foreign_toplevel_event_manager: None,
+      };
+      event_handler.destroy();
+
+      assert_eq!(*parent_events.borrow(), vec![ParentEvent::Destroyed]);
+      assert_eq!(unrelated_events.get(), 0);
+      assert_eq!(dialog.parent(), None);
+    }
+  }
+
+  #[test]
+  fn it_moves_transient_windows_but_not_popups_with_their_parent() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    unsafe {
+      let mut parent_surface: wlr_surface = mem::zeroed();
+      let mut parent_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut parent_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      init_toplevel(&mut parent_surface, &mut parent_xdg, &mut parent_toplevel);
+
+      let mut dialog_surface: wlr_surface = mem::zeroed();
+      let mut dialog_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut dialog_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      dialog_toplevel.parent = &mut parent_xdg;
+      init_toplevel(&mut dialog_surface, &mut dialog_xdg, &mut dialog_toplevel);
+
+      let mut menu_surface: wlr_surface = mem::zeroed();
+      let mut menu_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut menu_popup: wlr_xdg_popup = mem::zeroed();
+      init_popup(
+        &mut menu_surface,
+        &mut menu_xdg,
+        &mut menu_popup,
+        &mut parent_surface,
+      );
+
+      let parent = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut parent_xdg)),
+      );
+      let dialog = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut dialog_xdg)),
+      );
+      let menu =
+        window_manager.new_window(WindowLayer::Normal, Surface::Xdg(XdgSurface(&mut menu_xdg)));
+
+      dialog.move_to(Point { x: 50, y: 60 });
+      parent.move_to_with_children(Point { x: 100, y: 200 });
+
+      assert_eq!(*parent.top_left.borrow(), Point { x: 100, y: 200 });
+      assert_eq!(*dialog.top_left.borrow(), Point { x: 150, y: 260 });
+      assert_eq!(*menu.top_left.borrow(), Point::ZERO);
+    }
+  }
+
+  #[test]
+  fn it_notifies_children_when_the_parent_is_minimized_or_restored() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let animation_manager = AnimationManager::init(managers.output_manager.clone());
+
+    unsafe {
+      let mut parent_surface: wlr_surface = mem::zeroed();
+      let mut parent_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut parent_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      init_toplevel(&mut parent_surface, &mut parent_xdg, &mut parent_toplevel);
+
+      let mut dialog_surface: wlr_surface = mem::zeroed();
+      let mut dialog_xdg: wlr_xdg_surface = mem::zeroed();
+      let mut dialog_toplevel: wlr_xdg_toplevel = mem::zeroed();
+      dialog_toplevel.parent = &mut parent_xdg;
+      init_toplevel(&mut dialog_surface, &mut dialog_xdg, &mut dialog_toplevel);
+
+      let parent = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut parent_xdg)),
+      );
+      let dialog = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut dialog_xdg)),
+      );
+
+      let parent_events = Rc::new(RefCell::new(vec![]));
+      dialog.on_parent_event().subscribe(Box::new({
+        let parent_events = parent_events.clone();
+        move |event| parent_events.borrow_mut().push(*event)
+      }));
+
+      parent.minimize_to_target(&animation_manager);
+      assert!(parent.is_minimized());
+      assert_eq!(*parent_events.borrow(), vec![ParentEvent::Minimized(true)]);
+
+      parent.minimize_to_target(&animation_manager);
+      assert_eq!(parent_events.borrow().len(), 1);
+
+      parent.restore_from_minimize(&animation_manager);
+      assert!(!parent.is_minimized());
+      assert_eq!(
+        *parent_events.borrow(),
+        vec![ParentEvent::Minimized(true), ParentEvent::Minimized(false)]
+      );
+    }
+  }
+
+  #[test]
+  fn it_finds_the_client_that_sent_a_pong() {
+    unsafe {
+      let mut xdg_shell: wlr_xdg_shell = mem::zeroed();
+      let mut client: wlr_xdg_client = mem::zeroed();
+      let resource = 1 as *mut wl_resource;
+      client.resource = resource;
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_list_init, &mut xdg_shell.clients);
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_list_insert,
+        &mut xdg_shell.clients,
+        &mut client.link
+      );
+
+      let mut message: wl_protocol_logger_message = mem::zeroed();
+      message.resource = resource;
+      message.message_opcode = XDG_WM_BASE_PONG as i32;
+      assert_eq!(
+        pong_client(
+          &mut xdg_shell,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_REQUEST,
+          &message
+        ),
+        Some(&mut client as *mut _)
+      );
+      assert_eq!(
+        pong_client(
+          &mut xdg_shell,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_EVENT,
+          &message
+        ),
+        None
+      );
+
+      // Other requests of the client, and requests on other resources
+      message.message_opcode = 2;
+      assert_eq!(
+        pong_client(
+          &mut xdg_shell,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_REQUEST,
+          &message
+        ),
+        None
+      );
+      message.message_opcode = XDG_WM_BASE_PONG as i32;
+      message.resource = 2 as *mut wl_resource;
+      assert_eq!(
+        pong_client(
+          &mut xdg_shell,
+          wl_protocol_logger_type_WL_PROTOCOL_LOGGER_REQUEST,
+          &message
+        ),
+        None
+      );
+    }
+  }
+
+  #[test]
+  fn it_positions_nested_popups() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    unsafe {
+      let mut toplevel_surface: wlr_surface = mem::zeroed();
+      let mut menu_surface: wlr_surface = mem::zeroed();
+      let mut submenu_surface: wlr_surface = mem::zeroed();
+
+      // The toplevel draws client side shadows, so its window geometry is
+      // offset in the buffer
+      let mut toplevel: wlr_xdg_toplevel = mem::zeroed();
+      let mut toplevel_xdg: wlr_xdg_surface = mem::zeroed();
+      toplevel_xdg.role = wlr_xdg_surface_role_WLR_XDG_SURFACE_ROLE_TOPLEVEL;
+      toplevel_xdg.__bindgen_anon_1.toplevel = &mut toplevel;
+      toplevel_xdg.surface = &mut toplevel_surface;
+      toplevel_xdg.geometry = wlr_box(10, 10, 400, 300);
+      toplevel_surface.role_data = &mut toplevel_xdg as *mut _ as *mut libc::c_void;
+
+      let mut menu: wlr_xdg_popup = mem::zeroed();
+      menu.parent = &mut toplevel_surface;
+      menu.geometry = wlr_box(20, 30, 100, 200);
+      let mut menu_xdg: wlr_xdg_surface = mem::zeroed();
+      menu_xdg.role = wlr_xdg_surface_role_WLR_XDG_SURFACE_ROLE_POPUP;
+      menu_xdg.__bindgen_anon_1.popup = &mut menu;
+      menu_xdg.surface = &mut menu_surface;
+      menu_xdg.geometry = wlr_box(4, 4, 100, 200);
+      menu_surface.role_data = &mut menu_xdg as *mut _ as *mut libc::c_void;
+
+      let mut submenu: wlr_xdg_popup = mem::zeroed();
+      submenu.parent = &mut menu_surface;
+      submenu.geometry = wlr_box(100, 50, 80, 60);
+      let mut submenu_xdg: wlr_xdg_surface = mem::zeroed();
+      submenu_xdg.role = wlr_xdg_surface_role_WLR_XDG_SURFACE_ROLE_POPUP;
+      submenu_xdg.__bindgen_anon_1.popup = &mut submenu;
+      submenu_xdg.surface = &mut submenu_surface;
+      submenu_xdg.geometry = wlr_box(4, 4, 80, 60);
+      submenu_surface.role_data = &mut submenu_xdg as *mut _ as *mut libc::c_void;
+
+      let toplevel_window = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut toplevel_xdg)),
+      );
+      toplevel_window.move_to(Point { x: 100, y: 200 });
+      let menu_window =
+        window_manager.new_window(WindowLayer::Normal, Surface::Xdg(XdgSurface(&mut menu_xdg)));
+      let submenu_window = window_manager.new_window(
+        WindowLayer::Normal,
+        Surface::Xdg(XdgSurface(&mut submenu_xdg)),
+      );
+
+      assert_eq!(
+        submenu_window.surface.popup_root_wlr_surface(),
+        Some(&mut toplevel_surface as *mut _)
+      );
+      assert_eq!(
+        toplevel_window.extents().top_left(),
+        Point { x: 100, y: 200 }
+      );
+      assert_eq!(menu_window.extents().top_left(), Point { x: 120, y: 230 });
+      assert_eq!(
+        submenu_window.extents().top_left(),
+        Point { x: 220, y: 280 }
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+unsafe fn wlr_surface_is_xdg_surface(surface: *mut wlr_surface) -> bool {
+  !surface.is_null() && !(*surface).role_data.is_null()
+}
+#[cfg(test)]
+unsafe fn wlr_xdg_surface_from_wlr_surface(surface: *mut wlr_surface) -> *mut wlr_xdg_surface {
+  (*surface).role_data as *mut wlr_xdg_surface
+}
+#[cfg(test)]
+unsafe fn wlr_xdg_surface_get_geometry(surface: *mut wlr_xdg_surface, wlr_box: *mut wlr_box) {
+  *wlr_box = (*surface).geometry;
+}
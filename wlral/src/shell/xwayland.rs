@@ -1,15 +1,16 @@
 use crate::geometry::*;
 use crate::input::cursor::CursorManager;
+use crate::input::seat::SeatManager;
 use crate::output_manager::OutputManager;
 use crate::surface::{Surface, SurfaceEventManager, SurfaceExt};
 use crate::window::*;
 use crate::window_management_policy::WmPolicyManager;
 use crate::window_manager::{WindowLayer, WindowManager, WindowManagerExt};
-use log::{debug, info};
+use log::{debug, error, info};
 use ptr::NonNull;
 use std::cell::RefCell;
 use std::env;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::pin::Pin;
 use std::{ptr, rc::Rc};
 use wlroots_sys::*;
@@ -20,21 +21,203 @@ const CONFIGURE_SERIAL: u32 = 1;
 
 const SIZE_HINT_MINSIZE: u32 = 1 << 4;
 const SIZE_HINT_MAXSIZE: u32 = 1 << 5;
+const SIZE_HINT_RESIZE_INC: u32 = 1 << 6;
+const SIZE_HINT_ASPECT: u32 = 1 << 7;
+const SIZE_HINT_BASE_SIZE: u32 = 1 << 8;
+
+/// The EWMH window types and how they are classified
+const WINDOW_TYPE_ATOM_NAMES: [(&str, WindowType); 12] = [
+  ("_NET_WM_WINDOW_TYPE_NORMAL", WindowType::Toplevel),
+  ("_NET_WM_WINDOW_TYPE_DIALOG", WindowType::Dialog),
+  ("_NET_WM_WINDOW_TYPE_UTILITY", WindowType::Utility),
+  ("_NET_WM_WINDOW_TYPE_TOOLBAR", WindowType::Utility),
+  ("_NET_WM_WINDOW_TYPE_SPLASH", WindowType::Splash),
+  ("_NET_WM_WINDOW_TYPE_MENU", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_DROPDOWN_MENU", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_POPUP_MENU", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_TOOLTIP", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_COMBO", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_DND", WindowType::Popup),
+  ("_NET_WM_WINDOW_TYPE_NOTIFICATION", WindowType::Popup),
+];
+
+/// The _NET_WM_WINDOW_TYPE atoms of the X server
+///
+/// Atoms are only known once Xwayland is ready, until then X11 windows are
+/// classified by their transient and override redirect hints.
+#[derive(Debug, Default)]
+pub(crate) struct WindowTypeAtoms(RefCell<Vec<(xcb_atom_t, WindowType)>>);
+
+impl WindowTypeAtoms {
+  fn window_type(&self, atom: xcb_atom_t) -> Option<WindowType> {
+    self
+      .0
+      .borrow()
+      .iter()
+      .find(|(window_type_atom, _)| *window_type_atom == atom)
+      .map(|(_, window_type)| *window_type)
+  }
+
+  /// Interns the atoms on a connection of our own, as the connection of the
+  /// wlroots xwm is private
+  fn intern(&self, display_name: &str) -> Result<(), ()> {
+    let display_name = CString::new(display_name).map_err(|_| ())?;
+    unsafe {
+      let connection = xcb_connect(display_name.as_ptr(), ptr::null_mut());
+      if xcb_connection_has_error(connection) != 0 {
+        xcb_disconnect(connection);
+        return Err(());
+      }
+      let cookies: Vec<_> = WINDOW_TYPE_ATOM_NAMES
+        .iter()
+        .map(|(name, _)| xcb_intern_atom(connection, 0, name.len() as u16, name.as_ptr() as _))
+        .collect();
+      let mut atoms = vec![];
+      for (cookie, (name, window_type)) in cookies.into_iter().zip(WINDOW_TYPE_ATOM_NAMES.iter()) {
+        let mut error = ptr::null_mut();
+        let reply = xcb_intern_atom_reply(connection, cookie, &mut error);
+        if reply.is_null() {
+          error!("WindowTypeAtoms::intern: Could not intern {}", name);
+        } else {
+          atoms.push(((*reply).atom, *window_type));
+          libc::free(reply as _);
+        }
+        if !error.is_null() {
+          libc::free(error as _);
+        }
+      }
+      xcb_disconnect(connection);
+      *self.0.borrow_mut() = atoms;
+    }
+    Ok(())
+  }
+}
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct XwaylandSurface(*mut wlr_xwayland_surface);
+#[derive(Debug)]
+pub struct XwaylandSurface(*mut wlr_xwayland_surface, Rc<WindowTypeAtoms>);
+
+impl PartialEq for XwaylandSurface {
+  fn eq(&self, other: &XwaylandSurface) -> bool {
+    self.0 == other.0
+  }
+}
+impl Eq for XwaylandSurface {}
 
 impl XwaylandSurface {
-  pub(crate) fn from_wlr_surface(wlr_surface: *mut wlr_surface) -> Result<XwaylandSurface, ()> {
+  pub(crate) fn from_wlr_surface(
+    wlr_surface: *mut wlr_surface,
+    window_type_atoms: Rc<WindowTypeAtoms>,
+  ) -> Result<XwaylandSurface, ()> {
     unsafe {
       if wlr_surface_is_xwayland_surface(wlr_surface) {
         let xwayland_surface = wlr_xwayland_surface_from_wlr_surface(wlr_surface);
-        Ok(XwaylandSurface(xwayland_surface))
+        Ok(XwaylandSurface(xwayland_surface, window_type_atoms))
       } else {
         Err(())
       }
     }
   }
+
+  fn size_hints(&self, flag: u32) -> Option<&wlr_xwayland_surface_size_hints> {
+    unsafe {
+      let size_hints = (*self.0).size_hints;
+      if size_hints.is_null() || (*size_hints).flags & flag == 0 {
+        None
+      } else {
+        Some(&*size_hints)
+      }
+    }
+  }
+
+  /// The size that the window wants to be resized in steps of
+  pub(crate) fn size_increment(&self) -> Option<Size> {
+    self
+      .size_hints(SIZE_HINT_RESIZE_INC)
+      .filter(|size_hints| size_hints.width_inc > 0 && size_hints.height_inc > 0)
+      .map(|size_hints| Size {
+        width: size_hints.width_inc,
+        height: size_hints.height_inc,
+      })
+  }
+
+  /// The size that size increments are counted from
+  pub(crate) fn base_size(&self) -> Option<Size> {
+    self.size_hints(SIZE_HINT_BASE_SIZE).map(|size_hints| Size {
+      width: size_hints.base_width,
+      height: size_hints.base_height,
+    })
+  }
+
+  /// The minimum and maximum aspect ratio, as width / height
+  pub(crate) fn aspect_ratio_range(&self) -> Option<(f64, f64)> {
+    self
+      .size_hints(SIZE_HINT_ASPECT)
+      .filter(|size_hints| size_hints.min_aspect_den > 0 && size_hints.max_aspect_den > 0)
+      .map(|size_hints| {
+        (
+          size_hints.min_aspect_num as f64 / size_hints.min_aspect_den as f64,
+          size_hints.max_aspect_num as f64 / size_hints.max_aspect_den as f64,
+        )
+      })
+  }
+
+  /// The instance name from WM_CLASS
+  pub fn instance(&self) -> Option<String> {
+    unsafe {
+      NonNull::new((*self.0).instance).map(|instance| {
+        CStr::from_ptr(instance.as_ptr())
+          .to_string_lossy()
+          .into_owned()
+      })
+    }
+  }
+
+  /// The WM_WINDOW_ROLE of the window
+  pub fn role(&self) -> Option<String> {
+    unsafe {
+      NonNull::new((*self.0).role)
+        .map(|role| CStr::from_ptr(role.as_ptr()).to_string_lossy().into_owned())
+    }
+  }
+
+  /// Configures the position and size in a single request
+  pub(crate) fn configure(&self, top_left: Point, size: Size) {
+    unsafe {
+      wlr_xwayland_surface_configure(
+        self.0,
+        top_left.x as i16,
+        top_left.y as i16,
+        size.width as u16,
+        size.height as u16,
+      );
+    }
+  }
+
+  /// The position of the window in the layout, as last configured by either
+  /// the compositor or the client
+  pub(crate) fn position(&self) -> Point {
+    unsafe {
+      Point {
+        x: (*self.0).x as i32,
+        y: (*self.0).y as i32,
+      }
+    }
+  }
+
+  /// If the window is positioned by the client and should not be managed
+  pub(crate) fn override_redirect(&self) -> bool {
+    unsafe { (*self.0).override_redirect }
+  }
+
+  /// The PID of the X11 client as reported by _NET_WM_PID, if set
+  pub(crate) fn pid(&self) -> Option<libc::pid_t> {
+    let pid = unsafe { (*self.0).pid };
+    if pid > 0 {
+      Some(pid)
+    } else {
+      None
+    }
+  }
 }
 
 impl SurfaceExt for XwaylandSurface {
@@ -49,6 +232,19 @@ impl SurfaceExt for XwaylandSurface {
   fn parent_wlr_surface(&self) -> Option<*mut wlr_surface> {
     None
   }
+  fn popup_root_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    None
+  }
+  fn transient_for_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    unsafe {
+      let parent = (*self.0).parent;
+      if parent.is_null() || (*parent).surface.is_null() {
+        None
+      } else {
+        Some((*parent).surface)
+      }
+    }
+  }
 
   fn buffer_displacement(&self) -> Displacement {
     Displacement::ZERO
@@ -58,13 +254,25 @@ impl SurfaceExt for XwaylandSurface {
     Displacement::ZERO
   }
 
+  fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)> {
+    let mut sub_x = 0.0;
+    let mut sub_y = 0.0;
+    let surface = unsafe {
+      wlr_surface_surface_at((*self.0).surface, point.x, point.y, &mut sub_x, &mut sub_y)
+    };
+    if surface.is_null() {
+      None
+    } else {
+      Some((surface, FPoint { x: sub_x, y: sub_y }))
+    }
+  }
+
   fn extents(&self) -> Rectangle {
+    // The X position is the position of the window in the layout, which is
+    // tracked by the window itself
     unsafe {
       Rectangle {
-        top_left: Point {
-          x: (*self.0).x as i32,
-          y: (*self.0).y as i32,
-        },
+        top_left: Point::ZERO,
         size: Size {
           width: (*self.0).width as i32,
           height: (*self.0).height as i32,
@@ -144,8 +352,7 @@ impl SurfaceExt for XwaylandSurface {
   }
 
   fn can_receive_focus(&self) -> bool {
-    // TODO: Is this true?
-    true
+    !self.override_redirect()
   }
   fn activated(&self) -> bool {
     false
@@ -181,10 +388,37 @@ impl SurfaceExt for XwaylandSurface {
   fn set_resizing(&self, _resizing: bool) -> u32 {
     CONFIGURE_SERIAL
   }
+  fn set_tiled(&self, _edges: u32) -> u32 {
+    CONFIGURE_SERIAL
+  }
 
   fn is_toplevel(&self) -> bool {
-    // TODO: Is this true?
-    true
+    !self.override_redirect()
+  }
+  fn window_type(&self) -> WindowType {
+    unsafe {
+      // The first known type is used, as the types are listed in order of
+      // preference
+      let window_type = if (*self.0).window_type.is_null() {
+        None
+      } else {
+        std::slice::from_raw_parts((*self.0).window_type, (*self.0).window_type_len)
+          .iter()
+          .find_map(|atom| self.1.window_type(*atom))
+      };
+      if let Some(window_type) = window_type {
+        window_type
+      } else if (*self.0).override_redirect {
+        WindowType::Popup
+      } else if !(*self.0).parent.is_null() || (*self.0).modal {
+        WindowType::Dialog
+      } else {
+        WindowType::Toplevel
+      }
+    }
+  }
+  fn is_modal(&self) -> bool {
+    unsafe { (*self.0).modal }
   }
   fn app_id(&self) -> Option<String> {
     unsafe {
@@ -266,6 +500,10 @@ wayland_listener!(
       let handler = &mut this.data;
       handler.updated_title();
     };
+    set_geometry => set_geometry_func: |this: &mut XwaylandSurfaceEventManager, _data: *mut libc::c_void,| unsafe {
+      let handler = &mut this.data;
+      handler.updated_geometry();
+    };
   ]
 );
 
@@ -274,14 +512,31 @@ pub struct XwaylandEventHandler {
   output_manager: Rc<OutputManager>,
   window_manager: Rc<WindowManager>,
   cursor_manager: Rc<CursorManager>,
+  display_name: String,
+  window_type_atoms: Rc<WindowTypeAtoms>,
 }
 impl XwaylandEventHandler {
+  fn ready(&mut self) {
+    debug!("XwaylandEventHandler::ready");
+    if self.window_type_atoms.intern(&self.display_name).is_err() {
+      error!("XwaylandEventHandler::ready: Could not connect to Xwayland");
+    }
+  }
+
   fn new_surface(&mut self, xwayland_surface: *mut wlr_xwayland_surface) {
     debug!("XwaylandEventHandler::new_surface");
-    let window = self.window_manager.new_window(
-      WindowLayer::Normal,
-      Surface::Xwayland(XwaylandSurface(xwayland_surface)),
-    );
+    let surface = XwaylandSurface(xwayland_surface, self.window_type_atoms.clone());
+    // Override redirect windows are placed by the client and must never be
+    // managed by the policy
+    let unmanaged = surface.override_redirect();
+    let layer = if unmanaged {
+      WindowLayer::Unmanaged
+    } else {
+      WindowLayer::Normal
+    };
+    let window = self
+      .window_manager
+      .new_window(layer, Surface::Xwayland(surface));
 
     let mut event_manager = XwaylandSurfaceEventManager::new(WindowEventHandler {
       wm_policy_manager: self.wm_policy_manager.clone(),
@@ -289,7 +544,6 @@ impl XwaylandEventHandler {
       window_manager: self.window_manager.clone(),
       cursor_manager: self.cursor_manager.clone(),
       window: Rc::downgrade(&window),
-      foreign_toplevel_handle: None,
       foreign_toplevel_event_manager: None,
     });
 
@@ -299,18 +553,25 @@ impl XwaylandEventHandler {
       event_manager.unmap(&mut xwayland_surface.events.unmap);
       event_manager.destroy(&mut xwayland_surface.events.destroy);
       event_manager.commit(&mut (*xwayland_surface.surface).events.commit);
-      event_manager.request_move(&mut xwayland_surface.events.request_move);
-      event_manager.request_resize(&mut xwayland_surface.events.request_resize);
-      event_manager.request_maximize(&mut xwayland_surface.events.request_maximize);
-      event_manager.request_fullscreen(&mut xwayland_surface.events.request_fullscreen);
-      // TODO: minimize?
-      event_manager.set_class(&mut xwayland_surface.events.set_class);
-      event_manager.set_title(&mut xwayland_surface.events.set_title);
+      event_manager.set_geometry(&mut xwayland_surface.events.set_geometry);
+      if !unmanaged {
+        event_manager.request_move(&mut xwayland_surface.events.request_move);
+        event_manager.request_resize(&mut xwayland_surface.events.request_resize);
+        event_manager.request_maximize(&mut xwayland_surface.events.request_maximize);
+        event_manager.request_fullscreen(&mut xwayland_surface.events.request_fullscreen);
+        // TODO: minimize?
+        event_manager.set_class(&mut xwayland_surface.events.set_class);
+        event_manager.set_title(&mut xwayland_surface.events.set_title);
+      }
     }
 
     *window.event_manager.borrow_mut() = Some(SurfaceEventManager::Xwayland(event_manager));
 
-    self.wm_policy_manager.advise_new_window(window);
+    if !unmanaged {
+      let state = self.wm_policy_manager.initial_window_state(window.clone());
+      window.apply_initial_state(state);
+      self.wm_policy_manager.advise_new_window(window);
+    }
   }
 }
 
@@ -318,6 +579,10 @@ wayland_listener!(
   XwaylandEventManager,
   Rc<RefCell<XwaylandEventHandler>>,
   [
+     ready => ready_func: |this: &mut XwaylandEventManager, _data: *mut libc::c_void,| unsafe {
+         let handler = &mut this.data;
+         handler.borrow_mut().ready()
+     };
      new_surface => new_surface_func: |this: &mut XwaylandEventManager, data: *mut libc::c_void,| unsafe {
          let handler = &mut this.data;
          handler.borrow_mut().new_surface(data as _)
@@ -328,6 +593,8 @@ wayland_listener!(
 #[allow(unused)]
 pub(crate) struct XwaylandManager {
   xwayland: *mut wlr_xwayland,
+  display_name: String,
+  seat_manager: Rc<SeatManager>,
 
   event_manager: Pin<Box<XwaylandEventManager>>,
   event_handler: Rc<RefCell<XwaylandEventHandler>>,
@@ -339,12 +606,15 @@ impl XwaylandManager {
     output_manager: Rc<OutputManager>,
     window_manager: Rc<WindowManager>,
     cursor_manager: Rc<CursorManager>,
+    seat_manager: Rc<SeatManager>,
     display: *mut wl_display,
     compositor: *mut wlr_compositor,
+    lazy: bool,
   ) -> XwaylandManager {
     debug!("XwaylandManager::init");
 
-    let xwayland = unsafe { &mut *wlr_xwayland_create(display, compositor, true) };
+    // A lazy X server is only started when the first X11 client connects
+    let xwayland = unsafe { &mut *wlr_xwayland_create(display, compositor, lazy) };
 
     let socket_name = unsafe {
       CStr::from_ptr(xwayland.display_name)
@@ -354,23 +624,52 @@ impl XwaylandManager {
     env::set_var("_DISPLAY", socket_name.clone());
     info!("DISPLAY={}", socket_name);
 
+    let window_type_atoms = Rc::new(WindowTypeAtoms::default());
+    window_manager.set_window_type_atoms(window_type_atoms.clone());
+
     let event_handler = Rc::new(RefCell::new(XwaylandEventHandler {
       wm_policy_manager,
       output_manager,
       window_manager,
       cursor_manager,
+      display_name: socket_name.clone(),
+      window_type_atoms,
     }));
 
     let mut event_manager = XwaylandEventManager::new(event_handler.clone());
     unsafe {
+      event_manager.ready(&mut xwayland.events.ready);
       event_manager.new_surface(&mut xwayland.events.new_surface);
     }
 
-    XwaylandManager {
+    let xwayland_manager = XwaylandManager {
       xwayland,
+      display_name: socket_name,
+      seat_manager,
 
       event_manager,
       event_handler,
+    };
+    // Setting the seat lets wlroots bridge the clipboard and primary
+    // selection between X11 and Wayland clients
+    xwayland_manager.set_selection_bridge_enabled(true);
+    xwayland_manager
+  }
+
+  /// The X11 display that X11 clients connect to, like `:1`
+  pub(crate) fn display_name(&self) -> &str {
+    &self.display_name
+  }
+
+  pub(crate) fn set_selection_bridge_enabled(&self, enabled: bool) {
+    debug!("XwaylandManager::set_selection_bridge_enabled: {}", enabled);
+    let seat = if enabled {
+      self.seat_manager.raw_seat()
+    } else {
+      ptr::null_mut()
+    };
+    unsafe {
+      wlr_xwayland_set_seat(self.xwayland, seat);
     }
   }
 }
@@ -382,3 +681,119 @@ impl Drop for XwaylandManager {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::MockManagers;
+  use std::mem;
+
+  #[test]
+  fn it_does_not_clobber_queued_resizes() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let mut raw_surface: wlr_xwayland_surface = unsafe { mem::zeroed() };
+    let window = window_manager.new_window(
+      WindowLayer::Normal,
+      Surface::Xwayland(XwaylandSurface(&mut raw_surface, Rc::default())),
+    );
+
+    let first = Rectangle {
+      top_left: Point { x: 10, y: 20 },
+      size: Size {
+        width: 100,
+        height: 100,
+      },
+    };
+    let second = Rectangle {
+      top_left: Point { x: 50, y: 60 },
+      size: Size {
+        width: 200,
+        height: 150,
+      },
+    };
+    window.set_extents(&first);
+    window.set_extents(&second);
+
+    assert!(window.pending_updates.borrow().is_empty());
+    assert_eq!(*window.top_left.borrow(), second.top_left());
+    assert_eq!((raw_surface.x, raw_surface.y), (50, 60));
+    assert_eq!((raw_surface.width, raw_surface.height), (200, 150));
+  }
+
+  #[test]
+  fn it_classifies_windows_by_their_window_type_atoms() {
+    let atoms = Rc::new(WindowTypeAtoms::default());
+    *atoms.0.borrow_mut() = vec![
+      (10, WindowType::Toplevel),
+      (11, WindowType::Utility),
+      (12, WindowType::Splash),
+    ];
+    let mut raw_parent: wlr_xwayland_surface = unsafe { mem::zeroed() };
+    let mut raw_surface: wlr_xwayland_surface = unsafe { mem::zeroed() };
+    let surface = XwaylandSurface(&mut raw_surface, atoms.clone());
+
+    assert_eq!(surface.window_type(), WindowType::Toplevel);
+    // Transient windows without a window type are dialogs, but not modal
+    raw_surface.parent = &mut raw_parent;
+    assert_eq!(surface.window_type(), WindowType::Dialog);
+    assert!(!surface.is_modal());
+
+    // Unknown types are skipped in favour of the next known one
+    let mut window_types = [99, 12, 11];
+    raw_surface.window_type = window_types.as_mut_ptr();
+    raw_surface.window_type_len = window_types.len();
+    assert_eq!(surface.window_type(), WindowType::Splash);
+    window_types[1] = 98;
+    raw_surface.window_type = window_types.as_mut_ptr();
+    assert_eq!(surface.window_type(), WindowType::Utility);
+
+    // Before Xwayland is ready no atoms are known
+    let surface = XwaylandSurface(&mut raw_surface, Rc::default());
+    assert_eq!(surface.window_type(), WindowType::Dialog);
+  }
+
+  #[test]
+  fn it_places_unmanaged_windows_at_their_x_position() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let mut raw_wlr_surface: wlr_surface = unsafe { mem::zeroed() };
+    let mut raw_surface: wlr_xwayland_surface = unsafe { mem::zeroed() };
+    raw_surface.surface = &mut raw_wlr_surface;
+    raw_surface.override_redirect = true;
+    raw_surface.x = 300;
+    raw_surface.y = 200;
+    raw_surface.width = 50;
+    raw_surface.height = 20;
+    let window = window_manager.new_window(
+      WindowLayer::Unmanaged,
+      Surface::Xwayland(XwaylandSurface(&mut raw_surface, Rc::default())),
+    );
+
+    window.sync_unmanaged_position();
+    assert_eq!(window.buffer_extents().top_left(), Point { x: 300, y: 200 });
+    assert_eq!(window.extents().top_left(), Point { x: 300, y: 200 });
+
+    // The client moves the window with a ConfigureRequest
+    raw_surface.x = 320;
+    raw_surface.y = 180;
+    window.sync_unmanaged_position();
+    assert_eq!(window.buffer_extents().top_left(), Point { x: 320, y: 180 });
+  }
+}
+
+#[cfg(test)]
+unsafe fn wlr_xwayland_surface_configure(
+  surface: *mut wlr_xwayland_surface,
+  x: i16,
+  y: i16,
+  width: u16,
+  height: u16,
+) {
+  (*surface).x = x;
+  (*surface).y = y;
+  (*surface).width = width;
+  (*surface).height = height;
+}
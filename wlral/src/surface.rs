@@ -1,8 +1,11 @@
 use crate::geometry::*;
 use crate::shell::layer::{LayerSurface, LayerSurfaceEventManager};
 use crate::shell::xdg::{XdgSurface, XdgSurfaceEventManager};
-use crate::shell::xwayland::{XwaylandSurface, XwaylandSurfaceEventManager};
+use crate::shell::xwayland::{WindowTypeAtoms, XwaylandSurface, XwaylandSurfaceEventManager};
+use crate::window::WindowType;
+use std::ffi::CStr;
 use std::pin::Pin;
+use std::rc::Rc;
 use wlroots_sys::*;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -15,17 +18,98 @@ pub enum Surface {
 }
 
 impl Surface {
-  pub(crate) fn from_wlr_surface(wlr_surface: *mut wlr_surface) -> Surface {
+  pub(crate) fn from_wlr_surface(
+    wlr_surface: *mut wlr_surface,
+    window_type_atoms: Rc<WindowTypeAtoms>,
+  ) -> Surface {
     if let Ok(xdg_surface) = XdgSurface::from_wlr_surface(wlr_surface) {
       Surface::Xdg(xdg_surface)
     } else if let Ok(layer_surface) = LayerSurface::from_wlr_surface(wlr_surface) {
       Surface::Layer(layer_surface)
-    } else if let Ok(xwayland_surface) = XwaylandSurface::from_wlr_surface(wlr_surface) {
+    } else if let Ok(xwayland_surface) =
+      XwaylandSurface::from_wlr_surface(wlr_surface, window_type_atoms)
+    {
       Surface::Xwayland(xwayland_surface)
     } else {
       panic!("Unknown surface type");
     }
   }
+
+  /// Calls `f` with the surface and each of its subsurfaces, from back to
+  /// front, and their offsets from the top left of this surface
+  ///
+  /// Popups are not included as they are managed as windows of their own.
+  pub fn for_each_surface<F>(&self, mut f: F)
+  where
+    F: FnMut(*mut wlr_surface, Point),
+  {
+    unsafe extern "C" fn iterator<F>(
+      surface: *mut wlr_surface,
+      sx: i32,
+      sy: i32,
+      data: *mut libc::c_void,
+    ) where
+      F: FnMut(*mut wlr_surface, Point),
+    {
+      let f = &mut *(data as *mut F);
+      f(surface, Point { x: sx, y: sy });
+    }
+
+    let wlr_surface = self.wlr_surface();
+    if wlr_surface.is_null() {
+      return;
+    }
+    unsafe {
+      wlr_surface_for_each_surface(
+        wlr_surface,
+        Some(iterator::<F>),
+        &mut f as *mut F as *mut libc::c_void,
+      );
+    }
+  }
+
+  /// The name of the role wlroots assigned to the surface
+  pub fn role_name(&self) -> Option<String> {
+    role_name(self.wlr_surface())
+  }
+
+  pub fn is_xdg_toplevel(&self) -> bool {
+    match self {
+      Xdg(surface) => surface.is_toplevel(),
+      _ => false,
+    }
+  }
+
+  pub fn is_layer(&self) -> bool {
+    matches!(self, Layer(_))
+  }
+
+  /// Always false for windows, the surfaces passed by `for_each_surface`
+  /// can instead be checked with the `is_subsurface` function
+  pub fn is_subsurface(&self) -> bool {
+    is_subsurface(self.wlr_surface())
+  }
+}
+
+/// The name of the role wlroots assigned to the surface, like
+/// `"xdg_toplevel"`, `"wl_subsurface"` or `"wl_pointer-cursor"`, or None if
+/// the surface has no role yet
+pub fn role_name(wlr_surface: *mut wlr_surface) -> Option<String> {
+  if wlr_surface.is_null() {
+    return None;
+  }
+  unsafe {
+    let role = (*wlr_surface).role;
+    if role.is_null() || (*role).name.is_null() {
+      return None;
+    }
+    Some(CStr::from_ptr((*role).name).to_string_lossy().into_owned())
+  }
+}
+
+/// If the surface is a subsurface of another surface
+pub fn is_subsurface(wlr_surface: *mut wlr_surface) -> bool {
+  !wlr_surface.is_null() && unsafe { wlr_surface_is_subsurface(wlr_surface) }
 }
 
 use Surface::*;
@@ -34,8 +118,17 @@ pub(crate) trait SurfaceExt {
   fn wl_resource(&self) -> *mut wl_resource;
   fn wlr_surface(&self) -> *mut wlr_surface;
   fn parent_wlr_surface(&self) -> Option<*mut wlr_surface>;
+  /// The surface at the root of the popup tree that this surface belongs to,
+  /// which parent_displacement is relative to
+  fn popup_root_wlr_surface(&self) -> Option<*mut wlr_surface>;
+  /// The surface of the window this toplevel is transient for
+  fn transient_for_wlr_surface(&self) -> Option<*mut wlr_surface>;
   fn buffer_displacement(&self) -> Displacement;
   fn parent_displacement(&self) -> Displacement;
+  /// The surface under the point, relative to the top left of the buffer,
+  /// which can be a subsurface or popup, and the point in the coordinates
+  /// of that surface
+  fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)>;
 
   fn extents(&self) -> Rectangle;
   fn move_to(&self, top_left: Point);
@@ -61,8 +154,13 @@ pub(crate) trait SurfaceExt {
   fn resizing(&self) -> bool;
   /// Returns the associated configure serial
   fn set_resizing(&self, resizing: bool) -> u32;
+  /// Tells the client which edges of the window are tiled, as a bitmask of
+  /// wlr_edges
+  fn set_tiled(&self, edges: u32) -> u32;
 
   fn is_toplevel(&self) -> bool;
+  fn window_type(&self) -> WindowType;
+  fn is_modal(&self) -> bool;
   fn app_id(&self) -> Option<String>;
   fn title(&self) -> Option<String>;
 
@@ -99,6 +197,24 @@ impl SurfaceExt for Surface {
       Null => None,
     }
   }
+  fn popup_root_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    match self {
+      Layer(surface) => surface.popup_root_wlr_surface(),
+      Xdg(surface) => surface.popup_root_wlr_surface(),
+      Xwayland(surface) => surface.popup_root_wlr_surface(),
+      #[cfg(test)]
+      Null => None,
+    }
+  }
+  fn transient_for_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    match self {
+      Layer(surface) => surface.transient_for_wlr_surface(),
+      Xdg(surface) => surface.transient_for_wlr_surface(),
+      Xwayland(surface) => surface.transient_for_wlr_surface(),
+      #[cfg(test)]
+      Null => None,
+    }
+  }
 
   fn buffer_displacement(&self) -> Displacement {
     match self {
@@ -120,6 +236,16 @@ impl SurfaceExt for Surface {
     }
   }
 
+  fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)> {
+    match self {
+      Layer(surface) => surface.surface_at(point),
+      Xdg(surface) => surface.surface_at(point),
+      Xwayland(surface) => surface.surface_at(point),
+      #[cfg(test)]
+      Null => None,
+    }
+  }
+
   fn extents(&self) -> Rectangle {
     match self {
       Layer(surface) => surface.extents(),
@@ -269,6 +395,15 @@ impl SurfaceExt for Surface {
       Null => 1,
     }
   }
+  fn set_tiled(&self, edges: u32) -> u32 {
+    match self {
+      Layer(surface) => surface.set_tiled(edges),
+      Xdg(surface) => surface.set_tiled(edges),
+      Xwayland(surface) => surface.set_tiled(edges),
+      #[cfg(test)]
+      Null => 1,
+    }
+  }
 
   fn is_toplevel(&self) -> bool {
     match self {
@@ -279,6 +414,24 @@ impl SurfaceExt for Surface {
       Null => false,
     }
   }
+  fn window_type(&self) -> WindowType {
+    match self {
+      Layer(surface) => surface.window_type(),
+      Xdg(surface) => surface.window_type(),
+      Xwayland(surface) => surface.window_type(),
+      #[cfg(test)]
+      Null => WindowType::Toplevel,
+    }
+  }
+  fn is_modal(&self) -> bool {
+    match self {
+      Layer(surface) => surface.is_modal(),
+      Xdg(surface) => surface.is_modal(),
+      Xwayland(surface) => surface.is_modal(),
+      #[cfg(test)]
+      Null => false,
+    }
+  }
   fn app_id(&self) -> Option<String> {
     match self {
       Layer(surface) => surface.app_id(),
@@ -320,3 +473,26 @@ impl std::fmt::Debug for SurfaceEventManager {
     write!(fmt, "SurfaceEventManager")
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::mem;
+
+  #[test]
+  fn it_reads_the_role_name() {
+    let role_name_bytes = b"xdg_toplevel\0";
+    let mut role: wlr_surface_role = unsafe { mem::zeroed() };
+    role.name = role_name_bytes.as_ptr() as *const libc::c_char;
+    let mut surface: wlr_surface = unsafe { mem::zeroed() };
+    assert_eq!(role_name(&mut surface), None);
+
+    surface.role = &role;
+    assert_eq!(role_name(&mut surface), Some("xdg_toplevel".to_string()));
+    assert_eq!(role_name(std::ptr::null_mut()), None);
+    assert!(!is_subsurface(std::ptr::null_mut()));
+    assert_eq!(Surface::Null.role_name(), None);
+    assert!(!Surface::Null.is_layer());
+    assert!(!Surface::Null.is_xdg_toplevel());
+  }
+}
@@ -0,0 +1,146 @@
+use crate::geometry::Size;
+use log::error;
+use std::{fmt::Debug, fs::File, path::Path};
+use wlroots_sys::*;
+
+/// WL_SHM_FORMAT_ABGR8888, which is RGBA byte order in memory
+const WL_SHM_FORMAT_ABGR8888: u32 = 0x3432_4241;
+
+/// A GPU texture owned by the compositor
+pub struct Texture {
+  texture: *mut wlr_texture,
+}
+
+impl Texture {
+  /// Decodes a PNG file and uploads it to the GPU
+  pub fn from_png(renderer: *mut wlr_renderer, path: &Path) -> Result<Texture, ()> {
+    let file = File::open(path).map_err(|error| {
+      error!("Texture::from_png: Could not open {:?}: {}", path, error);
+    })?;
+    let mut decoder = png::Decoder::new(file);
+    // Expand palettes, low bit depths and tRNS chunks so that we always get
+    // 8 bits per channel
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(|error| {
+      error!("Texture::from_png: Could not decode {:?}: {}", path, error);
+    })?;
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).map_err(|error| {
+      error!("Texture::from_png: Could not decode {:?}: {}", path, error);
+    })?;
+
+    let data = to_rgba(info.color_type, data);
+    let width = info.width;
+    let height = info.height;
+
+    Texture::from_rgba(renderer, width, height, &data)
+  }
+
+  /// Uploads tightly packed RGBA pixels to the GPU
+  pub fn from_rgba(
+    renderer: *mut wlr_renderer,
+    width: u32,
+    height: u32,
+    data: &[u8],
+  ) -> Result<Texture, ()> {
+    if data.len() != (width * height * 4) as usize {
+      return Err(());
+    }
+    let texture = unsafe {
+      wlr_texture_from_pixels(
+        renderer,
+        WL_SHM_FORMAT_ABGR8888,
+        width * 4,
+        width,
+        height,
+        data.as_ptr() as *const libc::c_void,
+      )
+    };
+    if texture.is_null() {
+      error!("Texture::from_rgba: Could not create texture");
+      return Err(());
+    }
+    Ok(Texture { texture })
+  }
+
+  pub fn raw_ptr(&self) -> *mut wlr_texture {
+    self.texture
+  }
+
+  pub fn size(&self) -> Size {
+    let mut width = 0;
+    let mut height = 0;
+    unsafe {
+      wlr_texture_get_size(self.texture, &mut width, &mut height);
+    }
+    Size { width, height }
+  }
+}
+
+impl Debug for Texture {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Texture {{texture: {:?}}}", self.texture)
+  }
+}
+
+impl Drop for Texture {
+  fn drop(&mut self) {
+    unsafe {
+      wlr_texture_destroy(self.texture);
+    }
+  }
+}
+
+fn to_rgba(color_type: png::ColorType, data: Vec<u8>) -> Vec<u8> {
+  match color_type {
+    png::ColorType::RGBA => data,
+    png::ColorType::RGB => data
+      .chunks(3)
+      .flat_map(|p| vec![p[0], p[1], p[2], 255])
+      .collect(),
+    png::ColorType::GrayscaleAlpha => data
+      .chunks(2)
+      .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+      .collect(),
+    png::ColorType::Grayscale => data.iter().flat_map(|&p| vec![p, p, p, 255]).collect(),
+    // Palettes are expanded to RGB(A) by the decoder
+    png::ColorType::Indexed => data,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_expands_pixels_to_rgba() {
+    assert_eq!(
+      to_rgba(png::ColorType::RGB, vec![1, 2, 3, 4, 5, 6]),
+      vec![1, 2, 3, 255, 4, 5, 6, 255]
+    );
+    assert_eq!(
+      to_rgba(png::ColorType::GrayscaleAlpha, vec![7, 8]),
+      vec![7, 7, 7, 8]
+    );
+    assert_eq!(
+      to_rgba(png::ColorType::Grayscale, vec![9]),
+      vec![9, 9, 9, 255]
+    );
+  }
+}
+
+#[cfg(test)]
+unsafe fn wlr_texture_from_pixels(
+  _renderer: *mut wlr_renderer,
+  _fmt: u32,
+  _stride: u32,
+  _width: u32,
+  _height: u32,
+  _data: *const libc::c_void,
+) -> *mut wlr_texture {
+  std::ptr::null_mut()
+}
+#[cfg(test)]
+unsafe fn wlr_texture_get_size(_texture: *mut wlr_texture, _width: *mut i32, _height: *mut i32) {}
+#[cfg(test)]
+unsafe fn wlr_texture_destroy(_texture: *mut wlr_texture) {}
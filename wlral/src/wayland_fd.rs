@@ -0,0 +1,105 @@
+use log::error;
+use std::{ffi::c_void, os::unix::io::RawFd, panic};
+use wayland_sys::{
+  ffi_dispatch,
+  server::{wl_display, wl_event_source},
+};
+use wlroots_sys::WAYLAND_SERVER_HANDLE;
+
+/// The fd is readable
+pub(crate) const WL_EVENT_READABLE: u32 = 0x01;
+
+type Callback = extern "C" fn(i32, u32, *mut c_void) -> i32;
+
+/// Unpack a Rust closure, extracting a `void*` pointer to the data and a
+/// trampoline function which can be used to invoke it.
+///
+/// # Safety
+///
+/// It is the user's responsibility to ensure the closure outlives the returned
+/// `void*` pointer.
+///
+/// Calling the trampoline function with anything except the `void*` pointer
+/// will result in *Undefined Behaviour*.
+unsafe fn unpack_closure<F>(closure: *mut F) -> (*mut c_void, Callback)
+where
+  F: FnMut(u32),
+{
+  extern "C" fn trampoline<F>(_fd: i32, mask: u32, data: *mut c_void) -> i32
+  where
+    F: FnMut(u32),
+  {
+    let result = panic::catch_unwind(move || {
+      // The closure is owned by the WlFdSource and freed when it is dropped
+      let closure = unsafe { &mut *(data as *mut F) };
+      closure(mask);
+    });
+    if let Err(error) = result {
+      error!("Error while invoking fd callback: {:?}", error);
+    }
+    0
+  }
+
+  (closure as *mut F as *mut c_void, trampoline::<F>)
+}
+
+/// A wrapper around wl_event_loop fd sources to call a handler when
+/// a file descriptor becomes readable.
+///
+/// The fd is not closed when the source is dropped.
+pub(crate) struct WlFdSource(*mut wl_event_source, Option<Box<dyn FnOnce()>>);
+
+impl WlFdSource {
+  pub(crate) unsafe fn init<F>(
+    display: *mut wl_display,
+    fd: RawFd,
+    mask: u32,
+    handler: F,
+  ) -> Result<WlFdSource, ()>
+  where
+    F: FnMut(u32),
+    F: 'static,
+  {
+    let handler_ptr = Box::into_raw(Box::new(handler));
+    let drop_handler = Box::new(move || {
+      Box::from_raw(handler_ptr);
+    });
+    let (closure, callback) = unpack_closure(handler_ptr);
+
+    let event_loop = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_get_event_loop, display);
+    let source = ffi_dispatch!(
+      WAYLAND_SERVER_HANDLE,
+      wl_event_loop_add_fd,
+      event_loop,
+      fd,
+      mask,
+      callback,
+      closure
+    );
+    if source.is_null() {
+      drop_handler();
+      return Err(());
+    }
+
+    Ok(WlFdSource(source, Some(drop_handler)))
+  }
+}
+
+impl std::fmt::Debug for WlFdSource {
+  fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+    write!(fmt, "WlFdSource")
+  }
+}
+
+impl Drop for WlFdSource {
+  fn drop(&mut self) {
+    if !self.0.is_null() {
+      unsafe {
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, self.0);
+      }
+    }
+    if let Some(drop) = self.1.take() {
+      drop();
+    }
+  }
+}
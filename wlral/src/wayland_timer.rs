@@ -1,5 +1,5 @@
 use log::error;
-use std::{ffi::c_void, panic};
+use std::{cell::Cell, ffi::c_void, panic, ptr, rc::Rc};
 use wayland_sys::{
   ffi_dispatch,
   server::{wl_display, wl_event_source},
@@ -31,7 +31,8 @@ where
     F: FnMut(),
   {
     let result = panic::catch_unwind(move || {
-      let mut closure: Box<F> = unsafe { Box::from_raw(data as *mut F) };
+      // The closure is owned by the WlTimer and freed when it is dropped
+      let closure = unsafe { &mut *(data as *mut F) };
       closure();
     });
     if let Err(error) = result {
@@ -45,7 +46,12 @@ where
 
 /// A wrapper around wl_event_loop timers to call a handler after a
 /// specified timeout.
-pub(crate) struct WlTimer(*mut wl_event_source, Option<Box<dyn FnOnce()>>);
+pub(crate) struct WlTimer {
+  source: *mut wl_event_source,
+  /// The interval of a repeating timer, zero for one-shot timers
+  interval_ms: Rc<Cell<u32>>,
+  drop_handler: Option<Box<dyn FnOnce()>>,
+}
 
 impl WlTimer {
   pub(crate) unsafe fn init<F>(
@@ -57,48 +63,228 @@ impl WlTimer {
     F: FnMut(),
     F: 'static,
   {
-    let handler_ptr = Box::into_raw(Box::new(handler));
-    let drop_handler = Box::new(move || {
-      Box::from_raw(handler_ptr);
-    });
-    let (closure, callback) = unpack_closure(handler_ptr);
+    let (source, drop_handler) = add_timer(display, handler)?;
+    let timer = WlTimer {
+      source,
+      interval_ms: Rc::new(Cell::new(0)),
+      drop_handler: Some(drop_handler),
+    };
+    timer.update(timeout_ms)?;
+    Ok(timer)
+  }
 
-    let event_loop = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_get_event_loop, display);
-    let timer = ffi_dispatch!(
-      WAYLAND_SERVER_HANDLE,
-      wl_event_loop_add_timer,
-      event_loop,
-      callback,
-      closure
-    );
-    if timer.is_null() {
-      drop_handler();
-      return Err(());
+  /// Creates a timer which calls the handler every `interval_ms` until it
+  /// is canceled or dropped.
+  #[allow(unused)]
+  pub(crate) unsafe fn init_repeating<F>(
+    display: *mut wl_display,
+    interval_ms: u32,
+    mut handler: F,
+  ) -> Result<WlTimer, ()>
+  where
+    F: FnMut(),
+    F: 'static,
+  {
+    let interval = Rc::new(Cell::new(interval_ms));
+    let source = Rc::new(Cell::new(ptr::null_mut()));
+    let (timer_source, drop_handler) = add_timer(display, {
+      let interval = interval.clone();
+      let source = source.clone();
+      move || {
+        // Re-arm before calling the handler so that it can cancel or
+        // reschedule the timer
+        unsafe {
+          ffi_dispatch!(
+            WAYLAND_SERVER_HANDLE,
+            wl_event_source_timer_update,
+            source.get(),
+            interval.get() as i32
+          );
+        }
+        handler();
+      }
+    })?;
+    source.set(timer_source);
+    let timer = WlTimer {
+      source: timer_source,
+      interval_ms: interval,
+      drop_handler: Some(drop_handler),
+    };
+    timer.update(interval_ms)?;
+    Ok(timer)
+  }
+
+  /// Stops the timer from firing until it is rescheduled
+  pub(crate) fn cancel(&self) {
+    let _ = self.update(0);
+  }
+
+  /// Arms the timer to fire after `timeout_ms`, replacing any pending
+  /// timeout. For repeating timers this also changes the interval.
+  pub(crate) fn reschedule(&self, timeout_ms: u32) -> Result<(), ()> {
+    if self.interval_ms.get() > 0 && timeout_ms > 0 {
+      self.interval_ms.set(timeout_ms);
     }
-    let success = ffi_dispatch!(
-      WAYLAND_SERVER_HANDLE,
-      wl_event_source_timer_update,
-      timer,
-      timeout_ms as i32
-    );
+    self.update(timeout_ms)
+  }
+
+  fn update(&self, timeout_ms: u32) -> Result<(), ()> {
+    let success = unsafe {
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_event_source_timer_update,
+        self.source,
+        timeout_ms as i32
+      )
+    };
     if success < 0 {
-      drop_handler();
-      return Err(());
+      Err(())
+    } else {
+      Ok(())
     }
+  }
+}
+
+/// Adds a disarmed timer to the event loop of the display.
+///
+/// Returns the event source and a function which frees the handler. It must
+/// be called exactly once, after the event source has been removed.
+unsafe fn add_timer<F>(
+  display: *mut wl_display,
+  handler: F,
+) -> Result<(*mut wl_event_source, Box<dyn FnOnce()>), ()>
+where
+  F: FnMut(),
+  F: 'static,
+{
+  let handler_ptr = Box::into_raw(Box::new(handler));
+  let drop_handler = Box::new(move || {
+    Box::from_raw(handler_ptr);
+  });
+  let (closure, callback) = unpack_closure(handler_ptr);
+
+  let event_loop = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_get_event_loop, display);
+  let timer = ffi_dispatch!(
+    WAYLAND_SERVER_HANDLE,
+    wl_event_loop_add_timer,
+    event_loop,
+    callback,
+    closure
+  );
+  if timer.is_null() {
+    drop_handler();
+    return Err(());
+  }
+
+  Ok((timer, drop_handler))
+}
 
-    Ok(WlTimer(timer, Some(drop_handler)))
+impl std::fmt::Debug for WlTimer {
+  fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+    write!(fmt, "WlTimer")
   }
 }
 
 impl Drop for WlTimer {
   fn drop(&mut self) {
-    if !self.0.is_null() {
+    if !self.source.is_null() {
       unsafe {
-        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, self.0);
+        ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_event_source_remove, self.source);
       }
     }
-    if let Some(drop) = self.1.take() {
+    if let Some(drop) = self.drop_handler.take() {
       drop();
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct DropCounter(Rc<Cell<u32>>);
+
+  impl Drop for DropCounter {
+    fn drop(&mut self) {
+      self.0.set(self.0.get() + 1);
+    }
+  }
+
+  unsafe fn dispatch(display: *mut wl_display, timeout_ms: i32) {
+    let event_loop = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_get_event_loop, display);
+    ffi_dispatch!(
+      WAYLAND_SERVER_HANDLE,
+      wl_event_loop_dispatch,
+      event_loop,
+      timeout_ms
+    );
+  }
+
+  #[test]
+  fn it_repeats_until_cancelled() {
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      let calls = Rc::new(Cell::new(0));
+      let timer = WlTimer::init_repeating(display, 1, {
+        let calls = calls.clone();
+        move || calls.set(calls.get() + 1)
+      })
+      .unwrap();
+
+      // Each dispatch only returns early if the timer has been re-armed
+      for _ in 0..3 {
+        dispatch(display, 1000);
+      }
+      assert_eq!(calls.get(), 3);
+
+      timer.cancel();
+      dispatch(display, 20);
+      assert_eq!(calls.get(), 3);
+
+      drop(timer);
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+    }
+  }
+
+  #[test]
+  fn it_drops_the_handler_exactly_once() {
+    unsafe {
+      let display = ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_create,) as *mut wl_display;
+      let calls = Rc::new(Cell::new(0));
+      let drops = Rc::new(Cell::new(0));
+
+      let one_shot = WlTimer::init(display, 1, {
+        let calls = calls.clone();
+        let counter = DropCounter(drops.clone());
+        move || {
+          let _ = &counter;
+          calls.set(calls.get() + 1);
+        }
+      })
+      .unwrap();
+      let repeating = WlTimer::init_repeating(display, 1, {
+        let calls = calls.clone();
+        let counter = DropCounter(drops.clone());
+        move || {
+          let _ = &counter;
+          calls.set(calls.get() + 1);
+        }
+      })
+      .unwrap();
+
+      for _ in 0..3 {
+        dispatch(display, 1000);
+      }
+      assert!(calls.get() >= 3);
+      // Calling the handlers must not free them
+      assert_eq!(drops.get(), 0);
+
+      drop(one_shot);
+      assert_eq!(drops.get(), 1);
+      drop(repeating);
+      assert_eq!(drops.get(), 2);
+
+      ffi_dispatch!(WAYLAND_SERVER_HANDLE, wl_display_destroy, display);
+    }
+  }
+}
@@ -1,10 +1,11 @@
-use crate::geometry::{Displacement, FPoint, Point, Rectangle, Size};
+use crate::animation::{AnimationHandle, AnimationManager, Easing};
+use crate::geometry::{Displacement, FDisplacement, FPoint, Point, Rectangle, Size};
 use crate::input::cursor::CursorManager;
-use crate::output_manager::OutputManager;
+use crate::output_manager::{position_inside, position_partly_inside, OutputManager};
 use crate::surface::{Surface, SurfaceEventManager, SurfaceExt};
 use crate::window_management_policy::*;
 use crate::{
-  event::{Event, EventOnce},
+  event::{Event, EventOnce, Subscription},
   output::Output,
   window_manager::{WindowLayer, WindowManager},
 };
@@ -18,10 +19,15 @@ use std::{
   ffi::{CStr, CString, NulError},
   pin::Pin,
   rc::{Rc, Weak},
+  time::Duration,
 };
 use wlroots_sys::*;
 
+/// How long minimize and restore animations run
+const MINIMIZE_ANIMATION_DURATION: Duration = Duration::from_millis(250);
+
 bitflags! {
+  /// The bits match wlr_edges
   pub struct WindowEdge: u32 {
     const NONE   = 0b0000;
     const TOP    = 0b0001;
@@ -31,9 +37,210 @@ bitflags! {
   }
 }
 
+fn constrain_length(length: i32, min: Option<u32>, max: Option<u32>) -> i32 {
+  let length = match max {
+    Some(max) => length.min(max as i32),
+    None => length,
+  };
+  match min {
+    Some(min) => length.max(min as i32),
+    None => length,
+  }
+}
+
+fn snap_length_to_increment(length: i32, base: i32, increment: i32) -> i32 {
+  if length <= base {
+    length
+  } else {
+    base + (length - base) / increment * increment
+  }
+}
+
+fn snap_to_increment(size: Size, base: Size, increment: Size) -> Size {
+  Size {
+    width: snap_length_to_increment(size.width, base.width, increment.width),
+    height: snap_length_to_increment(size.height, base.height, increment.height),
+  }
+}
+
+fn constrain_size(
+  size: Size,
+  min_width: Option<u32>,
+  max_width: Option<u32>,
+  min_height: Option<u32>,
+  max_height: Option<u32>,
+) -> Size {
+  Size {
+    width: constrain_length(size.width, min_width, max_width),
+    height: constrain_length(size.height, min_height, max_height),
+  }
+}
+
+/// The role of a window
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum WindowType {
+  /// A normal application window
+  Toplevel,
+  /// A toplevel that is transient for another window
+  Dialog,
+  /// A toolbox or palette that belongs to another window
+  Utility,
+  /// A window shown while an application is starting
+  Splash,
+  /// A popup or menu that is positioned relative to its parent
+  Popup,
+  /// A layer shell surface like a panel or a background
+  Layer,
+}
+
+/// Something that happened to the parent of a window
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ParentEvent {
+  /// The parent window was destroyed
+  Destroyed,
+  /// The parent window was minimized or restored
+  Minimized(bool),
+}
+
+/// A region of an output that a window can be snapped to
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum SnapRegion {
+  Left,
+  Right,
+  Top,
+  Bottom,
+  TopLeft,
+  TopRight,
+  BottomLeft,
+  BottomRight,
+  Maximize,
+}
+
+impl SnapRegion {
+  /// The extents of the region inside of the passed area
+  pub fn extents(&self, area: &Rectangle) -> Rectangle {
+    let half_width = area.width() / 2;
+    let half_height = area.height() / 2;
+    let left = Rectangle {
+      top_left: area.top_left(),
+      size: area.size().with_width(half_width),
+    };
+    let right = Rectangle {
+      top_left: Point {
+        x: area.left() + half_width,
+        y: area.top(),
+      },
+      size: area.size().with_width(area.width() - half_width),
+    };
+    let top_height = |rectangle: Rectangle| Rectangle {
+      top_left: rectangle.top_left(),
+      size: rectangle.size().with_height(half_height),
+    };
+    let bottom_height = |rectangle: Rectangle| Rectangle {
+      top_left: Point {
+        x: rectangle.left(),
+        y: rectangle.top() + half_height,
+      },
+      size: rectangle.size().with_height(area.height() - half_height),
+    };
+
+    match self {
+      SnapRegion::Left => left,
+      SnapRegion::Right => right,
+      SnapRegion::Top => top_height(area.clone()),
+      SnapRegion::Bottom => bottom_height(area.clone()),
+      SnapRegion::TopLeft => top_height(left),
+      SnapRegion::TopRight => top_height(right),
+      SnapRegion::BottomLeft => bottom_height(left),
+      SnapRegion::BottomRight => bottom_height(right),
+      SnapRegion::Maximize => area.clone(),
+    }
+  }
+
+  /// The edges of the area that a window in the region is tiled against
+  pub fn tile_edges(&self) -> WindowEdge {
+    match self {
+      SnapRegion::Left => WindowEdge::TOP | WindowEdge::BOTTOM | WindowEdge::LEFT,
+      SnapRegion::Right => WindowEdge::TOP | WindowEdge::BOTTOM | WindowEdge::RIGHT,
+      SnapRegion::Top => WindowEdge::TOP | WindowEdge::LEFT | WindowEdge::RIGHT,
+      SnapRegion::Bottom => WindowEdge::BOTTOM | WindowEdge::LEFT | WindowEdge::RIGHT,
+      SnapRegion::TopLeft => WindowEdge::TOP | WindowEdge::LEFT,
+      SnapRegion::TopRight => WindowEdge::TOP | WindowEdge::RIGHT,
+      SnapRegion::BottomLeft => WindowEdge::BOTTOM | WindowEdge::LEFT,
+      SnapRegion::BottomRight => WindowEdge::BOTTOM | WindowEdge::RIGHT,
+      SnapRegion::Maximize => WindowEdge::all(),
+    }
+  }
+}
+
 #[derive(Debug)]
 pub struct PendingUpdate {
   top_left: Point,
+  size: Size,
+}
+
+/// A client that did not follow the protocol
+///
+/// The client is not disconnected, the window management policy can decide
+/// how to handle it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+  /// The window geometry committed with a configure that made the window
+  /// maximized, fullscreen or tiled is larger than the configured size
+  SizeExceedsConfigure { configured: Size, committed: Size },
+}
+
+/// Checks the size committed in response to a configure with the
+/// `configured` size, where a zero dimension lets the client decide
+///
+/// The configured size is only a hint for floating windows, so it is only
+/// enforced when the configure `constrained` the window by making it
+/// maximized, fullscreen or tiled.
+fn check_committed_size(
+  configured: Size,
+  committed: Size,
+  constrained: bool,
+) -> Option<ProtocolError> {
+  if !constrained {
+    return None;
+  }
+  let too_wide = configured.width > 0 && committed.width > configured.width;
+  let too_high = configured.height > 0 && committed.height > configured.height;
+  if too_wide || too_high {
+    Some(ProtocolError::SizeExceedsConfigure {
+      configured,
+      committed,
+    })
+  } else {
+    None
+  }
+}
+
+/// A drop shadow below a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadow {
+  /// RGBA color of the shadow
+  pub color: [f32; 4],
+  /// How far the shadow is blurred, in layout pixels
+  pub blur_radius: f32,
+}
+
+/// Effects that a window should be drawn with
+///
+/// The built-in renderer does not draw them, they are a standard place for
+/// custom render code to read them from.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RenderHints {
+  /// The radius of rounded corners in layout pixels, or 0 for square corners
+  pub corner_radius: f32,
+  pub shadow: Option<Shadow>,
+}
+
+/// Extents computed since the geometry of the window last changed
+#[derive(Debug, Default)]
+pub(crate) struct ExtentsCache {
+  extents: Option<Rectangle>,
+  buffer_extents: Option<Rectangle>,
 }
 
 #[derive(Debug)]
@@ -42,16 +249,77 @@ pub struct MinimizeTarget {
   rectangle: Rectangle,
 }
 
+impl MinimizeTarget {
+  /// The surface of the panel that registered the target
+  pub fn surface(&self) -> *mut wlr_surface {
+    self.surface
+  }
+  /// The position of the target, relative to the surface
+  pub fn rectangle(&self) -> Rectangle {
+    self.rectangle.clone()
+  }
+}
+
+/// Calculates the render translate and scale that places a window with
+/// `extents` in the center of `target`, keeping its aspect ratio
+fn minimize_transform(extents: &Rectangle, target: &Rectangle) -> (FDisplacement, f32) {
+  let scale = (target.width() as f32 / extents.width().max(1) as f32)
+    .min(target.height() as f32 / extents.height().max(1) as f32);
+  let size = extents.size() * scale;
+  let translate = FDisplacement {
+    dx: (target.center_x() - size.width / 2 - extents.left()) as f64,
+    dy: (target.center_y() - size.height / 2 - extents.top()) as f64,
+  };
+  (translate, scale)
+}
+
+/// The top left corner that centers a window of `size` on `center`, moved
+/// so that the window is fully inside `area` if it fits
+fn centered_top_left(size: Size, center: Point, area: &Rectangle) -> Point {
+  let extents = Rectangle {
+    top_left: Point {
+      x: center.x - size.width / 2,
+      y: center.y - size.height / 2,
+    },
+    size,
+  };
+  position_inside(&extents, area)
+}
+
+/// A stable identifier of a window
+///
+/// Ids are assigned in creation order and never reused while the compositor
+/// is running, so unlike surface pointers they can safely be used as keys.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct WindowId(pub(crate) u64);
+
 #[derive(Debug)]
 pub struct Window {
+  pub(crate) id: WindowId,
   pub(crate) output_manager: Rc<OutputManager>,
   pub(crate) window_manager: Rc<WindowManager>,
 
   pub(crate) surface: Surface,
-  pub(crate) layer: WindowLayer,
+  pub(crate) layer: RefCell<WindowLayer>,
+  /// The layer to return to when the window leaves fullscreen
+  pub(crate) layer_before_fullscreen: RefCell<Option<WindowLayer>>,
   pub(crate) mapped: RefCell<bool>,
   pub(crate) top_left: RefCell<Point>,
-  pub(crate) translate: RefCell<Displacement>,
+  pub(crate) extents_cache: RefCell<ExtentsCache>,
+  pub(crate) popup_root: RefCell<Weak<Window>>,
+  /// Popups positioned relative to this window
+  pub(crate) popups: RefCell<Vec<Weak<Window>>>,
+  pub(crate) translate: RefCell<FDisplacement>,
+  pub(crate) render_scale: RefCell<f32>,
+  pub(crate) render_hints: RefCell<RenderHints>,
+  pub(crate) minimized: RefCell<bool>,
+  pub(crate) minimize_animation: RefCell<Option<AnimationHandle>>,
+  pub(crate) tile_edges: RefCell<WindowEdge>,
+  pub(crate) responsive: RefCell<bool>,
+  pub(crate) awaiting_pong: RefCell<bool>,
+  pub(crate) foreign_toplevel_handle: RefCell<Option<ForeignToplevelHandle>>,
+  /// Forwards outputs entered and left to the foreign toplevel handle
+  pub(crate) foreign_toplevel_subscriptions: RefCell<Vec<Subscription>>,
 
   pub(crate) outputs: RefCell<Vec<Rc<Output>>>,
   pub(crate) minimize_targets: RefCell<Vec<MinimizeTarget>>,
@@ -60,12 +328,32 @@ pub struct Window {
 
   pub(crate) on_entered_output: Event<Rc<Output>>,
   pub(crate) on_left_output: Event<Rc<Output>>,
+  pub(crate) on_map: Event<()>,
+  pub(crate) on_unmap: Event<()>,
   pub(crate) on_destroy: EventOnce<()>,
+  pub(crate) on_ping_timeout: Event<()>,
+  pub(crate) on_parent_event: Event<ParentEvent>,
+  pub(crate) on_pong: Event<()>,
+  pub(crate) on_protocol_error: Event<ProtocolError>,
 
   pub(crate) event_manager: RefCell<Option<SurfaceEventManager>>,
 }
 
 impl Window {
+  pub fn id(&self) -> WindowId {
+    self.id
+  }
+
+  pub fn layer(&self) -> WindowLayer {
+    *self.layer.borrow()
+  }
+
+  /// The layer of the window when it is not fullscreen
+  pub fn original_layer(&self) -> WindowLayer {
+    let layer_before_fullscreen = *self.layer_before_fullscreen.borrow();
+    layer_before_fullscreen.unwrap_or_else(|| self.layer())
+  }
+
   pub(crate) fn surface(&self) -> &Surface {
     &self.surface
   }
@@ -88,6 +376,45 @@ impl Window {
     self.surface.wlr_surface()
   }
 
+  /// The PID of the process that owns the window
+  ///
+  /// For X11 windows this is read from _NET_WM_PID and is None if the client
+  /// has not set it.
+  pub fn pid(&self) -> Option<libc::pid_t> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.pid(),
+      _ => self.credentials().map(|(pid, _, _)| pid),
+    }
+  }
+
+  /// The PID, UID and GID of the process that owns the window
+  ///
+  /// Only available for Wayland clients, as all X11 windows are owned by
+  /// the Xwayland server.
+  pub fn credentials(&self) -> Option<(libc::pid_t, libc::uid_t, libc::gid_t)> {
+    if let Surface::Xwayland(_) = self.surface {
+      return None;
+    }
+    let wl_client = self.wl_client();
+    if wl_client.is_null() {
+      return None;
+    }
+    let mut pid = 0;
+    let mut uid = 0;
+    let mut gid = 0;
+    unsafe {
+      ffi_dispatch!(
+        WAYLAND_SERVER_HANDLE,
+        wl_client_get_credentials,
+        wl_client,
+        &mut pid,
+        &mut uid,
+        &mut gid
+      );
+    }
+    Some((pid, uid, gid))
+  }
+
   pub fn parent_wlr_surface(&self) -> Option<*mut wlr_surface> {
     self.surface.parent_wlr_surface()
   }
@@ -98,20 +425,115 @@ impl Window {
   pub fn on_left_output(&self) -> &Event<Rc<Output>> {
     &self.on_left_output
   }
+  /// Fired when the window is mapped and about to be shown
+  pub fn on_map(&self) -> &Event<()> {
+    &self.on_map
+  }
+  /// Fired when the window is unmapped and hidden, it can be mapped again
+  /// later
+  pub fn on_unmap(&self) -> &Event<()> {
+    &self.on_unmap
+  }
+  /// Fired when the client of the window does not follow the protocol
+  pub fn on_protocol_error(&self) -> &Event<ProtocolError> {
+    &self.on_protocol_error
+  }
   pub fn on_destroy(&self) -> &EventOnce<()> {
     &self.on_destroy
   }
+  /// Fired when the parent of the window is destroyed, minimized or
+  /// restored, so that the window can follow
+  pub fn on_parent_event(&self) -> &Event<ParentEvent> {
+    &self.on_parent_event
+  }
+  /// Fired when the client does not respond to a ping in time
+  pub fn on_ping_timeout(&self) -> &Event<()> {
+    &self.on_ping_timeout
+  }
+  /// Fired when the client has responded to a ping
+  pub fn on_pong(&self) -> &Event<()> {
+    &self.on_pong
+  }
 
-  fn position_displacement(&self) -> Displacement {
-    let parent_displacement = self
+  /// The surface of the window this window is a popup of or is transient
+  /// for
+  fn parent_or_transient_for_wlr_surface(&self) -> Option<*mut wlr_surface> {
+    self
       .surface
       .parent_wlr_surface()
-      .and_then(|parent_wlr_surface| {
-        self
-          .window_manager
-          .windows()
-          .find(|w| w.wlr_surface() == parent_wlr_surface)
-      })
+      .or_else(|| self.surface.transient_for_wlr_surface())
+  }
+
+  /// The window this window is a popup of or is transient for
+  pub fn parent(&self) -> Option<Rc<Window>> {
+    self
+      .window_manager
+      .window_by_surface(self.parent_or_transient_for_wlr_surface()?)
+  }
+
+  /// The windows that are popups of or transient for this window
+  pub fn children(&self) -> Vec<Rc<Window>> {
+    let wlr_surface = self.wlr_surface();
+    self
+      .window_manager
+      .windows()
+      .filter(|window| window.parent_or_transient_for_wlr_surface() == Some(wlr_surface))
+      .collect()
+  }
+
+  /// Sends outputs entered and left by the window to the foreign toplevel
+  /// handle, replacing the subscriptions for any previous handle
+  fn forward_outputs_to_foreign_toplevel(&self, handle: *mut wlr_foreign_toplevel_handle_v1) {
+    let subscriptions = vec![
+      self
+        .on_entered_output
+        .subscribe_scoped(Box::new(move |output| unsafe {
+          wlr_foreign_toplevel_handle_v1_output_enter(handle, output.raw_ptr());
+        })),
+      self
+        .on_left_output
+        .subscribe_scoped(Box::new(move |output| unsafe {
+          wlr_foreign_toplevel_handle_v1_output_leave(handle, output.raw_ptr());
+        })),
+    ];
+    *self.foreign_toplevel_subscriptions.borrow_mut() = subscriptions;
+  }
+
+  /// Groups the foreign toplevel handle of the window under the handle of
+  /// its parent, if both have one
+  fn update_foreign_toplevel_parent(&self) {
+    if let Some(handle) = self.foreign_toplevel_handle.borrow().as_ref() {
+      let parent = self.parent();
+      let parent_handle = parent
+        .as_ref()
+        .map(|parent| parent.foreign_toplevel_handle.borrow());
+      handle.set_parent(parent_handle.as_ref().and_then(|handle| handle.as_ref()));
+    }
+  }
+
+  fn notify_children(&self, event: ParentEvent) {
+    for child in self.children() {
+      child.on_parent_event.fire(event);
+    }
+  }
+
+  /// The window at the root of the popup tree of this window, which is only
+  /// looked up once as it can not change
+  fn popup_root(&self) -> Option<Rc<Window>> {
+    if let Some(root) = self.popup_root.borrow().upgrade() {
+      return Some(root);
+    }
+    let root = self
+      .surface
+      .popup_root_wlr_surface()
+      .and_then(|root_wlr_surface| self.window_manager.window_by_surface(root_wlr_surface))?;
+    *self.popup_root.borrow_mut() = Rc::downgrade(&root);
+    Some(root)
+  }
+
+  fn position_displacement(&self) -> Displacement {
+    let parent_displacement = self
+      .popup_root()
       .map(|w| w.buffer_extents().top_left().as_displacement())
       .unwrap_or_default();
 
@@ -121,9 +543,56 @@ impl Window {
       - self.surface.buffer_displacement()
   }
 
+  /// Tracks a popup positioned relative to this window, so that its
+  /// extents are invalidated together with the extents of this window
+  fn add_popup(&self, popup: &Rc<Window>) {
+    let mut popups = self.popups.borrow_mut();
+    popups.retain(|popup| popup.upgrade().is_some());
+    if !popups.iter().any(|existing| {
+      existing
+        .upgrade()
+        .map_or(false, |existing| Rc::ptr_eq(&existing, popup))
+    }) {
+      popups.push(Rc::downgrade(popup));
+    }
+  }
+
+  /// Drops the cached extents of the window and of its popups
+  pub(crate) fn invalidate_extents(&self) {
+    *self.extents_cache.borrow_mut() = ExtentsCache::default();
+    self.window_manager.invalidate_extents(self.id);
+    let popups: Vec<_> = self
+      .popups
+      .borrow()
+      .iter()
+      .filter_map(|popup| popup.upgrade())
+      .collect();
+    for popup in popups {
+      popup.invalidate_extents();
+    }
+  }
+
+  /// Returns the cached rectangle selected by `select`, computing it with
+  /// `compute` if the geometry of the window changed since it was cached
+  fn cached_extents<S, C>(&self, select: S, compute: C) -> Rectangle
+  where
+    S: Fn(&mut ExtentsCache) -> &mut Option<Rectangle>,
+    C: FnOnce() -> Rectangle,
+  {
+    if let Some(rectangle) = select(&mut self.extents_cache.borrow_mut()) {
+      return rectangle.clone();
+    }
+    let rectangle = compute();
+    *select(&mut self.extents_cache.borrow_mut()) = Some(rectangle.clone());
+    rectangle
+  }
+
   /// The position and size of the window
   pub fn extents(&self) -> Rectangle {
-    self.surface.extents() + self.position_displacement()
+    self.cached_extents(
+      |cache| &mut cache.extents,
+      || self.surface.extents() + self.position_displacement(),
+    )
   }
 
   /// The size of the window
@@ -137,44 +606,259 @@ impl Window {
   /// this is larger than the window extents to also fit
   /// said shadows.
   pub fn buffer_extents(&self) -> Rectangle {
-    let surface = unsafe { &*self.wlr_surface() };
+    self.cached_extents(
+      |cache| &mut cache.buffer_extents,
+      || {
+        let surface = unsafe { &*self.wlr_surface() };
 
-    let buffer_rect = Rectangle {
-      top_left: Point {
-        x: surface.current.dx,
-        y: surface.current.dy,
-      },
-      size: Size {
-        width: surface.current.width,
-        height: surface.current.height,
+        let buffer_rect = Rectangle {
+          top_left: Point {
+            x: surface.current.dx,
+            y: surface.current.dy,
+          },
+          size: Size {
+            width: surface.current.width,
+            height: surface.current.height,
+          },
+        };
+
+        buffer_rect + self.position_displacement()
       },
-    };
+    )
+  }
 
-    buffer_rect + self.position_displacement()
+  /// The surface under the point in layout coordinates, which can be the
+  /// surface of the window or one of its subsurfaces or popups, and the
+  /// point in the coordinates of that surface
+  pub fn surface_at(&self, point: FPoint) -> Option<(*mut wlr_surface, FPoint)> {
+    self
+      .surface
+      .surface_at(point - FPoint::from(self.buffer_extents().top_left()).as_displacement())
   }
 
   /// Atomically updates position and size
   ///
   /// As size updates have to be communicated to the client,
   /// this will not cause an immediately observable effect.
+  ///
+  /// The size is constrained to the limits of the window. If the size is
+  /// constrained while the top or left edge is moved, the position is adjusted
+  /// so that the opposite edge stays in place.
   pub fn set_extents(&self, extents: &Rectangle) {
-    self.pending_updates.borrow_mut().insert(
-      self.surface.resize(extents.size()),
-      PendingUpdate {
-        top_left: extents.top_left(),
-      },
-    );
+    let size = self.constrain_size(extents.size());
+    let current = self.extents();
+    let mut top_left = extents.top_left();
+    if extents.left() != current.left() {
+      top_left.x = extents.right() - size.width;
+    }
+    if extents.top() != current.top() {
+      top_left.y = extents.bottom() - size.height;
+    }
+
+    // X11 has no configure serials, so the position is configured together
+    // with the size instead of being applied when the matching commit arrives
+    if let Surface::Xwayland(surface) = &self.surface {
+      *self.top_left.borrow_mut() = top_left;
+      self.invalidate_extents();
+      surface.configure(top_left, size);
+      self.update_outputs();
+      return;
+    }
+
+    self
+      .pending_updates
+      .borrow_mut()
+      .insert(self.surface.resize(size), PendingUpdate { top_left, size });
+  }
+
+  /// Reads back the position of a window that positions itself, like X11
+  /// override redirect menus and tooltips
+  pub(crate) fn sync_unmanaged_position(&self) {
+    if self.layer() != WindowLayer::Unmanaged {
+      return;
+    }
+    if let Surface::Xwayland(surface) = &self.surface {
+      *self.top_left.borrow_mut() = surface.position();
+      self.invalidate_extents();
+    }
+  }
+
+  /// Configures a window that has not yet been mapped
+  pub(crate) fn apply_initial_state(&self, state: InitialWindowState) {
+    if state.maximized {
+      self.set_maximized(true);
+    }
+    if state.fullscreen {
+      self.set_fullscreen(true);
+    }
+    if let Some(extents) = state.extents {
+      self.set_extents(&extents);
+    }
+  }
+
+  /// Clamps the size to the minimum and maximum size of the window
+  ///
+  /// If the window has a size increment, like a terminal that resizes in
+  /// character cells, the size is first rounded down to a whole increment.
+  pub fn constrain_size(&self, size: Size) -> Size {
+    let size = match self.size_increment() {
+      Some(increment) => {
+        // ICCCM specifies that the minimum size is used as the base size
+        // when no base size is set
+        let base = self.base_size().unwrap_or_else(|| Size {
+          width: self.min_width().unwrap_or(0) as i32,
+          height: self.min_height().unwrap_or(0) as i32,
+        });
+        snap_to_increment(size, base, increment)
+      }
+      None => size,
+    };
+    constrain_size(
+      size,
+      self.min_width(),
+      self.max_width(),
+      self.min_height(),
+      self.max_height(),
+    )
   }
 
+  /// Moves the window, keeping it on screen if `keep_windows_on_screen` is
+  /// set in the config
   pub fn move_to(&self, top_left: Point) {
+    let top_left = if self.keeps_on_screen() {
+      let current = *self.top_left.borrow();
+      let extents = self.extents() + (top_left - current);
+      top_left + (self.clamped_top_left(&extents, &self.output_manager) - extents.top_left())
+    } else {
+      top_left
+    };
+    self.set_top_left(top_left);
+  }
+
+  fn set_top_left(&self, top_left: Point) {
     *self.top_left.borrow_mut() = top_left;
+    self.invalidate_extents();
 
     self.surface.move_to(top_left);
     self.update_outputs();
   }
 
+  /// If the window is moved back on screen when moved, which only applies
+  /// to normal windows with an independent position
+  fn keeps_on_screen(&self) -> bool {
+    self.original_layer() == WindowLayer::Normal
+      && self.surface.parent_wlr_surface().is_none()
+      && self
+        .output_manager
+        .config_manager()
+        .config()
+        .keep_windows_on_screen
+  }
+
+  fn keep_on_screen_margin(&self) -> i32 {
+    self
+      .output_manager
+      .config_manager()
+      .config()
+      .keep_on_screen_margin as i32
+  }
+
+  /// The top left corner of `extents` after moving it the shortest distance
+  /// so that it keeps the margin on some output
+  ///
+  /// A window spanning multiple outputs is not moved as long as it keeps
+  /// the margin on one of them.
+  fn clamped_top_left(&self, extents: &Rectangle, output_manager: &OutputManager) -> Point {
+    let margin = self.keep_on_screen_margin();
+    output_manager
+      .outputs()
+      .iter()
+      .map(|output| position_partly_inside(extents, &output.extents(), margin))
+      .min_by_key(|top_left| {
+        let distance = *top_left - extents.top_left();
+        distance.dx * distance.dx + distance.dy * distance.dy
+      })
+      .unwrap_or_else(|| extents.top_left())
+  }
+
+  /// Moves the window the shortest distance so that at least
+  /// `keep_on_screen_margin` pixels of it are on the output
+  pub fn clamp_to(&self, output: &Output) {
+    let current = *self.top_left.borrow();
+    let extents = self.extents();
+    let top_left =
+      position_partly_inside(&extents, &output.extents(), self.keep_on_screen_margin());
+    self.set_top_left(current + (top_left - extents.top_left()));
+  }
+
+  /// Moves the window the shortest distance so that at least
+  /// `keep_on_screen_margin` pixels of it are on some output
+  pub fn clamp_to_outputs(&self, output_manager: &OutputManager) {
+    let current = *self.top_left.borrow();
+    let extents = self.extents();
+    let top_left = self.clamped_top_left(&extents, output_manager);
+    self.set_top_left(current + (top_left - extents.top_left()));
+  }
+
+  /// Moves the window to the center of the output
+  ///
+  /// Windows larger than the output are aligned to its top left corner.
+  pub fn center_on(&self, output: &Output) {
+    let area = output.extents();
+    let center = Point {
+      x: area.center_x(),
+      y: area.center_y(),
+    };
+    self.move_to(centered_top_left(self.size(), center, &area));
+  }
+
+  /// Moves the window so that it is centered on the cursor while staying on
+  /// the output under the cursor
+  pub fn center_on_cursor(&self, cursor_manager: &CursorManager) {
+    let center: Point = cursor_manager.position().into();
+    let area = self
+      .output_manager
+      .outputs()
+      .iter()
+      .find(|output| output.contains_global(&center))
+      .map(|output| output.extents());
+    let top_left = match area {
+      Some(area) => centered_top_left(self.size(), center, &area),
+      None => Point {
+        x: center.x - self.size().width / 2,
+        y: center.y - self.size().height / 2,
+      },
+    };
+    self.move_to(top_left);
+  }
+
+  /// Moves the window together with all windows that are transient for it
+  ///
+  /// The descendants keep their position relative to this window. Popups are
+  /// already positioned relative to their parent so only windows with an
+  /// independent position are moved.
+  pub fn move_to_with_children(&self, top_left: Point) {
+    let previous = *self.top_left.borrow();
+    self.move_to(top_left);
+    let delta = *self.top_left.borrow() - previous;
+
+    let mut pending = self.children();
+    let mut visited: Vec<Rc<Window>> = vec![];
+    while let Some(window) = pending.pop() {
+      if visited.contains(&window) || &*window == self {
+        continue;
+      }
+      if window.surface.parent_wlr_surface().is_none() {
+        let top_left = *window.top_left.borrow() + delta;
+        window.move_to(top_left);
+      }
+      pending.extend(window.children());
+      visited.push(window);
+    }
+  }
+
   pub fn resize(&self, size: Size) {
-    self.surface.resize(size);
+    self.surface.resize(self.constrain_size(size));
   }
 
   pub fn min_height(&self) -> Option<u32> {
@@ -190,11 +874,145 @@ impl Window {
     self.surface.max_width()
   }
 
+  /// The size that the window wants to be resized in steps of
+  pub fn size_increment(&self) -> Option<Size> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.size_increment(),
+      _ => None,
+    }
+  }
+  /// The size that size increments are counted from
+  pub fn base_size(&self) -> Option<Size> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.base_size(),
+      _ => None,
+    }
+  }
+  /// The minimum and maximum aspect ratio, as width / height
+  pub fn aspect_ratio_range(&self) -> Option<(f64, f64)> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.aspect_ratio_range(),
+      _ => None,
+    }
+  }
+
   pub fn translate(&self) -> Displacement {
-    self.translate.borrow().clone()
+    self.translate.borrow().clone().into()
   }
   pub fn set_translate(&self, translate: Displacement) {
+    *self.translate.borrow_mut() = translate.into();
+  }
+
+  /// The translate and scale that the window is rendered with
+  pub fn render_transform(&self) -> (FDisplacement, f32) {
+    (self.translate.borrow().clone(), *self.render_scale.borrow())
+  }
+  /// Changes where and how large the window is rendered, without changing
+  /// its actual position or size. The scale is around the top left corner
+  /// of the window and is applied before the translate.
+  ///
+  /// Input is only mapped through the transform if
+  /// `WindowManager::set_hit_test_render_transform` is enabled.
+  pub fn set_render_transform(&self, translate: FDisplacement, scale: f32) {
     *self.translate.borrow_mut() = translate;
+    *self.render_scale.borrow_mut() = scale;
+  }
+
+  pub fn render_hints(&self) -> RenderHints {
+    self.render_hints.borrow().clone()
+  }
+  /// Rounds the corners of the window when drawn by a renderer that
+  /// supports it
+  pub fn set_corner_radius(&self, corner_radius: f32) {
+    self.render_hints.borrow_mut().corner_radius = corner_radius.max(0.0);
+  }
+  /// Draws a shadow below the window when drawn by a renderer that supports
+  /// it
+  pub fn set_shadow(&self, enabled: bool, color: [f32; 4], blur_radius: f32) {
+    self.render_hints.borrow_mut().shadow = if enabled {
+      Some(Shadow {
+        color,
+        blur_radius: blur_radius.max(0.0),
+      })
+    } else {
+      None
+    };
+  }
+
+  /// The buffer extents of the window as rendered, with the render
+  /// transform applied
+  pub fn render_extents(&self) -> Rectangle {
+    let buffer_extents = self.buffer_extents();
+    let (translate, scale) = self.render_transform();
+    Rectangle {
+      top_left: buffer_extents.top_left() + Displacement::from(translate),
+      size: buffer_extents.size() * scale,
+    }
+  }
+
+  /// The parts of the window that the client has marked as opaque, in
+  /// layout coordinates
+  ///
+  /// Empty while a render transform is set, as the region would not match
+  /// what is on screen.
+  pub fn opaque_region(&self) -> Vec<Rectangle> {
+    let (translate, scale) = self.render_transform();
+    if translate.dx != 0.0 || translate.dy != 0.0 || scale != 1.0 {
+      return vec![];
+    }
+    let top_left = self.buffer_extents().top_left();
+    unsafe {
+      let mut count = 0;
+      let rectangles =
+        pixman_region32_rectangles(&mut (*self.wlr_surface()).current.opaque, &mut count);
+      if rectangles.is_null() {
+        return vec![];
+      }
+      std::slice::from_raw_parts(rectangles, count as usize)
+        .iter()
+        .map(|rectangle| Rectangle {
+          top_left: Point {
+            x: top_left.x + rectangle.x1,
+            y: top_left.y + rectangle.y1,
+          },
+          size: Size {
+            width: rectangle.x2 - rectangle.x1,
+            height: rectangle.y2 - rectangle.y1,
+          },
+        })
+        .collect()
+    }
+  }
+
+  /// Maps a point in layout coordinates to the untransformed window, which
+  /// is the inverse of the render transform
+  pub fn untransform_point(&self, point: FPoint) -> FPoint {
+    let origin = FPoint::from(self.buffer_extents().top_left());
+    let (translate, scale) = self.render_transform();
+    let scale = if scale == 0.0 { 1.0 } else { scale as f64 };
+    FPoint {
+      x: origin.x + (point.x - origin.x - translate.dx) / scale,
+      y: origin.y + (point.y - origin.y - translate.dy) / scale,
+    }
+  }
+
+  /// Snaps the window to a region of the output
+  ///
+  /// The window is marked as tiled against the edges of the output that it
+  /// touches.
+  pub fn snap_to(&self, region: SnapRegion, output: &Output) {
+    self.set_tile_edges(region.tile_edges());
+    self.set_extents(&region.extents(&output.extents()));
+  }
+
+  /// The edges of the window that are tiled against other windows or the
+  /// edges of the output
+  pub fn tile_edges(&self) -> WindowEdge {
+    *self.tile_edges.borrow()
+  }
+  pub fn set_tile_edges(&self, edges: WindowEdge) {
+    *self.tile_edges.borrow_mut() = edges;
+    self.surface.set_tiled(edges.bits());
   }
 
   pub fn activated(&self) -> bool {
@@ -205,6 +1023,9 @@ impl Window {
   }
   pub fn set_activated(&self, activated: bool) {
     self.surface.set_activated(activated);
+    if let Some(handle) = self.foreign_toplevel_handle.borrow().as_ref() {
+      handle.set_activated(activated);
+    }
   }
 
   pub fn maximized(&self) -> bool {
@@ -212,12 +1033,37 @@ impl Window {
   }
   pub fn set_maximized(&self, maximized: bool) {
     self.surface.set_maximized(maximized);
+    if let Some(handle) = self.foreign_toplevel_handle.borrow().as_ref() {
+      handle.set_maximized(maximized);
+    }
   }
   pub fn fullscreen(&self) -> bool {
     self.surface.fullscreen()
   }
+  /// Fullscreen windows are moved to the fullscreen layer so that they
+  /// cover panels and are moved back to their original layer on exit
   pub fn set_fullscreen(&self, fullscreen: bool) {
     self.surface.set_fullscreen(fullscreen);
+    let layer_before_fullscreen = *self.layer_before_fullscreen.borrow();
+    match layer_before_fullscreen {
+      None if fullscreen => {
+        let layer = self.layer();
+        if layer == WindowLayer::Normal || layer == WindowLayer::Top {
+          *self.layer_before_fullscreen.borrow_mut() = Some(layer);
+          self
+            .window_manager
+            .set_window_layer(self, WindowLayer::Fullscreen);
+        }
+      }
+      Some(layer) if !fullscreen => {
+        *self.layer_before_fullscreen.borrow_mut() = None;
+        self.window_manager.set_window_layer(self, layer);
+      }
+      _ => {}
+    }
+    if let Some(handle) = self.foreign_toplevel_handle.borrow().as_ref() {
+      handle.set_fullscreen(fullscreen);
+    }
   }
   pub fn resizing(&self) -> bool {
     self.surface.resizing()
@@ -226,12 +1072,39 @@ impl Window {
     self.surface.set_resizing(resizing);
   }
 
+  pub fn window_type(&self) -> WindowType {
+    self.surface.window_type()
+  }
+  pub fn is_dialog(&self) -> bool {
+    self.window_type() == WindowType::Dialog
+  }
+  /// If the window should block input to its parent while open
+  ///
+  /// Only X11 windows can be modal, as xdg-shell has no modal hint.
+  pub fn is_modal(&self) -> bool {
+    self.surface.is_modal()
+  }
+
   pub fn app_id(&self) -> Option<String> {
     self.surface.app_id()
   }
   pub fn title(&self) -> Option<String> {
     self.surface.title()
   }
+  /// The X11 instance name, None for Wayland windows
+  pub fn instance(&self) -> Option<String> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.instance(),
+      _ => None,
+    }
+  }
+  /// The X11 window role, None for Wayland windows
+  pub fn role(&self) -> Option<String> {
+    match &self.surface {
+      Surface::Xwayland(surface) => surface.role(),
+      _ => None,
+    }
+  }
 
   /// Outputs that the window currently appears on.
   pub fn outputs(&self) -> Ref<Vec<Rc<Output>>> {
@@ -242,11 +1115,179 @@ impl Window {
   pub fn minimize_targets(&self) -> Ref<Vec<MinimizeTarget>> {
     self.minimize_targets.borrow()
   }
+  /// The minimize target in layout coordinates
+  ///
+  /// When multiple panels have registered rectangles, the target of the
+  /// panel that registered first is used. Targets on surfaces that are not
+  /// mapped windows are skipped.
+  pub fn minimize_target(&self) -> Option<Rectangle> {
+    self.minimize_targets.borrow().iter().find_map(|target| {
+      self
+        .window_manager
+        .window_by_surface(target.surface)
+        .filter(|w| w.is_mapped())
+        .map(|panel| target.rectangle.clone() + panel.extents().top_left().as_displacement())
+    })
+  }
+
+  /// If the window has been mapped by the client and not yet unmapped
+  ///
+  /// Windows are created before they have any content, unmapped windows
+  /// are not rendered and should not be focused.
+  pub fn is_mapped(&self) -> bool {
+    *self.mapped.borrow()
+  }
+
+  /// If the window has been hidden by minimize_to_target
+  pub fn is_minimized(&self) -> bool {
+    *self.minimized.borrow()
+  }
+
+  /// Animates the window into its minimize target and then hides it
+  ///
+  /// If there is no minimize target the window is hidden immediately.
+  pub fn minimize_to_target(self: &Rc<Self>, animation_manager: &Rc<AnimationManager>) {
+    self.cancel_minimize_animation();
+
+    let target = match self.minimize_target() {
+      Some(target) => target,
+      None => {
+        self.set_minimized(true);
+        return;
+      }
+    };
+    let (to_translate, to_scale) = minimize_transform(&self.buffer_extents(), &target);
+    let window = Rc::downgrade(self);
+    self.animate_render_transform(
+      animation_manager,
+      to_translate,
+      to_scale,
+      Box::new(move || {
+        if let Some(window) = window.upgrade() {
+          window.set_minimized(true);
+        }
+      }),
+    );
+  }
+
+  /// Shows a window hidden by minimize_to_target and animates it back from
+  /// its minimize target
+  pub fn restore_from_minimize(self: &Rc<Self>, animation_manager: &Rc<AnimationManager>) {
+    self.cancel_minimize_animation();
+    self.set_minimized(false);
+    self.animate_render_transform(animation_manager, Displacement::ZERO, 1.0, Box::new(|| {}));
+  }
+
+  fn set_minimized(&self, minimized: bool) {
+    if self.minimized.replace(minimized) != minimized {
+      self.notify_children(ParentEvent::Minimized(minimized));
+    }
+    if let Some(handle) = self.foreign_toplevel_handle.borrow().as_ref() {
+      handle.set_minimized(minimized);
+    }
+  }
+
+  fn cancel_minimize_animation(&self) {
+    if let Some(animation) = self.minimize_animation.borrow_mut().take() {
+      animation.cancel();
+    }
+  }
+
+  fn animate_render_transform(
+    self: &Rc<Self>,
+    animation_manager: &Rc<AnimationManager>,
+    to_translate: FDisplacement,
+    to_scale: f32,
+    on_done: Box<dyn Fn()>,
+  ) {
+    let (from_translate, from_scale) = self.render_transform();
+    let window = Rc::downgrade(self);
+    let animation = animation_manager.animate_window(
+      self,
+      MINIMIZE_ANIMATION_DURATION,
+      Easing::EaseInOut,
+      move |progress| {
+        if let Some(window) = window.upgrade() {
+          let lerp = |from: f64, to: f64| from + (to - from) * progress as f64;
+          window.set_render_transform(
+            FDisplacement {
+              dx: lerp(from_translate.dx, to_translate.dx),
+              dy: lerp(from_translate.dy, to_translate.dy),
+            },
+            from_scale + (to_scale - from_scale) * progress,
+          );
+          if progress >= 1.0 {
+            on_done();
+          }
+        }
+      },
+    );
+    *self.minimize_animation.borrow_mut() = Some(animation);
+  }
 
   pub fn ask_client_to_close(&self) {
     self.surface.ask_client_to_close()
   }
 
+  /// Checks if the client is responsive
+  ///
+  /// If the client does not respond within the ping timeout of the
+  /// WindowManager, on_ping_timeout is fired and is_responsive returns false
+  /// until it responds. Only Wayland toplevels and popups can be pinged.
+  /// X11 windows are always considered responsive, as wlroots does not
+  /// support _NET_WM_PING.
+  pub fn ping(&self) {
+    if let Surface::Xdg(surface) = &self.surface {
+      *self.awaiting_pong.borrow_mut() = true;
+      surface.ping();
+    }
+  }
+
+  /// Called when the client of the window responds to a ping
+  pub(crate) fn handle_pong(&self) {
+    let was_awaiting = self.awaiting_pong.replace(false);
+    let was_responsive = self.responsive.replace(true);
+    if was_awaiting || !was_responsive {
+      self.on_pong.fire(());
+    }
+  }
+
+  /// Called when the client of the window did not respond to a ping in time
+  pub(crate) fn handle_ping_timeout(&self) {
+    if self.responsive.replace(false) {
+      debug!("Window::handle_ping_timeout: Client did not respond to ping");
+      self.on_ping_timeout.fire(());
+    }
+  }
+
+  /// If the client responded to the last ping
+  pub fn is_responsive(&self) -> bool {
+    *self.responsive.borrow()
+  }
+
+  /// Sends `wl_surface.enter` or `wl_surface.leave` for the surface of the
+  /// window and its subsurfaces, so that the client renders with the scale
+  /// of the outputs it is on
+  fn send_output_event(&self, output: &Output, entered: bool) {
+    self.surface.for_each_surface(|surface, _| unsafe {
+      if entered {
+        wlr_surface_send_enter(surface, output.raw_ptr());
+      } else {
+        wlr_surface_send_leave(surface, output.raw_ptr());
+      }
+    });
+  }
+
+  /// Removes an output that is going away from the outputs of the window
+  pub(crate) fn leave_output(&self, output: &Rc<Output>) {
+    let was_on_output = self.outputs().iter().any(|o| o == output);
+    if was_on_output {
+      self.outputs.borrow_mut().retain(|o| o != output);
+      self.send_output_event(output, false);
+      self.on_left_output.fire(output.clone());
+    }
+  }
+
   pub(crate) fn update_outputs(&self) {
     for output in self.output_manager.outputs().iter() {
       let previously_on_output = self.outputs().iter().any(|o| o == output);
@@ -254,9 +1295,11 @@ impl Window {
 
       if currently_on_output && !previously_on_output {
         self.outputs.borrow_mut().push(output.clone());
+        self.send_output_event(output, true);
         self.on_entered_output.fire(output.clone());
       } else if !currently_on_output && previously_on_output {
         self.outputs.borrow_mut().retain(|o| o != output);
+        self.send_output_event(output, false);
         self.on_left_output.fire(output.clone());
       }
     }
@@ -328,6 +1371,65 @@ impl ForeignToplevelHandle {
     }
     Ok(())
   }
+
+  /// Declares the handle to be a child of `parent`, letting taskbars group
+  /// dialogs under their main window
+  pub(crate) fn set_parent(&self, parent: Option<&ForeignToplevelHandle>) {
+    unsafe {
+      wlr_foreign_toplevel_handle_v1_set_parent(
+        self.0,
+        parent.map_or(std::ptr::null_mut(), |parent| parent.0),
+      );
+    }
+  }
+
+  fn has_state(&self, state: wlr_foreign_toplevel_handle_v1_state) -> bool {
+    unsafe { (*self.0).state & state != 0 }
+  }
+
+  pub fn maximized(&self) -> bool {
+    self.has_state(
+      wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_MAXIMIZED,
+    )
+  }
+  pub(crate) fn set_maximized(&self, maximized: bool) {
+    unsafe {
+      wlr_foreign_toplevel_handle_v1_set_maximized(self.0, maximized);
+    }
+  }
+
+  pub fn minimized(&self) -> bool {
+    self.has_state(
+      wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_MINIMIZED,
+    )
+  }
+  pub(crate) fn set_minimized(&self, minimized: bool) {
+    unsafe {
+      wlr_foreign_toplevel_handle_v1_set_minimized(self.0, minimized);
+    }
+  }
+
+  pub fn activated(&self) -> bool {
+    self.has_state(
+      wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_ACTIVATED,
+    )
+  }
+  pub(crate) fn set_activated(&self, activated: bool) {
+    unsafe {
+      wlr_foreign_toplevel_handle_v1_set_activated(self.0, activated);
+    }
+  }
+
+  pub fn fullscreen(&self) -> bool {
+    self.has_state(
+      wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_FULLSCREEN,
+    )
+  }
+  pub(crate) fn set_fullscreen(&self, fullscreen: bool) {
+    unsafe {
+      wlr_foreign_toplevel_handle_v1_set_fullscreen(self.0, fullscreen);
+    }
+  }
 }
 
 pub(crate) struct ForeignToplevelEventHandler {
@@ -474,13 +1576,18 @@ pub(crate) struct WindowEventHandler {
   pub(crate) window_manager: Rc<WindowManager>,
   pub(crate) cursor_manager: Rc<CursorManager>,
   pub(crate) window: Weak<Window>,
-  pub(crate) foreign_toplevel_handle: Option<ForeignToplevelHandle>,
   pub(crate) foreign_toplevel_event_manager: Option<Pin<Box<ForeignToplevelEventManager>>>,
 }
 
 impl WindowEventHandler {
   pub(crate) fn map(&mut self) {
     if let Some(window) = self.window.upgrade() {
+      // Mapping happens while committing, before the commit event
+      window.invalidate_extents();
+      if let Some(popup_root) = window.popup_root() {
+        popup_root.add_popup(&window);
+      }
+      window.sync_unmanaged_position();
       if window.surface().is_toplevel() {
         unsafe {
           let foreign_toplevel_handle = wlr_foreign_toplevel_handle_v1_create(
@@ -500,17 +1607,9 @@ impl WindowEventHandler {
           event_manager.request_minimize(&mut (*foreign_toplevel_handle).events.request_minimize);
           event_manager.set_rectangle(&mut (*foreign_toplevel_handle).events.set_rectangle);
 
-          self
-            .foreign_toplevel_handle
-            .replace(ForeignToplevelHandle(foreign_toplevel_handle));
           self.foreign_toplevel_event_manager.replace(event_manager);
 
-          window.on_entered_output.subscribe(Box::new(move |output| {
-            wlr_foreign_toplevel_handle_v1_output_enter(foreign_toplevel_handle, output.raw_ptr());
-          }));
-          window.on_left_output.subscribe(Box::new(move |output| {
-            wlr_foreign_toplevel_handle_v1_output_leave(foreign_toplevel_handle, output.raw_ptr());
-          }));
+          window.forward_outputs_to_foreign_toplevel(foreign_toplevel_handle);
 
           if let Some(app_id) = window.app_id() {
             let result = ForeignToplevelHandle(foreign_toplevel_handle).set_app_id(app_id);
@@ -530,18 +1629,54 @@ impl WindowEventHandler {
               );
             }
           }
+
+          let handle = ForeignToplevelHandle(foreign_toplevel_handle);
+          handle.set_maximized(window.maximized());
+          handle.set_minimized(window.is_minimized());
+          handle.set_activated(window.activated());
+          handle.set_fullscreen(window.fullscreen());
+          window.foreign_toplevel_handle.replace(Some(handle));
+          window.update_foreign_toplevel_parent();
+          for child in window.children() {
+            child.update_foreign_toplevel_parent();
+          }
         };
       }
       window.update_outputs();
-      self.wm_policy_manager.handle_window_ready(window.clone());
+      if window.layer() != WindowLayer::Unmanaged {
+        self.wm_policy_manager.handle_window_ready(window.clone());
+      }
       *window.mapped.borrow_mut() = true;
+      window.on_map.fire(());
+    }
+  }
+
+  pub(crate) fn ping_timeout(&mut self) {
+    if let Some(window) = self.window.upgrade() {
+      window.handle_ping_timeout();
+    }
+  }
+
+  pub(crate) fn updated_geometry(&mut self) {
+    if let Some(window) = self.window.upgrade() {
+      window.invalidate_extents();
+      window.sync_unmanaged_position();
+      window.update_outputs();
     }
   }
 
   pub(crate) fn unmap(&mut self) {
     if let Some(window) = self.window.upgrade() {
+      window.invalidate_extents();
       *window.mapped.borrow_mut() = false;
-      if let Some(handle) = self.foreign_toplevel_handle.take() {
+      window.on_unmap.fire(());
+      for child in window.children() {
+        if let Some(handle) = child.foreign_toplevel_handle.borrow().as_ref() {
+          handle.set_parent(None);
+        }
+      }
+      window.foreign_toplevel_subscriptions.borrow_mut().clear();
+      if let Some(handle) = window.foreign_toplevel_handle.borrow_mut().take() {
         unsafe {
           wlr_foreign_toplevel_handle_v1_destroy(handle.0);
         }
@@ -553,21 +1688,42 @@ impl WindowEventHandler {
   pub(crate) fn destroy(&mut self) {
     debug!("WindowEventHandler::destroy");
     if let Some(window) = self.window.upgrade() {
+      window.notify_children(ParentEvent::Destroyed);
       window.on_destroy.fire(());
       self.wm_policy_manager.advise_delete_window(window.clone());
+      let autofocus = self
+        .output_manager
+        .config_manager()
+        .config()
+        .autofocus_on_close
+        && self.window_manager.window_has_focus(&window);
       self.window_manager.destroy_window(window);
+      if autofocus {
+        self.window_manager.focus_top_window();
+      }
     }
   }
 
   pub(crate) fn commit(&mut self, event: WindowCommitEvent) {
     if let Some(window) = self.window.upgrade() {
+      window.invalidate_extents();
       if !window.can_receive_focus() && self.window_manager.window_has_focus(&window) {
         self.window_manager.blur();
       }
 
-      match window.pending_updates.borrow_mut().remove(&event.serial) {
+      let update = window.pending_updates.borrow_mut().remove(&event.serial);
+      match update {
         Some(update) => {
           window.move_to(update.top_left);
+          // The maximized and fullscreen states are the ones the client
+          // acknowledged with this commit
+          let constrained =
+            window.maximized() || window.fullscreen() || !window.tile_edges().is_empty();
+          if let Some(error) =
+            check_committed_size(update.size, window.extents().size(), constrained)
+          {
+            window.on_protocol_error.fire(error);
+          }
         }
         _ => {
           window.update_outputs();
@@ -643,7 +1799,7 @@ impl WindowEventHandler {
 
   pub(crate) fn updated_app_id(&mut self) {
     if let Some(window) = self.window.upgrade() {
-      if let Some(ref mut handle) = self.foreign_toplevel_handle {
+      if let Some(handle) = window.foreign_toplevel_handle.borrow_mut().as_mut() {
         if let Some(app_id) = window.app_id() {
           let result = handle.set_app_id(app_id);
           if result.is_err() {
@@ -658,7 +1814,7 @@ impl WindowEventHandler {
   }
   pub(crate) fn updated_title(&mut self) {
     if let Some(window) = self.window.upgrade() {
-      if let Some(ref mut handle) = self.foreign_toplevel_handle {
+      if let Some(handle) = window.foreign_toplevel_handle.borrow_mut().as_mut() {
         if let Some(title) = window.title() {
           let result = handle.set_title(title);
           if result.is_err() {
@@ -672,3 +1828,439 @@ impl WindowEventHandler {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::MockManagers;
+  use crate::window_manager::WindowManagerExt;
+  use std::ptr;
+
+  #[test]
+  fn it_syncs_state_to_the_foreign_toplevel_handle() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    let mut raw_handle: wlr_foreign_toplevel_handle_v1 = unsafe { std::mem::zeroed() };
+    window
+      .foreign_toplevel_handle
+      .replace(Some(ForeignToplevelHandle(&mut raw_handle)));
+
+    window.set_maximized(true);
+    assert!(window
+      .foreign_toplevel_handle
+      .borrow()
+      .as_ref()
+      .unwrap()
+      .maximized());
+    window.set_fullscreen(true);
+    assert!(window
+      .foreign_toplevel_handle
+      .borrow()
+      .as_ref()
+      .unwrap()
+      .fullscreen());
+    window.set_maximized(false);
+    let handle = window.foreign_toplevel_handle.borrow();
+    let handle = handle.as_ref().unwrap();
+    assert!(!handle.maximized());
+    assert!(handle.fullscreen());
+    assert!(!handle.activated());
+    assert!(!handle.minimized());
+  }
+
+  #[test]
+  fn it_stores_render_hints() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    assert_eq!(window.render_hints(), RenderHints::default());
+
+    window.set_corner_radius(8.0);
+    window.set_shadow(true, [0.0, 0.0, 0.0, 0.5], 12.0);
+    assert_eq!(
+      window.render_hints(),
+      RenderHints {
+        corner_radius: 8.0,
+        shadow: Some(Shadow {
+          color: [0.0, 0.0, 0.0, 0.5],
+          blur_radius: 12.0,
+        }),
+      }
+    );
+
+    window.set_shadow(false, [0.0, 0.0, 0.0, 0.5], 12.0);
+    assert_eq!(window.render_hints().shadow, None);
+  }
+
+  #[test]
+  fn it_tracks_responsiveness_from_pings() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    let timeouts = Rc::new(std::cell::Cell::new(0));
+    let pongs = Rc::new(std::cell::Cell::new(0));
+    window.on_ping_timeout().subscribe(Box::new({
+      let timeouts = timeouts.clone();
+      move |_| timeouts.set(timeouts.get() + 1)
+    }));
+    window.on_pong().subscribe(Box::new({
+      let pongs = pongs.clone();
+      move |_| pongs.set(pongs.get() + 1)
+    }));
+
+    // Pongs without a ping to a responsive window are not reported
+    window.handle_pong();
+    assert!(window.is_responsive());
+    assert_eq!(pongs.get(), 0);
+
+    *window.awaiting_pong.borrow_mut() = true;
+    window.handle_pong();
+    assert!(window.is_responsive());
+    assert_eq!(pongs.get(), 1);
+
+    // wlroots emits ping_timeout for every surface of the client, even
+    // the ones that were not pinged
+    window.handle_ping_timeout();
+    window.handle_ping_timeout();
+    assert!(!window.is_responsive());
+    assert_eq!(timeouts.get(), 1);
+
+    // A late pong recovers the window
+    window.handle_pong();
+    assert!(window.is_responsive());
+    assert_eq!(pongs.get(), 2);
+  }
+
+  #[test]
+  fn it_detects_sizes_exceeding_the_configure() {
+    let size = |width, height| Size { width, height };
+    assert_eq!(
+      check_committed_size(size(800, 600), size(800, 600), true),
+      None
+    );
+    assert_eq!(
+      check_committed_size(size(800, 600), size(400, 300), true),
+      None
+    );
+    assert_eq!(
+      check_committed_size(size(0, 600), size(1200, 600), true),
+      None
+    );
+    // Floating windows may be larger, like when their minimum size is
+    // larger than the configured size
+    assert_eq!(
+      check_committed_size(size(800, 600), size(1024, 768), false),
+      None
+    );
+    assert_eq!(
+      check_committed_size(size(800, 600), size(800, 601), true),
+      Some(ProtocolError::SizeExceedsConfigure {
+        configured: size(800, 600),
+        committed: size(800, 601),
+      })
+    );
+  }
+
+  #[test]
+  fn it_moves_fullscreen_windows_above_panels() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    let panel = window_manager.new_window(WindowLayer::Top, Surface::Null);
+    let rendered = || {
+      window_manager
+        .windows_to_render()
+        .map(|window| window.id())
+        .collect::<Vec<_>>()
+    };
+    *window.mapped.borrow_mut() = true;
+    *panel.mapped.borrow_mut() = true;
+
+    window.set_fullscreen(true);
+    assert_eq!(window.layer(), WindowLayer::Fullscreen);
+    assert_eq!(window.original_layer(), WindowLayer::Normal);
+    assert_eq!(rendered(), vec![panel.id(), window.id()]);
+
+    window.set_fullscreen(false);
+    assert_eq!(window.layer(), WindowLayer::Normal);
+    assert_eq!(rendered(), vec![window.id(), panel.id()]);
+
+    panel.set_fullscreen(true);
+    panel.set_fullscreen(true);
+    panel.set_fullscreen(false);
+    assert_eq!(panel.layer(), WindowLayer::Top);
+  }
+
+  #[test]
+  fn it_replaces_foreign_toplevel_output_subscriptions() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    // Mapped, unmapped and mapped again
+    window.forward_outputs_to_foreign_toplevel(ptr::null_mut());
+    window.foreign_toplevel_subscriptions.borrow_mut().clear();
+    assert_eq!(window.on_entered_output.listener_count(), 0);
+    window.forward_outputs_to_foreign_toplevel(ptr::null_mut());
+    window.forward_outputs_to_foreign_toplevel(ptr::null_mut());
+
+    assert_eq!(window.on_entered_output.listener_count(), 1);
+    assert_eq!(window.on_left_output.listener_count(), 1);
+  }
+
+  #[test]
+  fn it_invalidates_cached_extents_when_moved() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    assert_eq!(window.extents().top_left(), Point::ZERO);
+    assert_eq!(window.extents_cache.borrow().extents, Some(Rectangle::ZERO));
+
+    window.move_to(Point { x: 10, y: 20 });
+    assert_eq!(window.extents().top_left(), Point { x: 10, y: 20 });
+  }
+
+  #[test]
+  fn it_only_invalidates_the_extents_of_the_window_and_its_popups() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    let popup = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    let other = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    window.add_popup(&popup);
+    window.add_popup(&popup);
+    assert_eq!(window.popups.borrow().len(), 1);
+
+    for window in &[&window, &popup, &other] {
+      window.extents();
+    }
+    window.move_to(Point { x: 10, y: 20 });
+
+    assert_eq!(window.extents_cache.borrow().extents, None);
+    assert_eq!(popup.extents_cache.borrow().extents, None);
+    assert_eq!(other.extents_cache.borrow().extents, Some(Rectangle::ZERO));
+  }
+
+  #[test]
+  fn it_calculates_snap_region_extents() {
+    let area = Rectangle {
+      top_left: Point { x: 10, y: 20 },
+      size: Size {
+        width: 101,
+        height: 50,
+      },
+    };
+
+    assert_eq!(SnapRegion::Maximize.extents(&area), area);
+    assert_eq!(
+      SnapRegion::Left.extents(&area),
+      Rectangle {
+        top_left: Point { x: 10, y: 20 },
+        size: Size {
+          width: 50,
+          height: 50
+        },
+      }
+    );
+    assert_eq!(
+      SnapRegion::BottomRight.extents(&area),
+      Rectangle {
+        top_left: Point { x: 60, y: 45 },
+        size: Size {
+          width: 51,
+          height: 25
+        },
+      }
+    );
+  }
+
+  #[test]
+  fn it_snaps_size_to_increments() {
+    let base = Size {
+      width: 4,
+      height: 2,
+    };
+    let increment = Size {
+      width: 10,
+      height: 20,
+    };
+
+    assert_eq!(
+      snap_to_increment(
+        Size {
+          width: 125,
+          height: 61
+        },
+        base,
+        increment
+      ),
+      Size {
+        width: 124,
+        height: 42
+      }
+    );
+    assert_eq!(
+      snap_to_increment(
+        Size {
+          width: 3,
+          height: 2
+        },
+        base,
+        increment
+      ),
+      Size {
+        width: 3,
+        height: 2
+      }
+    );
+  }
+
+  #[test]
+  fn it_calculates_minimize_transform() {
+    let extents = Rectangle {
+      top_left: Point { x: 100, y: 100 },
+      size: Size {
+        width: 400,
+        height: 200,
+      },
+    };
+    let target = Rectangle {
+      top_left: Point { x: 0, y: 1000 },
+      size: Size {
+        width: 40,
+        height: 40,
+      },
+    };
+
+    assert_eq!(
+      minimize_transform(&extents, &target),
+      (
+        FDisplacement {
+          dx: -100.0,
+          dy: 910.0
+        },
+        0.1
+      )
+    );
+  }
+
+  #[test]
+  fn it_centers_windows_inside_the_area() {
+    let area = Rectangle {
+      top_left: Point { x: 1000, y: 0 },
+      size: Size {
+        width: 800,
+        height: 600,
+      },
+    };
+    let size = Size {
+      width: 200,
+      height: 100,
+    };
+
+    assert_eq!(
+      centered_top_left(size, Point { x: 1400, y: 300 }, &area),
+      Point { x: 1300, y: 250 }
+    );
+    assert_eq!(
+      centered_top_left(size, Point { x: 1790, y: 10 }, &area),
+      Point { x: 1600, y: 0 }
+    );
+    assert_eq!(
+      centered_top_left(
+        Size {
+          width: 1000,
+          height: 700
+        },
+        Point { x: 1400, y: 300 },
+        &area
+      ),
+      Point { x: 1000, y: 0 }
+    );
+  }
+
+  #[test]
+  fn it_constrains_size() {
+    let size = Size {
+      width: 100,
+      height: 100,
+    };
+
+    assert_eq!(constrain_size(size, None, None, None, None), size);
+    assert_eq!(
+      constrain_size(size, Some(200), None, None, Some(50)),
+      Size {
+        width: 200,
+        height: 50
+      }
+    );
+    assert_eq!(
+      constrain_size(size, Some(10), Some(80), Some(120), Some(300)),
+      Size {
+        width: 80,
+        height: 120
+      }
+    );
+  }
+}
+
+#[cfg(test)]
+unsafe fn set_handle_state(
+  handle: *mut wlr_foreign_toplevel_handle_v1,
+  state: wlr_foreign_toplevel_handle_v1_state,
+  enabled: bool,
+) {
+  if enabled {
+    (*handle).state |= state;
+  } else {
+    (*handle).state &= !state;
+  }
+}
+#[cfg(test)]
+unsafe fn wlr_foreign_toplevel_handle_v1_set_maximized(
+  handle: *mut wlr_foreign_toplevel_handle_v1,
+  maximized: bool,
+) {
+  set_handle_state(
+    handle,
+    wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_MAXIMIZED,
+    maximized,
+  );
+}
+#[cfg(test)]
+unsafe fn wlr_foreign_toplevel_handle_v1_set_minimized(
+  handle: *mut wlr_foreign_toplevel_handle_v1,
+  minimized: bool,
+) {
+  set_handle_state(
+    handle,
+    wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_MINIMIZED,
+    minimized,
+  );
+}
+#[cfg(test)]
+unsafe fn wlr_foreign_toplevel_handle_v1_set_activated(
+  handle: *mut wlr_foreign_toplevel_handle_v1,
+  activated: bool,
+) {
+  set_handle_state(
+    handle,
+    wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_ACTIVATED,
+    activated,
+  );
+}
+#[cfg(test)]
+unsafe fn wlr_foreign_toplevel_handle_v1_set_fullscreen(
+  handle: *mut wlr_foreign_toplevel_handle_v1,
+  fullscreen: bool,
+) {
+  set_handle_state(
+    handle,
+    wlr_foreign_toplevel_handle_v1_state_WLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_FULLSCREEN,
+    fullscreen,
+  );
+}
@@ -1,8 +1,17 @@
-use crate::geometry::FPoint;
+use crate::geometry::{FPoint, Rectangle};
 use crate::output::Output;
 use crate::window::{ForeignToplevelHandle, Window, WindowEdge};
 use std::cell::RefCell;
 use std::{fmt::Debug, rc::Rc};
+use wlroots_sys::libc;
+
+/// State to configure a new window with before it is first shown
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InitialWindowState {
+  pub extents: Option<Rectangle>,
+  pub maximized: bool,
+  pub fullscreen: bool,
+}
 
 pub enum RequestOriginator<'a> {
   Application,
@@ -59,9 +68,17 @@ pub struct MinimizeRequest<'a> {
 
 pub trait WindowManagementPolicy {
   fn handle_window_ready(&self, _window: Rc<Window>) {}
+  /// Called before advise_new_window, while the window is not yet mapped.
+  /// The returned state is sent as the first configure of the window so that
+  /// it is already in place when first shown.
+  fn initial_window_state(&self, _window: Rc<Window>) -> InitialWindowState {
+    InitialWindowState::default()
+  }
   fn advise_new_window(&self, _window: Rc<Window>) {}
   fn advise_configured_window(&self, _window: Rc<Window>) {}
   fn advise_focused_window(&self, _window: Rc<Window>) {}
+  /// Called when focus is removed from a window without focusing another one
+  fn advise_blurred_window(&self, _window: Rc<Window>) {}
   fn advise_delete_window(&self, _window: Rc<Window>) {}
 
   fn handle_request_activate(&self, _request: ActivateRequest) {}
@@ -75,6 +92,19 @@ pub trait WindowManagementPolicy {
   fn advise_output_create(&self, _output: Rc<Output>) {}
   fn advise_output_update(&self, _output: Rc<Output>) {}
   fn advise_output_delete(&self, _output: Rc<Output>) {}
+  /// Called when `removed_output` is removed and `window` is no longer on
+  /// any output. Return true if the window has been handled, otherwise it
+  /// is moved onto the nearest remaining output.
+  fn handle_window_orphaned(&self, _window: Rc<Window>, _removed_output: Rc<Output>) -> bool {
+    false
+  }
+
+  /// Called when the client with `pid` lists or binds the DMA-BUF export
+  /// global. Return false to hide it, so that the client can not capture
+  /// the content of outputs.
+  fn allow_export_dmabuf(&self, _pid: libc::pid_t) -> bool {
+    true
+  }
 }
 
 pub(crate) struct WmPolicyManager {
@@ -106,6 +136,12 @@ impl WmPolicyManager {
       policy.handle_window_ready(window)
     }
   }
+  pub(crate) fn initial_window_state(&self, window: Rc<Window>) -> InitialWindowState {
+    match *self.policy.borrow() {
+      Some(ref policy) => policy.initial_window_state(window),
+      None => InitialWindowState::default(),
+    }
+  }
   pub(crate) fn advise_new_window(&self, window: Rc<Window>) {
     if let Some(ref policy) = *self.policy.borrow() {
       policy.advise_new_window(window)
@@ -121,6 +157,11 @@ impl WmPolicyManager {
       policy.advise_focused_window(window)
     }
   }
+  pub(crate) fn advise_blurred_window(&self, window: Rc<Window>) {
+    if let Some(ref policy) = *self.policy.borrow() {
+      policy.advise_blurred_window(window)
+    }
+  }
   pub(crate) fn advise_delete_window(&self, window: Rc<Window>) {
     if let Some(ref policy) = *self.policy.borrow() {
       policy.advise_delete_window(window)
@@ -178,4 +219,20 @@ impl WmPolicyManager {
       policy.advise_output_delete(output)
     }
   }
+  pub(crate) fn handle_window_orphaned(
+    &self,
+    window: Rc<Window>,
+    removed_output: Rc<Output>,
+  ) -> bool {
+    match *self.policy.borrow() {
+      Some(ref policy) => policy.handle_window_orphaned(window, removed_output),
+      None => false,
+    }
+  }
+  pub(crate) fn allow_export_dmabuf(&self, pid: libc::pid_t) -> bool {
+    match *self.policy.borrow() {
+      Some(ref policy) => policy.allow_export_dmabuf(pid),
+      None => true,
+    }
+  }
 }
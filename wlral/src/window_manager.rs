@@ -1,37 +1,215 @@
-use crate::geometry::{Displacement, Point};
+use crate::geometry::{FDisplacement, FPoint, Point, Rectangle};
+use crate::shell::xwayland::WindowTypeAtoms;
 use crate::surface::{Surface, SurfaceExt};
 use crate::{
   event::{Event, EventOnce},
   input::seat::SeatManager,
+  output::Output,
   output_manager::OutputManager,
-  window::Window,
+  window::{ExtentsCache, RenderHints, Window, WindowEdge, WindowId},
   window_management_policy::WmPolicyManager,
 };
-use log::{trace, warn};
+use log::trace;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ptr;
 use std::rc::{Rc, Weak};
 use wlroots_sys::*;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum WindowLayer {
   Background,
   Bottom,
   Normal,
   Top,
+  /// Fullscreen windows, which cover panels but not menus or overlays
+  Fullscreen,
+  /// X11 override redirect windows like menus and tooltips, which are
+  /// positioned by the client and never managed by the policy
+  Unmanaged,
   Overlay,
 }
 
+/// Hit-testing scans all windows when there are fewer than this
+const SPATIAL_INDEX_THRESHOLD: usize = 32;
+/// The width and height of the cells of the spatial index
+const SPATIAL_INDEX_CELL_SIZE: i32 = 256;
+/// Rectangles that would cover more cells than this are instead tested for
+/// every point
+const SPATIAL_INDEX_MAX_CELLS: i64 = 1024;
+
+/// A grid over the layout that maps each cell to the entries that may
+/// cover it
+#[derive(Debug, Default)]
+struct SpatialIndex {
+  cells: HashMap<(i32, i32), Vec<usize>>,
+  /// Entries that are too large to be put in cells
+  everywhere: Vec<usize>,
+}
+
+impl SpatialIndex {
+  fn cell(coordinate: i32) -> i32 {
+    coordinate.div_euclid(SPATIAL_INDEX_CELL_SIZE)
+  }
+
+  /// The cells covered by the rectangle, or None if it covers too many
+  fn covered_cells(rectangle: &Rectangle) -> Option<Vec<(i32, i32)>> {
+    if rectangle.width() <= 0 || rectangle.height() <= 0 {
+      return Some(vec![]);
+    }
+    let left = SpatialIndex::cell(rectangle.left());
+    let right = SpatialIndex::cell(rectangle.right() - 1);
+    let top = SpatialIndex::cell(rectangle.top());
+    let bottom = SpatialIndex::cell(rectangle.bottom() - 1);
+    if (right - left + 1) as i64 * (bottom - top + 1) as i64 > SPATIAL_INDEX_MAX_CELLS {
+      return None;
+    }
+    Some(
+      (top..=bottom)
+        .flat_map(|y| (left..=right).map(move |x| (x, y)))
+        .collect(),
+    )
+  }
+
+  fn insert(&mut self, entry: usize, rectangle: &Rectangle) {
+    match SpatialIndex::covered_cells(rectangle) {
+      Some(cells) => {
+        for cell in cells {
+          self.cells.entry(cell).or_default().push(entry);
+        }
+      }
+      None => self.everywhere.push(entry),
+    }
+  }
+
+  /// Removes an entry that was inserted with the rectangle
+  fn remove(&mut self, entry: usize, rectangle: &Rectangle) {
+    match SpatialIndex::covered_cells(rectangle) {
+      Some(cells) => {
+        for cell in cells {
+          if let Some(entries) = self.cells.get_mut(&cell) {
+            entries.retain(|&other| other != entry);
+            if entries.is_empty() {
+              self.cells.remove(&cell);
+            }
+          }
+        }
+      }
+      None => self.everywhere.retain(|&other| other != entry),
+    }
+  }
+
+  /// The entries that may contain the point, in ascending order
+  fn candidates(&self, point: &Point) -> Vec<usize> {
+    let cell = (SpatialIndex::cell(point.x), SpatialIndex::cell(point.y));
+    let mut candidates = self.cells.get(&cell).cloned().unwrap_or_default();
+    candidates.extend(self.everywhere.iter());
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+  }
+}
+
+/// A spatial index of the windows in `windows`, valid while the window order
+/// is unchanged
+struct WindowIndex {
+  windows: Rc<[Rc<Window>]>,
+  /// The entry of each window in `windows`
+  entries: HashMap<WindowId, usize>,
+  /// The extents and buffer extents each entry was inserted with
+  rectangles: Vec<(Rectangle, Rectangle)>,
+  index: SpatialIndex,
+}
+
+impl WindowIndex {
+  fn new(windows: Rc<[Rc<Window>]>) -> WindowIndex {
+    let mut window_index = WindowIndex {
+      entries: windows
+        .iter()
+        .enumerate()
+        .map(|(entry, window)| (window.id(), entry))
+        .collect(),
+      rectangles: vec![],
+      index: SpatialIndex::default(),
+      windows,
+    };
+    for entry in 0..window_index.windows.len() {
+      let rectangles = window_index.window_rectangles(entry);
+      window_index.index.insert(entry, &rectangles.0);
+      window_index.index.insert(entry, &rectangles.1);
+      window_index.rectangles.push(rectangles);
+    }
+    window_index
+  }
+
+  fn window_rectangles(&self, entry: usize) -> (Rectangle, Rectangle) {
+    let window = &self.windows[entry];
+    (window.extents(), window.buffer_extents())
+  }
+
+  /// Moves the window to the cells of its current extents
+  fn update(&mut self, window_id: WindowId) {
+    let entry = match self.entries.get(&window_id) {
+      Some(&entry) => entry,
+      None => return,
+    };
+    let rectangles = self.window_rectangles(entry);
+    if rectangles == self.rectangles[entry] {
+      return;
+    }
+    let (old_extents, old_buffer_extents) = &self.rectangles[entry];
+    self.index.remove(entry, old_extents);
+    self.index.remove(entry, old_buffer_extents);
+    self.index.insert(entry, &rectangles.0);
+    self.index.insert(entry, &rectangles.1);
+    self.rectangles[entry] = rectangles;
+  }
+}
+
+/// Why a window could not be focused
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum FocusError {
+  /// The window does not accept keyboard focus, like a panel or a popup
+  CannotReceiveFocus,
+  /// Input is inhibited for the client of the window, for example by a
+  /// screen locker
+  InputInhibited,
+}
+
+impl std::fmt::Display for FocusError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      FocusError::CannotReceiveFocus => write!(f, "Window can not receive focus"),
+      FocusError::InputInhibited => write!(f, "Input is inhibited"),
+    }
+  }
+}
+
+impl std::error::Error for FocusError {}
+
 #[derive(Default)]
 struct WindowLayers {
   background: Vec<Rc<Window>>,
   bottom: Vec<Rc<Window>>,
   normal: Vec<Rc<Window>>,
   top: Vec<Rc<Window>>,
+  fullscreen: Vec<Rc<Window>>,
+  unmanaged: Vec<Rc<Window>>,
   overlay: Vec<Rc<Window>>,
+  /// All windows from back to front, built on first use after the layers
+  /// have changed
+  ordered: RefCell<Option<Rc<[Rc<Window>]>>>,
 }
 
 impl WindowLayers {
+  fn ordered(&self) -> Rc<[Rc<Window>]> {
+    self
+      .ordered
+      .borrow_mut()
+      .get_or_insert_with(|| self.all_windows().collect::<Vec<_>>().into())
+      .clone()
+  }
+
   fn all_windows(&self) -> impl '_ + DoubleEndedIterator<Item = Rc<Window>> {
     self
       .background
@@ -39,29 +217,110 @@ impl WindowLayers {
       .chain(self.bottom.iter())
       .chain(self.normal.iter())
       .chain(self.top.iter())
+      .chain(self.fullscreen.iter())
+      .chain(self.unmanaged.iter())
       .chain(self.overlay.iter())
       .cloned()
   }
 
+  fn get(&self, layer: WindowLayer) -> &Vec<Rc<Window>> {
+    match layer {
+      WindowLayer::Background => &self.background,
+      WindowLayer::Bottom => &self.bottom,
+      WindowLayer::Normal => &self.normal,
+      WindowLayer::Top => &self.top,
+      WindowLayer::Fullscreen => &self.fullscreen,
+      WindowLayer::Unmanaged => &self.unmanaged,
+      WindowLayer::Overlay => &self.overlay,
+    }
+  }
+
   fn update<F>(&mut self, layer: WindowLayer, mut f: F)
   where
     F: FnMut(&mut Vec<Rc<Window>>),
   {
+    *self.ordered.get_mut() = None;
     match layer {
       WindowLayer::Background => f(&mut self.background),
       WindowLayer::Bottom => f(&mut self.bottom),
       WindowLayer::Normal => f(&mut self.normal),
       WindowLayer::Top => f(&mut self.top),
+      WindowLayer::Fullscreen => f(&mut self.fullscreen),
+      WindowLayer::Unmanaged => f(&mut self.unmanaged),
       WindowLayer::Overlay => f(&mut self.overlay),
     }
   }
 }
 
+/// Iterates over a shared list of windows so that layers can be changed
+/// while iterating
+struct Windows {
+  windows: Rc<[Rc<Window>]>,
+  front: usize,
+  back: usize,
+}
+
+impl Windows {
+  fn new(windows: Rc<[Rc<Window>]>) -> Windows {
+    let back = windows.len();
+    Windows {
+      windows,
+      front: 0,
+      back,
+    }
+  }
+}
+
+impl Iterator for Windows {
+  type Item = Rc<Window>;
+
+  fn next(&mut self) -> Option<Rc<Window>> {
+    if self.front == self.back {
+      return None;
+    }
+    self.front += 1;
+    Some(self.windows[self.front - 1].clone())
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.back - self.front;
+    (len, Some(len))
+  }
+}
+
+impl DoubleEndedIterator for Windows {
+  fn next_back(&mut self) -> Option<Rc<Window>> {
+    if self.front == self.back {
+      return None;
+    }
+    self.back -= 1;
+    Some(self.windows[self.back].clone())
+  }
+}
+
+impl ExactSizeIterator for Windows {}
+
 pub struct WindowManager {
   wm_policy_manager: Rc<WmPolicyManager>,
   seat_manager: Rc<SeatManager>,
   output_manager: RefCell<Weak<OutputManager>>,
   layers: RefCell<WindowLayers>,
+  next_window_id: RefCell<u64>,
+  /// The window focused on the primary seat, so that it does not have to be
+  /// looked up from the seat
+  focused_window: RefCell<Weak<Window>>,
+  /// Focused windows, most recently focused first
+  focus_history: RefCell<Vec<Weak<Window>>>,
+  ping_timeout_ms: RefCell<u32>,
+  /// The shell that pings are sent through, which holds the ping timeout
+  xdg_shell: RefCell<*mut wlr_xdg_shell>,
+  /// The window type atoms of Xwayland, for X11 surfaces without a window
+  window_type_atoms: RefCell<Rc<WindowTypeAtoms>>,
+  hit_test_render_transform: RefCell<bool>,
+  spatial_index_enabled: RefCell<bool>,
+  spatial_index: RefCell<Option<WindowIndex>>,
+  /// Windows whose extents changed since the spatial index was updated
+  dirty_extents: RefCell<HashSet<WindowId>>,
   foreign_toplevel_manager: *mut wlr_foreign_toplevel_manager_v1,
 }
 
@@ -87,147 +346,516 @@ impl WindowManager {
       seat_manager,
       output_manager: RefCell::new(Weak::<OutputManager>::new()),
       layers: RefCell::new(WindowLayers::default()),
+      next_window_id: RefCell::new(1),
+      focused_window: RefCell::new(Weak::new()),
+      focus_history: RefCell::new(vec![]),
+      ping_timeout_ms: RefCell::new(5000),
+      xdg_shell: RefCell::new(ptr::null_mut()),
+      window_type_atoms: RefCell::new(Rc::default()),
+      hit_test_render_transform: RefCell::new(false),
+      spatial_index_enabled: RefCell::new(true),
+      spatial_index: RefCell::new(None),
+      dirty_extents: RefCell::new(HashSet::new()),
       foreign_toplevel_manager,
     }
   }
 
+  pub(crate) fn seat_manager(&self) -> Rc<SeatManager> {
+    self.seat_manager.clone()
+  }
+
   pub fn raw_foreign_toplevel_manager(&self) -> *mut wlr_foreign_toplevel_manager_v1 {
     self.foreign_toplevel_manager
   }
 
+  /// How long a client has to respond to a ping before it is considered
+  /// unresponsive
+  pub fn ping_timeout_ms(&self) -> u32 {
+    *self.ping_timeout_ms.borrow()
+  }
+  pub fn set_ping_timeout_ms(&self, timeout: u32) {
+    *self.ping_timeout_ms.borrow_mut() = timeout;
+    self.apply_ping_timeout();
+  }
+
+  pub(crate) fn set_xdg_shell(&self, xdg_shell: *mut wlr_xdg_shell) {
+    *self.xdg_shell.borrow_mut() = xdg_shell;
+    self.apply_ping_timeout();
+  }
+
+  pub(crate) fn set_window_type_atoms(&self, window_type_atoms: Rc<WindowTypeAtoms>) {
+    *self.window_type_atoms.borrow_mut() = window_type_atoms;
+  }
+
+  fn apply_ping_timeout(&self) {
+    let xdg_shell = *self.xdg_shell.borrow();
+    if !xdg_shell.is_null() {
+      unsafe {
+        (*xdg_shell).ping_timeout = self.ping_timeout_ms();
+      }
+    }
+  }
+
+  /// If input hit-testing maps points through the render transform of
+  /// windows, so that input goes to where windows are rendered instead
+  /// of where they are positioned
+  pub fn hit_test_render_transform(&self) -> bool {
+    *self.hit_test_render_transform.borrow()
+  }
+  pub fn set_hit_test_render_transform(&self, enabled: bool) {
+    *self.hit_test_render_transform.borrow_mut() = enabled
+  }
+
+  /// If hit-testing uses a spatial index when there are many windows,
+  /// instead of testing every window
+  ///
+  /// The index is not used while hit-testing through the render transform.
+  pub fn spatial_index_enabled(&self) -> bool {
+    *self.spatial_index_enabled.borrow()
+  }
+  pub fn set_spatial_index_enabled(&self, enabled: bool) {
+    *self.spatial_index_enabled.borrow_mut() = enabled;
+    if !enabled {
+      *self.spatial_index.borrow_mut() = None;
+      self.dirty_extents.borrow_mut().clear();
+    }
+  }
+
+  /// Maps a point in layout coordinates to the untransformed window if
+  /// hit-testing through the render transform is enabled
+  pub(crate) fn hit_test_point(&self, window: &Window, point: FPoint) -> FPoint {
+    if self.hit_test_render_transform() {
+      window.untransform_point(point)
+    } else {
+      point
+    }
+  }
+
   pub fn windows_to_render(&self) -> impl '_ + Iterator<Item = Rc<Window>> {
-    self.windows().filter(|window| *window.mapped.borrow())
+    self
+      .windows()
+      .filter(|window| window.is_mapped() && !window.is_minimized())
   }
 
   pub fn window_at(&self, point: &Point) -> Option<Rc<Window>> {
-    self
-      .layers
-      .borrow()
-      .all_windows()
-      // Reverse as windows is from back to front
-      .rev()
-      .find(|window| window.extents().contains(point))
+    self.hit_test(point, |window| window.extents())
   }
 
   pub(crate) fn window_buffer_at(&self, point: &Point) -> Option<Rc<Window>> {
-    self
-      .layers
-      .borrow()
-      .all_windows()
+    self.hit_test(point, |window| window.buffer_extents())
+  }
+
+  /// The front-most window that is not minimized where the area returned
+  /// by `area` contains the point
+  fn hit_test<F>(&self, point: &Point, area: F) -> Option<Rc<Window>>
+  where
+    F: Fn(&Window) -> Rectangle,
+  {
+    let windows = self.layers.borrow().ordered();
+    let hits = |window: &&Rc<Window>| {
+      let point = self.hit_test_point(window, (*point).into()).into();
+      !window.is_minimized() && area(window).contains(&point)
+    };
+
+    if !self.spatial_index_enabled()
+      || self.hit_test_render_transform()
+      || windows.len() < SPATIAL_INDEX_THRESHOLD
+    {
       // Reverse as windows is from back to front
+      return windows.iter().rev().find(hits).cloned();
+    }
+
+    self.update_spatial_index(&windows);
+    let candidates = self
+      .spatial_index
+      .borrow()
+      .as_ref()
+      .map(|window_index| window_index.index.candidates(point))
+      .unwrap_or_default();
+    candidates
+      .into_iter()
       .rev()
-      .find(|window| window.buffer_extents().contains(point))
+      .map(|entry| &windows[entry])
+      .find(hits)
+      .cloned()
+  }
+
+  /// Marks the extents of the window as changed, so that the spatial index
+  /// moves it before the next hit-test
+  pub(crate) fn invalidate_extents(&self, window_id: WindowId) {
+    if self.spatial_index.borrow().is_some() {
+      self.dirty_extents.borrow_mut().insert(window_id);
+    }
+  }
+
+  /// Rebuilds the spatial index if windows have been added, removed or
+  /// restacked since it was built, or else moves the windows whose extents
+  /// changed
+  fn update_spatial_index(&self, windows: &Rc<[Rc<Window>]>) {
+    let dirty_extents = std::mem::take(&mut *self.dirty_extents.borrow_mut());
+    let mut spatial_index = self.spatial_index.borrow_mut();
+    if let Some(window_index) = spatial_index.as_mut() {
+      if Rc::ptr_eq(&window_index.windows, windows) {
+        for window_id in dirty_extents {
+          window_index.update(window_id);
+        }
+        return;
+      }
+    }
+    *spatial_index = Some(WindowIndex::new(windows.clone()));
   }
 
   pub(crate) fn destroy_window(&self, destroyed_window: Rc<Window>) {
     self
       .layers
       .borrow_mut()
-      .update(destroyed_window.layer, |windows| {
+      .update(destroyed_window.layer(), |windows| {
         windows.retain(|window| *window != destroyed_window)
       });
+    self.focus_history.borrow_mut().retain(|window| {
+      window
+        .upgrade()
+        .map_or(false, |window| window != destroyed_window)
+    });
+    let was_focused = self
+      .focused_window
+      .borrow()
+      .upgrade()
+      .map_or(false, |window| window == destroyed_window);
+    if was_focused {
+      *self.focused_window.borrow_mut() = Weak::new();
+    }
   }
 
+  /// All windows from back to front
+  ///
+  /// The list is shared between calls until a window is added, removed or
+  /// restacked, so this does not allocate on every frame.
   pub fn windows(&self) -> impl '_ + DoubleEndedIterator<Item = Rc<Window>> {
-    let windows = self.layers.borrow().all_windows().collect::<Vec<_>>();
+    Windows::new(self.layers.borrow().ordered())
+  }
+
+  /// The windows in the layer, from back to front
+  pub fn windows_in_layer(
+    &self,
+    layer: WindowLayer,
+  ) -> impl '_ + DoubleEndedIterator<Item = Rc<Window>> {
+    let windows = self.layers.borrow().get(layer).clone();
     windows.into_iter()
   }
 
-  /// Returns the window that holds keyboard focus
-  pub fn focused_window(&self) -> Option<Rc<Window>> {
-    let focused_surface = unsafe {
-      (*self.seat_manager.raw_seat())
-        .keyboard_state
-        .focused_surface
-    };
+  /// Moves the window to the front of another layer
+  pub fn set_window_layer(&self, window: &Window, layer: WindowLayer) {
+    let previous_layer = window.layer();
+    if previous_layer == layer {
+      return;
+    }
+    let mut layers = self.layers.borrow_mut();
+    let mut moved_window = None;
+    layers.update(previous_layer, |windows| {
+      if let Some(index) = windows.iter().position(|w| std::ptr::eq(&**w, window)) {
+        moved_window = Some(windows.remove(index));
+      }
+    });
+    if let Some(moved_window) = moved_window {
+      layers.update(layer, |windows| {
+        windows.push(moved_window.clone());
+      });
+    }
+    *window.layer.borrow_mut() = layer;
+  }
+
+  /// The windows that are at least partly visible on the output, from back
+  /// to front
+  pub fn windows_on_output<'a>(
+    &'a self,
+    output: &'a Output,
+  ) -> impl 'a + DoubleEndedIterator<Item = Rc<Window>> {
+    self
+      .windows()
+      .filter(move |window| window.outputs().iter().any(|o| **o == *output))
+  }
+
+  pub fn window_by_id(&self, id: WindowId) -> Option<Rc<Window>> {
     self
       .layers
       .borrow()
       .all_windows()
-      .find(|w| w.wlr_surface() == focused_surface)
+      .find(|window| window.id() == id)
+  }
+
+  /// The window that has the passed surface as its main surface
+  pub fn window_by_surface(&self, wlr_surface: *mut wlr_surface) -> Option<Rc<Window>> {
+    self
+      .layers
+      .borrow()
+      .all_windows()
+      .find(|window| window.wlr_surface() == wlr_surface)
+  }
+
+  /// All seats, the primary seat first
+  fn seats(&self) -> Vec<Rc<SeatManager>> {
+    let mut seats = vec![self.seat_manager.clone()];
+    seats.extend(
+      self
+        .seat_manager
+        .secondary_seats
+        .borrow()
+        .iter()
+        .filter_map(|seat_manager| seat_manager.upgrade()),
+    );
+    seats
+  }
+
+  fn focused_surface(seat_manager: &SeatManager) -> *mut wlr_surface {
+    unsafe { (*seat_manager.raw_seat()).keyboard_state.focused_surface }
+  }
+
+  /// If another seat than the passed one has keyboard focus on the surface
+  fn is_focused_by_other_seat(
+    &self,
+    seat_manager: &SeatManager,
+    wlr_surface: *mut wlr_surface,
+  ) -> bool {
+    self.seats().iter().any(|other| {
+      other.raw_seat() != seat_manager.raw_seat()
+        && WindowManager::focused_surface(other) == wlr_surface
+    })
   }
 
-  /// If the window have keyboard focus
+  fn is_primary_seat(&self, seat_manager: &SeatManager) -> bool {
+    seat_manager.raw_seat() == self.seat_manager.raw_seat()
+  }
+
+  /// Returns the window that holds keyboard focus on the seat
+  pub fn focused_window(&self, seat_manager: &SeatManager) -> Option<Rc<Window>> {
+    if !self.is_primary_seat(seat_manager) {
+      return self.window_by_surface(WindowManager::focused_surface(seat_manager));
+    }
+    let focused_surface = WindowManager::focused_surface(seat_manager);
+    if focused_surface.is_null() {
+      return None;
+    }
+    // The seat changes focus by itself, like when input is inhibited, so the
+    // cached window is only trusted while it still holds focus
+    let cached = self
+      .focused_window
+      .borrow()
+      .upgrade()
+      .filter(|window| window.wlr_surface() == focused_surface);
+    if cached.is_some() {
+      return cached;
+    }
+    let window = self.window_by_surface(focused_surface)?;
+    *self.focused_window.borrow_mut() = Rc::downgrade(&window);
+    Some(window)
+  }
+
+  /// If any window have keyboard focus on any seat
+  pub fn is_any_window_focused(&self) -> bool {
+    self
+      .seats()
+      .iter()
+      .any(|seat_manager| self.focused_window(seat_manager).is_some())
+  }
+
+  /// If the window have keyboard focus on any seat
   pub fn window_has_focus(&self, window: &Window) -> bool {
-    let wlr_surface = window.wlr_surface();
-    let focused_surface = unsafe {
-      (*self.seat_manager.raw_seat())
-        .keyboard_state
-        .focused_surface
-    };
-    wlr_surface == focused_surface
+    self.seats().iter().any(|seat_manager| {
+      self
+        .focused_window(seat_manager)
+        .map_or(false, |focused| *focused == *window)
+    })
+  }
+
+  /// Gives keyboard focus to the window on the primary seat
+  pub fn focus_window(&self, window: Rc<Window>) -> Result<(), FocusError> {
+    self.focus_window_on_seat(window, &self.seat_manager)
   }
 
-  /// Gives keyboard focus to the window
-  pub fn focus_window(&self, window: Rc<Window>) {
+  /// Gives keyboard focus to the window on the passed seat
+  ///
+  /// Focusing a window that already has focus on the seat succeeds without
+  /// doing anything.
+  pub fn focus_window_on_seat(
+    &self,
+    window: Rc<Window>,
+    seat_manager: &SeatManager,
+  ) -> Result<(), FocusError> {
     if !window.can_receive_focus() {
-      warn!("Window can not receive focus");
-      return;
+      return Err(FocusError::CannotReceiveFocus);
     }
-    if !self.seat_manager.is_input_allowed(&window) {
-      warn!("Refusing to set focus, input is inhibited");
-      return;
+    if !seat_manager.is_input_allowed(&window) {
+      return Err(FocusError::InputInhibited);
     }
     let wlr_surface = window.wlr_surface();
     unsafe {
-      let old_wlr_surface = (*self.seat_manager.raw_seat())
-        .keyboard_state
-        .focused_surface;
+      let old_wlr_surface = WindowManager::focused_surface(seat_manager);
 
       if wlr_surface == old_wlr_surface {
-        return;
+        return Ok(());
       }
 
-      trace!("Focusing window \"{:?}\"", window.title());
+      trace!(
+        "Focusing window \"{:?}\" on {}",
+        window.title(),
+        seat_manager.name()
+      );
 
-      if !old_wlr_surface.is_null() {
+      if !old_wlr_surface.is_null() && !self.is_focused_by_other_seat(seat_manager, old_wlr_surface)
+      {
         // Deactivate the previously focused window. This lets the client know
         // it no longer has focus and the client will repaint accordingly, e.g.
         // stop displaying a caret.
-        let surface = Surface::from_wlr_surface(old_wlr_surface);
-        surface.set_activated(false);
+        self.deactivate_surface(old_wlr_surface);
       }
 
       // Move the view to the front
-      self.layers.borrow_mut().update(window.layer, |windows| {
+      self.layers.borrow_mut().update(window.layer(), |windows| {
         windows.retain(|s| *s != window);
         windows.push(window.clone());
       });
 
       // Activate the new window
-      window.surface().set_activated(true);
+      window.set_activated(true);
+
+      let mut focus_history = self.focus_history.borrow_mut();
+      focus_history.retain(|w| w.upgrade().map_or(false, |w| w != window));
+      focus_history.insert(0, Rc::downgrade(&window));
+      drop(focus_history);
+      if self.is_primary_seat(seat_manager) {
+        *self.focused_window.borrow_mut() = Rc::downgrade(&window);
+      }
 
       // Tell the seat to have the keyboard enter this window. wlroots will keep
       // track of this and automatically send key events to the appropriate
       // clients without additional work on your part.
-      let keyboard = wlr_seat_get_keyboard(self.seat_manager.raw_seat());
-      wlr_seat_keyboard_notify_enter(
-        self.seat_manager.raw_seat(),
-        wlr_surface,
-        (*keyboard).keycodes.as_mut_ptr(),
-        (*keyboard).num_keycodes,
-        &mut (*keyboard).modifiers,
-      );
+      // A seat without a keyboard, like a pointer only seat or a headless
+      // compositor, still tracks focus but has no pressed keys to send.
+      let keyboard = wlr_seat_get_keyboard(seat_manager.raw_seat());
+      if keyboard.is_null() {
+        wlr_seat_keyboard_notify_enter(
+          seat_manager.raw_seat(),
+          wlr_surface,
+          ptr::null_mut(),
+          0,
+          ptr::null_mut(),
+        );
+      } else {
+        wlr_seat_keyboard_notify_enter(
+          seat_manager.raw_seat(),
+          wlr_surface,
+          (*keyboard).keycodes.as_mut_ptr(),
+          (*keyboard).num_keycodes,
+          &mut (*keyboard).modifiers,
+        );
+      }
     }
     self.wm_policy_manager.advise_focused_window(window);
+    Ok(())
+  }
+
+  /// The windows that have had keyboard focus, most recently focused first
+  pub fn focus_history(&self) -> Vec<Rc<Window>> {
+    let mut focus_history = self.focus_history.borrow_mut();
+    focus_history.retain(|window| window.upgrade().is_some());
+    focus_history
+      .iter()
+      .filter_map(|window| window.upgrade())
+      .collect()
+  }
+
+  fn focusable_history(&self) -> Vec<Rc<Window>> {
+    self
+      .focus_history()
+      .into_iter()
+      .filter(|window| *window.mapped.borrow() && window.can_receive_focus())
+      .collect()
   }
 
-  /// Blurs the currently focused window without focusing another one
+  /// Focuses the next window in the focus history
+  ///
+  /// The currently focused window is moved to the back of the history so that
+  /// repeated calls cycle through all focusable windows.
+  pub fn focus_next(&self) {
+    let windows = self.focusable_history();
+    let current = match windows.first() {
+      Some(window) => window.clone(),
+      None => return,
+    };
+    if !self.window_has_focus(&current) {
+      let _ = self.focus_window(current);
+      return;
+    }
+    if let Some(next) = windows.get(1) {
+      if self.focus_window(next.clone()).is_ok() {
+        let mut focus_history = self.focus_history.borrow_mut();
+        focus_history.retain(|w| w.upgrade().map_or(false, |w| w != current));
+        focus_history.push(Rc::downgrade(&current));
+      }
+    }
+  }
+
+  /// Focuses the least recently focused window in the focus history
+  ///
+  /// This is the inverse of `focus_next`.
+  pub fn focus_previous(&self) {
+    let windows = self.focusable_history();
+    if let Some(previous) = windows.last() {
+      let _ = self.focus_window(previous.clone());
+    }
+  }
+
+  /// Focuses the front-most window that can receive focus, starting with
+  /// the highest layer
+  pub fn focus_top_window(&self) {
+    let window = self.layers.borrow().all_windows().rev().find(|window| {
+      *window.mapped.borrow()
+        && !window.is_minimized()
+        && window.can_receive_focus()
+        && self.seat_manager.is_input_allowed(window)
+    });
+    if let Some(window) = window {
+      let _ = self.focus_window(window);
+    }
+  }
+
+  /// Blurs the focused windows of all seats without focusing other ones
   pub fn blur(&self) {
-    unsafe {
-      let old_wlr_surface = (*self.seat_manager.raw_seat())
-        .keyboard_state
-        .focused_surface;
-      if !old_wlr_surface.is_null() {
-        // Deactivate the previously focused window. This lets the client know
-        // it no longer has focus and the client will repaint accordingly, e.g.
-        // stop displaying a caret.
-        let surface = Surface::from_wlr_surface(old_wlr_surface);
-        surface.set_activated(false);
+    for seat_manager in self.seats() {
+      let blurred_window = unsafe {
+        let old_wlr_surface = WindowManager::focused_surface(&seat_manager);
+        let blurred_window = if !old_wlr_surface.is_null() {
+          // Deactivate the previously focused window. This lets the client know
+          // it no longer has focus and the client will repaint accordingly, e.g.
+          // stop displaying a caret.
+          self.deactivate_surface(old_wlr_surface)
+        } else {
+          None
+        };
+
+        wlr_seat_keyboard_clear_focus(seat_manager.raw_seat());
+        blurred_window
+      };
+      if self.is_primary_seat(&seat_manager) {
+        *self.focused_window.borrow_mut() = Weak::new();
+      }
+      if let Some(window) = blurred_window {
+        self.wm_policy_manager.advise_blurred_window(window);
       }
+    }
+  }
 
-      wlr_seat_keyboard_clear_focus(self.seat_manager.raw_seat());
+  /// Deactivates the surface, going through its window when there is one so
+  /// that the foreign toplevel handle is kept in sync
+  unsafe fn deactivate_surface(&self, wlr_surface: *mut wlr_surface) -> Option<Rc<Window>> {
+    let window = self.window_by_surface(wlr_surface);
+    match &window {
+      Some(window) => window.set_activated(false),
+      None => Surface::from_wlr_surface(wlr_surface, self.window_type_atoms.borrow().clone())
+        .set_activated(false),
     }
+    window
   }
 }
 
@@ -250,20 +878,46 @@ impl WindowManagerExt for Rc<WindowManager> {
   }
 
   fn new_window(&self, layer: WindowLayer, surface: Surface) -> Rc<Window> {
+    let id = {
+      let mut next_window_id = self.next_window_id.borrow_mut();
+      let id = WindowId(*next_window_id);
+      *next_window_id += 1;
+      id
+    };
     let window = Rc::new(Window {
+      id,
       output_manager: self.output_manager.borrow().upgrade().expect("window_manager should be initialized with and output_manager before windows can be created"),
       window_manager: self.clone(),
-      layer,
+      layer: RefCell::new(layer),
+      layer_before_fullscreen: RefCell::new(None),
       surface,
       mapped: RefCell::new(false),
       top_left: RefCell::new(Point::ZERO),
-      translate: RefCell::new(Displacement::ZERO),
+      extents_cache: RefCell::new(ExtentsCache::default()),
+      popup_root: RefCell::new(Weak::new()),
+      popups: RefCell::new(vec![]),
+      translate: RefCell::new(FDisplacement::ZERO),
+      render_scale: RefCell::new(1.0),
+      render_hints: RefCell::new(RenderHints::default()),
+      minimized: RefCell::new(false),
+      minimize_animation: RefCell::new(None),
+      tile_edges: RefCell::new(WindowEdge::NONE),
+      responsive: RefCell::new(true),
+      awaiting_pong: RefCell::new(false),
+      foreign_toplevel_handle: RefCell::new(None),
+      foreign_toplevel_subscriptions: RefCell::new(vec![]),
       outputs: RefCell::new(vec![]),
       minimize_targets: RefCell::new(vec![]),
       pending_updates: RefCell::new(BTreeMap::new()),
       on_entered_output: Event::default(),
       on_left_output: Event::default(),
+      on_map: Event::default(),
+      on_unmap: Event::default(),
       on_destroy: EventOnce::default(),
+      on_ping_timeout: Event::default(),
+      on_parent_event: Event::default(),
+      on_pong: Event::default(),
+      on_protocol_error: Event::default(),
       event_manager: RefCell::new(None),
     });
     // If the window can receive focus, add it to the back so that
@@ -286,46 +940,91 @@ impl WindowManagerExt for Rc<WindowManager> {
 mod tests {
   use super::*;
   use crate::input::{cursor::CursorManager, event_filter::EventFilterManager};
-  use crate::output_manager::OutputManager;
+  use crate::test_util::MockManagers;
   use crate::window::WindowEventHandler;
-  use crate::{config::ConfigManager, window_management_policy::WmPolicyManager};
   use std::ptr;
   use std::rc::Rc;
 
+  fn rect(x: i32, y: i32, width: i32, height: i32) -> Rectangle {
+    Rectangle {
+      top_left: Point { x, y },
+      size: crate::geometry::Size { width, height },
+    }
+  }
+
   #[test]
-  fn it_drops_and_cleans_up_on_destroy() {
-    let config_manager = Rc::new(ConfigManager::default());
-    let wm_policy_manager = Rc::new(WmPolicyManager::new());
-    let seat_manager = SeatManager::mock(ptr::null_mut(), ptr::null_mut());
-    let window_manager = Rc::new(WindowManager::init(
-      wm_policy_manager.clone(),
-      seat_manager.clone(),
-      ptr::null_mut(),
-    ));
-    let output_manager = OutputManager::mock(
-      config_manager,
-      wm_policy_manager.clone(),
-      window_manager.clone(),
+  fn it_finds_spatial_index_candidates() {
+    let mut index = SpatialIndex::default();
+    index.insert(0, &rect(0, 0, 100, 100));
+    index.insert(1, &rect(-300, -300, 600, 600));
+    index.insert(2, &rect(1000, 1000, 10, 10));
+    index.insert(3, &rect(0, 0, 100_000, 100_000));
+    index.insert(4, &rect(50, 50, 0, 10));
+
+    assert_eq!(index.candidates(&Point { x: 50, y: 50 }), vec![0, 1, 3]);
+    assert_eq!(index.candidates(&Point { x: -10, y: -10 }), vec![1, 3]);
+    assert_eq!(index.candidates(&Point { x: 1005, y: 1005 }), vec![2, 3]);
+    assert_eq!(index.candidates(&Point { x: 5000, y: -5000 }), vec![3]);
+
+    index.remove(0, &rect(0, 0, 100, 100));
+    index.remove(3, &rect(0, 0, 100_000, 100_000));
+    assert_eq!(index.candidates(&Point { x: 50, y: 50 }), vec![1]);
+    assert_eq!(index.candidates(&Point { x: 1005, y: 1005 }), vec![2]);
+    assert_eq!(index.candidates(&Point { x: 5000, y: -5000 }), vec![]);
+  }
+
+  #[test]
+  fn it_only_moves_windows_with_changed_extents_in_the_spatial_index() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let windows: Vec<_> = (0..SPATIAL_INDEX_THRESHOLD)
+      .map(|_| window_manager.new_window(WindowLayer::Normal, Surface::Null))
+      .collect();
+
+    // Geometry changes before the index is built are not tracked
+    windows[0].move_to(Point { x: 1, y: 1 });
+    assert!(window_manager.dirty_extents.borrow().is_empty());
+
+    window_manager.window_at(&Point::ZERO);
+    let ordered = window_manager.layers.borrow().ordered();
+    windows[1].move_to(Point { x: 300, y: 400 });
+    assert_eq!(window_manager.dirty_extents.borrow().len(), 1);
+
+    window_manager.window_at(&Point::ZERO);
+    assert!(window_manager.dirty_extents.borrow().is_empty());
+    let spatial_index = window_manager.spatial_index.borrow();
+    let window_index = spatial_index.as_ref().unwrap();
+    assert!(Rc::ptr_eq(&window_index.windows, &ordered));
+    let entry = window_index.entries[&windows[1].id()];
+    assert_eq!(
+      window_index.rectangles[entry].0.top_left(),
+      Point { x: 300, y: 400 }
     );
+  }
+
+  #[test]
+  fn it_drops_and_cleans_up_on_destroy() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+    let output_manager = &managers.output_manager;
     let cursor_manager = CursorManager::mock(
+      managers.config_manager.clone(),
       output_manager.clone(),
       window_manager.clone(),
-      seat_manager.clone(),
+      managers.seat_manager.clone(),
       Rc::new(EventFilterManager::new()),
       ptr::null_mut(),
       ptr::null_mut(),
     );
 
-    window_manager.set_output_manager(output_manager.clone());
     let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
 
     let mut event_handler = WindowEventHandler {
-      wm_policy_manager,
+      wm_policy_manager: managers.wm_policy_manager.clone(),
       output_manager: output_manager.clone(),
       window_manager: window_manager.clone(),
       cursor_manager: cursor_manager.clone(),
       window: Rc::downgrade(&window),
-      foreign_toplevel_handle: None,
       foreign_toplevel_event_manager: None,
     };
 
@@ -340,6 +1039,167 @@ mod tests {
     assert!(window_manager.windows().count() == 0);
     assert!(weak_window.upgrade().is_none());
   }
+
+  #[test]
+  fn it_reuses_the_window_order_until_layers_change() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let background = window_manager.new_window(WindowLayer::Background, Surface::Null);
+    let top = window_manager.new_window(WindowLayer::Top, Surface::Null);
+
+    let ordered = window_manager.layers.borrow().ordered();
+    assert!(Rc::ptr_eq(
+      &ordered,
+      &window_manager.layers.borrow().ordered()
+    ));
+    assert_eq!(
+      window_manager
+        .windows()
+        .rev()
+        .map(|window| window.id())
+        .collect::<Vec<_>>(),
+      vec![top.id(), background.id()]
+    );
+
+    window_manager.destroy_window(top);
+    assert!(!Rc::ptr_eq(
+      &ordered,
+      &window_manager.layers.borrow().ordered()
+    ));
+    assert_eq!(window_manager.windows().count(), 1);
+  }
+
+  /// Compares `windows()` with collecting the layers on every call, like
+  /// it used to. Run with `cargo test -- --ignored --nocapture`.
+  #[test]
+  #[ignore]
+  fn bench_iterating_200_windows() {
+    use std::time::Instant;
+
+    const ITERATIONS: u32 = 10_000;
+
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let layers = [
+      WindowLayer::Background,
+      WindowLayer::Bottom,
+      WindowLayer::Normal,
+      WindowLayer::Top,
+      WindowLayer::Overlay,
+    ];
+    for i in 0..200 {
+      window_manager.new_window(layers[i % layers.len()], Surface::Null);
+    }
+
+    let started_at = Instant::now();
+    for _ in 0..ITERATIONS {
+      let windows = window_manager
+        .layers
+        .borrow()
+        .all_windows()
+        .collect::<Vec<_>>();
+      assert_eq!(windows.into_iter().rev().count(), 200);
+    }
+    let collected = started_at.elapsed();
+
+    let started_at = Instant::now();
+    for _ in 0..ITERATIONS {
+      assert_eq!(window_manager.windows().rev().count(), 200);
+    }
+    let shared = started_at.elapsed();
+
+    println!(
+      "200 windows, {} iterations: collected {:?}, shared {:?}",
+      ITERATIONS, collected, shared
+    );
+  }
+
+  #[test]
+  fn it_refuses_to_focus_windows_that_can_not_receive_focus() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    assert_eq!(
+      window_manager.focus_window(window),
+      Err(FocusError::CannotReceiveFocus)
+    );
+  }
+
+  #[test]
+  fn it_does_not_trust_the_cached_focus_after_the_seat_changed_focus() {
+    let mut raw_seat: wlr_seat = unsafe { std::mem::zeroed() };
+    let mut other_surface: wlr_surface = unsafe { std::mem::zeroed() };
+    let managers =
+      MockManagers::with_seat_manager(SeatManager::mock(&mut raw_seat, ptr::null_mut()));
+    let seat_manager = &managers.seat_manager;
+    let window_manager = &managers.window_manager;
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+
+    *window_manager.focused_window.borrow_mut() = Rc::downgrade(&window);
+    // Like when an input inhibitor clears focus and later is released
+    raw_seat.keyboard_state.focused_surface = ptr::null_mut();
+    assert!(window_manager.focused_window(seat_manager).is_none());
+    assert!(!window_manager.window_has_focus(&window));
+
+    raw_seat.keyboard_state.focused_surface = &mut other_surface;
+    assert!(window_manager.focused_window(seat_manager).is_none());
+    assert!(!window_manager.is_any_window_focused());
+  }
+
+  #[test]
+  fn it_assigns_unique_window_ids_and_finds_windows_by_id() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let first = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    let second = window_manager.new_window(WindowLayer::Top, Surface::Null);
+
+    assert_ne!(first.id(), second.id());
+    assert!(first.id() < second.id());
+    assert_eq!(
+      window_manager
+        .window_by_id(second.id())
+        .map(|window| window.id()),
+      Some(second.id())
+    );
+    assert_eq!(
+      window_manager
+        .windows_in_layer(WindowLayer::Top)
+        .map(|window| window.id())
+        .collect::<Vec<_>>(),
+      vec![second.id()]
+    );
+  }
+
+  #[test]
+  fn it_moves_windows_between_layers() {
+    let managers = MockManagers::new();
+    let window_manager = &managers.window_manager;
+
+    let window = window_manager.new_window(WindowLayer::Normal, Surface::Null);
+    let panel = window_manager.new_window(WindowLayer::Top, Surface::Null);
+    let ids = |windows: Vec<Rc<Window>>| windows.iter().map(|w| w.id()).collect::<Vec<_>>();
+    assert_eq!(
+      ids(window_manager.windows().collect()),
+      vec![window.id(), panel.id()]
+    );
+
+    window_manager.set_window_layer(&window, WindowLayer::Fullscreen);
+
+    assert_eq!(window.layer(), WindowLayer::Fullscreen);
+    assert_eq!(
+      window_manager.windows_in_layer(WindowLayer::Normal).count(),
+      0
+    );
+    assert_eq!(
+      ids(window_manager.windows().collect()),
+      vec![panel.id(), window.id()]
+    );
+  }
 }
 
 #[cfg(test)]
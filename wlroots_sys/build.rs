@@ -20,6 +20,9 @@ fn main() {
     .whitelist_type(r"^wlr_.*$")
     .whitelist_function(r"^_?pixman_.*$")
     .whitelist_function(r"^_?wlr_.*$")
+    .whitelist_function(r"^libinput_device_config_.*$")
+    .whitelist_function(r"^wl_(display_add_protocol_logger|display_set_global_filter|protocol_logger_destroy)$")
+    .whitelist_function(r"^xcb_(connect|connection_has_error|disconnect|intern_atom(_reply)?)$")
     .ctypes_prefix("libc")
     .clang_arg("-Iwlroots/include")
     .clang_arg("-Iwlroots/include/wlr")
@@ -39,7 +42,7 @@ fn main() {
     .blacklist_type(r"^xkb_.*$")
     .blacklist_type(r"^XKB_.*$")
     // Work around duplicate wayland types from wlroots and wayland_sys
-    .blacklist_type(r"^wl_(client|display|list|listener|resource|signal)$");
+    .blacklist_type(r"^wl_(client|display|global|list|listener|resource|signal)$");
   if cfg!(feature = "unstable") {
     builder = builder
       .clang_arg("-DWLR_USE_UNSTABLE")